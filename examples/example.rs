@@ -1,6 +1,6 @@
 extern crate rusql;
 
-use rusql::{rusql_exec, Rusql, RowFormat};
+use rusql::{Rusql, QueryResult, RowFormat};
 
 fn main() {
     let mut db = Rusql::new();
@@ -13,7 +13,11 @@ fn main() {
                    INSERT INTO Yarp VALUES(2, \"Yarp2\"); \
                    SELECT * FROM Foo, Yarp;";
 
-    rusql_exec(&mut db, sql_str, |row, _| {
-        println!("{}", RowFormat(row));
-    });
+    let results = db.execute(sql_str).unwrap();
+
+    if let Some(&QueryResult::Rows(ref table)) = results.last() {
+        for row in table.data.values() {
+            println!("{}", RowFormat(row));
+        }
+    }
 }
@@ -0,0 +1,78 @@
+//! Rusql::backup_to, copying a database into another one a step at a time.
+//! The backup copies the tables as they were when it started, which it
+//! shares with the source like a snapshot does, so the source can go on
+//! changing meanwhile, e.g. if the backup runs on a snapshot in another
+//! thread. The copy is built apart and only takes the place of what the
+//! other database held once it's whole.
+
+use definitions::RusqlStatement;
+use error::{RusqlError, RusqlResult};
+use persist;
+use rusql::Rusql;
+use table::Table;
+
+use std::io::{MemReader, MemWriter};
+use std::sync::Arc;
+
+/// How many rows each step copies.
+pub const STEP: usize = 1000;
+
+/// How far a backup got, handed to its callback after every step.
+#[derive(Copy, Show, Clone, PartialEq)]
+pub struct Progress {
+    /// How many rows were copied so far, of `total`.
+    pub copied: usize,
+    pub total: usize,
+}
+
+/// Copies the tables of `source`, their rows and the rest of its schema into
+/// `dest`, replacing its own, and writes `dest` to its file if it has one.
+/// Temporary tables aren't copied.
+pub fn backup<F: FnMut(Progress)>(source: &Rusql, dest: &mut Rusql, mut progress: F) -> RusqlResult<()> {
+    if dest.in_transaction() {
+        return Err(RusqlError::InvalidTransaction("cannot back up into a database within a transaction".to_string()));
+    }
+
+    let tables: Vec<Arc<Table>> = source.map.values().map(|table| table.clone()).collect();
+    let mut schema = MemWriter::new();
+    try!(persist::write_schema(&mut schema, source));
+
+    let mut copy = Rusql::new();
+    let mut done = Progress { copied: 0, total: tables.iter().fold(0, |total, table| total + table.data.len()) };
+
+    for table in tables.iter() {
+        match try!(persist::parse_one(table.to_sql().as_slice())) {
+            RusqlStatement::CreateTable(table_def) => try!(copy.create_table(table_def)),
+            _ => return Err(RusqlError::Corrupt(format!("bad definition of table {}", table.name))),
+        }
+
+        let copied = try!(copy.get_mut_table(&table.name));
+        for (i, (pk, row)) in table.data.iter().enumerate() {
+            copied.data.insert(pk, row.clone());
+            done.copied += 1;
+
+            if (i + 1) % STEP == 0 {
+                progress(done);
+            }
+        }
+        copied.max_pk = table.max_pk;
+        copied.memory = table.memory;
+
+        if table.data.len() % STEP != 0 || table.data.is_empty() {
+            progress(done);
+        }
+    }
+
+    // The indexes are built on the rows copied.
+    try!(persist::read_schema(&mut MemReader::new(schema.into_inner()), &mut copy));
+    copy.stats = source.stats.clone();
+
+    dest.map = copy.map;
+    dest.indexes = copy.indexes;
+    dest.views = copy.views;
+    dest.materialized_views = copy.materialized_views;
+    dest.triggers = copy.triggers;
+    dest.stats = copy.stats;
+    dest.parse_cache.clear();
+    dest.flush()
+}
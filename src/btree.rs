@@ -0,0 +1,728 @@
+//! The B-trees a paged file keeps its tables in, see pager, and the Storage
+//! of the tables of a database from open_paged.
+//!
+//! A leaf page holds rows by key, an interior page the smallest key and the
+//! page of each of its children. A leaf cell is the key, the length of the
+//! row, the length of the part of it in the leaf and that part; the rest
+//! of a long row is in a chain of overflow pages. Rows are written as by
+//! persist.
+//!
+//! A B-tree is never changed: syncing the changes made to a table writes
+//! the leaves they're in and the pages above them again elsewhere, and the
+//! rest is shared with the B-tree before.
+
+use error::{RusqlError, RusqlResult};
+use pager::{Pager, PageNo, be_bytes, be_uint};
+use persist;
+use storage::Storage;
+use table::{TableRow, PkType};
+
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::collections::Bound::{Included, Unbounded};
+use std::io::{IoResult, MemReader, MemWriter};
+use std::mem::replace;
+use std::sync::{Arc, Mutex};
+use std::usize;
+
+const LEAF: u8 = 1;
+const INTERIOR: u8 = 2;
+// The kind of page and how many cells it has.
+const PAGE_HEADER: usize = 3;
+// The key and the page of a child.
+const INTERIOR_CELL: usize = 16;
+// The key, both lengths and the first overflow page of a leaf cell.
+const CELL_HEADER: usize = 22;
+
+/// A B-tree as a sync left it, which the tables sharing it read until they
+/// change. The pages it no longer shares with the B-trees after it are
+/// freed when nothing reads it, or the ones before it, anymore.
+pub struct Version {
+    pager: Arc<Mutex<Pager>>,
+    root: PageNo,
+    generation: u64,
+    /// The pages to free with it, None while it's the latest.
+    obsolete: Mutex<Option<Vec<PageNo>>>,
+}
+
+impl Version {
+    fn new(pager: Arc<Mutex<Pager>>, root: PageNo) -> Version {
+        let generation = pager.lock().unwrap().register();
+        Version { pager: pager, root: root, generation: generation, obsolete: Mutex::new(None) }
+    }
+
+    /// Frees every page of the B-tree, once nothing reads it anymore; for
+    /// the B-trees of the tables dropped.
+    pub fn abandon(&self) -> RusqlResult<()> {
+        let mut obsolete = self.obsolete.lock().unwrap();
+
+        if obsolete.is_none() {
+            *obsolete = Some(try!(tree_pages(&mut *self.pager.lock().unwrap(), self.root)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Version {
+    fn drop(&mut self) {
+        let pages = self.obsolete.lock().unwrap().take().unwrap_or(Vec::new());
+        self.pager.lock().unwrap().release(self.generation, pages);
+    }
+}
+
+/// The rows of a table kept in a B-tree, and the changes made to them since
+/// the last sync, which stay in memory until then. The leaves read are
+/// kept until the next sync too, as the rows handed out point into them.
+/// open_paged reads every page first, to report the corrupt ones, so only
+/// a file that can't be read again later panics, as Storage can't report it.
+pub struct PagedStorage {
+    version: Arc<Version>,
+    /// The rows inserted or replaced since the last sync, and None for
+    /// those deleted.
+    changes: BTreeMap<PkType, Option<TableRow>>,
+    /// Whether the rows of the B-tree were all deleted since.
+    cleared: bool,
+    len: usize,
+    leaves: Mutex<BTreeMap<PageNo, Box<Vec<(PkType, TableRow)>>>>,
+}
+
+// A page as read from the B-tree.
+enum Node<'a> {
+    Interior(Vec<(PkType, PageNo)>),
+    Leaf(&'a [(PkType, TableRow)]),
+}
+
+impl PagedStorage {
+    /// The B-tree starting at `root`, 0 for an empty one, holding `count`
+    /// rows.
+    pub fn open(pager: Arc<Mutex<Pager>>, root: PageNo, count: usize) -> PagedStorage {
+        PagedStorage {
+            version: Arc::new(Version::new(pager, root)),
+            changes: BTreeMap::new(),
+            cleared: false,
+            len: count,
+            leaves: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Writes `rows`, in the order of their keys, to a new B-tree.
+    pub fn create<'a, I>(pager: Arc<Mutex<Pager>>, rows: I) -> RusqlResult<PagedStorage>
+        where I: Iterator<Item=(PkType, &'a TableRow)> {
+        let (root, count) = {
+            let mut pager = pager.lock().unwrap();
+            let mut cells: Vec<(PkType, Vec<u8>)> = Vec::new();
+
+            for (pk, row) in rows {
+                cells.push((pk, try!(new_cell(&mut *pager, pk, row))));
+            }
+
+            let count = cells.len();
+            let leaves = try!(write_leaves(&mut *pager, cells));
+            (try!(write_root(&mut *pager, leaves)), count)
+        };
+
+        Ok(PagedStorage::open(pager, root, count))
+    }
+
+    pub fn version(&self) -> Arc<Version> {
+        self.version.clone()
+    }
+
+    /// The page the B-tree starts at, as of the last sync.
+    pub fn root(&self) -> PageNo {
+        self.version.root
+    }
+
+    /// Writes the changes to a new B-tree, which takes the place of the one
+    /// before for this table.
+    pub fn sync(&mut self) -> RusqlResult<()> {
+        if self.changes.is_empty() && !self.cleared {
+            return Ok(());
+        }
+
+        let changes: Vec<(PkType, Option<TableRow>)> = replace(&mut self.changes, BTreeMap::new()).into_iter()
+                                                                                                  .collect();
+        let pager = self.version.pager.clone();
+        let mut obsolete: Vec<PageNo> = Vec::new();
+
+        let written = {
+            let mut guard = pager.lock().unwrap();
+            let pager = &mut *guard;
+            let children = if self.cleared || self.version.root == 0 {
+                rebuild(pager, self.version.root, changes.as_slice(), &mut obsolete)
+            } else {
+                apply(pager, self.version.root, changes.as_slice(), &mut obsolete)
+            };
+
+            children.and_then(|children| write_root(pager, children))
+        };
+
+        let root = match written {
+            Ok(root) => root,
+            Err(err) => {
+                self.changes = changes.into_iter().collect();
+                return Err(err);
+            }
+        };
+
+        *self.version.obsolete.lock().unwrap() = Some(obsolete);
+        self.version = Arc::new(Version::new(pager, root));
+        self.cleared = false;
+        self.unload();
+        Ok(())
+    }
+
+    /// Drops the rows read from the B-tree so far.
+    pub fn unload(&mut self) {
+        self.leaves.lock().unwrap().clear();
+    }
+
+    /// Calls `f` on every row of the B-tree as of the last sync, reading
+    /// them one leaf at a time without keeping them.
+    pub fn for_each_row<F: FnMut(PkType, TableRow)>(&self, mut f: F) -> RusqlResult<()> {
+        let mut pages = if self.version.root == 0 { Vec::new() } else { vec![self.version.root] };
+
+        while let Some(page) = pages.pop() {
+            let mut pager = self.version.pager.lock().unwrap();
+            let bytes = try!(pager.read(page));
+
+            if bytes[0] == LEAF {
+                let rows = try!(leaf_rows(&mut *pager, bytes.as_slice()));
+                drop(pager);
+                for (pk, row) in rows.into_iter() {
+                    f(pk, row);
+                }
+            } else {
+                pages.extend(try!(interior_cells(bytes.as_slice())).into_iter().rev().map(|(_, child)| child));
+            }
+        }
+        Ok(())
+    }
+
+    fn node<'a>(&'a self, page: PageNo) -> Node<'a> {
+        let mut leaves = self.leaves.lock().unwrap();
+
+        if !leaves.contains_key(&page) {
+            let mut pager = self.version.pager.lock().unwrap();
+            let bytes = pager.read(page).ok().expect("can't read a page of the database");
+
+            if bytes[0] != LEAF {
+                return Node::Interior(interior_cells(bytes.as_slice()).ok().expect("corrupt database page"));
+            }
+
+            let rows = leaf_rows(&mut *pager, bytes.as_slice()).ok().expect("corrupt database page");
+            leaves.insert(page, box rows);
+        }
+
+        // The leaves are only dropped through &mut self, so the rows live
+        // as long as the borrow of self does.
+        let rows: *const Vec<(PkType, TableRow)> = &**leaves.get(&page).unwrap();
+        Node::Leaf(unsafe { (*rows).as_slice() })
+    }
+
+    fn base_rows<'a>(&'a self, from: PkType) -> BaseRows<'a> {
+        let mut rows = BaseRows { storage: self, path: Vec::new(), rows: &[], position: 0 };
+
+        if !self.cleared && self.version.root != 0 {
+            rows.descend(self.version.root, Some(from));
+        }
+        rows
+    }
+
+    fn base_get(&self, pk: PkType) -> Option<&TableRow> {
+        match self.base_rows(pk).next() {
+            Some((key, row)) if key == pk => Some(row),
+            _ => None,
+        }
+    }
+
+    fn base_last_key(&self) -> Option<PkType> {
+        if self.cleared || self.version.root == 0 {
+            return None;
+        }
+
+        let mut page = self.version.root;
+        loop {
+            match self.node(page) {
+                Node::Interior(children) => page = children[children.len() - 1].1,
+                Node::Leaf(rows) => return rows.last().map(|&(pk, _)| pk),
+            }
+        }
+    }
+
+    fn rows<'a>(&'a self, from: PkType, to: PkType) -> Rows<'a> {
+        let mut base = self.base_rows(from);
+        let mut changes = self.changes.range(Included(&from), Unbounded);
+
+        Rows {
+            next_base: base.next(),
+            base: base,
+            next_change: changes.next(),
+            changes: box changes,
+            to: to,
+        }
+    }
+}
+
+impl Storage for PagedStorage {
+    fn get(&self, pk: PkType) -> Option<&TableRow> {
+        match self.changes.get(&pk) {
+            Some(change) => change.as_ref(),
+            None => self.base_get(pk),
+        }
+    }
+
+    fn get_mut(&mut self, pk: PkType) -> Option<&mut TableRow> {
+        if !self.changes.contains_key(&pk) {
+            match self.base_get(pk).map(|row| row.clone()) {
+                Some(row) => { self.changes.insert(pk, Some(row)); }
+                None => return None,
+            }
+        }
+        self.changes.get_mut(&pk).and_then(|change| change.as_mut())
+    }
+
+    fn insert(&mut self, pk: PkType, row: TableRow) -> Option<TableRow> {
+        let old = self.get(pk).map(|row| row.clone());
+
+        if old.is_none() {
+            self.len += 1;
+        }
+        self.changes.insert(pk, Some(row));
+        old
+    }
+
+    fn delete(&mut self, pk: PkType) -> Option<TableRow> {
+        let old = self.get(pk).map(|row| row.clone());
+
+        if old.is_some() {
+            self.len -= 1;
+            self.changes.insert(pk, None);
+        }
+        old
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.changes.clear();
+        self.cleared = true;
+        self.len = 0;
+    }
+
+    fn scan<'a>(&'a self) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a> {
+        box self.rows(0, usize::MAX)
+    }
+
+    // Every row is copied into the changes first, which is only done when
+    // the whole table changes anyway, e.g. when a column is added.
+    fn scan_mut<'a>(&'a mut self) -> Box<Iterator<Item=(PkType, &'a mut TableRow)> + 'a> {
+        let rows: Vec<(PkType, TableRow)> = self.base_rows(0).filter(|&(pk, _)| !self.changes.contains_key(&pk))
+                                                             .map(|(pk, row)| (pk, row.clone()))
+                                                             .collect();
+        for (pk, row) in rows.into_iter() {
+            self.changes.insert(pk, Some(row));
+        }
+
+        box self.changes.iter_mut().filter_map(|(pk, change)| change.as_mut().map(|row| (*pk, row)))
+    }
+
+    fn range<'a>(&'a self, from: PkType, to: PkType) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a> {
+        box self.rows(from, to)
+    }
+
+    fn last_key(&self) -> Option<PkType> {
+        let changed = self.changes.iter().rev().find(|&(_, change)| change.is_some()).map(|(pk, _)| *pk);
+
+        match self.base_last_key() {
+            Some(pk) if !self.changes.contains_key(&pk) => max(Some(pk), changed),
+            Some(_) => self.scan().last().map(|(pk, _)| pk),
+            None => changed,
+        }
+    }
+
+    fn into_rows(self: Box<Self>) -> Vec<(PkType, TableRow)> {
+        let rows = self.scan().map(|(pk, row)| (pk, row.clone())).collect();
+        rows
+    }
+
+    fn box_clone(&self) -> Box<Storage> {
+        box PagedStorage {
+            version: self.version.clone(),
+            changes: self.changes.clone(),
+            cleared: self.cleared,
+            len: self.len,
+            leaves: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn paged(&mut self) -> Option<&mut PagedStorage> {
+        Some(self)
+    }
+}
+
+// The rows of the B-tree from a key on, in order.
+struct BaseRows<'a> {
+    storage: &'a PagedStorage,
+    /// The interior pages above the leaf being read, each with the child
+    /// that's being read.
+    path: Vec<(Vec<(PkType, PageNo)>, usize)>,
+    rows: &'a [(PkType, TableRow)],
+    position: usize,
+}
+
+impl<'a> BaseRows<'a> {
+    // Goes down from `page` to the leaf that holds `from`, or to the first
+    // leaf without one.
+    fn descend(&mut self, mut page: PageNo, from: Option<PkType>) {
+        loop {
+            match self.storage.node(page) {
+                Node::Interior(children) => {
+                    let i = from.map_or(0, |key| child_index(children.as_slice(), key));
+                    page = children[i].1;
+                    self.path.push((children, i));
+                }
+                Node::Leaf(rows) => {
+                    self.rows = rows;
+                    self.position = from.map_or(0, |key| rows.iter().take_while(|&&(pk, _)| pk < key).count());
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for BaseRows<'a> {
+    type Item = (PkType, &'a TableRow);
+
+    fn next(&mut self) -> Option<(PkType, &'a TableRow)> {
+        while self.position == self.rows.len() {
+            match self.path.pop() {
+                Some((children, i)) => {
+                    if i + 1 < children.len() {
+                        let page = children[i + 1].1;
+                        self.path.push((children, i + 1));
+                        self.descend(page, None);
+                    }
+                }
+                None => return None,
+            }
+        }
+
+        let &(pk, ref row) = &self.rows[self.position];
+        self.position += 1;
+        Some((pk, row))
+    }
+}
+
+// The rows of the B-tree merged with the changes, up to a key.
+struct Rows<'a> {
+    base: BaseRows<'a>,
+    next_base: Option<(PkType, &'a TableRow)>,
+    changes: Box<Iterator<Item=(&'a PkType, &'a Option<TableRow>)> + 'a>,
+    next_change: Option<(&'a PkType, &'a Option<TableRow>)>,
+    to: PkType,
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = (PkType, &'a TableRow);
+
+    fn next(&mut self) -> Option<(PkType, &'a TableRow)> {
+        loop {
+            let take_base = match (self.next_base, self.next_change) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some((pk, _)), Some((&changed, _))) => {
+                    // A changed row takes the place of the one in the B-tree.
+                    if pk == changed {
+                        self.next_base = self.base.next();
+                    }
+                    pk < changed
+                }
+            };
+
+            let (pk, row) = if take_base {
+                let (pk, row) = self.next_base.unwrap();
+                self.next_base = self.base.next();
+                (pk, Some(row))
+            } else {
+                let (&pk, change) = self.next_change.unwrap();
+                self.next_change = self.changes.next();
+                (pk, change.as_ref())
+            };
+
+            if pk > self.to {
+                return None;
+            }
+            if let Some(row) = row {
+                return Some((pk, row));
+            }
+        }
+    }
+}
+
+// The child of an interior page whose keys `key` belongs with.
+fn child_index(children: &[(PkType, PageNo)], key: PkType) -> usize {
+    max(children.iter().take_while(|&&(first, _)| first <= key).count(), 1) - 1
+}
+
+// Merges `changes`, in the order of their keys, into the B-tree under
+// `page`. Returns the pages that take its place, each with its smallest key,
+// none if its rows were all deleted. The pages replaced go to `obsolete`.
+fn apply(pager: &mut Pager, page: PageNo, changes: &[(PkType, Option<TableRow>)], obsolete: &mut Vec<PageNo>)
+        -> RusqlResult<Vec<(PkType, PageNo)>> {
+    let bytes = try!(pager.read(page));
+    obsolete.push(page);
+
+    if bytes[0] != LEAF {
+        let children = try!(interior_cells(bytes.as_slice()));
+        let mut replaced: Vec<(PkType, PageNo)> = Vec::new();
+        let mut start = 0;
+
+        for (i, &(first, child)) in children.iter().enumerate() {
+            let end = if i + 1 < children.len() {
+                start + changes[start..].iter().take_while(|&&(pk, _)| pk < children[i + 1].0).count()
+            } else {
+                changes.len()
+            };
+
+            if end > start {
+                replaced.extend(try!(apply(pager, child, &changes[start..end], obsolete)).into_iter());
+            } else {
+                replaced.push((first, child));
+            }
+            start = end;
+        }
+
+        return write_interiors(pager, replaced);
+    }
+
+    let mut cells: Vec<(PkType, Vec<u8>)> = Vec::new();
+    let mut changes = changes.iter().peekable();
+
+    for (pk, cell) in try!(leaf_cells(bytes.as_slice())).into_iter() {
+        while changes.peek().map_or(false, |&&(changed, _)| changed < pk) {
+            let &(changed, ref change) = changes.next().unwrap();
+            if let Some(ref row) = *change {
+                cells.push((changed, try!(new_cell(pager, changed, row))));
+            }
+        }
+
+        match changes.peek() {
+            Some(&&(changed, _)) if changed == pk => {
+                obsolete.extend(try!(overflow_pages(pager, cell.as_slice())).into_iter());
+            }
+            _ => {
+                cells.push((pk, cell));
+                continue;
+            }
+        }
+
+        if let Some(row) = changes.next().unwrap().1.as_ref() {
+            cells.push((pk, try!(new_cell(pager, pk, row))));
+        }
+    }
+
+    for &(changed, ref change) in changes {
+        if let Some(ref row) = *change {
+            cells.push((changed, try!(new_cell(pager, changed, row))));
+        }
+    }
+
+    write_leaves(pager, cells)
+}
+
+// Writes the rows among `changes` to new leaves, for a B-tree whose rows
+// were all deleted, and frees the pages of the old one.
+fn rebuild(pager: &mut Pager, root: PageNo, changes: &[(PkType, Option<TableRow>)], obsolete: &mut Vec<PageNo>)
+        -> RusqlResult<Vec<(PkType, PageNo)>> {
+    obsolete.extend(try!(tree_pages(pager, root)).into_iter());
+
+    let mut cells: Vec<(PkType, Vec<u8>)> = Vec::new();
+    for &(pk, ref change) in changes.iter() {
+        if let Some(ref row) = *change {
+            cells.push((pk, try!(new_cell(pager, pk, row))));
+        }
+    }
+    write_leaves(pager, cells)
+}
+
+// Adds interior pages above `children` until there's one page on top.
+fn write_root(pager: &mut Pager, mut children: Vec<(PkType, PageNo)>) -> RusqlResult<PageNo> {
+    while children.len() > 1 {
+        children = try!(write_interiors(pager, children));
+    }
+    Ok(children.first().map_or(0, |&(_, page)| page))
+}
+
+fn write_leaves(pager: &mut Pager, cells: Vec<(PkType, Vec<u8>)>) -> RusqlResult<Vec<(PkType, PageNo)>> {
+    let mut pages: Vec<(PkType, PageNo)> = Vec::new();
+    let mut contents: Vec<u8> = Vec::new();
+    let mut count = 0;
+    let mut first = 0;
+
+    for (pk, cell) in cells.into_iter() {
+        if count > 0 && PAGE_HEADER + contents.len() + cell.len() > pager.page_size {
+            pages.push((first, try!(write_page(pager, LEAF, count, contents.as_slice()))));
+            contents.clear();
+            count = 0;
+        }
+        if count == 0 {
+            first = pk;
+        }
+        contents.push_all(cell.as_slice());
+        count += 1;
+    }
+
+    if count > 0 {
+        pages.push((first, try!(write_page(pager, LEAF, count, contents.as_slice()))));
+    }
+    Ok(pages)
+}
+
+fn write_interiors(pager: &mut Pager, children: Vec<(PkType, PageNo)>) -> RusqlResult<Vec<(PkType, PageNo)>> {
+    let per_page = (pager.page_size - PAGE_HEADER) / INTERIOR_CELL;
+    let mut pages: Vec<(PkType, PageNo)> = Vec::new();
+
+    for chunk in children.chunks(per_page) {
+        let mut contents: Vec<u8> = Vec::new();
+        for &(first, child) in chunk.iter() {
+            contents.push_all(be_bytes(first as u64, 8).as_slice());
+            contents.push_all(be_bytes(child, 8).as_slice());
+        }
+        pages.push((chunk[0].0, try!(write_page(pager, INTERIOR, chunk.len(), contents.as_slice()))));
+    }
+    Ok(pages)
+}
+
+fn write_page(pager: &mut Pager, kind: u8, count: usize, cells: &[u8]) -> RusqlResult<PageNo> {
+    let page = pager.allocate();
+    let mut contents = vec![kind];
+
+    contents.push_all(be_bytes(count as u64, 2).as_slice());
+    contents.push_all(cells);
+    try!(pager.write(page, contents.as_slice()));
+    Ok(page)
+}
+
+// The cell of a row, whose overflow pages, if it has any, are written.
+fn new_cell(pager: &mut Pager, pk: PkType, row: &TableRow) -> RusqlResult<Vec<u8>> {
+    let mut w = MemWriter::new();
+    for value in row.iter() {
+        try!(persist::write_value(&mut w, value));
+    }
+    let record = w.into_inner();
+
+    // Big enough that a leaf holds at least four rows.
+    let max_local = (pager.page_size - PAGE_HEADER) / 4 - CELL_HEADER;
+    let local = min(record.len(), max_local);
+    let mut cell = be_bytes(pk as u64, 8);
+
+    cell.push_all(be_bytes(record.len() as u64, 4).as_slice());
+    cell.push_all(be_bytes(local as u64, 2).as_slice());
+    cell.push_all(&record[..local]);
+
+    if local < record.len() {
+        let rest = &record[local..];
+        let pages: Vec<PageNo> = range(0, pager.chain_length(rest.len())).map(|_| pager.allocate()).collect();
+        try!(pager.write_chain(rest, pages.as_slice()));
+        cell.push_all(be_bytes(pages[0], 8).as_slice());
+    }
+    Ok(cell)
+}
+
+// The key, the length of the row and the length of the part in the leaf.
+fn cell_header(cell: &[u8]) -> (PkType, usize, usize) {
+    (be_uint(&cell[0..8]) as PkType, be_uint(&cell[8..12]) as usize, be_uint(&cell[12..14]) as usize)
+}
+
+fn leaf_cells(page: &[u8]) -> RusqlResult<Vec<(PkType, Vec<u8>)>> {
+    let mut cells: Vec<(PkType, Vec<u8>)> = Vec::new();
+    let mut pos = PAGE_HEADER;
+
+    for _ in range(0, be_uint(&page[1..3])) {
+        if pos + CELL_HEADER - 8 > page.len() {
+            return Err(RusqlError::Corrupt("leaf cell past the end of its page".to_string()));
+        }
+
+        let (pk, len, local) = cell_header(&page[pos..]);
+        let end = pos + CELL_HEADER - 8 + local + if local < len { 8 } else { 0 };
+        if end > page.len() {
+            return Err(RusqlError::Corrupt("leaf cell past the end of its page".to_string()));
+        }
+
+        cells.push((pk, page[pos..end].to_vec()));
+        pos = end;
+    }
+    Ok(cells)
+}
+
+fn leaf_rows(pager: &mut Pager, page: &[u8]) -> RusqlResult<Vec<(PkType, TableRow)>> {
+    let mut rows: Vec<(PkType, TableRow)> = Vec::new();
+
+    for (pk, cell) in try!(leaf_cells(page)).into_iter() {
+        let (_, len, local) = cell_header(cell.as_slice());
+        let start = CELL_HEADER - 8;
+        let mut record = cell[start..start + local].to_vec();
+
+        if local < len {
+            let first = be_uint(&cell[start + local..]);
+            record.push_all(try!(pager.read_chain(first, len - local)).0.as_slice());
+        }
+
+        let mut r = MemReader::new(record);
+        let mut row: TableRow = Vec::new();
+        while !r.eof() {
+            row.push(try!(persist::read_value(&mut r)));
+        }
+        rows.push((pk, row));
+    }
+    Ok(rows)
+}
+
+fn interior_cells(page: &[u8]) -> RusqlResult<Vec<(PkType, PageNo)>> {
+    let count = be_uint(&page[1..3]) as usize;
+
+    if count == 0 || PAGE_HEADER + count * INTERIOR_CELL > page.len() {
+        return Err(RusqlError::Corrupt("bad interior page".to_string()));
+    }
+
+    Ok(range(0, count).map(|i| {
+        let pos = PAGE_HEADER + i * INTERIOR_CELL;
+        (be_uint(&page[pos..pos + 8]) as PkType, be_uint(&page[pos + 8..pos + 16]))
+    }).collect())
+}
+
+fn overflow_pages(pager: &mut Pager, cell: &[u8]) -> RusqlResult<Vec<PageNo>> {
+    let (_, len, local) = cell_header(cell);
+
+    if local == len {
+        return Ok(Vec::new());
+    }
+
+    let first = be_uint(&cell[CELL_HEADER - 8 + local..]);
+    Ok(try!(pager.read_chain(first, len - local)).1)
+}
+
+// Every page of the B-tree under `root`, its overflow pages included.
+fn tree_pages(pager: &mut Pager, root: PageNo) -> RusqlResult<Vec<PageNo>> {
+    let mut pages: Vec<PageNo> = Vec::new();
+    let mut pending = if root == 0 { Vec::new() } else { vec![root] };
+
+    while let Some(page) = pending.pop() {
+        let bytes = try!(pager.read(page));
+        pages.push(page);
+
+        if bytes[0] == LEAF {
+            for (_, cell) in try!(leaf_cells(bytes.as_slice())).into_iter() {
+                pages.extend(try!(overflow_pages(pager, cell.as_slice())).into_iter());
+            }
+        } else {
+            pending.extend(try!(interior_cells(bytes.as_slice())).into_iter().map(|(_, child)| child));
+        }
+    }
+    Ok(pages)
+}
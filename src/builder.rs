@@ -0,0 +1,248 @@
+//! Builds SELECTs out of the same definitions the parser produces, e.g.
+//! `Select::from("Foo").column("Id").filter(col("Id").eq(1))`.
+
+use definitions::{SelectDef, ResultColumn, FromClause, JoinOperator, JoinConstraint};
+use definitions::{OrderingTerm, Order, LimitDef, Expression, BinaryOperator, UnaryOperator};
+use definitions::LiteralValue;
+
+use std::mem;
+use std::sync::Arc;
+
+/// Something that can be used as an operand, like a column or a value.
+pub trait ToExpression {
+    fn to_expression(self) -> Expression;
+}
+
+impl ToExpression for Expression {
+    fn to_expression(self) -> Expression {
+        self
+    }
+}
+
+impl ToExpression for LiteralValue {
+    fn to_expression(self) -> Expression {
+        match self {
+            LiteralValue::Null => Expression::Null,
+            value => Expression::LiteralValue(value),
+        }
+    }
+}
+
+impl ToExpression for isize {
+    fn to_expression(self) -> Expression {
+        LiteralValue::Integer(self).to_expression()
+    }
+}
+
+impl ToExpression for i32 {
+    fn to_expression(self) -> Expression {
+        (self as isize).to_expression()
+    }
+}
+
+impl ToExpression for i64 {
+    fn to_expression(self) -> Expression {
+        (self as isize).to_expression()
+    }
+}
+
+impl ToExpression for f64 {
+    fn to_expression(self) -> Expression {
+        LiteralValue::Real(self).to_expression()
+    }
+}
+
+impl ToExpression for bool {
+    fn to_expression(self) -> Expression {
+        LiteralValue::Boolean(self).to_expression()
+    }
+}
+
+impl<'a> ToExpression for &'a str {
+    fn to_expression(self) -> Expression {
+        LiteralValue::text(self).to_expression()
+    }
+}
+
+impl ToExpression for String {
+    fn to_expression(self) -> Expression {
+        LiteralValue::Text(Arc::new(self)).to_expression()
+    }
+}
+
+/// A column, optionally qualified by its table as in "Foo.Id".
+pub fn col(name: &str) -> Expression {
+    match name.find('.') {
+        Some(i) => Expression::TableName((name.slice_to(i).to_string(),
+                                          box Expression::ColumnName(name.slice_from(i + 1).to_string()))),
+        None => Expression::ColumnName(name.to_string()),
+    }
+}
+
+/// A literal value.
+pub fn val<T: ToExpression>(value: T) -> Expression {
+    value.to_expression()
+}
+
+/// A call to one of the built in functions, e.g. `func("lower", vec![col("Name")])`.
+pub fn func(name: &str, args: Vec<Expression>) -> Expression {
+    Expression::Function((name.to_string(), args))
+}
+
+impl Expression {
+    fn binary<T: ToExpression>(self, op: BinaryOperator, other: T) -> Expression {
+        Expression::BinaryOperator((op, box self, box other.to_expression()))
+    }
+
+    pub fn eq<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::Equals, other)
+    }
+
+    pub fn ne<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::NotEquals, other)
+    }
+
+    pub fn lt<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::Less, other)
+    }
+
+    pub fn le<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::LessEq, other)
+    }
+
+    pub fn gt<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::Greater, other)
+    }
+
+    pub fn ge<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::GreaterEq, other)
+    }
+
+    pub fn is_null(self) -> Expression {
+        self.binary(BinaryOperator::Is, Expression::Null)
+    }
+
+    pub fn is_not_null(self) -> Expression {
+        self.binary(BinaryOperator::IsNot, Expression::Null)
+    }
+
+    pub fn and<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::And, other)
+    }
+
+    pub fn or<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::Or, other)
+    }
+
+    pub fn plus<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::Plus, other)
+    }
+
+    pub fn minus<T: ToExpression>(self, other: T) -> Expression {
+        self.binary(BinaryOperator::Minus, other)
+    }
+
+    pub fn not(self) -> Expression {
+        Expression::UnaryOperator((UnaryOperator::Not, box self))
+    }
+}
+
+#[derive(Clone)]
+pub struct Select {
+    def: SelectDef,
+}
+
+impl Select {
+    pub fn from(table_name: &str) -> Select {
+        Select {
+            def: SelectDef {
+                result_column: ResultColumn::Asterisk,
+                from_clause: Some(FromClause::TableOrSubquery(vec![table_name.to_string()])),
+                where_expr: None,
+                ordering_terms: None,
+                limit: None,
+            }
+        }
+    }
+
+    /// Adds a column to the result, which is every column until one is added.
+    pub fn column(self, name: &str) -> Select {
+        self.expr(col(name))
+    }
+
+    pub fn expr(mut self, expr: Expression) -> Select {
+        let mut exprs = match mem::replace(&mut self.def.result_column, ResultColumn::Asterisk) {
+            ResultColumn::Expressions(exprs) => exprs,
+            ResultColumn::Asterisk => Vec::new(),
+        };
+
+        exprs.push(expr);
+        self.def.result_column = ResultColumn::Expressions(exprs);
+        self
+    }
+
+    pub fn join(mut self, table_name: &str, on: Expression) -> Select {
+        self.add_join(JoinOperator::Inner, table_name, Some(JoinConstraint::On(on)));
+        self
+    }
+
+    pub fn natural_join(mut self, table_name: &str) -> Select {
+        self.add_join(JoinOperator::Natural, table_name, None);
+        self
+    }
+
+    fn add_join(&mut self, operator: JoinOperator, table_name: &str, constraint: Option<JoinConstraint>) {
+        let join_clause = (operator, table_name.to_string(), constraint);
+
+        self.def.from_clause = match self.def.from_clause.take() {
+            Some(FromClause::TableOrSubquery(mut names)) => {
+                // A join only follows the first table, as in the grammar.
+                let name = names.remove(0);
+                Some(FromClause::JoinClause(name, Some(vec![join_clause])))
+            }
+            Some(FromClause::JoinClause(name, join_clauses)) => {
+                let mut join_clauses = join_clauses.unwrap_or(Vec::new());
+                join_clauses.push(join_clause);
+                Some(FromClause::JoinClause(name, Some(join_clauses)))
+            }
+            None => None,
+        };
+    }
+
+    /// Adds a condition to the WHERE clause, ANDing it with any already there.
+    pub fn filter(mut self, expr: Expression) -> Select {
+        self.def.where_expr = match self.def.where_expr.take() {
+            Some(where_expr) => Some(where_expr.and(expr)),
+            None => Some(expr),
+        };
+        self
+    }
+
+    pub fn order_by(mut self, expr: Expression, order: Order) -> Select {
+        let mut terms = self.def.ordering_terms.take().unwrap_or(Vec::new());
+
+        terms.push(OrderingTerm { expr: expr, order: order });
+        self.def.ordering_terms = Some(terms);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Select {
+        let offset = self.def.limit.take().and_then(|limit_def| limit_def.offset);
+        self.def.limit = Some(LimitDef { limit: (limit as isize).to_expression(), offset: offset });
+        self
+    }
+
+    /// Skips the first `offset` rows, without a limit unless one is set.
+    pub fn offset(mut self, offset: usize) -> Select {
+        let limit = match self.def.limit.take() {
+            Some(limit_def) => limit_def.limit,
+            None => (-1is).to_expression(),
+        };
+        self.def.limit = Some(LimitDef { limit: limit, offset: Some((offset as isize).to_expression()) });
+        self
+    }
+
+    pub fn build(self) -> SelectDef {
+        self.def
+    }
+}
@@ -0,0 +1,102 @@
+//! The encryption of the pages of a paged file, see Rusql::open_encrypted,
+//! behind the `encryption` feature. Every page but the header is sealed
+//! with AES-256-GCM under the key given when the file is opened, with a
+//! random nonce each time it's written and its number as associated data,
+//! so a page can't be moved to another place in the file unnoticed. The
+//! nonce and the tag take the last RESERVED bytes of the page.
+//!
+//! The header only says where things are; the schema and the rows are in
+//! the pages it points to.
+
+use pager::PageNo;
+
+use std::io::{IoError, IoErrorKind, IoResult};
+
+/// How many bytes at the end of a page the nonce and the tag take.
+pub const RESERVED: usize = 28;
+/// How long a key is.
+pub const KEY_LEN: usize = 32;
+
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+
+pub struct Cipher {
+    key: Vec<u8>,
+}
+
+impl Cipher {
+    /// None if `key` isn't KEY_LEN bytes long.
+    pub fn new(key: &[u8]) -> Option<Cipher> {
+        if key.len() == KEY_LEN { Some(Cipher { key: key.to_vec() }) } else { None }
+    }
+
+    /// The page as written to the file, RESERVED bytes longer than `plain`.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(&self, page: PageNo, plain: &[u8]) -> IoResult<Vec<u8>> {
+        use crypto::aead::AeadEncryptor;
+        use crypto::aes::KeySize;
+        use crypto::aes_gcm::AesGcm;
+        use pager::be_bytes;
+        use std::iter::repeat;
+        use std::rand::{OsRng, Rng};
+
+        let mut nonce: Vec<u8> = repeat(0).take(NONCE_LEN).collect();
+        try!(OsRng::new()).fill_bytes(nonce.as_mut_slice());
+
+        let mut sealed: Vec<u8> = repeat(0).take(plain.len()).collect();
+        let mut tag: Vec<u8> = repeat(0).take(RESERVED - NONCE_LEN).collect();
+        let aad = be_bytes(page, 8);
+        AesGcm::new(KeySize::KeySize256, self.key.as_slice(), nonce.as_slice(), aad.as_slice())
+            .encrypt(plain, sealed.as_mut_slice(), tag.as_mut_slice());
+
+        sealed.push_all(nonce.as_slice());
+        sealed.push_all(tag.as_slice());
+        Ok(sealed)
+    }
+
+    /// The page as it was before encrypt, failing if it was written with
+    /// another key or changed since.
+    #[cfg(feature = "encryption")]
+    pub fn decrypt(&self, page: PageNo, sealed: &[u8]) -> IoResult<Vec<u8>> {
+        use crypto::aead::AeadDecryptor;
+        use crypto::aes::KeySize;
+        use crypto::aes_gcm::AesGcm;
+        use pager::be_bytes;
+        use std::iter::repeat;
+
+        let len = sealed.len() - RESERVED;
+        let (nonce, tag) = (&sealed[len..len + NONCE_LEN], &sealed[len + NONCE_LEN..]);
+        let mut plain: Vec<u8> = repeat(0).take(len).collect();
+        let aad = be_bytes(page, 8);
+
+        if AesGcm::new(KeySize::KeySize256, self.key.as_slice(), nonce, aad.as_slice())
+               .decrypt(&sealed[..len], plain.as_mut_slice(), tag) {
+            Ok(plain)
+        } else {
+            Err(IoError {
+                kind: IoErrorKind::OtherIoError,
+                desc: "can't decrypt a page, the key is wrong or the page was changed",
+                detail: Some(format!("page {}", page)),
+            })
+        }
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn encrypt(&self, _: PageNo, _: &[u8]) -> IoResult<Vec<u8>> {
+        Err(not_built())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn decrypt(&self, _: PageNo, _: &[u8]) -> IoResult<Vec<u8>> {
+        Err(not_built())
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn not_built() -> IoError {
+    IoError {
+        kind: IoErrorKind::OtherIoError,
+        desc: "encrypted databases need rusql built with the encryption feature",
+        detail: None,
+    }
+}
@@ -0,0 +1,172 @@
+//! The column-oriented copy kept of the tables created COLUMNAR: the values
+//! of each column are kept together, in a vector typed after the column when
+//! they all fit it, in the order of the keys. A filter comparing a column to
+//! a value is then checked on the whole vector in one go, instead of on each
+//! row through the expression evaluator.
+
+use definitions::{LiteralValue, BinaryOperator, ColumnType};
+use table::{TableHeader, TableRow, PkType};
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+#[derive(Show, Clone, PartialEq)]
+pub enum Column {
+    Integer(Vec<Option<isize>>),
+    Text(Vec<Option<Arc<String>>>),
+    /// The values of a column of another type, or of one holding values
+    /// that don't fit its type.
+    Values(Vec<LiteralValue>),
+}
+
+impl Column {
+    fn new(column_type: Option<ColumnType>) -> Column {
+        match column_type {
+            Some(ColumnType::Integer) => Column::Integer(Vec::new()),
+            Some(ColumnType::Text) => Column::Text(Vec::new()),
+            _ => Column::Values(Vec::new()),
+        }
+    }
+
+    fn insert(&mut self, position: usize, value: &LiteralValue) {
+        let fits = match (&*self, value) {
+            (&Column::Integer(..), &LiteralValue::Integer(..)) | (&Column::Text(..), &LiteralValue::Text(..)) => true,
+            (_, &LiteralValue::Null) | (&Column::Values(..), _) => true,
+            _ => false,
+        };
+
+        if !fits {
+            let values = Column::Values(self.values());
+            *self = values;
+        }
+
+        match *self {
+            Column::Integer(ref mut values) => values.insert(position, match value {
+                &LiteralValue::Integer(i) => Some(i),
+                _ => None,
+            }),
+            Column::Text(ref mut values) => values.insert(position, match value {
+                &LiteralValue::Text(ref text) => Some(text.clone()),
+                _ => None,
+            }),
+            Column::Values(ref mut values) => values.insert(position, value.clone()),
+        }
+    }
+
+    fn remove(&mut self, position: usize) {
+        match *self {
+            Column::Integer(ref mut values) => { values.remove(position); }
+            Column::Text(ref mut values) => { values.remove(position); }
+            Column::Values(ref mut values) => { values.remove(position); }
+        }
+    }
+
+    fn values(&self) -> Vec<LiteralValue> {
+        match *self {
+            Column::Integer(ref values) => values.iter().map(|value| match *value {
+                Some(i) => LiteralValue::Integer(i),
+                None => LiteralValue::Null,
+            }).collect(),
+            Column::Text(ref values) => values.iter().map(|value| match *value {
+                Some(ref text) => LiteralValue::Text(text.clone()),
+                None => LiteralValue::Null,
+            }).collect(),
+            Column::Values(ref values) => values.clone(),
+        }
+    }
+
+    // Clears `selected` for the values that don't compare to `value` the
+    // way `op` says, with the same results as the operator in an
+    // expression.
+    fn filter(&self, op: BinaryOperator, value: &LiteralValue, selected: &mut Vec<bool>) {
+        let holds = |ordering: Ordering| match op {
+            BinaryOperator::Equals => ordering == Ordering::Equal,
+            BinaryOperator::Less => ordering == Ordering::Less,
+            BinaryOperator::LessEq => ordering != Ordering::Greater,
+            BinaryOperator::Greater => ordering == Ordering::Greater,
+            BinaryOperator::GreaterEq => ordering != Ordering::Less,
+            _ => false,
+        };
+
+        match (self, value) {
+            (&Column::Integer(ref values), &LiteralValue::Integer(x)) => {
+                for (selected, value) in selected.iter_mut().zip(values.iter()) {
+                    *selected = *selected && value.map_or(false, |i| holds(i.cmp(&x)));
+                }
+            }
+            (&Column::Text(ref values), &LiteralValue::Text(ref x)) => {
+                for (selected, value) in selected.iter_mut().zip(values.iter()) {
+                    *selected = *selected && value.as_ref().map_or(false, |text| holds(text.cmp(x)));
+                }
+            }
+            _ => {
+                for (selected, column_value) in selected.iter_mut().zip(self.values().iter()) {
+                    *selected = *selected && compare_values(column_value, op, value);
+                }
+            }
+        }
+    }
+}
+
+fn compare_values(left: &LiteralValue, op: BinaryOperator, right: &LiteralValue) -> bool {
+    match op {
+        BinaryOperator::Equals => left.equals(right),
+        BinaryOperator::Less => left.lt(right).to_bool(),
+        BinaryOperator::LessEq => left.le(right).to_bool(),
+        BinaryOperator::Greater => left.gt(right).to_bool(),
+        BinaryOperator::GreaterEq => left.ge(right).to_bool(),
+        _ => false,
+    }
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct ColumnStore {
+    /// The keys of the rows, in order. The values of each column are in the
+    /// same order.
+    pub keys: Vec<PkType>,
+    pub columns: Vec<Column>,
+}
+
+impl ColumnStore {
+    pub fn new(header: &TableHeader) -> ColumnStore {
+        ColumnStore {
+            keys: Vec::new(),
+            columns: header.iter().map(|column_def| Column::new(column_def.column_type)).collect(),
+        }
+    }
+
+    pub fn insert(&mut self, pk: PkType, row: &TableRow) {
+        // Rows are mostly added with a key larger than the others.
+        let position = if self.keys.last().map_or(true, |&last| last < pk) {
+            self.keys.len()
+        } else {
+            self.keys.iter().position(|&key| key > pk).unwrap_or(self.keys.len())
+        };
+
+        self.keys.insert(position, pk);
+        for (column, value) in self.columns.iter_mut().zip(row.iter()) {
+            column.insert(position, value);
+        }
+    }
+
+    pub fn remove(&mut self, pk: PkType) {
+        if let Some(position) = self.keys.iter().position(|&key| key == pk) {
+            self.keys.remove(position);
+            for column in self.columns.iter_mut() {
+                column.remove(position);
+            }
+        }
+    }
+
+    /// The keys of the rows whose values pass every comparison, each a
+    /// column, how it compares and the value it's compared to.
+    pub fn select(&self, comparisons: &Vec<(usize, BinaryOperator, LiteralValue)>) -> Vec<PkType> {
+        let mut selected: Vec<bool> = self.keys.iter().map(|_| true).collect();
+
+        for &(column, op, ref value) in comparisons.iter() {
+            self.columns[column].filter(op, value, &mut selected);
+        }
+
+        self.keys.iter().zip(selected.iter()).filter(|&(_, selected)| *selected).map(|(key, _)| *key).collect()
+    }
+}
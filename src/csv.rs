@@ -0,0 +1,294 @@
+//! Loading CSV files into tables, see Rusql::import_csv, and writing tables
+//! out as CSV, see Table::to_csv. Records are as in RFC 4180: fields are
+//! separated by the delimiter, records by line breaks, and a field in quotes
+//! can hold both, with a quote inside written twice.
+//! https://www.rfc-editor.org/rfc/rfc4180
+//!
+//! The delimiter can be any character, e.g. a tab for TSV files or '|', and
+//! how fields are quoted and what stands for NULL are options too.
+
+use definitions::{ColumnDef, ColumnType, LiteralValue, TableDef};
+use error::{RusqlError, RusqlResult};
+use rusql::Rusql;
+use table::{Table, TableRow};
+
+use std::ascii::AsciiExt;
+use std::io::{File, IoResult};
+use std::mem::replace;
+use std::sync::Arc;
+
+/// Which fields are written in quotes.
+#[derive(Copy, Show, Clone, PartialEq)]
+pub enum Quoting {
+    /// Only those that would be read otherwise, see export.
+    Minimal,
+    /// Every one but NULL.
+    Always,
+    /// None, and quotes aren't special when reading either, as in most TSV
+    /// files. A field can't hold the delimiter or a line break then.
+    Never,
+}
+
+/// How a CSV file is read or written.
+#[derive(Show, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// The character between the fields of a record.
+    pub delimiter: char,
+    /// The character around a quoted field.
+    pub quote: char,
+    pub quoting: Quoting,
+    /// What NULL is written as. An unquoted field holding it is read as
+    /// NULL.
+    pub null: String,
+    /// Whether the first record names the columns, None to guess, see
+    /// import. Written unless it's Some(false).
+    pub header: Option<bool>,
+    /// Whether unquoted fields that look like numbers are read as numbers.
+    pub infer_types: bool,
+}
+
+impl CsvOptions {
+    /// Commas, double quotes where needed, empty fields for NULL, a guessed
+    /// header and inferred types.
+    pub fn new() -> CsvOptions {
+        CsvOptions {
+            delimiter: ',',
+            quote: '"',
+            quoting: Quoting::Minimal,
+            null: String::new(),
+            header: None,
+            infer_types: true,
+        }
+    }
+
+    /// The same, with tabs between fields and no quotes.
+    pub fn tsv() -> CsvOptions {
+        CsvOptions { delimiter: '\t', quoting: Quoting::Never, ..CsvOptions::new() }
+    }
+}
+
+// A field as read, with whether it was quoted.
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+/// Inserts the records of the CSV file at `path` into the table called
+/// `name`, creating it if there's none; returns how many there were. A new
+/// table takes its column names from the header, or is given c1, c2 and so
+/// on, and its column types from the values: INTEGER or TEXT if they're all
+/// of the type, none otherwise. Unless told, the first record is taken for a
+/// header if it names the columns of the table, or, for a new table, if none
+/// of its fields is empty, NULL or a number.
+pub fn import(db: &mut Rusql, path: &Path, name: &str, options: &CsvOptions) -> RusqlResult<usize> {
+    let text = try!(File::open(path).read_to_string());
+    let mut records = try!(read_records(text.as_slice(), options));
+    let name = name.to_string();
+
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    let columns: Option<Vec<String>> = db.find_table(&name).map(|table| {
+        table.header.iter().map(|column| column.name.clone()).collect()
+    });
+    let header = match options.header {
+        Some(header) => header,
+        None => match columns {
+            Some(ref columns) => {
+                records[0].len() == columns.len() && records[0].iter().zip(columns.iter()).all(|(field, column)| {
+                    field.text.as_slice().eq_ignore_ascii_case(column.as_slice())
+                })
+            }
+            None => records[0].iter().all(|field| {
+                !field.text.is_empty() && field.text != options.null && number(field.text.as_slice()).is_none()
+            }),
+        },
+    };
+    let names: Vec<String> = if header {
+        records.remove(0).into_iter().map(|field| field.text).collect()
+    } else {
+        range(0, records[0].len()).map(|i| format!("c{}", i + 1)).collect()
+    };
+
+    let rows: Vec<TableRow> = records.into_iter().map(|record| {
+        record.into_iter().map(|field| value(field, options)).collect()
+    }).collect();
+
+    if columns.is_none() {
+        let column_defs = names.into_iter().enumerate().map(|(i, name)| {
+            ColumnDef { name: name, column_type: column_type(rows.as_slice(), i), column_constraints: Vec::new() }
+        }).collect();
+
+        try!(db.create_table(TableDef {
+            table_name: name.clone(),
+            columns: column_defs,
+            constraints: Vec::new(),
+            if_not_exists: false,
+            temporary: false,
+            as_select: None,
+            strict: false,
+            columnar: false,
+        }));
+    }
+
+    db.copy_from(name.as_slice(), rows.into_iter())
+}
+
+/// Writes the rows of `table` as CSV, after a header naming its columns.
+/// With Quoting::Minimal, text is quoted if it holds the delimiter, the
+/// quote or a line break, and also if it's empty, looks like a number or is
+/// what NULL is written as, so that it's read back as it was. Records end
+/// with CRLF, as in the RFC.
+pub fn export<W: Writer>(table: &Table, w: &mut W, options: &CsvOptions) -> IoResult<()> {
+    if options.header != Some(false) {
+        let names = table.header.iter().map(|column| quote(column.name.as_slice(), false, options)).collect();
+        try!(write_record(w, names, options));
+    }
+
+    for row in table.data.values() {
+        let fields = row.iter().map(|value| match *value {
+            LiteralValue::Null => options.null.clone(),
+            LiteralValue::Text(ref text) => {
+                let ambiguous = text.is_empty() || **text == options.null || number(text.as_slice()).is_some();
+                quote(text.as_slice(), ambiguous, options)
+            }
+            ref value => quote(format!("{}", value).as_slice(), false, options),
+        }).collect();
+        try!(write_record(w, fields, options));
+    }
+
+    Ok(())
+}
+
+fn write_record<W: Writer>(w: &mut W, fields: Vec<String>, options: &CsvOptions) -> IoResult<()> {
+    try!(w.write_str(fields.connect(options.delimiter.to_string().as_slice()).as_slice()));
+    w.write_str("\r\n")
+}
+
+// `text` in quotes if the options say so; with Quoting::Minimal if it's
+// `ambiguous` or would be read otherwise.
+fn quote(text: &str, ambiguous: bool, options: &CsvOptions) -> String {
+    let needed = match options.quoting {
+        Quoting::Minimal => {
+            ambiguous || text.chars().any(|c| c == options.delimiter || c == options.quote || c == '\n' || c == '\r')
+        }
+        Quoting::Always => true,
+        Quoting::Never => false,
+    };
+
+    if needed {
+        let mark = options.quote.to_string();
+        let escaped = text.replace(mark.as_slice(), format!("{}{}", mark, mark).as_slice());
+        format!("{}{}{}", mark, escaped, mark)
+    } else {
+        text.to_string()
+    }
+}
+
+fn read_records(text: &str, options: &CsvOptions) -> RusqlResult<Vec<Vec<Field>>> {
+    let mut records: Vec<Vec<Field>> = Vec::new();
+    let mut record: Vec<Field> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut line = 1us;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c != options.quote {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            } else if chars.peek() == Some(&options.quote) {
+                chars.next();
+                field.push(c);
+            } else {
+                in_quotes = false;
+            }
+        } else if c == options.quote && options.quoting != Quoting::Never && field.is_empty() && !quoted {
+            in_quotes = true;
+            quoted = true;
+        } else if c == options.delimiter {
+            record.push(Field { text: replace(&mut field, String::new()), quoted: quoted });
+            quoted = false;
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            record.push(Field { text: replace(&mut field, String::new()), quoted: quoted });
+            quoted = false;
+            push_record(&mut records, replace(&mut record, Vec::new()));
+            line += 1;
+        } else if quoted {
+            return Err(RusqlError::ParseError(format!("CSV line {}: text after a quoted field", line)));
+        } else {
+            field.push(c);
+        }
+    }
+
+    if in_quotes {
+        return Err(RusqlError::ParseError(format!("CSV line {}: unterminated quoted field", line)));
+    }
+    if !field.is_empty() || quoted || !record.is_empty() {
+        record.push(Field { text: field, quoted: quoted });
+        push_record(&mut records, record);
+    }
+
+    Ok(records)
+}
+
+// Blank lines aren't records.
+fn push_record(records: &mut Vec<Vec<Field>>, record: Vec<Field>) {
+    if record.len() > 1 || record[0].quoted || !record[0].text.is_empty() {
+        records.push(record);
+    }
+}
+
+fn value(field: Field, options: &CsvOptions) -> LiteralValue {
+    if field.quoted {
+        return LiteralValue::Text(Arc::new(field.text));
+    }
+    if field.text == options.null {
+        return LiteralValue::Null;
+    }
+    if !options.infer_types {
+        return LiteralValue::Text(Arc::new(field.text));
+    }
+
+    match number(field.text.as_slice()) {
+        Some(value) => value,
+        None => LiteralValue::Text(Arc::new(field.text)),
+    }
+}
+
+// The number `text` is written as, if it's one.
+fn number(text: &str) -> Option<LiteralValue> {
+    if !text.chars().any(|c| c.is_digit(10)) || !text.chars().all(|c| c.is_digit(10) || "+-.eE".contains_char(c)) {
+        return None;
+    }
+
+    match text.parse::<isize>() {
+        Some(i) => Some(LiteralValue::Integer(i)),
+        None => text.parse::<f64>().map(|r| LiteralValue::Real(r)),
+    }
+}
+
+// INTEGER or TEXT if every value in the column that isn't NULL is of the
+// type.
+fn column_type(rows: &[TableRow], i: usize) -> Option<ColumnType> {
+    let values: Vec<&LiteralValue> = rows.iter().filter_map(|row| row.get(i)).filter(|value| !value.is_null())
+                                                .collect();
+
+    if values.is_empty() {
+        None
+    } else if values.iter().all(|value| match **value { LiteralValue::Integer(..) => true, _ => false }) {
+        Some(ColumnType::Integer)
+    } else if values.iter().all(|value| match **value { LiteralValue::Text(..) => true, _ => false }) {
+        Some(ColumnType::Text)
+    } else {
+        None
+    }
+}
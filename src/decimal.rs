@@ -0,0 +1,228 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::isize;
+
+/// The scale used for the result of a division, unless one of the operands
+/// already has a bigger one.
+const DIV_SCALE: usize = 6;
+
+/// A fixed-point number, `value` / 10^`scale`, which is what NUMERIC(p,s)
+/// columns hold so that e.g. money adds up exactly.
+#[derive(Copy, Clone, Show)]
+pub struct Decimal {
+    pub value: isize,
+    pub scale: usize,
+}
+
+impl Decimal {
+    pub fn new(value: isize, scale: usize) -> Decimal {
+        Decimal { value: value, scale: scale }
+    }
+
+    pub fn from_int(i: isize) -> Decimal {
+        Decimal::new(i, 0)
+    }
+
+    /// The closest decimal with `scale` digits after the point, or None if
+    /// it doesn't fit.
+    pub fn from_real(r: f64, scale: usize) -> Option<Decimal> {
+        let value = match pow10(scale) {
+            Some(multiplier) => (r * multiplier as f64).round(),
+            None => return None,
+        };
+
+        if value.is_nan() || value.abs() >= isize::MAX as f64 {
+            return None;
+        }
+
+        Some(Decimal::new(value as isize, scale))
+    }
+
+    /// Parses strings like "-12.50", keeping every digit after the point, or
+    /// returns None if it isn't a number or has too many digits.
+    pub fn from_str(s: &str) -> Option<Decimal> {
+        let s = s.trim();
+        let (negative, digits) = match s.chars().next() {
+            Some('-') => (true, s.slice_from(1)),
+            Some('+') => (false, s.slice_from(1)),
+            _ => (false, s),
+        };
+
+        let mut value = 0is;
+        let mut scale = 0us;
+        let mut seen_digit = false;
+        let mut seen_point = false;
+
+        for c in digits.chars() {
+            match c {
+                '.' if !seen_point => seen_point = true,
+                '0'...'9' => {
+                    let digit = c.to_digit(10).unwrap() as isize;
+                    value = match value.checked_mul(10).and_then(|value| value.checked_add(digit)) {
+                        Some(value) => value,
+                        None => return None,
+                    };
+                    seen_digit = true;
+
+                    if seen_point {
+                        scale += 1;
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        if !seen_digit || pow10(scale).is_none() {
+            return None;
+        }
+
+        Some(Decimal::new(if negative { -value } else { value }, scale))
+    }
+
+    /// Changes the number of digits after the point, rounding half away from
+    /// zero if digits are dropped, or returns None if the result doesn't fit.
+    pub fn rescale(&self, scale: usize) -> Option<Decimal> {
+        if scale >= self.scale {
+            return pow10(scale - self.scale).and_then(|multiplier| self.value.checked_mul(multiplier))
+                                             .map(|value| Decimal::new(value, scale));
+        }
+
+        // Dropping more digits than an isize has leaves nothing.
+        let divisor = match pow10(self.scale - scale) {
+            Some(divisor) => divisor,
+            None => return Some(Decimal::new(0, scale)),
+        };
+        let quotient = self.value / divisor;
+        let remainder = (self.value % divisor).abs();
+
+        // Whether the remainder is at least half the divisor, without doubling it.
+        if remainder >= divisor - remainder {
+            Some(Decimal::new(if self.value < 0 { quotient - 1 } else { quotient + 1 }, scale))
+        } else {
+            Some(Decimal::new(quotient, scale))
+        }
+    }
+
+    /// The number of significant digits, as checked against the precision of
+    /// a NUMERIC(p,s) column.
+    pub fn precision(&self) -> usize {
+        let mut value = self.value.abs();
+        let mut digits = 1us;
+
+        while value >= 10 {
+            value /= 10;
+            digits += 1;
+        }
+
+        digits
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.value == 0
+    }
+
+    pub fn to_int(&self) -> isize {
+        // A divisor too big for an isize is bigger than any value.
+        pow10(self.scale).map_or(0, |divisor| self.value / divisor)
+    }
+
+    pub fn to_real(&self) -> f64 {
+        self.value as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    pub fn checked_add(&self, rhs: &Decimal) -> Option<Decimal> {
+        self.align(rhs).and_then(|(x, y, scale)| x.checked_add(y).map(|value| Decimal::new(value, scale)))
+    }
+
+    pub fn checked_sub(&self, rhs: &Decimal) -> Option<Decimal> {
+        self.align(rhs).and_then(|(x, y, scale)| x.checked_sub(y).map(|value| Decimal::new(value, scale)))
+    }
+
+    /// Multiplies, rounding to as many digits after the point as the operand
+    /// with the most, or returns None if the product doesn't fit.
+    pub fn checked_mul(&self, rhs: &Decimal) -> Option<Decimal> {
+        let scale = if self.scale > rhs.scale { self.scale } else { rhs.scale };
+
+        self.value.checked_mul(rhs.value)
+                  .and_then(|value| Decimal::new(value, self.scale + rhs.scale).rescale(scale))
+    }
+
+    /// Divides with at least DIV_SCALE digits after the point, or returns
+    /// None for a division by zero or a quotient that doesn't fit.
+    pub fn checked_div(&self, rhs: &Decimal) -> Option<Decimal> {
+        if rhs.is_zero() {
+            return None;
+        }
+
+        let mut scale = if self.scale > rhs.scale { self.scale } else { rhs.scale };
+        if scale < DIV_SCALE {
+            scale = DIV_SCALE;
+        }
+
+        pow10(scale + rhs.scale - self.scale).and_then(|multiplier| self.value.checked_mul(multiplier))
+                                             .map(|numerator| Decimal::new(numerator / rhs.value, scale))
+    }
+
+    // The values of both at the same scale, if they fit.
+    fn align(&self, other: &Decimal) -> Option<(isize, isize, usize)> {
+        let scale = if self.scale > other.scale { self.scale } else { other.scale };
+
+        match (self.rescale(scale), other.rescale(scale)) {
+            (Some(x), Some(y)) => Some((x.value, y.value, scale)),
+            _ => None,
+        }
+    }
+}
+
+// 10^exp, or None if it doesn't fit in an isize.
+fn pow10(exp: usize) -> Option<isize> {
+    let mut result = 1is;
+
+    for _ in range(0, exp) {
+        result = match result.checked_mul(10) {
+            Some(result) => result,
+            None => return None,
+        };
+    }
+
+    Some(result)
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        match self.align(other) {
+            Some((x, y, _)) => x.cmp(&y),
+            // Too far apart to line up, so the difference shows as reals.
+            None => self.to_real().partial_cmp(&other.to_real()).unwrap_or(Ordering::Equal),
+        }
+    }
+}
+
+impl fmt::String for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.value);
+        }
+
+        // The digits, with zeros in front for at least one before the point.
+        let sign = if self.value < 0 { "-" } else { "" };
+        let digits = format!("{:01$}", self.value.abs(), self.scale + 1);
+        let point = digits.len() - self.scale;
+
+        write!(f, "{}{}.{}", sign, digits.slice_to(point), digits.slice_from(point))
+    }
+}
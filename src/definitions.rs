@@ -1,39 +1,484 @@
+use decimal::Decimal;
+
+use std::ascii::AsciiExt;
 use std::cmp::Ordering;
 use std::cmp::Ordering::*;
 use std::fmt;
 use std::ops::{Add, Sub, Mul, Div, Rem, BitAnd, BitOr, Shl, Shr};
+use std::sync::Arc;
 
+#[derive(Show, Clone, PartialEq)]
 pub enum RusqlStatement {
     AlterTable(AlterTableDef),
+    /// ANALYZE, collecting statistics on the named table or on all of them.
+    Analyze(Option<String>),
+    /// ATTACH DATABASE, with the path of the file, or :memory:, and the
+    /// name to attach it under.
+    Attach(String, String),
+    Begin,
+    /// CHECKPOINT, folding the WAL back into the file, see wal.
+    Checkpoint,
+    Commit,
+    CreateIndex(IndexDef),
     CreateTable(TableDef),
+    CreateTrigger(TriggerDef),
+    CreateView(ViewDef),
     Delete(DeleteDef),
+    Detach(String),
+    DropIndex(DropIndexDef),
     DropTable(DropTableDef),
+    DropTrigger(DropTriggerDef),
+    DropView(DropViewDef),
+    /// EXPLAIN, describing the statement instead of running it.
+    Explain(Box<RusqlStatement>),
+    /// EXPLAIN QUERY PLAN, showing how the statement would read its tables.
+    ExplainQueryPlan(Box<RusqlStatement>),
     Insert(InsertDef),
+    Pragma(PragmaDef),
+    RefreshMaterializedView(String),
+    Release(String),
+    /// ROLLBACK, or ROLLBACK TO the named savepoint.
+    Rollback(Option<String>),
+    Savepoint(String),
     Select(SelectDef),
     Update(UpdateDef),
+    /// VACUUM, rebuilding the tables of the main database or of the one
+    /// attached under the name.
+    Vacuum(Option<String>),
+}
+
+impl RusqlStatement {
+    /// Every expression in a DELETE, INSERT, SELECT or UPDATE statement, e.g.
+    /// to substitute values into them.
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression> {
+        let mut exprs: Vec<&mut Expression> = Vec::new();
+
+        match self {
+            &mut RusqlStatement::Delete(ref mut def) => {
+                if let Some(ref mut expr) = def.where_expr {
+                    exprs.push(expr);
+                }
+                if let Some(ref mut limit_def) = def.limit {
+                    exprs.extend(limit_def.expressions_mut().into_iter());
+                }
+            }
+            &mut RusqlStatement::Insert(ref mut def) => {
+                match def.data_source {
+                    InsertDataSource::Values(ref mut rows) => {
+                        for row in rows.iter_mut() {
+                            for expr in row.iter_mut() {
+                                exprs.push(expr);
+                            }
+                        }
+                    }
+                    InsertDataSource::Select(ref mut select_def) => exprs = select_def.expressions_mut(),
+                    _ => {}
+                }
+                if let Some(UpsertDef { action: UpsertAction::Update(ref mut set, ref mut where_expr), .. }) = def.upsert {
+                    for &mut (_, ref mut expr) in set.iter_mut() {
+                        exprs.push(expr);
+                    }
+                    if let Some(ref mut expr) = *where_expr {
+                        exprs.push(expr);
+                    }
+                }
+            }
+            &mut RusqlStatement::Select(ref mut def) => exprs = def.expressions_mut(),
+            &mut RusqlStatement::Update(ref mut def) => {
+                for &mut (_, ref mut expr) in def.set.iter_mut() {
+                    exprs.push(expr);
+                }
+                if let Some(ref mut expr) = def.where_expr {
+                    exprs.push(expr);
+                }
+                if let Some(ref mut limit_def) = def.limit {
+                    exprs.extend(limit_def.expressions_mut().into_iter());
+                }
+            }
+            _ => {}
+        }
+
+        exprs
+    }
+
+    /// Follows the rename of the table `old_name` through a DELETE, INSERT,
+    /// SELECT or UPDATE statement, e.g. in the body of a trigger.
+    pub fn rename_table(&mut self, old_name: &String, new_name: &String) {
+        match self {
+            &mut RusqlStatement::Delete(ref mut def) => rename_one(&mut def.name, old_name, new_name),
+            &mut RusqlStatement::Insert(ref mut def) => {
+                rename_one(&mut def.table_name, old_name, new_name);
+                if let InsertDataSource::Select(ref mut select_def) = def.data_source {
+                    select_def.rename_table(old_name, new_name);
+                }
+            }
+            &mut RusqlStatement::Select(ref mut def) => def.rename_table(old_name, new_name),
+            &mut RusqlStatement::Update(ref mut def) => rename_one(&mut def.name, old_name, new_name),
+            _ => {}
+        }
+    }
+
+    /// Follows the rename of a column of the table `table_name` through a
+    /// DELETE, INSERT, SELECT or UPDATE statement that reads or writes it.
+    pub fn rename_column(&mut self, table_name: &String, old_name: &String, new_name: &String) {
+        let writes = match self {
+            &mut RusqlStatement::Delete(ref def) => &def.name == table_name,
+            &mut RusqlStatement::Update(ref def) => &def.name == table_name,
+            &mut RusqlStatement::Insert(ref def) => &def.table_name == table_name,
+            _ => false,
+        };
+
+        match self {
+            &mut RusqlStatement::Insert(ref mut def) => {
+                if writes {
+                    if let Some(ref mut names) = def.column_names {
+                        rename_in(names, old_name, new_name);
+                    }
+                    if let Some(ref mut upsert) = def.upsert {
+                        if let Some(ref mut target) = upsert.target {
+                            rename_in(target, old_name, new_name);
+                        }
+                        if let UpsertAction::Update(ref mut set, ref mut where_expr) = upsert.action {
+                            for &mut (ref mut name, ref mut expr) in set.iter_mut() {
+                                rename_one(name, old_name, new_name);
+                                expr.rename_column(old_name, new_name);
+                            }
+                            if let Some(ref mut expr) = *where_expr {
+                                expr.rename_column(old_name, new_name);
+                            }
+                        }
+                    }
+                }
+                if let InsertDataSource::Select(ref mut select_def) = def.data_source {
+                    select_def.rename_column(table_name, old_name, new_name);
+                }
+            }
+            &mut RusqlStatement::Select(ref mut def) => def.rename_column(table_name, old_name, new_name),
+            &mut RusqlStatement::Update(ref mut def) if writes => {
+                for &mut (ref mut name, _) in def.set.iter_mut() {
+                    rename_one(name, old_name, new_name);
+                }
+            }
+            _ => {}
+        }
+
+        // An INSERT's expressions that use the table were followed above.
+        if writes {
+            if let &mut RusqlStatement::Insert(..) = self {
+                return;
+            }
+            for expr in self.expressions_mut().into_iter() {
+                expr.rename_column(old_name, new_name);
+            }
+        }
+    }
+}
+
+/// The DELETE, INSERT, SELECT or UPDATE as SQL, e.g. for the body of a
+/// trigger.
+impl fmt::String for RusqlStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RusqlStatement::Delete(ref def) => {
+                try!(write!(f, "DELETE FROM {}", def.name));
+                if let Some(ref expr) = def.where_expr {
+                    try!(write!(f, " WHERE {}", expr));
+                }
+                if let Some(ref returning) = def.returning {
+                    try!(write!(f, " RETURNING {}", returning));
+                }
+                write_order_and_limit(f, &def.ordering_terms, &def.limit)
+            }
+            &RusqlStatement::Insert(ref def) => {
+                try!(write!(f, "INSERT"));
+                if def.on_conflict != ConflictResolution::Abort {
+                    try!(write!(f, " OR {}", def.on_conflict));
+                }
+                try!(write!(f, " INTO {}", def.table_name));
+                if let Some(ref names) = def.column_names {
+                    try!(write!(f, "({})", names.connect(", ")));
+                }
+                try!(match def.data_source {
+                    InsertDataSource::Values(ref rows) => {
+                        let rows: Vec<String> = rows.iter().map(|row| {
+                            let values: Vec<String> = row.iter().map(|expr| format!("{}", expr)).collect();
+                            format!("({})", values.connect(", "))
+                        }).collect();
+                        write!(f, " VALUES {}", rows.connect(", "))
+                    }
+                    InsertDataSource::Select(ref select_def) => write!(f, " {}", select_def),
+                    InsertDataSource::DefaultValues | InsertDataSource::Error => write!(f, " DEFAULT VALUES"),
+                });
+                if let Some(ref upsert) = def.upsert {
+                    try!(write!(f, " ON CONFLICT"));
+                    if let Some(ref target) = upsert.target {
+                        try!(write!(f, "({})", target.connect(", ")));
+                    }
+                    try!(match upsert.action {
+                        UpsertAction::Nothing => write!(f, " DO NOTHING"),
+                        UpsertAction::Update(ref set, _) => write!(f, " DO UPDATE SET {}", assignments(set)),
+                    });
+                    if let UpsertAction::Update(_, Some(ref expr)) = upsert.action {
+                        try!(write!(f, " WHERE {}", expr));
+                    }
+                }
+                if let Some(ref returning) = def.returning {
+                    try!(write!(f, " RETURNING {}", returning));
+                }
+                Ok(())
+            }
+            &RusqlStatement::Select(ref def) => write!(f, "{}", def),
+            &RusqlStatement::Update(ref def) => {
+                try!(write!(f, "UPDATE {} SET {}", def.name, assignments(&def.set)));
+                if let Some(ref expr) = def.where_expr {
+                    try!(write!(f, " WHERE {}", expr));
+                }
+                if let Some(ref returning) = def.returning {
+                    try!(write!(f, " RETURNING {}", returning));
+                }
+                write_order_and_limit(f, &def.ordering_terms, &def.limit)
+            }
+            stmt => write!(f, "{:?}", stmt),
+        }
+    }
+}
+
+fn assignments(set: &Vec<(String, Expression)>) -> String {
+    let set: Vec<String> = set.iter().map(|&(ref name, ref expr)| format!("{} = {}", name, expr)).collect();
+    set.connect(", ")
+}
+
+fn write_order_and_limit(f: &mut fmt::Formatter, ordering_terms: &Option<Vec<OrderingTerm>>,
+                         limit: &Option<LimitDef>) -> fmt::Result {
+    if let &Some(ref ordering_terms) = ordering_terms {
+        let terms: Vec<String> = ordering_terms.iter().map(|term| match term.order {
+            Order::Ascending => format!("{}", term.expr),
+            Order::Descending => format!("{} DESC", term.expr),
+        }).collect();
+        try!(write!(f, " ORDER BY {}", terms.connect(", ")));
+    }
+    if let &Some(ref limit_def) = limit {
+        try!(write!(f, " LIMIT {}", limit_def.limit));
+        if let Some(ref offset) = limit_def.offset {
+            try!(write!(f, " OFFSET {}", offset));
+        }
+    }
+    Ok(())
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Show, Clone, PartialEq, Eq)]
 pub enum ColumnType {
     Integer,
     Text,
+    Blob,
+    Boolean,
+    Real,
+    /// NUMERIC(precision, scale)
+    Numeric(usize, usize),
+}
+
+impl ColumnType {
+    /// Whether a STRICT table may store the value in a column of this type.
+    pub fn accepts(&self, value: &LiteralValue) -> bool {
+        match (*self, value) {
+            (_, &LiteralValue::Null) => true,
+            (ColumnType::Integer, &LiteralValue::Integer(..))
+                | (ColumnType::Integer, &LiteralValue::Boolean(..)) => true,
+            (ColumnType::Text, &LiteralValue::Text(..)) => true,
+            (ColumnType::Blob, &LiteralValue::Blob(..)) => true,
+            (ColumnType::Boolean, &LiteralValue::Boolean(..)) => true,
+            (ColumnType::Real, &LiteralValue::Real(..)) | (ColumnType::Real, &LiteralValue::Integer(..)) => true,
+            (ColumnType::Numeric(precision, _), &LiteralValue::Decimal(d)) => d.precision() <= precision,
+            _ => false,
+        }
+    }
+
+    /// Converts a value stored in a column of this type where it can be done
+    /// without losing information, like the type affinity of SQLite.
+    /// https://www.sqlite.org/datatype3.html#type_affinity
+    pub fn apply_affinity(&self, value: LiteralValue) -> LiteralValue {
+        match (*self, value) {
+            (ColumnType::Integer, LiteralValue::Text(t)) => {
+                if let Some(i) = t.as_slice().trim().parse::<isize>() {
+                    LiteralValue::Integer(i)
+                } else if let Some(r) = t.as_slice().trim().parse::<f64>() {
+                    ColumnType::Integer.apply_affinity(LiteralValue::Real(r))
+                } else {
+                    LiteralValue::Text(t)
+                }
+            }
+            (ColumnType::Integer, LiteralValue::Real(r)) if r == (r as isize) as f64 => {
+                LiteralValue::Integer(r as isize)
+            }
+            (ColumnType::Integer, LiteralValue::Decimal(d)) if d.rescale(0) == Some(d) => LiteralValue::Integer(d.to_int()),
+            (ColumnType::Text, value @ LiteralValue::Integer(..))
+                | (ColumnType::Text, value @ LiteralValue::Real(..))
+                | (ColumnType::Text, value @ LiteralValue::Decimal(..)) => LiteralValue::Text(Arc::new(format!("{}", value))),
+            (ColumnType::Boolean, LiteralValue::Integer(i)) => LiteralValue::Boolean(i != 0),
+            (ColumnType::Real, LiteralValue::Integer(i)) => LiteralValue::Real(i as f64),
+            (ColumnType::Real, LiteralValue::Decimal(d)) => LiteralValue::Real(d.to_real()),
+            (ColumnType::Real, LiteralValue::Text(t)) => {
+                match t.as_slice().trim().parse::<f64>() {
+                    Some(r) => LiteralValue::Real(r),
+                    None => LiteralValue::Text(t),
+                }
+            }
+            (ColumnType::Numeric(_, scale), LiteralValue::Integer(i)) => {
+                numeric(Decimal::from_int(i).rescale(scale), i as f64)
+            }
+            (ColumnType::Numeric(_, scale), LiteralValue::Real(r)) => numeric(Decimal::from_real(r, scale), r),
+            (ColumnType::Numeric(_, scale), LiteralValue::Decimal(d)) => numeric(d.rescale(scale), d.to_real()),
+            (ColumnType::Numeric(_, scale), LiteralValue::Text(t)) => {
+                match Decimal::from_str(t.as_slice()) {
+                    Some(d) => numeric(d.rescale(scale), d.to_real()),
+                    None => ColumnType::Real.apply_affinity(LiteralValue::Text(t)),
+                }
+            }
+            (_, value) => value,
+        }
+    }
+}
+
+// A value for a NUMERIC column, which is a real if it has too many digits
+// for a decimal.
+fn numeric(decimal: Option<Decimal>, real: f64) -> LiteralValue {
+    decimal.map_or(LiteralValue::Real(real), |d| LiteralValue::Decimal(d))
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+impl fmt::String for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColumnType::Integer => write!(f, "INTEGER"),
+            ColumnType::Text => write!(f, "TEXT"),
+            ColumnType::Blob => write!(f, "BLOB"),
+            ColumnType::Boolean => write!(f, "BOOLEAN"),
+            ColumnType::Real => write!(f, "REAL"),
+            ColumnType::Numeric(precision, scale) => write!(f, "NUMERIC({},{})", precision, scale),
+        }
+    }
+}
+
+#[derive(Show, Clone, PartialEq)]
 pub enum ColumnConstraint {
     PrimaryKey,
+    Autoincrement,
+    Unique,
+    Check(Expression),
+    Default(Expression),
+    References(ForeignKeyClause),
+    /// The flag is set for STORED columns, unset for VIRTUAL ones.
+    Generated(Expression, bool),
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    Check(Expression),
+    ForeignKey(Vec<String>, ForeignKeyClause),
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct ForeignKeyClause {
+    pub table_name: String,
+    pub column_names: Option<Vec<String>>,
+    pub on_delete: ForeignKeyAction,
+    pub on_update: ForeignKeyAction,
+}
+
+impl ForeignKeyClause {
+    pub fn rename_column(&mut self, old_name: &String, new_name: &String) {
+        if let Some(ref mut names) = self.column_names {
+            rename_in(names, old_name, new_name);
+        }
+    }
+}
+
+impl fmt::String for ColumnConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ColumnConstraint::PrimaryKey => write!(f, "PRIMARY KEY"),
+            &ColumnConstraint::Autoincrement => write!(f, "AUTOINCREMENT"),
+            &ColumnConstraint::Unique => write!(f, "UNIQUE"),
+            &ColumnConstraint::Check(ref expr) => write!(f, "CHECK({})", expr),
+            &ColumnConstraint::Default(ref expr) => write!(f, "DEFAULT ({})", expr),
+            &ColumnConstraint::References(ref clause) => write!(f, "{}", clause),
+            &ColumnConstraint::Generated(ref expr, stored) => {
+                write!(f, "AS ({}) {}", expr, if stored { "STORED" } else { "VIRTUAL" })
+            }
+        }
+    }
+}
+
+impl fmt::String for TableConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TableConstraint::PrimaryKey(ref names) => write!(f, "PRIMARY KEY({})", names.connect(", ")),
+            &TableConstraint::Unique(ref names) => write!(f, "UNIQUE({})", names.connect(", ")),
+            &TableConstraint::Check(ref expr) => write!(f, "CHECK({})", expr),
+            &TableConstraint::ForeignKey(ref names, ref clause) => {
+                write!(f, "FOREIGN KEY({}) {}", names.connect(", "), clause)
+            }
+        }
+    }
+}
+
+impl fmt::String for ForeignKeyClause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "REFERENCES {}", self.table_name));
+        if let Some(ref names) = self.column_names {
+            try!(write!(f, "({})", names.connect(", ")));
+        }
+        if self.on_delete != ForeignKeyAction::NoAction {
+            try!(write!(f, " ON DELETE {}", self.on_delete));
+        }
+        if self.on_update != ForeignKeyAction::NoAction {
+            try!(write!(f, " ON UPDATE {}", self.on_update));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Show, Clone, PartialEq)]
+pub enum ForeignKeyAction {
+    NoAction,
+    Restrict,
+    SetNull,
+    SetDefault,
+    Cascade,
+}
+
+impl fmt::String for ForeignKeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match *self {
+            ForeignKeyAction::NoAction => "NO ACTION",
+            ForeignKeyAction::Restrict => "RESTRICT",
+            ForeignKeyAction::SetNull => "SET NULL",
+            ForeignKeyAction::SetDefault => "SET DEFAULT",
+            ForeignKeyAction::Cascade => "CASCADE",
+        };
+        write!(f, "{}", action)
+    }
 }
 
 #[derive(Show, Clone, PartialEq)]
 pub enum LiteralValue {
     Integer(isize),
-    Text(String),
+    /// Text is shared rather than copied, so that a table can keep a single
+    /// copy of each text value however many rows have it.
+    Text(Arc<String>),
     Real(f64),
     Boolean(bool),
+    Blob(Vec<u8>),
+    Decimal(Decimal),
     Null,
 }
 
 impl LiteralValue {
+    pub fn text(text: &str) -> LiteralValue {
+        LiteralValue::Text(Arc::new(text.to_string()))
+    }
+
     pub fn to_uint(&self) -> usize {
         match self {
             &LiteralValue::Integer(i) => i as usize,
@@ -44,6 +489,7 @@ impl LiteralValue {
         match self {
             &LiteralValue::Integer(i) => i,
             &LiteralValue::Boolean(b) => if b { 1 } else { 0 },
+            &LiteralValue::Decimal(d) => d.to_int(),
             _ => 0, // FIXME ???
         }
     }
@@ -52,23 +498,17 @@ impl LiteralValue {
         match self {
             &LiteralValue::Integer(i) => i != 0,
             &LiteralValue::Boolean(b) => b,
+            &LiteralValue::Decimal(d) => !d.is_zero(),
             _ => false, // FIXME ???
         }
     }
 
-    pub fn cmp(&self, other: &Self) -> Ordering {
-        if self.is_int() && other.is_int() {
-            let x = self.to_int();
-            let y = other.to_int();
-
-            return x.cmp(&y)
+    pub fn lt(&self, other: &Self) -> LiteralValue {
+        if self.is_null() || other.is_null() {
+            return LiteralValue::Null;
         }
 
-        Equal
-    }
-
-    pub fn lt(&self, other: &Self) -> LiteralValue {
-        match self.cmp(other) {
+        match self.compare(other) {
             Less => LiteralValue::Boolean(true),
             Equal => LiteralValue::Boolean(false),
             Greater => LiteralValue::Boolean(false),
@@ -76,7 +516,11 @@ impl LiteralValue {
     }
 
     pub fn le(&self, other: &Self) -> LiteralValue {
-        match self.cmp(other) {
+        if self.is_null() || other.is_null() {
+            return LiteralValue::Null;
+        }
+
+        match self.compare(other) {
             Less => LiteralValue::Boolean(true),
             Equal => LiteralValue::Boolean(true),
             Greater => LiteralValue::Boolean(false),
@@ -84,7 +528,11 @@ impl LiteralValue {
     }
 
     pub fn gt(&self, other: &Self) -> LiteralValue {
-        match self.cmp(other) {
+        if self.is_null() || other.is_null() {
+            return LiteralValue::Null;
+        }
+
+        match self.compare(other) {
             Less => LiteralValue::Boolean(false),
             Equal => LiteralValue::Boolean(false),
             Greater => LiteralValue::Boolean(true),
@@ -92,13 +540,23 @@ impl LiteralValue {
     }
 
     pub fn ge(&self, other: &Self) -> LiteralValue {
-        match self.cmp(other) {
+        if self.is_null() || other.is_null() {
+            return LiteralValue::Null;
+        }
+
+        match self.compare(other) {
             Less => LiteralValue::Boolean(false),
             Equal => LiteralValue::Boolean(true),
             Greater => LiteralValue::Boolean(true),
         }
     }
 
+    /// Equality as used by the = operator, where numbers of different storage
+    /// classes are equal if they have the same value, e.g. TRUE = 1.
+    pub fn equals(&self, other: &Self) -> bool {
+        self == other || (self.class_rank() == 1 && other.class_rank() == 1 && self.compare(other) == Equal)
+    }
+
     pub fn is_int(&self) -> bool {
         match self {
             &LiteralValue::Integer(..) => true,
@@ -106,13 +564,127 @@ impl LiteralValue {
         }
     }
 
+    pub fn is_null(&self) -> bool {
+        match self {
+            &LiteralValue::Null => true,
+            _ => false,
+        }
+    }
+
+    pub fn to_real(&self) -> f64 {
+        match self {
+            &LiteralValue::Integer(i) => i as f64,
+            &LiteralValue::Real(r) => r,
+            &LiteralValue::Boolean(b) => if b { 1.0 } else { 0.0 },
+            &LiteralValue::Decimal(d) => d.to_real(),
+            _ => 0.0,
+        }
+    }
+
+    pub fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (&LiteralValue::Integer(x), &LiteralValue::Integer(y)) => x.cmp(&y),
+            (&LiteralValue::Text(ref x), &LiteralValue::Text(ref y)) => {
+                // Values taken from the same table often share their text.
+                if &**x as *const String == &**y as *const String { Equal } else { x.cmp(y) }
+            }
+            (&LiteralValue::Blob(ref x), &LiteralValue::Blob(ref y)) => x.cmp(y),
+            _ if self.decimal_pair(other).is_some() => {
+                let (x, y) = self.decimal_pair(other).unwrap();
+                x.cmp(&y)
+            }
+            _ if self.class_rank() == 1 && other.class_rank() == 1 => {
+                self.to_real().partial_cmp(&other.to_real()).unwrap_or(Equal)
+            }
+            _ => self.class_rank().cmp(&other.class_rank()),
+        }
+    }
+
+    // Rank used to order values of different storage classes, same as SQLite:
+    // NULL, then numbers, then text, then blobs.
+    fn class_rank(&self) -> usize {
+        match self {
+            &LiteralValue::Null => 0,
+            &LiteralValue::Boolean(..) | &LiteralValue::Integer(..) | &LiteralValue::Real(..)
+                | &LiteralValue::Decimal(..) => 1,
+            &LiteralValue::Text(..) => 2,
+            &LiteralValue::Blob(..) => 3,
+        }
+    }
+
+    fn variant_rank(&self) -> usize {
+        match self {
+            &LiteralValue::Null => 0,
+            &LiteralValue::Boolean(..) => 1,
+            &LiteralValue::Integer(..) => 2,
+            &LiteralValue::Decimal(..) => 3,
+            &LiteralValue::Real(..) => 4,
+            &LiteralValue::Text(..) => 5,
+            &LiteralValue::Blob(..) => 6,
+        }
+    }
+
+    /// The name of the storage class of the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            &LiteralValue::Integer(..) | &LiteralValue::Boolean(..) => "INTEGER",
+            &LiteralValue::Text(..) => "TEXT",
+            &LiteralValue::Real(..) => "REAL",
+            &LiteralValue::Blob(..) => "BLOB",
+            &LiteralValue::Decimal(..) => "NUMERIC",
+            &LiteralValue::Null => "NULL",
+        }
+    }
+
+    /// Renders the value the way it would be written in a SQL statement.
+    pub fn to_sql(&self) -> String {
+        match self {
+            &LiteralValue::Text(ref t) => format!("'{}'", t.replace("'", "''")),
+            &LiteralValue::Real(r) if r.is_nan() => "NULL".to_string(),
+            // Too big to be read back as anything but infinity, like SQLite does.
+            &LiteralValue::Real(r) if r.is_infinite() => (if r > 0.0 { "1e999" } else { "-1e999" }).to_string(),
+            &LiteralValue::Real(r) if r != 0.0 && (r.abs() >= 1e16 || r.abs() < 1e-5) => format!("{:e}", r),
+            &LiteralValue::Real(r) => {
+                // With a point, so that it's read back as a REAL.
+                let real = format!("{}", r);
+                if real.chars().all(|c| c == '-' || c.is_digit(10)) { format!("{}.0", real) } else { real }
+            }
+            &LiteralValue::Boolean(b) => (if b { "TRUE" } else { "FALSE" }).to_string(),
+            &LiteralValue::Blob(..) => format!("X'{}'", self),
+            &LiteralValue::Null => "NULL".to_string(),
+            _ => format!("{}", self),
+        }
+    }
+
     pub fn neg(&self) -> LiteralValue {
         match self {
             &LiteralValue::Integer(i) => LiteralValue::Integer(-i),
+            &LiteralValue::Real(r) => LiteralValue::Real(-r),
+            &LiteralValue::Decimal(d) => LiteralValue::Decimal(Decimal::new(-d.value, d.scale)),
             _ => self.clone(),
         }
     }
 
+    // Arithmetic and comparisons are exact as long as only decimals and
+    // integers are involved, and the results fit.
+    fn decimal_pair(&self, rhs: &LiteralValue) -> Option<(Decimal, Decimal)> {
+        match (self, rhs) {
+            (&LiteralValue::Decimal(x), &LiteralValue::Decimal(y)) => Some((x, y)),
+            (&LiteralValue::Decimal(x), &LiteralValue::Integer(y)) => Some((x, Decimal::from_int(y))),
+            (&LiteralValue::Integer(x), &LiteralValue::Decimal(y)) => Some((Decimal::from_int(x), y)),
+            _ => None,
+        }
+    }
+
+    // Anything else with a real in it is worked out in floating point.
+    fn real_pair(&self, rhs: &LiteralValue) -> Option<(f64, f64)> {
+        match (self, rhs) {
+            (&LiteralValue::Real(..), _) | (_, &LiteralValue::Real(..))
+                if self.class_rank() == 1 && rhs.class_rank() == 1 => Some((self.to_real(), rhs.to_real())),
+            _ => None,
+        }
+    }
+
     fn int_add(&self, x: isize, rhs: LiteralValue) -> LiteralValue {
         match rhs {
             LiteralValue::Integer(i) => LiteralValue::Integer(x + i),
@@ -134,21 +706,43 @@ impl LiteralValue {
         }
     }
 
+    // Division by zero is NULL, as in SQLite.
     fn int_div(&self, x: isize, rhs: LiteralValue) -> LiteralValue {
         match rhs {
-            LiteralValue::Integer(i) => LiteralValue::Integer(x / i),
+            LiteralValue::Integer(0) => LiteralValue::Null,
+            // Only isize::MIN / -1 overflows, into a real.
+            LiteralValue::Integer(i) => x.checked_div(i).map_or(LiteralValue::Real(-(x as f64)), LiteralValue::Integer),
             _ => LiteralValue::Null,
         }
     }
 
     fn int_rem(&self, x: isize, rhs: LiteralValue) -> LiteralValue {
         match rhs {
-            LiteralValue::Integer(i) => LiteralValue::Integer(x % i),
+            LiteralValue::Integer(0) => LiteralValue::Null,
+            LiteralValue::Integer(i) => LiteralValue::Integer(x.checked_rem(i).unwrap_or(0)),
             _ => LiteralValue::Null,
         }
     }
 }
 
+impl Eq for LiteralValue {}
+
+impl PartialOrd for LiteralValue {
+    fn partial_cmp(&self, other: &LiteralValue) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LiteralValue {
+    fn cmp(&self, other: &LiteralValue) -> Ordering {
+        // Keep the ordering consistent with PartialEq, e.g. for 1 and 1.0.
+        match self.compare(other) {
+            Equal => self.variant_rank().cmp(&other.variant_rank()),
+            ord => ord,
+        }
+    }
+}
+
 impl fmt::String for LiteralValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -156,6 +750,13 @@ impl fmt::String for LiteralValue {
             &LiteralValue::Text(ref t) => write!(f, "{}", t),
             &LiteralValue::Real(ref r) => write!(f, "{}", r),
             &LiteralValue::Boolean(ref b) => write!(f, "{}", b),
+            &LiteralValue::Decimal(ref d) => write!(f, "{}", d),
+            &LiteralValue::Blob(ref bytes) => {
+                for byte in bytes.iter() {
+                    try!(write!(f, "{:02X}", byte));
+                }
+                Ok(())
+            }
             &LiteralValue::Null => write!(f, "null"),
         }
     }
@@ -164,6 +765,15 @@ impl fmt::String for LiteralValue {
 impl Add for LiteralValue {
     type Output = LiteralValue;
     fn add(self, rhs: LiteralValue) -> LiteralValue {
+        if let Some((x, y)) = self.decimal_pair(&rhs) {
+            // Too many digits for a decimal.
+            let real = LiteralValue::Real(x.to_real() + y.to_real());
+            return x.checked_add(&y).map_or(real, |d| LiteralValue::Decimal(d));
+        }
+        if let Some((x, y)) = self.real_pair(&rhs) {
+            return LiteralValue::Real(x + y);
+        }
+
         match self {
             LiteralValue::Integer(i) => self.int_add(i, rhs),
             _ => LiteralValue::Null,
@@ -174,6 +784,14 @@ impl Add for LiteralValue {
 impl Sub for LiteralValue {
     type Output = LiteralValue;
     fn sub(self, rhs: LiteralValue) -> LiteralValue {
+        if let Some((x, y)) = self.decimal_pair(&rhs) {
+            let real = LiteralValue::Real(x.to_real() - y.to_real());
+            return x.checked_sub(&y).map_or(real, |d| LiteralValue::Decimal(d));
+        }
+        if let Some((x, y)) = self.real_pair(&rhs) {
+            return LiteralValue::Real(x - y);
+        }
+
         match self {
             LiteralValue::Integer(i) => self.int_sub(i, rhs),
             _ => LiteralValue::Null,
@@ -184,6 +802,14 @@ impl Sub for LiteralValue {
 impl Mul for LiteralValue {
     type Output = LiteralValue;
     fn mul(self, rhs: LiteralValue) -> LiteralValue {
+        if let Some((x, y)) = self.decimal_pair(&rhs) {
+            let real = LiteralValue::Real(x.to_real() * y.to_real());
+            return x.checked_mul(&y).map_or(real, |d| LiteralValue::Decimal(d));
+        }
+        if let Some((x, y)) = self.real_pair(&rhs) {
+            return LiteralValue::Real(x * y);
+        }
+
         match self {
             LiteralValue::Integer(i) => self.int_mul(i, rhs),
             _ => LiteralValue::Null,
@@ -194,6 +820,17 @@ impl Mul for LiteralValue {
 impl Div for LiteralValue {
     type Output = LiteralValue;
     fn div(self, rhs: LiteralValue) -> LiteralValue {
+        if let Some((x, y)) = self.decimal_pair(&rhs) {
+            if y.is_zero() {
+                return LiteralValue::Null;
+            }
+            let real = LiteralValue::Real(x.to_real() / y.to_real());
+            return x.checked_div(&y).map_or(real, |d| LiteralValue::Decimal(d));
+        }
+        if let Some((x, y)) = self.real_pair(&rhs) {
+            return if y == 0.0 { LiteralValue::Null } else { LiteralValue::Real(x / y) };
+        }
+
         match self {
             LiteralValue::Integer(i) => self.int_div(i, rhs),
             _ => LiteralValue::Null,
@@ -257,69 +894,516 @@ impl Shr<LiteralValue> for LiteralValue {
 
 
 
+#[derive(Show, Clone, PartialEq)]
 pub struct TableDef {
     pub table_name: String,
     pub columns: Vec<ColumnDef>,
+    pub constraints: Vec<TableConstraint>,
     pub if_not_exists: bool,
+    pub temporary: bool,
+    pub as_select: Option<SelectDef>,
+    pub strict: bool,
+    /// Whether the table keeps a column-oriented copy of its rows.
+    pub columnar: bool,
 }
 
+#[derive(Show, Clone, PartialEq)]
 pub enum ResultColumn {
     Expressions(Vec<Expression>),
     Asterisk,
 }
 
+impl fmt::String for ResultColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ResultColumn::Expressions(ref exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|expr| format!("{}", expr)).collect();
+                write!(f, "{}", exprs.connect(", "))
+            }
+            &ResultColumn::Asterisk => write!(f, "*"),
+        }
+    }
+}
+
+#[derive(Show, Clone, PartialEq)]
 pub struct InsertDef {
     pub table_name: String,
     pub column_names: Option<Vec<String>>,
     pub data_source: InsertDataSource,
+    pub on_conflict: ConflictResolution,
+    pub upsert: Option<UpsertDef>,
+    pub returning: Option<ResultColumn>,
+}
+
+/// ON CONFLICT(target) DO ..., where the target is the columns of the
+/// PRIMARY KEY or a UNIQUE constraint.
+#[derive(Show, Clone, PartialEq)]
+pub struct UpsertDef {
+    pub target: Option<Vec<String>>,
+    pub action: UpsertAction,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub enum UpsertAction {
+    Nothing,
+    Update(Vec<(String, Expression)>, Option<Expression>),
+}
+
+/// What to do when a row violates a constraint, as in INSERT OR REPLACE.
+/// https://www.sqlite.org/lang_conflict.html
+#[derive(Copy, Show, Clone, PartialEq)]
+pub enum ConflictResolution {
+    Abort,
+    Fail,
+    Ignore,
+    Replace,
+    Rollback,
+}
+
+impl fmt::String for ConflictResolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            ConflictResolution::Abort => "ABORT",
+            ConflictResolution::Fail => "FAIL",
+            ConflictResolution::Ignore => "IGNORE",
+            ConflictResolution::Replace => "REPLACE",
+            ConflictResolution::Rollback => "ROLLBACK",
+        })
+    }
 }
 
+#[derive(Show, Clone, PartialEq)]
 pub enum InsertDataSource {
-    Values(Vec<Vec<LiteralValue>>),
+    Values(Vec<Vec<Expression>>),
     Select(SelectDef),
     DefaultValues,
     Error,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Show, Clone, PartialEq)]
 pub struct ColumnDef {
     pub name: String,
     pub column_type: Option<ColumnType>,
     pub column_constraints: Vec<ColumnConstraint>,
 }
 
+impl fmt::String for ColumnDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.name));
+        if let Some(column_type) = self.column_type {
+            try!(write!(f, " {}", column_type));
+        }
+        for constraint in self.column_constraints.iter() {
+            try!(write!(f, " {}", constraint));
+        }
+        Ok(())
+    }
+}
+
+impl ColumnDef {
+    pub fn get_default(&self) -> Option<&Expression> {
+        for constraint in self.column_constraints.iter() {
+            if let &ColumnConstraint::Default(ref expr) = constraint {
+                return Some(expr);
+            }
+        }
+        None
+    }
+
+    pub fn get_generated(&self) -> Option<&Expression> {
+        for constraint in self.column_constraints.iter() {
+            if let &ColumnConstraint::Generated(ref expr, _) = constraint {
+                return Some(expr);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Show, Clone, PartialEq)]
 pub struct SelectDef {
     pub result_column: ResultColumn,
     pub from_clause: Option<FromClause>,
     pub where_expr: Option<Expression>,
     pub ordering_terms: Option<Vec<OrderingTerm>>,
+    pub limit: Option<LimitDef>,
+}
+
+impl SelectDef {
+    pub fn table_names(&self) -> Vec<String> {
+        match self.from_clause {
+            Some(FromClause::TableOrSubquery(ref names)) => names.clone(),
+            Some(FromClause::JoinClause(ref name, ref join_clauses)) => {
+                let mut names = vec![name.clone()];
+
+                if let &Some(ref join_clauses) = join_clauses {
+                    for &(_, ref name, _) in join_clauses.iter() {
+                        names.push(name.clone());
+                    }
+                }
+                names
+            }
+            None => Vec::new(),
+        }
+    }
+
+    pub fn rename_table(&mut self, old_name: &String, new_name: &String) {
+        match self.from_clause {
+            Some(FromClause::TableOrSubquery(ref mut names)) => rename_in(names, old_name, new_name),
+            Some(FromClause::JoinClause(ref mut name, ref mut join_clauses)) => {
+                if name == old_name {
+                    *name = new_name.clone();
+                }
+
+                if let &mut Some(ref mut join_clauses) = join_clauses {
+                    for &mut (_, ref mut name, _) in join_clauses.iter_mut() {
+                        if name == old_name {
+                            *name = new_name.clone();
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Follows a column rename in the table `table_name`, if the select reads
+    /// from it.
+    pub fn rename_column(&mut self, table_name: &String, old_name: &String, new_name: &String) {
+        if !self.table_names().contains(table_name) {
+            return;
+        }
+
+        for expr in self.expressions_mut().into_iter() {
+            expr.rename_column(old_name, new_name);
+        }
+    }
+
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression> {
+        let mut exprs: Vec<&mut Expression> = Vec::new();
+
+        if let ResultColumn::Expressions(ref mut result_exprs) = self.result_column {
+            for expr in result_exprs.iter_mut() {
+                exprs.push(expr);
+            }
+        }
+
+        if let Some(FromClause::JoinClause(_, Some(ref mut join_clauses))) = self.from_clause {
+            for &mut (_, _, ref mut constraint) in join_clauses.iter_mut() {
+                if let &mut Some(JoinConstraint::On(ref mut expr)) = constraint {
+                    exprs.push(expr);
+                }
+            }
+        }
+
+        if let Some(ref mut expr) = self.where_expr {
+            exprs.push(expr);
+        }
+
+        if let Some(ref mut ordering_terms) = self.ordering_terms {
+            for term in ordering_terms.iter_mut() {
+                exprs.push(&mut term.expr);
+            }
+        }
+
+        if let Some(ref mut limit_def) = self.limit {
+            exprs.extend(limit_def.expressions_mut().into_iter());
+        }
+
+        exprs
+    }
+}
+
+/// The SELECT as SQL, e.g. for the definition of a view.
+impl fmt::String for SelectDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "SELECT {}", self.result_column));
+        match self.from_clause {
+            Some(FromClause::TableOrSubquery(ref names)) => try!(write!(f, " FROM {}", names.connect(", "))),
+            Some(FromClause::JoinClause(ref name, ref join_clauses)) => {
+                try!(write!(f, " FROM {}", name));
+                if let &Some(ref join_clauses) = join_clauses {
+                    for &(join_operator, ref name, ref constraint) in join_clauses.iter() {
+                        try!(match join_operator {
+                            JoinOperator::Inner => write!(f, " JOIN {}", name),
+                            JoinOperator::Natural => write!(f, " NATURAL JOIN {}", name),
+                        });
+                        if let &Some(JoinConstraint::On(ref expr)) = constraint {
+                            try!(write!(f, " ON {}", expr));
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+        if let Some(ref expr) = self.where_expr {
+            try!(write!(f, " WHERE {}", expr));
+        }
+        write_order_and_limit(f, &self.ordering_terms, &self.limit)
+    }
+}
+
+#[derive(Copy, Show, Clone, PartialEq)]
+pub enum TriggerTime {
+    Before,
+    After,
+    InsteadOf,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub enum TriggerEvent {
+    Insert,
+    Update(Option<Vec<String>>),
+    Delete,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct TriggerDef {
+    pub name: String,
+    pub table_name: String,
+    pub time: TriggerTime,
+    pub event: TriggerEvent,
+    pub when: Option<Expression>,
+    pub body: Vec<RusqlStatement>,
+    pub if_not_exists: bool,
+    /// The CREATE TRIGGER statement as it was written.
+    pub sql: String,
+}
+
+impl TriggerDef {
+    /// Follows the rename of the table `old_name`, whether the trigger is on
+    /// it or its body uses it.
+    pub fn rename_table(&mut self, old_name: &String, new_name: &String) {
+        let before = self.clone();
+
+        rename_one(&mut self.table_name, old_name, new_name);
+        for stmt in self.body.iter_mut() {
+            stmt.rename_table(old_name, new_name);
+        }
+
+        self.update_sql(before);
+    }
+
+    /// Follows the rename of a column of the table `table_name` through the
+    /// body and, for a trigger on the table, through its WHEN clause, its
+    /// UPDATE OF columns and the OLD and NEW rows.
+    pub fn rename_column(&mut self, table_name: &String, old_name: &String, new_name: &String) {
+        let before = self.clone();
+
+        for stmt in self.body.iter_mut() {
+            stmt.rename_column(table_name, old_name, new_name);
+        }
+
+        if &self.table_name == table_name {
+            if let Some(ref mut expr) = self.when {
+                expr.rename_column(old_name, new_name);
+            }
+            if let TriggerEvent::Update(Some(ref mut names)) = self.event {
+                rename_in(names, old_name, new_name);
+            }
+            for stmt in self.body.iter_mut() {
+                for expr in stmt.expressions_mut().into_iter() {
+                    expr.rename_qualified_column("OLD", old_name, new_name);
+                    expr.rename_qualified_column("NEW", old_name, new_name);
+                }
+            }
+        }
+
+        self.update_sql(before);
+    }
+
+    // The trigger is saved as its SQL, which has to follow any change.
+    fn update_sql(&mut self, before: TriggerDef) {
+        if *self != before {
+            self.sql = format!("{}", self);
+        }
+    }
+}
+
+impl fmt::String for TriggerDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "CREATE TRIGGER {} {} ", self.name, match self.time {
+            TriggerTime::Before => "BEFORE",
+            TriggerTime::After => "AFTER",
+            TriggerTime::InsteadOf => "INSTEAD OF",
+        }));
+        try!(match self.event {
+            TriggerEvent::Insert => write!(f, "INSERT"),
+            TriggerEvent::Delete => write!(f, "DELETE"),
+            TriggerEvent::Update(None) => write!(f, "UPDATE"),
+            TriggerEvent::Update(Some(ref names)) => write!(f, "UPDATE OF {}", names.connect(", ")),
+        });
+        try!(write!(f, " ON {}", self.table_name));
+        if let Some(ref expr) = self.when {
+            try!(write!(f, " WHEN {}", expr));
+        }
+        try!(write!(f, " BEGIN"));
+        for stmt in self.body.iter() {
+            try!(write!(f, " {};", stmt));
+        }
+        write!(f, " END")
+    }
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct DropTriggerDef {
+    pub name: String,
+    pub if_exists: bool,
 }
 
+#[derive(Show, Clone, PartialEq)]
+pub struct ViewDef {
+    pub name: String,
+    pub select: SelectDef,
+    pub if_not_exists: bool,
+    pub materialized: bool,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct DropViewDef {
+    pub name: String,
+    pub if_exists: bool,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct PragmaDef {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct IndexDef {
+    pub name: String,
+    pub table_name: String,
+    pub columns: Vec<Expression>,
+    pub unique: bool,
+    pub if_not_exists: bool,
+    pub where_expr: Option<Expression>,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct DropIndexDef {
+    pub name: String,
+    pub if_exists: bool,
+}
+
+#[derive(Show, Clone, PartialEq)]
 pub struct DropTableDef {
     pub name: String,
+    pub if_exists: bool,
 }
 
+#[derive(Show, Clone, PartialEq)]
 pub enum AlterTable {
     RenameTo(String),
+    RenameColumn(String, String),
     AddColumn(ColumnDef),
+    DropColumn(String),
 }
 
+#[derive(Show, Clone, PartialEq)]
 pub struct AlterTableDef {
     pub name: String,
     pub mode: AlterTable,
 }
 
-#[derive(Show, Clone)]
+#[derive(Show, Clone, PartialEq)]
 pub enum Expression {
     LiteralValue(LiteralValue),
     TableName((String, Box<Expression>)),
     ColumnName(String),
     BinaryOperator((BinaryOperator, Box<Expression>, Box<Expression>)),
     UnaryOperator((UnaryOperator, Box<Expression>)),
+    Function((String, Vec<Expression>)),
+    /// A bind parameter like ?, ?2 or :name, NULL unless a value is bound.
+    Parameter(String),
     Null,
 }
 
 impl Expression {
+    pub fn references_column(&self, name: &String) -> bool {
+        match self {
+            &Expression::ColumnName(ref column_name) => column_name == name,
+            &Expression::TableName((_, ref expr)) => expr.references_column(name),
+            &Expression::BinaryOperator((_, ref left, ref right)) => {
+                left.references_column(name) || right.references_column(name)
+            }
+            &Expression::UnaryOperator((_, ref expr)) => expr.references_column(name),
+            &Expression::Function((_, ref args)) => args.iter().any(|arg| arg.references_column(name)),
+            _ => false,
+        }
+    }
+
+    /// The names of all the columns used in the expression.
+    pub fn column_names(&self) -> Vec<String> {
+        match self {
+            &Expression::ColumnName(ref name) => vec![name.clone()],
+            &Expression::TableName((_, ref expr)) => expr.column_names(),
+            &Expression::BinaryOperator((_, ref left, ref right)) => {
+                let mut names = left.column_names();
+                names.push_all(right.column_names().as_slice());
+                names
+            }
+            &Expression::UnaryOperator((_, ref expr)) => expr.column_names(),
+            &Expression::Function((_, ref args)) => {
+                let mut names: Vec<String> = Vec::new();
+
+                for arg in args.iter() {
+                    names.push_all(arg.column_names().as_slice());
+                }
+                names
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn rename_column(&mut self, old_name: &String, new_name: &String) {
+        match self {
+            &mut Expression::ColumnName(ref mut column_name) => {
+                if column_name == old_name {
+                    *column_name = new_name.clone();
+                }
+            }
+            &mut Expression::TableName((_, ref mut expr)) => expr.rename_column(old_name, new_name),
+            &mut Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+                left.rename_column(old_name, new_name);
+                right.rename_column(old_name, new_name);
+            }
+            &mut Expression::UnaryOperator((_, ref mut expr)) => expr.rename_column(old_name, new_name),
+            &mut Expression::Function((_, ref mut args)) => {
+                for arg in args.iter_mut() {
+                    arg.rename_column(old_name, new_name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Renames the column only where it's qualified by `qualifier`, e.g. the
+    /// NEW row in a trigger.
+    pub fn rename_qualified_column(&mut self, qualifier: &str, old_name: &String, new_name: &String) {
+        match self {
+            &mut Expression::TableName((ref table, ref mut expr)) => {
+                if table.as_slice().eq_ignore_ascii_case(qualifier) {
+                    expr.rename_column(old_name, new_name);
+                }
+            }
+            &mut Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+                left.rename_qualified_column(qualifier, old_name, new_name);
+                right.rename_qualified_column(qualifier, old_name, new_name);
+            }
+            &mut Expression::UnaryOperator((_, ref mut expr)) => {
+                expr.rename_qualified_column(qualifier, old_name, new_name);
+            }
+            &mut Expression::Function((_, ref mut args)) => {
+                for arg in args.iter_mut() {
+                    arg.rename_qualified_column(qualifier, old_name, new_name);
+                }
+            }
+            _ => {}
+        }
+    }
+
     pub fn unwrap_binary_operator(&self) -> (BinaryOperator, Expression, Expression) {
         match self {
             &Expression::BinaryOperator((b, ref left, ref right)) => (b, *left.clone(), *right.clone()),
@@ -328,6 +1412,30 @@ impl Expression {
     }
 }
 
+impl fmt::String for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Expression::LiteralValue(ref value) => write!(f, "{}", value.to_sql()),
+            &Expression::TableName((ref table, ref expr)) => write!(f, "{}.{}", table, **expr),
+            &Expression::ColumnName(ref name) => write!(f, "{}", name),
+            &Expression::BinaryOperator((b, ref left, ref right)) => {
+                // A binary operator can only end up on the left through parentheses.
+                match **left {
+                    Expression::BinaryOperator(..) => write!(f, "({}) {} {}", **left, b, **right),
+                    _ => write!(f, "{} {} {}", **left, b, **right),
+                }
+            }
+            &Expression::UnaryOperator((u, ref expr)) => write!(f, "{}{}", u, **expr),
+            &Expression::Function((ref name, ref args)) => {
+                let args: Vec<String> = args.iter().map(|arg| format!("{}", arg)).collect();
+                write!(f, "{}({})", name, args.connect(", "))
+            }
+            &Expression::Parameter(ref name) => write!(f, "{}", name),
+            &Expression::Null => write!(f, "NULL"),
+        }
+    }
+}
+
 #[derive(Copy, Show, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum BinaryOperator {
     Null,
@@ -346,6 +1454,13 @@ pub enum BinaryOperator {
     GreaterEq,
     Equals,
     NotEquals,
+    Is,
+    IsNot,
+    /// LIKE, which ignores case.
+    Like,
+    /// LIKE under PRAGMA case_sensitive_like = ON, which a statement's LIKEs
+    /// are switched to when it runs.
+    CaseSensitiveLike,
     And,
     Or,
 }
@@ -359,6 +1474,16 @@ impl BinaryOperator {
         }
     }
 
+    /// Comparisons are never true when one of the operands is NULL.
+    pub fn is_comparison(&self) -> bool {
+        match *self {
+            BinaryOperator::Less | BinaryOperator::LessEq | BinaryOperator::Greater
+                | BinaryOperator::GreaterEq | BinaryOperator::Equals | BinaryOperator::NotEquals
+                | BinaryOperator::Like | BinaryOperator::CaseSensitiveLike => true,
+            _ => false,
+        }
+    }
+
     pub fn ord_val(&self) -> usize {
         match *self {
             BinaryOperator::Null => 0,
@@ -368,14 +1493,45 @@ impl BinaryOperator {
                 | BinaryOperator::BitAnd | BinaryOperator::BitOr => 4,
             BinaryOperator::Less | BinaryOperator::LessEq
                 | BinaryOperator::Greater | BinaryOperator::GreaterEq => 5,
-            BinaryOperator::Equals | BinaryOperator::NotEquals => 6,
+            BinaryOperator::Equals | BinaryOperator::NotEquals
+                | BinaryOperator::Is | BinaryOperator::IsNot
+                | BinaryOperator::Like | BinaryOperator::CaseSensitiveLike => 6,
             BinaryOperator::And => 7,
             BinaryOperator::Or => 8,
         }
     }
 }
 
-#[derive(Copy, Show, Clone)]
+impl fmt::String for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            BinaryOperator::Null => "",
+            BinaryOperator::Mult => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::LShift => "<<",
+            BinaryOperator::RShift => ">>",
+            BinaryOperator::BitAnd => "&",
+            BinaryOperator::BitOr => "|",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEq => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEq => ">=",
+            BinaryOperator::Equals => "=",
+            BinaryOperator::NotEquals => "!=",
+            BinaryOperator::Is => "IS",
+            BinaryOperator::IsNot => "IS NOT",
+            BinaryOperator::Like | BinaryOperator::CaseSensitiveLike => "LIKE",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Copy, Show, Clone, PartialEq)]
 pub enum UnaryOperator {
     Plus,
     Minus,
@@ -383,6 +1539,18 @@ pub enum UnaryOperator {
     BitNeg,
 }
 
+impl fmt::String for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            UnaryOperator::Plus => "+",
+            UnaryOperator::Minus => "-",
+            UnaryOperator::Not => "NOT ",
+            UnaryOperator::BitNeg => "~",
+        };
+        write!(f, "{}", op)
+    }
+}
+
 impl UnaryOperator {
     pub fn neg(&self) -> UnaryOperator {
         match *self {
@@ -393,24 +1561,50 @@ impl UnaryOperator {
     }
 }
 
+#[derive(Show, Clone, PartialEq)]
 pub struct DeleteDef {
     pub name: String,
     pub where_expr: Option<Expression>,
+    pub returning: Option<ResultColumn>,
+    pub ordering_terms: Option<Vec<OrderingTerm>>,
+    pub limit: Option<LimitDef>,
 }
 
+#[derive(Show, Clone, PartialEq)]
 pub struct UpdateDef {
     pub name: String,
     pub set: Vec<(String, Expression)>,
     pub where_expr: Option<Expression>,
+    pub returning: Option<ResultColumn>,
+    pub ordering_terms: Option<Vec<OrderingTerm>>,
+    pub limit: Option<LimitDef>,
 }
 
-#[derive(Clone)]
+#[derive(Show, Clone, PartialEq)]
 pub struct OrderingTerm {
     pub expr: Expression,
     pub order: Order,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Show, Clone, PartialEq)]
+pub struct LimitDef {
+    pub limit: Expression,
+    pub offset: Option<Expression>,
+}
+
+impl LimitDef {
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression> {
+        let mut exprs: Vec<&mut Expression> = vec![&mut self.limit];
+
+        if let Some(ref mut expr) = self.offset {
+            exprs.push(expr);
+        }
+
+        exprs
+    }
+}
+
+#[derive(Copy, Show, Clone, PartialEq)]
 pub enum Order {
     Ascending,
     Descending,
@@ -418,18 +1612,35 @@ pub enum Order {
 
 pub type JoinClause = (JoinOperator, String, Option<JoinConstraint>);
 
+#[derive(Show, Clone, PartialEq)]
 pub enum FromClause {
     TableOrSubquery(Vec<String>),
     JoinClause(String, Option<Vec<JoinClause>>),
 }
 
-#[derive(Copy)]
+#[derive(Copy, Show, Clone, PartialEq)]
 pub enum JoinOperator {
     Inner,
     Natural,
 }
 
-#[derive(Clone)]
+#[derive(Show, Clone, PartialEq)]
 pub enum JoinConstraint {
     On(Expression),
 }
+
+/// Sets `name` to `new_name` if it's `old_name`.
+pub fn rename_one(name: &mut String, old_name: &String, new_name: &String) {
+    if name == old_name {
+        *name = new_name.clone();
+    }
+}
+
+/// Replaces every occurrence of `old_name` in a list of column names.
+pub fn rename_in(names: &mut Vec<String>, old_name: &String, new_name: &String) {
+    for name in names.iter_mut() {
+        if name == old_name {
+            *name = new_name.clone();
+        }
+    }
+}
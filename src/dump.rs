@@ -0,0 +1,68 @@
+//! The database as a SQL script, like the .dump command of SQLite's shell.
+
+use rusql::Rusql;
+use table::Table;
+
+/// The statements that create the tables, views, indexes and triggers of
+/// `db` and insert the rows of its tables, in one transaction. Running them
+/// on an empty database gives the same one back, except for the keys
+/// AUTOINCREMENT already handed out. Temporary tables aren't dumped, and
+/// materialized views are filled again by their CREATE. SQLite runs them
+/// too, unless the database uses what only rusql has, like materialized
+/// views or COLUMNAR tables.
+pub fn dump(db: &Rusql) -> String {
+    let mut sql = "BEGIN TRANSACTION;\n".to_string();
+
+    for (name, table) in db.map.iter().filter(|&(name, _)| !db.materialized_views.contains_key(name)) {
+        sql.push_str(format!("{};\n", table.to_sql()).as_slice());
+        sql.push_str(dump_rows(&**table).as_slice());
+    }
+
+    // The indexes come after the rows, so that they're built once.
+    for (name, table) in db.map.iter() {
+        for index in table.indexes.iter() {
+            sql.push_str(format!("{};\n", index.to_sql(name)).as_slice());
+        }
+    }
+
+    for (name, select_def) in db.views.iter() {
+        sql.push_str(format!("CREATE VIEW {} AS {};\n", name, select_def).as_slice());
+    }
+
+    for (name, select_def) in db.materialized_views.iter() {
+        sql.push_str(format!("CREATE MATERIALIZED VIEW {} AS {};\n", name, select_def).as_slice());
+    }
+
+    // And the triggers last, so that the rows don't fire them.
+    for (table_name, triggers) in db.triggers.iter() {
+        if db.map.contains_key(table_name) || db.views.contains_key(table_name) {
+            for trigger in triggers.iter() {
+                sql.push_str(format!("{};\n", trigger.sql).as_slice());
+            }
+        }
+    }
+
+    sql.push_str("COMMIT;\n");
+    sql
+}
+
+/// An INSERT statement for each row of `table`. Generated columns are left
+/// out, since they're computed again.
+pub fn dump_rows(table: &Table) -> String {
+    let stored: Vec<usize> = range(0, table.header.len()).filter(|&i| table.header[i].get_generated().is_none())
+                                                         .collect();
+    let columns = if stored.len() == table.header.len() {
+        String::new()
+    } else {
+        let names: Vec<&str> = stored.iter().map(|&i| table.header[i].name.as_slice()).collect();
+        format!("({})", names.connect(","))
+    };
+    let mut sql = String::new();
+
+    for row in table.data.values() {
+        let values: Vec<String> = stored.iter().map(|&i| row[i].to_sql()).collect();
+        sql.push_str(format!("INSERT INTO {}{} VALUES({});\n", table.name, columns, values.connect(",")).as_slice());
+    }
+
+    sql
+}
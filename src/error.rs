@@ -0,0 +1,75 @@
+use std::error::FromError;
+use std::fmt;
+use std::io::IoError;
+
+#[derive(Show, Clone, PartialEq)]
+pub enum RusqlError {
+    ConstraintViolation(String),
+    NoSuchTable(String),
+    NoSuchColumn(String),
+    NoSuchIndex(String),
+    NoSuchTrigger(String),
+    NoSuchView(String),
+    NoSuchParameter(String),
+    NoSuchFunction(String),
+    /// A call to a function with the wrong number of arguments.
+    WrongArgumentCount(String),
+    NoRows,
+    InvalidSchemaChange(String),
+    ViewNotWritable(String),
+    InvalidTransaction(String),
+    ParseError(String),
+    TypeMismatch(String),
+    Interrupted,
+    Timeout,
+    OutOfMemory,
+    ReadOnly,
+    Io(String),
+    /// A saved database that can't be read back.
+    Corrupt(String),
+    /// Something that went wrong inside rusql itself, e.g. a thread that
+    /// panicked.
+    Internal(String),
+}
+
+pub type RusqlResult<T> = Result<T, RusqlError>;
+
+impl fmt::String for RusqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RusqlError::ConstraintViolation(ref s) => write!(f, "{}", s),
+            &RusqlError::NoSuchTable(ref name) => write!(f, "no such table: {}", name),
+            &RusqlError::NoSuchColumn(ref name) => write!(f, "no such column: {}", name),
+            &RusqlError::NoSuchIndex(ref name) => write!(f, "no such index: {}", name),
+            &RusqlError::NoSuchTrigger(ref name) => write!(f, "no such trigger: {}", name),
+            &RusqlError::NoSuchView(ref name) => write!(f, "no such view: {}", name),
+            &RusqlError::NoSuchParameter(ref name) => write!(f, "no such parameter: {}", name),
+            &RusqlError::NoSuchFunction(ref name) => write!(f, "no such function: {}", name),
+            &RusqlError::WrongArgumentCount(ref name) => {
+                write!(f, "wrong number of arguments to function {}()", name)
+            }
+            &RusqlError::NoRows => write!(f, "query returned no rows"),
+            &RusqlError::InvalidSchemaChange(ref s) => write!(f, "{}", s),
+            &RusqlError::ViewNotWritable(ref name) => write!(f, "cannot modify {} because it is a view", name),
+            &RusqlError::InvalidTransaction(ref s) => write!(f, "{}", s),
+            &RusqlError::ParseError(ref s) => write!(f, "syntax error: {}", s),
+            &RusqlError::TypeMismatch(ref s) => write!(f, "{}", s),
+            &RusqlError::Interrupted => write!(f, "interrupted"),
+            &RusqlError::Timeout => write!(f, "statement timed out"),
+            &RusqlError::OutOfMemory => write!(f, "out of memory"),
+            &RusqlError::ReadOnly => write!(f, "attempt to write a readonly database"),
+            &RusqlError::Io(ref s) => write!(f, "disk I/O error: {}", s),
+            &RusqlError::Corrupt(ref s) => write!(f, "database disk image is malformed: {}", s),
+            &RusqlError::Internal(ref s) => write!(f, "internal error: {}", s),
+        }
+    }
+}
+
+impl FromError<IoError> for RusqlError {
+    fn from_error(err: IoError) -> RusqlError {
+        match err.detail {
+            Some(detail) => RusqlError::Io(format!("{} ({})", err.desc, detail)),
+            None => RusqlError::Io(err.desc.to_string()),
+        }
+    }
+}
@@ -1,253 +1,1651 @@
-use table::{TableRow, TableHeader, Table};
+use table::{TableRow, TableHeader, Table, PkType};
 use definitions::{ResultColumn, RusqlStatement, InsertDef, SelectDef};
-use definitions::{AlterTableDef, AlterTable, Expression, FromClause, JoinOperator};
-use definitions::{DeleteDef, InsertDataSource, UpdateDef, Order, JoinConstraint};
-use definitions::{BinaryOperator};
-use expressions::{ExpressionResult, ExpressionEvaluator, expr_to_literal, result_to_literal};
-use rusql::Rusql;
+use definitions::{AlterTableDef, AlterTable, Expression};
+use definitions::{DeleteDef, InsertDataSource, UpdateDef, Order};
+use definitions::{OrderingTerm, LimitDef, LiteralValue};
+use definitions::{PragmaDef, TableDef, ColumnDef, DropTableDef};
+use definitions::{DropIndexDef, DropViewDef, ViewDef, DropTriggerDef};
+use definitions::{TriggerTime, TriggerEvent, UpsertDef, UpsertAction};
+use definitions::{ConflictResolution, ColumnType};
+use error::{RusqlError, RusqlResult};
+use explain::{explain, explain_query_plan};
+use expressions::{ExpressionResult, ExpressionEvaluator, result_to_literal};
+use expressions::{bind_trigger_rows, bind_row, bind_function, bind_case_sensitive_like, eval_constant};
+use expressions::check_function;
+use memory::{row_size, rows_size};
+use planner::{QueryPlan, Access, Search, plan_select, plan_write};
+use rusql::{Rusql, InterruptHandle};
+use stats::TableStats;
+use visitor::{Visitor, walk_expression};
+#[cfg(feature = "parallel")]
+use parallel;
+
+use std::cmp::{max, Ordering};
+use std::collections::{BTreeMap, HashMap};
+use std::isize;
+use std::sync::Arc;
+use std::vec;
+use time;
+
+/// The catalog of the schema, which can be queried like a table.
+pub const MASTER_TABLE: &'static str = "rusql_master";
 
 peg_file! parser("sql.rustpeg");
 
-pub fn rusql_exec<F: FnMut(&TableRow, &TableHeader)>(db: &mut Rusql, sql_str: &str, callback: F) -> Option<Table> {
-    match parser::rusql_parse(sql_str) {
-        Ok(res) => {
-            for stmt in res.into_iter() {
-                match stmt {
-                    RusqlStatement::AlterTable(alter_table_def) => alter_table(db, alter_table_def),
-                    RusqlStatement::CreateTable(table_def) => db.create_table(table_def),
-                    RusqlStatement::Delete(delete_def) => delete(db, delete_def),
-                    RusqlStatement::DropTable(drop_table_def) => db.drop_table(&drop_table_def.name),
-                    RusqlStatement::Insert(insert_def) => insert(db, insert_def),
-                    RusqlStatement::Select(select_def) => return Some(select(db, select_def, callback)),
-                    RusqlStatement::Update(update_def) => update(db, update_def),
-                }
+/// What a statement run by `execute` produced.
+pub enum QueryResult {
+    /// The results of a SELECT, or of a RETURNING clause.
+    Rows(Table),
+    /// The number of rows inserted, updated or deleted, 0 for other
+    /// statements.
+    Changes(usize),
+}
+
+#[deprecated = "use Rusql::execute, which returns errors instead of printing them"]
+pub fn rusql_exec<F: FnMut(&TableRow, &TableHeader)>(db: &mut Rusql, sql_str: &str, callback: F) -> Vec<Table> {
+    match exec_sql(db, sql_str, callback) {
+        Ok(results) => results,
+        Err(e @ RusqlError::ParseError(_)) => {
+            println!("{}", e);
+            Vec::new()
+        }
+        Err(e) => {
+            println!("error: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Runs the statements in `sql_str` until one fails, returning what each of
+/// them produced.
+pub fn execute(db: &mut Rusql, sql_str: &str) -> RusqlResult<Vec<QueryResult>> {
+    execute_statements(db, try!(parse_cached(db, sql_str)))
+}
+
+/// Like execute, for statements that have already been parsed, e.g. to
+/// rewrite them first.
+pub fn execute_statements(db: &mut Rusql, stmts: Vec<RusqlStatement>) -> RusqlResult<Vec<QueryResult>> {
+    let mut results: Vec<QueryResult> = Vec::new();
+
+    for stmt in stmts.into_iter() {
+        results.push(try!(exec_stmt(db, stmt)));
+    }
+
+    Ok(results)
+}
+
+/// Runs a single SELECT, whose rows are produced as they're iterated over.
+pub fn query(db: &mut Rusql, sql_str: &str) -> RusqlResult<Rows> {
+    let mut stmts = try!(parse_cached(db, sql_str));
+
+    match (stmts.pop(), stmts.is_empty()) {
+        (Some(mut stmt), true) => {
+            try!(bind_db_functions(db, &mut stmt));
+
+            match stmt {
+                RusqlStatement::Select(select_def) => Rows::new(db, select_def),
+                _ => Err(RusqlError::ParseError("expected a single SELECT statement".to_string())),
+            }
+        }
+        _ => Err(RusqlError::ParseError("expected a single SELECT statement".to_string())),
+    }
+}
+
+/// Runs the statements in `sql_str` until one fails, returning the results
+/// of every SELECT or RETURNING clause in order, after passing each of their
+/// rows to `callback`.
+pub fn exec_sql<F: FnMut(&TableRow, &TableHeader)>(db: &mut Rusql, sql_str: &str, mut callback: F)
+                                                   -> RusqlResult<Vec<Table>> {
+    let mut result_sets: Vec<Table> = Vec::new();
+
+    for stmt in try!(parse_cached(db, sql_str)).into_iter() {
+        if let QueryResult::Rows(results_table) = try!(exec_stmt(db, stmt)) {
+            for row in results_table.data.values() {
+                callback(row, &results_table.header);
+            }
+            result_sets.push(results_table);
+        }
+    }
+
+    Ok(result_sets)
+}
+
+/// Parses `sql_str` into statements without running them, failing with a
+/// ParseError for invalid SQL.
+pub fn parse(sql_str: &str) -> RusqlResult<Vec<RusqlStatement>> {
+    parser::rusql_parse(sql_str).map_err(|e| RusqlError::ParseError(format!("{}", e)))
+}
+
+/// Like parse, but the statements of SQL that was parsed recently are
+/// taken from the database's parse cache instead.
+pub fn parse_cached(db: &mut Rusql, sql_str: &str) -> RusqlResult<Vec<RusqlStatement>> {
+    if let Some(stmts) = db.parse_cache.get(sql_str) {
+        return Ok(stmts);
+    }
+
+    let stmts = try!(parse(sql_str));
+    db.parse_cache.insert(sql_str, stmts.clone());
+    Ok(stmts)
+}
+
+pub fn exec_stmt(db: &mut Rusql, mut stmt: RusqlStatement) -> RusqlResult<QueryResult> {
+    // The results of a SELECT or a RETURNING clause.
+    let mut returned: Option<Table> = None;
+
+    let writes = match stmt {
+        RusqlStatement::Delete(_) | RusqlStatement::Insert(_) | RusqlStatement::Update(_) => true,
+        _ => false,
+    };
+    let reads = match stmt {
+        RusqlStatement::Select(_) | RusqlStatement::Explain(_) | RusqlStatement::ExplainQueryPlan(_) => true,
+        _ => false,
+    };
+
+    try!(bind_db_functions(db, &mut stmt));
+    db.start_statement();
+
+    try!(match stmt {
+        RusqlStatement::AlterTable(alter_table_def) => alter_table(db, alter_table_def),
+        RusqlStatement::Analyze(name) => db.analyze(name.as_ref()),
+        RusqlStatement::Attach(path, name) => db.attach(path.as_slice(), name),
+        RusqlStatement::Begin => db.begin(),
+        RusqlStatement::Checkpoint => db.checkpoint(),
+        RusqlStatement::Commit => db.commit(),
+        RusqlStatement::CreateIndex(index_def) => db.create_index(index_def),
+        RusqlStatement::CreateTable(table_def) => create_table(db, table_def),
+        RusqlStatement::CreateTrigger(trigger_def) => db.create_trigger(trigger_def),
+        RusqlStatement::CreateView(view_def) => create_view(db, view_def),
+        RusqlStatement::Delete(delete_def) => delete(db, delete_def).map(|results| returned = results),
+        RusqlStatement::Detach(name) => db.detach(&name),
+        RusqlStatement::DropIndex(drop_index_def) => drop_index(db, drop_index_def),
+        RusqlStatement::DropTable(drop_table_def) => drop_table(db, drop_table_def),
+        RusqlStatement::DropTrigger(drop_trigger_def) => drop_trigger(db, drop_trigger_def),
+        RusqlStatement::DropView(drop_view_def) => drop_view(db, drop_view_def),
+        RusqlStatement::Explain(box stmt) => Ok(returned = Some(explain(&stmt))),
+        RusqlStatement::ExplainQueryPlan(box stmt) => explain_query_plan(db, &stmt).map(|results| returned = Some(results)),
+        RusqlStatement::Insert(insert_def) => insert(db, insert_def).map(|results| returned = results),
+        RusqlStatement::Pragma(pragma_def) => pragma(db, pragma_def).map(|results| returned = results),
+        RusqlStatement::RefreshMaterializedView(name) => refresh_materialized_view(db, &name),
+        RusqlStatement::Release(name) => db.release(&name),
+        RusqlStatement::Rollback(None) => db.rollback(),
+        RusqlStatement::Rollback(Some(name)) => db.rollback_to(&name),
+        RusqlStatement::Savepoint(name) => Ok(db.savepoint(name)),
+        RusqlStatement::Select(select_def) => select(db, select_def, |_,_| {}).map(|results| returned = Some(results)),
+        RusqlStatement::Update(update_def) => update(db, update_def).map(|results| returned = results),
+        RusqlStatement::Vacuum(schema) => db.vacuum(schema.as_ref()),
+    });
+
+    if !reads {
+        try!(db.flush());
+    }
+
+    Ok(match returned {
+        Some(results_table) => QueryResult::Rows(results_table),
+        None if writes => QueryResult::Changes(db.changes()),
+        None => QueryResult::Changes(0),
+    })
+}
+
+/// Checks the calls to SQL functions, then replaces the ones that depend on
+/// the state of the database, which expressions can't see, with their
+/// values, and applies the PRAGMAs that change how expressions are evaluated.
+fn bind_db_functions(db: &Rusql, stmt: &mut RusqlStatement) -> RusqlResult<()> {
+    let mut check = FunctionCheck { error: None };
+    check.visit_statement(stmt);
+    if let Some(e) = check.error {
+        return Err(e);
+    }
+
+    let rowid = LiteralValue::Integer(db.last_insert_rowid() as isize);
+
+    for expr in stmt.expressions_mut().into_iter() {
+        bind_function(expr, "last_insert_rowid", &rowid);
+
+        if db.case_sensitive_like {
+            bind_case_sensitive_like(expr);
+        }
+    }
+
+    Ok(())
+}
+
+// Finds the first call to a function that doesn't exist, or that gets the
+// wrong number of arguments.
+struct FunctionCheck {
+    error: Option<RusqlError>,
+}
+
+impl Visitor for FunctionCheck {
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        if self.error.is_none() {
+            if let Expression::Function((ref name, ref args)) = *expr {
+                self.error = check_function(name, args.len()).err();
             }
         }
-        Err(e) => println!("syntax error: {}", e),
+        walk_expression(self, expr)
     }
-    None
 }
 
-fn alter_table(db: &mut Rusql, alter_table_def: AlterTableDef) {
+fn alter_table(db: &mut Rusql, alter_table_def: AlterTableDef) -> RusqlResult<()> {
     match alter_table_def.mode {
         AlterTable::RenameTo(new_name) => db.rename_table(&alter_table_def.name, new_name),
-        AlterTable::AddColumn(column_def) => db.get_mut_table(&alter_table_def.name)
-                                               .add_column(column_def),
+        AlterTable::RenameColumn(old_name, new_name) => {
+            db.rename_column(&alter_table_def.name, &old_name, new_name)
+        }
+        AlterTable::AddColumn(column_def) => {
+            try!(db.get_mut_table(&alter_table_def.name)).add_column(column_def);
+            Ok(())
+        }
+        AlterTable::DropColumn(column_name) => {
+            if db.is_column_referenced(&alter_table_def.name, &column_name) {
+                return Err(RusqlError::InvalidSchemaChange(
+                        format!("cannot drop column referenced by a foreign key: {}", column_name)));
+            }
+
+            try!(db.get_mut_table(&alter_table_def.name)).drop_column(&column_name)
+        }
     }
 }
 
-fn delete(db: &mut Rusql, delete_def: DeleteDef) {
-    let table = db.get_mut_table(&delete_def.name);
+fn create_table(db: &mut Rusql, mut table_def: TableDef) -> RusqlResult<()> {
+    if let Some(select_def) = table_def.as_select.take() {
+        let exists = if table_def.temporary {
+            db.temp.contains_key(&table_def.table_name)
+        } else if table_def.table_name.contains(".") {
+            db.find_table(&table_def.table_name).is_some()
+        } else {
+            db.map.contains_key(&table_def.table_name)
+        };
 
-    if let Some(ref expr) = delete_def.where_expr {
-        // FIXME just making the borrow checker happy...
-        let header = table.header.clone();
-        table.delete_where(|row| ExpressionEvaluator::new(row, &header).eval_bool(expr));
-    } else {
-        table.clear();
+        if table_def.if_not_exists && exists {
+            return Ok(());
+        }
+
+        let name = table_def.table_name.clone();
+        let results_table = try!(select(db, select_def, |_,_| {}));
+
+        // Only the names and types carry over, not the constraints.
+        table_def.columns = results_table.header.iter().map(|def| {
+            ColumnDef {
+                name: def.name.clone(),
+                column_type: def.column_type,
+                column_constraints: Vec::new(),
+            }
+        }).collect();
+        try!(db.create_table(table_def));
+
+        let rows: Vec<TableRow> = results_table.data.into_rows().into_iter().map(|(_, row)| row).collect();
+        return try!(db.get_mut_table(&name)).insert_rows(rows);
+    }
+
+    db.create_table(table_def)
+}
+
+fn create_view(db: &mut Rusql, view_def: ViewDef) -> RusqlResult<()> {
+    if !view_def.materialized {
+        return db.create_view(view_def);
+    }
+
+    if db.has_view(&view_def.name) || db.find_table(&view_def.name).is_some() {
+        if view_def.if_not_exists {
+            return Ok(());
+        }
+        return Err(RusqlError::InvalidSchemaChange(
+                format!("table {} already exists", view_def.name)));
+    }
+
+    // The results are stored the same way as with CREATE TABLE ... AS SELECT.
+    let table_def = TableDef {
+        table_name: view_def.name.clone(),
+        columns: Vec::new(),
+        constraints: Vec::new(),
+        if_not_exists: false,
+        temporary: false,
+        as_select: Some(view_def.select.clone()),
+        strict: false,
+        columnar: false,
+    };
+    try!(create_table(db, table_def));
+
+    db.materialized_views.insert(view_def.name, view_def.select);
+    Ok(())
+}
+
+fn drop_index(db: &mut Rusql, drop_index_def: DropIndexDef) -> RusqlResult<()> {
+    if drop_index_def.if_exists && !db.indexes.contains_key(&drop_index_def.name) {
+        return Ok(());
+    }
+
+    db.drop_index(&drop_index_def.name)
+}
+
+fn drop_table(db: &mut Rusql, drop_table_def: DropTableDef) -> RusqlResult<()> {
+    if drop_table_def.if_exists && db.find_table(&drop_table_def.name).is_none() {
+        return Ok(());
+    }
+
+    db.drop_table(&drop_table_def.name)
+}
+
+fn drop_trigger(db: &mut Rusql, drop_trigger_def: DropTriggerDef) -> RusqlResult<()> {
+    if drop_trigger_def.if_exists && db.find_trigger(&drop_trigger_def.name).is_none() {
+        return Ok(());
+    }
+
+    db.drop_trigger(&drop_trigger_def.name)
+}
+
+fn drop_view(db: &mut Rusql, drop_view_def: DropViewDef) -> RusqlResult<()> {
+    if drop_view_def.if_exists && !db.has_view(&drop_view_def.name) {
+        return Ok(());
     }
+
+    db.drop_view(&drop_view_def.name)
+}
+
+fn delete(db: &mut Rusql, delete_def: DeleteDef) -> RusqlResult<Option<Table>> {
+    if db.views.contains_key(&delete_def.name) {
+        let view = try!(view_contents(db, &delete_def.name));
+        let changes: Vec<TriggerRows> = view.data.values().filter(|row| {
+            match delete_def.where_expr {
+                Some(ref expr) => ExpressionEvaluator::new(*row, &view.header).eval_bool(expr),
+                None => true,
+            }
+        }).map(|row| (Some(row.clone()), None)).collect();
+
+        return fire_instead_of_triggers(db, &delete_def.name, &view.header, &TriggerEvent::Delete, &changes)
+                   .map(|_| None);
+    }
+
+    let (keys, changes, deleted, header) = {
+        let table = try!(db.get_table(&delete_def.name));
+        let keys = try!(affected_keys(table, &delete_def.where_expr, &delete_def.ordering_terms, &delete_def.limit,
+                                       db.stats.get(&table.name)));
+
+        let changes: Vec<TriggerRows> = if db.has_triggers(&delete_def.name) {
+            keys.iter().map(|key| (table.data.get(key).map(|row| row.clone()), None)).collect()
+        } else {
+            Vec::new()
+        };
+
+        let deleted: Vec<TableRow> = if delete_def.returning.is_some() {
+            keys.iter().map(|key| table.data.get(key).unwrap().clone()).collect()
+        } else {
+            Vec::new()
+        };
+
+        (keys, changes, deleted, table.header.clone())
+    };
+
+    try!(fire_triggers(db, &delete_def.name, TriggerTime::Before, &TriggerEvent::Delete, &changes));
+    let deleted_count = keys.len();
+    try!(db.delete_rows(&delete_def.name, keys));
+    db.record_changes(deleted_count);
+    try!(fire_triggers(db, &delete_def.name, TriggerTime::After, &TriggerEvent::Delete, &changes));
+
+    returning(&delete_def.returning, &delete_def.name, header, deleted)
 }
 
-fn insert(db: &mut Rusql, insert_def: InsertDef) {
-    match insert_def.data_source {
-        InsertDataSource::Values(column_data) => {
-            let mut table = db.get_mut_table(&insert_def.table_name);
-            table.insert(column_data, &insert_def.column_names);
+fn insert(db: &mut Rusql, insert_def: InsertDef) -> RusqlResult<Option<Table>> {
+    let view = if db.views.contains_key(&insert_def.table_name) {
+        Some(try!(view_contents(db, &insert_def.table_name)))
+    } else {
+        None
+    };
+
+    let rows = match insert_def.data_source {
+        InsertDataSource::Values(values) => {
+            let column_data = try!(eval_values(&values));
+
+            match view {
+                Some(ref view) => try!(view.build_rows(column_data, &insert_def.column_names)),
+                None => try!(try!(db.get_table(&insert_def.table_name)).build_rows(column_data, &insert_def.column_names)),
+            }
         }
         InsertDataSource::Select(select_def) => {
-            let results_table = select(db, select_def, |_,_| {});
-            let mut table = db.get_mut_table(&insert_def.table_name);
+            let results_table = try!(select(db, select_def, |_,_| {}));
+            let column_data = results_table.data.into_rows().into_iter().map(|(_, row)| row).collect();
 
-            for (_, row) in results_table.data.into_iter() {
-                table.push_row(row);
+            match view {
+                Some(ref view) => try!(view.build_rows(column_data, &insert_def.column_names)),
+                None => try!(try!(db.get_table(&insert_def.table_name)).build_rows(column_data, &insert_def.column_names)),
             }
         }
-        _ => {}
+        InsertDataSource::DefaultValues => {
+            // A row where no column is given a value.
+            let column_data = vec![Vec::new()];
+            let column_names = Some(Vec::new());
+
+            match view {
+                Some(ref view) => try!(view.build_rows(column_data, &column_names)),
+                None => try!(try!(db.get_table(&insert_def.table_name)).build_rows(column_data, &column_names)),
+            }
+        }
+        InsertDataSource::Error => return Ok(None),
+    };
+
+    if let Some(ref view) = view {
+        let changes: Vec<TriggerRows> = rows.into_iter().map(|row| (None, Some(row))).collect();
+        return fire_instead_of_triggers(db, &insert_def.table_name, &view.header, &TriggerEvent::Insert, &changes)
+                   .map(|_| None);
     }
+
+    let mut upserted = 0us;
+    let rows = match insert_def.upsert {
+        Some(ref upsert_def) => {
+            let mut remaining: Vec<TableRow> = Vec::new();
+
+            for row in rows.into_iter() {
+                if let Some(row) = try!(upsert(db, &insert_def.table_name, row, upsert_def, &mut upserted)) {
+                    remaining.push(row);
+                }
+            }
+            remaining
+        }
+        None => rows,
+    };
+
+    // BEFORE triggers see the rows as they'll be stored, but for the keys
+    // they're yet to be given.
+    let changes: Vec<TriggerRows> = if db.has_triggers(&insert_def.table_name) {
+        let table = try!(db.get_table(&insert_def.table_name));
+        rows.iter().map(|row| {
+            let mut row = row.clone();
+            table.prepare_row(&mut row);
+            (None, Some(row))
+        }).collect()
+    } else {
+        Vec::new()
+    };
+
+    try!(fire_triggers(db, &insert_def.table_name, TriggerTime::Before, &TriggerEvent::Insert, &changes));
+    try!(db.check_foreign_keys(&insert_def.table_name, &rows));
+    try!(db.reserve_memory(rows_size(&rows)));
+    let keys = match try!(db.get_mut_table(&insert_def.table_name)).insert_rows_with(rows, insert_def.on_conflict) {
+        Ok(keys) => keys,
+        Err(e) => {
+            // OR ROLLBACK undoes the whole transaction, not just the statement.
+            if insert_def.on_conflict == ConflictResolution::Rollback && db.in_transaction() {
+                try!(db.rollback());
+            }
+            return Err(e);
+        }
+    };
+    if let Some(&rowid) = keys.last() {
+        db.record_insert(rowid);
+    }
+    db.record_changes(keys.len() + upserted);
+
+    // RETURNING gives the rows as stored, with the keys they were given.
+    let (header, inserted) = if insert_def.returning.is_some() {
+        let table = try!(db.get_table(&insert_def.table_name));
+        let inserted: Vec<TableRow> = keys.iter().filter_map(|key| table.data.get(key))
+                                                 .map(|row| row.clone())
+                                                 .collect();
+        (table.header.clone(), inserted)
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    // AFTER triggers see the rows that were stored, and not the ones OR
+    // IGNORE left out.
+    if !changes.is_empty() {
+        let changes: Vec<TriggerRows> = {
+            let table = try!(db.get_table(&insert_def.table_name));
+            keys.iter().filter_map(|key| table.data.get(key)).map(|row| (None, Some(row.clone()))).collect()
+        };
+        try!(fire_triggers(db, &insert_def.table_name, TriggerTime::After, &TriggerEvent::Insert, &changes));
+    }
+
+    returning(&insert_def.returning, &insert_def.table_name, header, inserted)
 }
 
-fn update(db: &mut Rusql, update_def: UpdateDef) {
-    let mut table = db.get_mut_table(&update_def.name);
+// The expressions in VALUES can't refer to any column.
+fn eval_values(values: &Vec<Vec<Expression>>) -> RusqlResult<Vec<TableRow>> {
+    let mut rows: Vec<TableRow> = Vec::new();
+
+    for exprs in values.iter() {
+        let mut row: TableRow = Vec::new();
 
-    for (_, row) in table.data.iter_mut() {
-        if let Some(ref expr) = update_def.where_expr {
-            if !ExpressionEvaluator::new(row, &table.header).eval_bool(expr) {
-                continue;
+        for expr in exprs.iter() {
+            if let Some(name) = expr.column_names().pop() {
+                return Err(RusqlError::NoSuchColumn(name));
             }
+            row.push(eval_constant(expr));
+        }
+
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+fn pragma(db: &mut Rusql, pragma_def: PragmaDef) -> RusqlResult<Option<Table>> {
+    match db.find_pragma(&pragma_def.name) {
+        Some(handler) => handler(db, pragma_def.value),
+        None => Ok(None), // Unknown pragmas are ignored, same as SQLite.
+    }
+}
+
+/// Applies the ON CONFLICT clause if the row conflicts with an existing one,
+/// otherwise gives the row back to be inserted.
+fn upsert(db: &mut Rusql, table_name: &String, mut row: TableRow, upsert_def: &UpsertDef,
+          updated: &mut usize) -> RusqlResult<Option<TableRow>> {
+    let (pk, old_row, header) = {
+        let table = try!(db.get_table(table_name));
+        table.prepare_row(&mut row);
+
+        match table.conflicting_rows(&row, upsert_def.target.as_ref()).first() {
+            Some(&pk) => (pk, table.data.get(&pk).unwrap().clone(), table.header.clone()),
+            None => return Ok(Some(row)),
         }
+    };
 
-        for &(ref name, ref expr) in update_def.set.iter() {
-            let x = table.header.iter().position(|ref cols| &cols.name == name).unwrap();
+    let (set, where_expr) = match upsert_def.action {
+        UpsertAction::Nothing => return Ok(None),
+        UpsertAction::Update(ref set, ref where_expr) => (set, where_expr),
+    };
 
-            row[x] = expr_to_literal(expr);
+    if let &Some(ref expr) = where_expr {
+        let mut expr = expr.clone();
+        bind_row(&mut expr, "excluded", &header, &row);
+
+        if !ExpressionEvaluator::new(&old_row, &header).eval_bool(&expr) {
+            return Ok(None);
         }
     }
+
+    let mut new_row = old_row.clone();
+
+    for &(ref name, ref expr) in set.iter() {
+        let i = match header.iter().position(|def| &def.name == name) {
+            Some(i) => i,
+            None => return Err(RusqlError::NoSuchColumn(name.clone())),
+        };
+
+        let mut expr = expr.clone();
+        bind_row(&mut expr, "excluded", &header, &row);
+        new_row[i] = result_to_literal(ExpressionEvaluator::new(&old_row, &header).eval_expr(&expr));
+    }
+
+    let event = TriggerEvent::Update(Some(set.iter().map(|&(ref name, _)| name.clone()).collect()));
+    let changes: Vec<TriggerRows> = vec![(Some(old_row), Some(new_row.clone()))];
+
+    try!(fire_triggers(db, table_name, TriggerTime::Before, &event, &changes));
+    try!(db.update_rows(table_name, vec![(pk, new_row)]));
+    *updated += 1;
+    try!(fire_triggers(db, table_name, TriggerTime::After, &event, &changes));
+
+    Ok(None)
 }
 
-fn product(tables: Vec<&Table>, input_product: &mut Table, new_row_opt: Option<TableRow>) {
-    let mut remaining = tables.clone();
+fn update(db: &mut Rusql, update_def: UpdateDef) -> RusqlResult<Option<Table>> {
+    let mut updates: Vec<(PkType, TableRow)> = Vec::new();
+    let view = if db.views.contains_key(&update_def.name) {
+        Some(try!(view_contents(db, &update_def.name)))
+    } else {
+        None
+    };
+
+    {
+        let table = match view {
+            Some(ref view) => view,
+            None => try!(db.get_table(&update_def.name)),
+        };
+
+        let keys = try!(affected_keys(table, &update_def.where_expr, &update_def.ordering_terms, &update_def.limit,
+                                       db.stats.get(&table.name)));
+        let set_exprs: Vec<&Expression> = update_def.set.iter().map(|&(_, ref expr)| expr).collect();
+        try!(check_column_names(&set_exprs, &vec![table]));
+
+        for key in keys.iter() {
+            let row = table.data.get(key).unwrap();
+            let mut new_row = row.clone();
+
+            // Every SET expression sees the values from before the update.
+            for &(ref name, ref expr) in update_def.set.iter() {
+                let x = match table.header.iter().position(|ref cols| &cols.name == name) {
+                    Some(x) => x,
+                    None => return Err(RusqlError::NoSuchColumn(name.clone())),
+                };
+
+                new_row[x] = result_to_literal(ExpressionEvaluator::new(row, &table.header).eval_expr(expr));
+            }
 
-    if remaining.len() == 0 {
-        if let Some(new_row) = new_row_opt {
-            input_product.push_row(new_row);
+            updates.push((*key, new_row));
         }
+    }
+
+    let event = TriggerEvent::Update(Some(update_def.set.iter().map(|&(ref name, _)| name.clone()).collect()));
+
+    if let Some(ref view) = view {
+        let changes: Vec<TriggerRows> = updates.into_iter().map(|(key, row)| {
+            (view.data.get(&key).map(|row| row.clone()), Some(row))
+        }).collect();
+        return fire_instead_of_triggers(db, &update_def.name, &view.header, &event, &changes).map(|_| None);
+    }
+
+    let changes: Vec<TriggerRows> = if db.has_triggers(&update_def.name) {
+        let table = try!(db.get_table(&update_def.name));
+        updates.iter().map(|&(ref key, ref row)| (table.data.get(key).map(|row| row.clone()), Some(row.clone()))).collect()
     } else {
-        let table = remaining.remove(0);
-        for row in table.data.values() {
-            let mut new_row: TableRow = if let Some(ref new_row) = new_row_opt {
-                new_row.clone()
-            } else {
-                Vec::new()
-            };
+        Vec::new()
+    };
+
+    let updated: Vec<TableRow> = if update_def.returning.is_some() {
+        updates.iter().map(|&(_, ref row)| row.clone()).collect()
+    } else {
+        Vec::new()
+    };
 
-            new_row.push_all(&*row.clone());
+    try!(fire_triggers(db, &update_def.name, TriggerTime::Before, &event, &changes));
+    {
+        let table = try!(db.get_table(&update_def.name));
+        let (old_size, new_size) = updates.iter().fold((0, 0), |(old_size, new_size), &(ref key, ref row)| {
+            (old_size + table.data.get(key).map_or(0, |row| row_size(row)), new_size + row_size(row))
+        });
 
-            product(remaining.clone(), input_product, Some(new_row));
+        if new_size > old_size {
+            try!(db.reserve_memory(new_size - old_size));
         }
     }
+    let updated_count = updates.len();
+    try!(db.update_rows(&update_def.name, updates));
+    db.record_changes(updated_count);
+    try!(fire_triggers(db, &update_def.name, TriggerTime::After, &event, &changes));
+
+    let table = try!(db.get_table(&update_def.name));
+    let updated: Vec<TableRow> = updated.into_iter().map(|mut row| {
+        table.prepare_row(&mut row);
+        row
+    }).collect();
+
+    returning(&update_def.returning, &update_def.name, table.header.clone(), updated)
 }
 
-fn select<F: FnMut(&TableRow, &TableHeader)>(db: &mut Rusql, select_def: SelectDef, mut callback: F) -> Table {
-    let mut input_tables: Vec<&Table> = Vec::new();
-    let mut input_product = generate_inputs(db, &mut input_tables, &select_def);
+/// The keys of the rows an UPDATE or DELETE applies to, going by its WHERE,
+/// ORDER BY and LIMIT clauses.
+fn affected_keys(table: &Table, where_expr: &Option<Expression>, ordering_terms: &Option<Vec<OrderingTerm>>,
+                 limit_def: &Option<LimitDef>, stats: Option<&TableStats>) -> RusqlResult<Vec<PkType>> {
+    let mut exprs: Vec<&Expression> = where_expr.iter().collect();
+    if let Some(ref ordering_terms) = *ordering_terms {
+        exprs.extend(ordering_terms.iter().map(|term| &term.expr));
+    }
+    try!(check_column_names(&exprs, &vec![table]));
 
-    filter_inputs(&mut input_product, &input_tables, &select_def);
+    let plan = plan_write(table, where_expr, ordering_terms.is_some(), stats);
+    let mut keys: Vec<PkType> = match *where_expr {
+        Some(ref expr) => search_keys(table, &plan.tables[0].search).into_iter().filter(|key| {
+            ExpressionEvaluator::new(table.data.get(key).unwrap(), &table.header).eval_bool(expr)
+        }).collect(),
+        None => table.data.keys().collect(),
+    };
 
-    let results_table = generate_result_set(input_product, &input_tables, &select_def);
+    if let Some(ref ordering_terms) = *ordering_terms {
+        let mut sort_keys: Vec<(TableRow, PkType)> = keys.iter().map(|key| {
+            let row = table.data.get(key).unwrap();
+            let values: TableRow = ordering_terms.iter().map(|term| {
+                result_to_literal(ExpressionEvaluator::new(row, &table.header).eval_expr(&term.expr))
+            }).collect();
+
+            (values, *key)
+        }).collect();
+
+        sort_keys.as_mut_slice().sort_by(|&(ref a, _), &(ref b, _)| {
+            for (term, (x, y)) in ordering_terms.iter().zip(a.iter().zip(b.iter())) {
+                let ordering = match term.order {
+                    Order::Ascending => x.cmp(y),
+                    Order::Descending => y.cmp(x),
+                };
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+
+        keys = sort_keys.into_iter().map(|(_, key)| key).collect();
+    }
+
+    if let Some(ref limit_def) = *limit_def {
+        let (offset, limit) = limit_bounds(limit_def);
+        let limit = limit.unwrap_or(keys.len());
+
+        keys = keys.into_iter().skip(offset).take(limit).collect();
+    }
+
+    Ok(keys)
+}
+
+/// How many rows LIMIT skips, and how many it keeps after those, if not all.
+fn limit_bounds(limit_def: &LimitDef) -> (usize, Option<usize>) {
+    let offset = match limit_def.offset {
+        Some(ref expr) => max(eval_constant(expr).to_int(), 0) as usize,
+        None => 0,
+    };
+    // Like SQLite, a negative limit means there is none.
+    let limit = match eval_constant(&limit_def.limit).to_int() {
+        n if n < 0 => None,
+        n => Some(n as usize),
+    };
+
+    (offset, limit)
+}
+
+/// Fails with NoSuchColumn if `exprs` use a column none of `tables` have,
+/// rather than evaluating them against a row without it.
+fn check_column_names(exprs: &Vec<&Expression>, tables: &Vec<&Table>) -> RusqlResult<()> {
+    for expr in exprs.iter() {
+        for name in expr.column_names().into_iter() {
+            if !tables.iter().any(|table| table.get_column_index(&name).is_some()) {
+                return Err(RusqlError::NoSuchColumn(name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Evaluates the RETURNING clause of a write statement against the rows it
+/// inserted, updated or deleted, the same way as the result columns of a
+/// SELECT.
+fn returning(result_column: &Option<ResultColumn>, table_name: &String, header: TableHeader,
+             rows: Vec<TableRow>) -> RusqlResult<Option<Table>> {
+    let result_column = match *result_column {
+        Some(ref result_column) => result_column.clone(),
+        None => return Ok(None),
+    };
+
+    let mut input_table = Table::new_result_table(header);
+    input_table.name = table_name.clone();
+
+    for row in rows.into_iter() {
+        input_table.push_row(row).ok();
+    }
+
+    let select_def = SelectDef {
+        result_column: result_column,
+        from_clause: None,
+        where_expr: None,
+        ordering_terms: None,
+        limit: None,
+    };
+
+    let input_tables = vec![Arc::new(input_table)];
+    let plan = plan_select(&select_def, &borrow_tables(&input_tables), &BTreeMap::new());
+
+    // The rows were written already, so this part isn't interrupted.
+    let join = try!(JoinCursor::new(input_tables.as_slice(), plan, None));
+    generate_result_set(join, input_tables.as_slice(), &select_def).map(|results| Some(results))
+}
+
+// The OLD and NEW rows a trigger runs for, whichever exist.
+type TriggerRows = (Option<TableRow>, Option<TableRow>);
+
+fn fire_triggers(db: &mut Rusql, table_name: &String, time: TriggerTime, event: &TriggerEvent,
+                 changes: &Vec<TriggerRows>) -> RusqlResult<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let header = try!(db.get_table(table_name)).header.clone();
+    run_triggers(db, table_name, &header, time, event, changes)
+}
+
+// Views can't be written to, only their INSTEAD OF triggers can do something
+// with the changes.
+fn fire_instead_of_triggers(db: &mut Rusql, view_name: &String, header: &TableHeader, event: &TriggerEvent,
+                            changes: &Vec<TriggerRows>) -> RusqlResult<()> {
+    let has_trigger = match db.triggers.get(view_name) {
+        Some(triggers) => triggers.iter().any(|trigger| trigger.time == TriggerTime::InsteadOf
+                                                        && fires_on(&trigger.event, event)),
+        None => false,
+    };
+
+    if !has_trigger {
+        return Err(RusqlError::ViewNotWritable(view_name.clone()));
+    }
+
+    try!(run_triggers(db, view_name, header, TriggerTime::InsteadOf, event, changes));
+    db.record_changes(changes.len());
+    Ok(())
+}
+
+fn fires_on(trigger_event: &TriggerEvent, event: &TriggerEvent) -> bool {
+    match (trigger_event, event) {
+        (&TriggerEvent::Update(Some(ref columns)), &TriggerEvent::Update(Some(ref updated))) => {
+            columns.iter().any(|name| updated.contains(name))
+        }
+        (&TriggerEvent::Update(_), &TriggerEvent::Update(_)) => true,
+        (trigger_event, event) => trigger_event == event,
+    }
+}
+
+fn run_triggers(db: &mut Rusql, table_name: &String, header: &TableHeader, time: TriggerTime,
+                event: &TriggerEvent, changes: &Vec<TriggerRows>) -> RusqlResult<()> {
+    let triggers = db.triggers_for(table_name, time);
+
+    for trigger in triggers.iter() {
+        if !fires_on(&trigger.event, event) {
+            continue;
+        }
+
+        for &(ref old_row, ref new_row) in changes.iter() {
+            if let Some(ref when) = trigger.when {
+                let mut when = when.clone();
+                bind_trigger_rows(&mut when, header, old_row, new_row);
+
+                if !eval_constant(&when).to_bool() {
+                    continue;
+                }
+            }
+
+            db.trigger_stack.push(trigger.name.clone());
+            let result = run_trigger_body(db, &trigger.body, header, old_row, new_row);
+            db.trigger_stack.pop();
+
+            try!(result);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_trigger_body(db: &mut Rusql, body: &Vec<RusqlStatement>, header: &TableHeader,
+                    old_row: &Option<TableRow>, new_row: &Option<TableRow>) -> RusqlResult<()> {
+    for stmt in body.iter() {
+        let mut stmt = stmt.clone();
+
+        for expr in stmt.expressions_mut().into_iter() {
+            bind_trigger_rows(expr, header, old_row, new_row);
+        }
+        try!(bind_db_functions(db, &mut stmt));
+
+        try!(match stmt {
+            RusqlStatement::Delete(delete_def) => delete(db, delete_def).map(|_| ()),
+            RusqlStatement::Insert(insert_def) => insert(db, insert_def).map(|_| ()),
+            RusqlStatement::Select(select_def) => select(db, select_def, |_,_| {}).map(|_| ()),
+            RusqlStatement::Update(update_def) => update(db, update_def).map(|_| ()),
+            _ => Ok(()),
+        });
+    }
+
+    Ok(())
+}
+
+fn refresh_materialized_view(db: &mut Rusql, name: &String) -> RusqlResult<()> {
+    let select_def = match db.materialized_views.get(name) {
+        Some(select_def) => select_def.clone(),
+        None => return Err(RusqlError::NoSuchView(name.clone())),
+    };
+
+    let results_table = try!(select(db, select_def, |_,_| {}));
+    let rows: Vec<TableRow> = results_table.data.into_rows().into_iter().map(|(_, row)| row).collect();
+    let table = try!(db.get_mut_table(name));
+
+    table.clear();
+    table.max_pk = 0;
+    table.insert_rows(rows)
+}
+
+fn select<F: FnMut(&TableRow, &TableHeader)>(db: &mut Rusql, select_def: SelectDef, mut callback: F) -> RusqlResult<Table> {
+    let views = try!(expand_views(db, &select_def));
+    let input_tables = try!(get_inputs(db, &views, &select_def));
+    let mut input_header: TableHeader = Vec::new();
+
+    for table in input_tables.iter() {
+        input_header.push_all(table.header.as_slice());
+    }
+
+    let tables = borrow_tables(&input_tables);
+    try!(check_select_columns(&select_def, &tables, &input_header));
+
+    let plan = plan_select(&select_def, &tables, &db.stats);
+    let join = try!(JoinCursor::new(input_tables.as_slice(), plan, Some(Cancel::new(db))));
+
+    let results_table = try!(generate_result_set(join, input_tables.as_slice(), &select_def));
 
     for row in results_table.data.values() {
         callback(row, &results_table.header);
     }
 
-    results_table
+    Ok(results_table)
+}
+
+/// The rows of a SELECT, made one at a time as they're iterated over: the
+/// join is walked row by row following the plan, and LIMIT is applied on the
+/// way, instead of building the whole product first. With ORDER BY,
+/// everything has to be read before the first row, so the result set is
+/// built up front. The rows come from the tables as they were when the
+/// query ran, as later writes copy a table the rows still share.
+pub struct Rows {
+    /// The tables the select reads from, in the order of the FROM clause.
+    tables: Vec<Arc<Table>>,
+    header: TableHeader,
+    result_exprs: Option<Vec<Expression>>,
+    join: Option<JoinCursor>,
+    offset: usize,
+    remaining: Option<usize>,
+    sorted: Option<vec::IntoIter<TableRow>>,
+    /// Why the rows stopped before the last one, e.g. Interrupted.
+    error: Option<RusqlError>,
+}
+
+impl Rows {
+    fn new(db: &mut Rusql, select_def: SelectDef) -> RusqlResult<Rows> {
+        db.start_statement();
+
+        if select_def.ordering_terms.is_some() {
+            let results_table = try!(select(db, select_def, |_,_| {}));
+            let header = results_table.header.clone();
+            let rows: Vec<TableRow> = results_table.data.into_rows().into_iter().map(|(_, row)| row).collect();
+
+            return Ok(Rows {
+                tables: Vec::new(),
+                header: header,
+                result_exprs: None,
+                join: None,
+                offset: 0,
+                remaining: None,
+                sorted: Some(rows.into_iter()),
+                error: None,
+            });
+        }
+
+        let views = try!(expand_views(db, &select_def));
+        let input_tables = try!(get_inputs(db, &views, &select_def));
+
+        let (header, join) = {
+            let tables = borrow_tables(&input_tables);
+            let mut input_header: TableHeader = Vec::new();
+
+            for table in tables.iter() {
+                input_header.push_all(table.header.as_slice());
+            }
+
+            try!(check_select_columns(&select_def, &tables, &input_header));
+
+            let header = match select_def.result_column {
+                ResultColumn::Asterisk => input_header.clone(),
+                ResultColumn::Expressions(ref exprs) => {
+                    // The header doesn't depend on the values, so any row will do.
+                    let mut scratch = Table::new_result_table(Vec::new());
+                    let null_row: TableRow = input_header.iter().map(|_| LiteralValue::Null).collect();
+                    generate_row_from_expressions(&mut scratch, &null_row, exprs, input_tables.as_slice());
+                    scratch.header
+                }
+            };
+
+            let plan = plan_select(&select_def, &tables, &db.stats);
+
+            (header, try!(JoinCursor::new(input_tables.as_slice(), plan, Some(Cancel::new(db)))))
+        };
+
+        let (offset, remaining) = match select_def.limit {
+            Some(ref limit_def) => limit_bounds(limit_def),
+            None => (0, None),
+        };
+
+        let result_exprs = match select_def.result_column {
+            ResultColumn::Expressions(exprs) => Some(exprs),
+            ResultColumn::Asterisk => None,
+        };
+
+        Ok(Rows {
+            tables: input_tables,
+            header: header,
+            result_exprs: result_exprs,
+            join: Some(join),
+            offset: offset,
+            remaining: remaining,
+            sorted: None,
+            error: None,
+        })
+    }
+
+    pub fn header(&self) -> &TableHeader {
+        &self.header
+    }
+
+    /// The error that ended the rows early, if any. They end the same way
+    /// whether or not there are more.
+    pub fn error(&self) -> Option<&RusqlError> {
+        self.error.as_ref()
+    }
 }
 
-fn natural_join(tables: &Vec<&Table>, constraints: &mut Vec<JoinConstraint>) {
-    // FIXME ...
-    let mut columns: Vec<(String, String, String)> = Vec::new();
+impl Iterator for Rows {
+    type Item = TableRow;
+
+    fn next(&mut self) -> Option<TableRow> {
+        if let Some(ref mut sorted) = self.sorted {
+            return sorted.next();
+        }
+
+        loop {
+            if self.remaining == Some(0) {
+                return None;
+            }
+
+            let row = match self.join {
+                Some(ref mut join) => join.next_row(self.tables.as_slice()),
+                None => Ok(None),
+            };
+            let row = match row {
+                Ok(Some(row)) => row,
+                Ok(None) => return None,
+                Err(e) => {
+                    self.error = Some(e);
+                    self.join = None;
+                    return None;
+                }
+            };
 
-    for table in tables.iter() {
-        for other in tables.iter() {
-            if table == other {
+            if self.offset > 0 {
+                self.offset -= 1;
                 continue;
             }
 
-            for col in table.header.iter() {
-                for other_col in other.header.iter() {
-                    if col.name == other_col.name {
-                        columns.push((table.name.clone(), other.name.clone(), other_col.name.clone()));
-                    }
+            if let Some(ref mut remaining) = self.remaining {
+                *remaining -= 1;
+            }
+
+            return Some(match self.result_exprs {
+                Some(ref exprs) => exprs.iter().map(|expr| {
+                    result_to_literal(ExpressionEvaluator::new(&row, &self.header).with_tables(self.tables.as_slice())
+                                                                                  .eval_expr(expr))
+                }).collect(),
+                None => row,
+            });
+        }
+    }
+}
+
+// Fails with NoSuchColumn if the select uses a column its inputs don't have.
+fn check_select_columns(select_def: &SelectDef, input_tables: &Vec<&Table>, input_header: &TableHeader)
+                        -> RusqlResult<()> {
+    let mut exprs: Vec<&Expression> = Vec::new();
+    if let ResultColumn::Expressions(ref result_exprs) = select_def.result_column {
+        exprs.extend(result_exprs.iter());
+    }
+    exprs.extend(select_def.where_expr.iter());
+    try!(check_column_names(&exprs, input_tables));
+
+    // ORDER BY sorts the result set, so it can only use its columns.
+    if let Some(ref ordering_terms) = select_def.ordering_terms {
+        let result_names: Vec<String> = match select_def.result_column {
+            ResultColumn::Asterisk => input_header.iter().map(|def| def.name.clone()).collect(),
+            ResultColumn::Expressions(ref result_exprs) => result_exprs.iter().filter_map(|expr| {
+                match expr {
+                    &Expression::ColumnName(ref name) => Some(name.clone()),
+                    &Expression::TableName((_, box Expression::ColumnName(ref name))) => Some(name.clone()),
+                    _ => None,
+                }
+            }).collect(),
+        };
+
+        for term in ordering_terms.iter() {
+            for name in term.expr.column_names().into_iter() {
+                if !result_names.contains(&name) {
+                    return Err(RusqlError::NoSuchColumn(name));
                 }
             }
         }
     }
 
-    for (table1, table2, column_name) in columns.into_iter() {
-        constraints.push(JoinConstraint::On(
-                Expression::BinaryOperator((BinaryOperator::Equals,
-                    box Expression::TableName((table1, box Expression::ColumnName(column_name.clone()))),
-                    box Expression::TableName((table2, box Expression::ColumnName(column_name)))))));
+    Ok(())
+}
+
+// Runs the select of every view the query reads from, so they can be used like
+// tables.
+fn expand_views(db: &mut Rusql, select_def: &SelectDef) -> RusqlResult<Vec<Arc<Table>>> {
+    let mut views: Vec<Arc<Table>> = Vec::new();
+
+    for name in select_def.table_names().iter() {
+        if db.views.contains_key(name) {
+            views.push(Arc::new(try!(view_contents(db, name))));
+        } else if name.as_slice() == MASTER_TABLE && db.find_table(name).is_none() {
+            views.push(Arc::new(master_table(db)));
+        }
     }
+
+    Ok(views)
 }
 
-fn generate_inputs<'a>(db: &'a Rusql, input_tables: &mut Vec<&'a Table>, select_def: &SelectDef) -> Table {
+/// The catalog of the tables, indexes and views, with the SQL to recreate
+/// each of them, like SQLite's sqlite_master.
+fn master_table(db: &Rusql) -> Table {
+    let header: TableHeader = ["type", "name", "tbl_name", "sql"].iter().map(|name| {
+        ColumnDef { name: name.to_string(), column_type: Some(ColumnType::Text), column_constraints: Vec::new() }
+    }).collect();
+    let mut master = Table::new_result_table(header);
+    let text = |s: &str| LiteralValue::text(s);
+
+    master.name = MASTER_TABLE.to_string();
+
+    for (name, table) in db.map.iter().filter(|&(name, _)| !db.materialized_views.contains_key(name)) {
+        master.push_row(vec![text("table"), text(name.as_slice()), text(name.as_slice()),
+                             text(table.to_sql().as_slice())]).ok();
+
+        for index in table.indexes.iter() {
+            master.push_row(vec![text("index"), text(index.name.as_slice()), text(name.as_slice()),
+                                 text(index.to_sql(name).as_slice())]).ok();
+        }
+    }
+
+    for (name, select_def) in db.views.iter() {
+        master.push_row(vec![text("view"), text(name.as_slice()), text(name.as_slice()),
+                             text(format!("CREATE VIEW {} AS {}", name, select_def).as_slice())]).ok();
+    }
+
+    for (name, select_def) in db.materialized_views.iter() {
+        master.push_row(vec![text("view"), text(name.as_slice()), text(name.as_slice()),
+                             text(format!("CREATE MATERIALIZED VIEW {} AS {}", name, select_def).as_slice())]).ok();
+    }
+
+    master
+}
+
+fn view_contents(db: &mut Rusql, name: &String) -> RusqlResult<Table> {
+    let view_def = match db.views.get(name) {
+        Some(view_def) => view_def.clone(),
+        None => return Err(RusqlError::NoSuchView(name.clone())),
+    };
+
+    let mut view = try!(select(db, view_def, |_,_| {}));
+    view.name = name.clone();
+
+    Ok(view)
+}
+
+fn get_input(db: &Rusql, views: &Vec<Arc<Table>>, name: &String) -> RusqlResult<Arc<Table>> {
+    if let Some(view) = views.iter().find(|view| &view.name == name) {
+        return Ok(view.clone());
+    }
+
+    match db.find_shared_table(name) {
+        Some(table) => Ok(table),
+        None => Err(RusqlError::NoSuchTable(name.clone())),
+    }
+}
+
+// The input tables are shared, so that the rows of a query can keep them
+// without going back to the database for every row.
+fn get_inputs(db: &Rusql, views: &Vec<Arc<Table>>, select_def: &SelectDef) -> RusqlResult<Vec<Arc<Table>>> {
     // https://www.sqlite.org/lang_select.html#fromclause
-    let mut input_header: TableHeader = Vec::new();
+    let mut input_tables: Vec<Arc<Table>> = Vec::new();
 
-    if let Some(ref from_clause) = select_def.from_clause {
-        // FIXME CLEANUP PLZ
-        match from_clause {
-            &FromClause::TableOrSubquery(ref table_or_subquery) => {
-                for name in table_or_subquery.iter() {
-                    let table = db.get_table(name);
-                    input_tables.push(table);
-                    input_header.push_all(&*table.header.clone());
-                }
+    for name in select_def.table_names().iter() {
+        input_tables.push(try!(get_input(db, views, name)));
+    }
 
-                let mut input_product = Table::new_result_table(input_header);
+    Ok(input_tables)
+}
 
-                product(input_tables.clone(), &mut input_product, None);
+// The input tables as the planner takes them.
+fn borrow_tables(tables: &Vec<Arc<Table>>) -> Vec<&Table> {
+    tables.iter().map(|table| &**table).collect()
+}
 
-                input_product
-            },
-            &FromClause::JoinClause(ref name, ref join_clauses) => {
-                let table = db.get_table(name);
-                let mut constraints: Vec<JoinConstraint> = Vec::new();
-                input_tables.push(table);
-                input_header.push_all(&*table.header.clone());
+/// The plan EXPLAIN QUERY PLAN shows for a SELECT, made from the tables it
+/// would read.
+pub fn query_plan(db: &mut Rusql, select_def: &SelectDef) -> RusqlResult<QueryPlan> {
+    let views = try!(expand_views(db, select_def));
+    let input_tables = try!(get_inputs(db, &views, select_def));
 
-                if let &Some(ref join_clauses) = join_clauses {
-                    for &(ref join_operator, ref name, ref join_const) in join_clauses.iter() {
-                        let table = db.get_table(name);
-                        input_tables.push(table);
-                        input_header.push_all(&*table.header.clone());
+    Ok(plan_select(select_def, &borrow_tables(&input_tables), &db.stats))
+}
 
-                        if let &Some(ref constraint) = join_const {
-                            constraints.push(constraint.clone());
-                        }
+// Walks the rows of a join one at a time, following its plan: the rows of
+// each table that can go with the ones joined before it are tried in turn,
+// the last table's changing fastest, and the conditions are checked as soon
+// as their tables are in. It only holds keys, and the tables are passed in
+// again for each row.
+struct JoinCursor {
+    plan: QueryPlan,
+    /// The columns of all the tables, in the order of the FROM clause.
+    header: TableHeader,
+    /// Where the columns of each table of the FROM clause start in the
+    /// joined rows.
+    offsets: Vec<usize>,
+    /// The keys of the rows of each table, in the order of the plan, that
+    /// pass its own filters.
+    scans: Vec<Vec<PkType>>,
+    /// The keys of the tables joined on an equality, by the value of their
+    /// side of it.
+    hash_tables: Vec<HashMap<String, Vec<(LiteralValue, PkType)>>>,
+    merges: Vec<Merge>,
+    /// For each table joined so far, the keys of its rows that can go with
+    /// the rows of the tables before it, None for all of its scan, and how
+    /// many of them were tried.
+    stack: Vec<(Option<Vec<PkType>>, usize)>,
+    /// The row being put together. The columns of the tables not joined
+    /// yet are NULL.
+    row: TableRow,
+    started: bool,
+    /// Checked before every row is tried, None if the join can't be
+    /// interrupted.
+    cancel: Option<Cancel>,
+}
+
+impl JoinCursor {
+    fn new(input_tables: &[Arc<Table>], plan: QueryPlan, cancel: Option<Cancel>) -> RusqlResult<JoinCursor> {
+        // https://www.sqlite.org/lang_select.html#whereclause
+        let mut header: TableHeader = Vec::new();
+        let mut offsets: Vec<usize> = Vec::new();
+
+        for table in input_tables.iter() {
+            offsets.push(header.len());
+            header.push_all(table.header.as_slice());
+        }
+
+        let no_filters: Vec<Expression> = Vec::new();
+        let mut scans: Vec<Vec<PkType>> = Vec::new();
+
+        for table_access in plan.tables.iter() {
+            let table = &input_tables[table_access.position];
+
+            // Scanning the columns already leaves out the rows failing the
+            // filters it's made from.
+            let searched = match table_access.search {
+                Some(Search::Columns(..)) if table.columns.is_some() => &no_filters,
+                _ => &table_access.searched,
+            };
 
-                        match join_operator {
-                            &JoinOperator::Natural => natural_join(input_tables, &mut constraints),
-                            _ => {}
+            let filters: Vec<&Expression> = searched.iter().chain(table_access.filters.iter()).collect();
+            scans.push(try!(scan_keys(table, search_keys(&**table, &table_access.search), &filters)));
+        }
+
+        let mut hash_tables: Vec<HashMap<String, Vec<(LiteralValue, PkType)>>> = Vec::new();
+        let mut merges: Vec<Merge> = Vec::new();
+
+        for (table_access, keys) in plan.tables.iter().zip(scans.iter()) {
+            let position = table_access.position;
+            let tables = &input_tables[position..position + 1];
+            let value = |key: &PkType, expr: &Expression| {
+                eval_planned(tables[0].data.get(key).unwrap(), &tables[0].header, tables, expr)
+            };
+            let mut hash_table: HashMap<String, Vec<(LiteralValue, PkType)>> = HashMap::new();
+            let mut merge = Merge { rows: Vec::new(), position: 0, last: None };
+
+            match table_access.access {
+                Access::Scan => {}
+                Access::Hash { ref key, .. } => {
+                    for pk in keys.iter() {
+                        let value = value(pk, key);
+                        let hash = hash_value(&value);
+
+                        if !hash_table.contains_key(&hash) {
+                            hash_table.insert(hash.clone(), Vec::new());
                         }
+                        hash_table.get_mut(&hash).unwrap().push((value, *pk));
                     }
                 }
+                Access::Merge { ref key, .. } => {
+                    merge.rows = keys.iter().map(|pk| (value(pk, key), *pk)).collect();
+                    // Already in order, unless some keys are negative.
+                    merge.rows.as_mut_slice().sort_by(|&(ref a, _), &(ref b, _)| a.compare(b));
+                }
+            }
 
-                let mut input_product = Table::new_result_table(input_header);
+            hash_tables.push(hash_table);
+            merges.push(merge);
+        }
 
-                product(input_tables.clone(), &mut input_product, None);
+        Ok(JoinCursor {
+            plan: plan,
+            row: header.iter().map(|_| LiteralValue::Null).collect(),
+            header: header,
+            offsets: offsets,
+            scans: scans,
+            hash_tables: hash_tables,
+            merges: merges,
+            stack: Vec::new(),
+            started: false,
+            cancel: cancel,
+        })
+    }
 
-                let header = input_product.header.clone();
+    // The next joined row, from the same tables as the ones before, or None
+    // once they've all been made.
+    fn next_row(&mut self, input_tables: &[Arc<Table>]) -> RusqlResult<Option<TableRow>> {
+        if !self.started {
+            self.started = true;
 
-                for constraint in constraints.into_iter() {
-                    match constraint {
-                        JoinConstraint::On(ref expr) => {
-                            input_product.delete_where(|row| {
-                                !ExpressionEvaluator::new(row, &header).with_tables(input_tables.clone())
-                                                                       .eval_bool(expr)
-                            });
-                        }
-                    }
+            if self.plan.tables.is_empty() {
+                // Without a FROM clause there is a single, empty row.
+                return Ok(if self.filters_hold(&self.plan.filters, input_tables) { Some(Vec::new()) } else { None });
+            }
+
+            let keys = self.matching_keys(0, input_tables);
+            self.stack.push((keys, 0));
+        }
+
+        while !self.stack.is_empty() {
+            if let Some(ref mut cancel) = self.cancel {
+                try!(cancel.check());
+            }
+
+            let i = self.stack.len() - 1;
+            let key = {
+                let &mut (ref keys, ref mut tried) = &mut self.stack[i];
+                *tried += 1;
+                keys.as_ref().unwrap_or(&self.scans[i]).get(*tried - 1).map(|key| *key)
+            };
+            let key = match key {
+                Some(key) => key,
+                None => {
+                    self.stack.pop();
+                    continue;
                 }
+            };
+
+            let position = self.plan.tables[i].position;
+            let values = input_tables[position].data.get(&key).unwrap();
+            let offset = self.offsets[position];
+
+            // With no conditions left to check, the row of the last table
+            // goes straight into the joined row, so that each value is only
+            // copied once.
+            if i + 1 == self.plan.tables.len() && self.plan.tables[i].join_filters.is_empty()
+                    && self.plan.filters.is_empty() {
+                let mut row: TableRow = Vec::with_capacity(self.row.len());
+                row.push_all(&self.row[..offset]);
+                row.push_all(values.as_slice());
+                row.push_all(&self.row[offset + values.len()..]);
+                return Ok(Some(row));
+            }
+
+            for (j, value) in values.iter().enumerate() {
+                self.row[offset + j] = value.clone();
+            }
+
+            if !self.filters_hold(&self.plan.tables[i].join_filters, input_tables) {
+                continue;
+            }
 
-                input_product
-            },
+            if i + 1 < self.plan.tables.len() {
+                let keys = self.matching_keys(i + 1, input_tables);
+                self.stack.push((keys, 0));
+            } else if self.filters_hold(&self.plan.filters, input_tables) {
+                return Ok(Some(self.row.clone()));
+            }
         }
-    } else {
-       let mut input_product = Table::new_result_table(input_header);
-       let empty_row: TableRow = Vec::new();
-       input_product.push_row(empty_row);
 
-       input_product
+        Ok(None)
+    }
+
+    // The keys of the rows of the `i`th table of the plan that can go with
+    // the row joined so far, None if they all can.
+    fn matching_keys(&mut self, i: usize, input_tables: &[Arc<Table>]) -> Option<Vec<PkType>> {
+        Some(match self.plan.tables[i].access {
+            Access::Scan => return None,
+            Access::Hash { ref probe, .. } => {
+                let value = eval_planned(&self.row, &self.header, input_tables, probe);
+
+                match self.hash_tables[i].get(&hash_value(&value)) {
+                    Some(entries) => entries.iter().filter(|&&(ref key, _)| key.equals(&value))
+                                                   .map(|&(_, pk)| pk).collect(),
+                    None => Vec::new(),
+                }
+            }
+            Access::Merge { ref probe, .. } => {
+                let value = eval_planned(&self.row, &self.header, input_tables, probe);
+
+                self.merges[i].matching(value)
+            }
+        })
+    }
+
+    fn filters_hold(&self, filters: &Vec<Expression>, input_tables: &[Arc<Table>]) -> bool {
+        filters.iter().all(|expr| holds(&self.row, &self.header, input_tables, expr))
     }
 }
 
-fn filter_inputs(input_product: &mut Table, input_tables: &Vec<&Table>, select_def: &SelectDef) {
-    // https://www.sqlite.org/lang_select.html#whereclause
+// Stops a join once its statement is interrupted, runs past its timeout or
+// its results would go over the memory limit.
+struct Cancel {
+    interrupt: InterruptHandle,
+    deadline: Option<u64>,
+    /// How many times the join was checked on.
+    checks: usize,
+    /// How many more bytes of results fit under the memory limit.
+    memory_left: Option<usize>,
+}
 
-    if let Some(ref expr) = select_def.where_expr {
-        let header = input_product.header.clone();
-        input_product.delete_where(|row| {
-            !ExpressionEvaluator::new(row, &header).with_tables(input_tables.clone())
-                                                   .eval_bool(expr)
-        });
+impl Cancel {
+    fn new(db: &Rusql) -> Cancel {
+        let used = db.memory_used();
+
+        Cancel {
+            interrupt: db.interrupt_handle(),
+            deadline: db.deadline(),
+            checks: 0,
+            memory_left: db.memory_limit().map(|limit| if limit > used { limit - used } else { 0 }),
+        }
+    }
+
+    // Counts `size` more bytes of results against the memory limit.
+    fn reserve(&mut self, size: usize) -> RusqlResult<()> {
+        match self.memory_left {
+            Some(left) if size > left => Err(RusqlError::OutOfMemory),
+            Some(left) => {
+                self.memory_left = Some(left - size);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check(&mut self) -> RusqlResult<()> {
+        if self.interrupt.is_interrupted() {
+            return Err(RusqlError::Interrupted);
+        }
+
+        self.checks += 1;
+        // Reading the clock takes longer than trying a row, so it's only
+        // read every so often.
+        match self.deadline {
+            Some(deadline) if self.checks % 256 == 0 && time::precise_time_ns() > deadline => Err(RusqlError::Timeout),
+            _ => Ok(()),
+        }
     }
 }
 
-fn generate_result_set(input_product: Table, input_tables: &Vec<&Table>, select_def: &SelectDef) -> Table {
+// The keys of a table joined by a merge, sorted by the values of its side of
+// the equality, and how far into them the merge got.
+struct Merge {
+    rows: Vec<(LiteralValue, PkType)>,
+    /// The first row whose value isn't less than the last one looked up.
+    position: usize,
+    last: Option<LiteralValue>,
+}
+
+impl Merge {
+    // The keys of the rows whose value is equal to `value`. Values usually
+    // come in order, so the rows are only walked once, but a smaller one
+    // starts over.
+    fn matching(&mut self, value: LiteralValue) -> Vec<PkType> {
+        if self.last.as_ref().map_or(false, |last| value.compare(last) == Ordering::Less) {
+            self.position = 0;
+        }
+
+        while self.position < self.rows.len() && self.rows[self.position].0.compare(&value) == Ordering::Less {
+            self.position += 1;
+        }
+
+        let keys = self.rows.iter().skip(self.position).take_while(|&&(ref key, _)| key.equals(&value))
+                                   .map(|&(_, pk)| pk).collect();
+        self.last = Some(value);
+        keys
+    }
+}
+
+// The keys of the rows of `table` a search finds, in order, or all of them
+// without one. They still have to be checked against the filters it was
+// made from.
+fn search_keys(table: &Table, search: &Option<Search>) -> Vec<PkType> {
+    match *search {
+        Some(Search::Index { column, ref value, .. }) if table.index_on(column).is_some() => {
+            let mut keys = table.index_on(column).unwrap().lookup_equal(&eval_constant(value));
+            keys.sort();
+            keys
+        }
+        // Negative keys are stored after the others, so the keys are only in
+        // the order of their values without them.
+        Some(Search::PrimaryKey { ref lower, ref upper })
+                if table.data.last_key().map_or(true, |key| key <= isize::MAX as PkType) => {
+            let lower = lower.as_ref().map(|&(ref expr, inclusive)| (eval_constant(expr), inclusive));
+            let upper = upper.as_ref().map(|&(ref expr, inclusive)| (eval_constant(expr), inclusive));
+
+            if let (&Some((ref value, true)), &Some((LiteralValue::Integer(i), true))) = (&lower, &upper) {
+                if value == &LiteralValue::Integer(i) {
+                    return if table.has_row(i as PkType) { vec![i as PkType] } else { Vec::new() };
+                }
+            }
+
+            let within = |key: PkType, bound: &Option<(LiteralValue, bool)>, past: Ordering| match *bound {
+                Some((ref value, inclusive)) => match LiteralValue::Integer(key as isize).compare(value) {
+                    Ordering::Equal => inclusive,
+                    ordering => ordering != past,
+                },
+                None => true,
+            };
+
+            let from = bound_key(&lower, 0);
+            let to = bound_key(&upper, table.data.last_key().unwrap_or(0));
+            if from > to {
+                return Vec::new();
+            }
+
+            table.data.range(from, to).map(|(key, _)| key)
+                                      .skip_while(|&key| !within(key, &lower, Ordering::Less))
+                                      .take_while(|&key| within(key, &upper, Ordering::Greater))
+                                      .collect()
+        }
+        Some(Search::Columns(ref comparisons)) if table.columns.is_some() => {
+            let comparisons = comparisons.iter().map(|&(column, op, ref value)| (column, op, eval_constant(value)))
+                                                .collect();
+            table.columns.as_ref().unwrap().select(&comparisons)
+        }
+        _ => table.data.keys().collect(),
+    }
+}
+
+// The key a range of primary keys starts or stops at for a numeric bound,
+// rounded towards 0 so `within` has the last word about the keys at either
+// end; `default` for other bounds.
+fn bound_key(bound: &Option<(LiteralValue, bool)>, default: PkType) -> PkType {
+    match *bound {
+        Some((ref value @ LiteralValue::Integer(..), _)) | Some((ref value @ LiteralValue::Real(..), _))
+                | Some((ref value @ LiteralValue::Decimal(..), _)) | Some((ref value @ LiteralValue::Boolean(..), _)) => {
+            let real = value.to_real();
+            if !(real > 0.0) {
+                0
+            } else if real >= isize::MAX as f64 {
+                isize::MAX as PkType
+            } else {
+                real as PkType
+            }
+        }
+        _ => default,
+    }
+}
+
+// What a value is put in a hash table under: values that are equal, like 1
+// and 1.0, always get the same one.
+fn hash_value(value: &LiteralValue) -> String {
+    match value {
+        &LiteralValue::Integer(..) | &LiteralValue::Real(..) | &LiteralValue::Boolean(..)
+            | &LiteralValue::Decimal(..) => {
+            let real = value.to_real();
+            // So that 0.0 and -0.0 agree.
+            format!("{}", if real == 0.0 { 0.0 } else { real })
+        }
+        _ => value.to_sql(),
+    }
+}
+
+// The value of an expression from the plan for `row`.
+fn eval_planned(row: &TableRow, header: &TableHeader, tables: &[Arc<Table>], expr: &Expression) -> LiteralValue {
+    result_to_literal(ExpressionEvaluator::new(row, header).with_tables(tables)
+                                                           .ordered()
+                                                           .eval_expr(expr))
+}
+
+// The keys among `keys` of the rows of `table` for which every filter holds.
+fn filter_keys(table: &Arc<Table>, keys: Vec<PkType>, filters: &Vec<&Expression>) -> Vec<PkType> {
+    let tables = [table.clone()];
+
+    keys.into_iter().filter(|key| {
+        let row = table.data.get(key).unwrap();
+        filters.iter().all(|expr| holds(row, &table.header, &tables, *expr))
+    }).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn scan_keys(table: &Arc<Table>, keys: Vec<PkType>, filters: &Vec<&Expression>) -> RusqlResult<Vec<PkType>> {
+    Ok(filter_keys(table, keys, filters))
+}
+
+// Like filter_keys, but the rows of large tables are split between threads.
+#[cfg(feature = "parallel")]
+fn scan_keys(table: &Arc<Table>, keys: Vec<PkType>, filters: &Vec<&Expression>) -> RusqlResult<Vec<PkType>> {
+    if filters.is_empty() || keys.len() < parallel::MIN_ROWS {
+        return Ok(filter_keys(table, keys, filters));
+    }
+
+    parallel::map_chunks(keys.as_slice(), |chunk| filter_keys(table, chunk.to_vec(), filters))
+}
+
+// Whether a condition from the plan holds for `row`, true like an operand of
+// AND.
+fn holds(row: &TableRow, header: &TableHeader, tables: &[Arc<Table>], expr: &Expression) -> bool {
+    eval_planned(row, header, tables, expr).to_bool()
+}
+
+fn generate_result_set(mut join: JoinCursor, input_tables: &[Arc<Table>], select_def: &SelectDef)
+                       -> RusqlResult<Table> {
     // https://www.sqlite.org/lang_select.html#resultset
     let results_header: TableHeader = Vec::new();
     let mut results_table = Table::new_result_table(results_header);
     let mut rows: Vec<TableRow> = Vec::new();
 
-    for row in input_product.data.values() {
+    // Without ORDER BY the rows come in the order they're returned, so the
+    // join stops as soon as LIMIT has enough of them.
+    let (mut offset, mut remaining) = match (&select_def.ordering_terms, &select_def.limit) {
+        (&None, &Some(ref limit_def)) => limit_bounds(limit_def),
+        _ => (0, None),
+    };
+
+    while remaining != Some(0) {
+        let row = match try!(join.next_row(input_tables)) {
+            Some(row) => row,
+            None => break,
+        };
+
+        if offset > 0 {
+            offset -= 1;
+            continue;
+        }
+
+        if let Some(ref mut remaining) = remaining {
+            *remaining -= 1;
+        }
+
+        // The rows are kept until the result set is complete.
+        if let Some(ref mut cancel) = join.cancel {
+            try!(cancel.reserve(row_size(&row)));
+        }
+
         match select_def.result_column {
-            ResultColumn::Expressions(ref exprs) => generate_row_from_expressions(&mut results_table, row, exprs, input_tables),
+            ResultColumn::Expressions(ref exprs) => generate_row_from_expressions(&mut results_table, &row, exprs, input_tables),
             ResultColumn::Asterisk => {
                 if results_table.header.len() == 0 {
-                    results_table.header = input_product.header.clone();
+                    results_table.header = join.header.clone();
                 }
-                rows.push(row.clone());
+                rows.push(row);
             }
         }
     }
@@ -282,30 +1680,46 @@ fn generate_result_set(input_product: Table, input_tables: &Vec<&Table>, select_
             });
         }
     }
-    results_table.insert(rows, &None);
+    results_table.insert(rows, &None).ok();
+
+    if let (&Some(_), &Some(ref limit_def)) = (&select_def.ordering_terms, &select_def.limit) {
+        let (offset, limit) = limit_bounds(limit_def);
+        let keys: Vec<PkType> = results_table.data.keys().collect();
+        let limit = limit.unwrap_or(keys.len());
+
+        for (i, key) in keys.into_iter().enumerate() {
+            if i < offset || i - offset >= limit {
+                results_table.remove_row(key);
+            }
+        }
+    }
 
-    results_table
+    Ok(results_table)
 }
 
-fn generate_row_from_expressions(results_table: &mut Table, row: &TableRow, exprs: &Vec<Expression>, input_tables: &Vec<&Table>) {
+fn generate_row_from_expressions(results_table: &mut Table, row: &TableRow, exprs: &Vec<Expression>, input_tables: &[Arc<Table>]) {
     let mut new_row: TableRow = Vec::new();
     let push_header = if results_table.header.len() == 0 { true } else { false };
 
     for expr in exprs.iter() {
         if push_header {
-            match ExpressionEvaluator::new(row, &results_table.header).with_tables(input_tables.clone())
+            match ExpressionEvaluator::new(row, &results_table.header).with_tables(input_tables)
                                                                       .with_column_def()
                                                                       .eval_expr(expr) {
                 ExpressionResult::ColumnDef(def) => results_table.header.push(def.clone()),
-                _ => {}, // FIXME No idea
+                _ => results_table.header.push(ColumnDef {
+                    name: format!("{}", expr),
+                    column_type: None,
+                    column_constraints: Vec::new(),
+                }),
             }
         }
-        match ExpressionEvaluator::new(row, &results_table.header).with_tables(input_tables.clone())
+        match ExpressionEvaluator::new(row, &results_table.header).with_tables(input_tables)
                                                                   .eval_expr(expr) {
             ExpressionResult::Value(v) => new_row.push(v),
             _ => {}, // FIXME No idea
         }
     }
 
-    results_table.push_row(new_row);
+    results_table.push_row(new_row).ok();
 }
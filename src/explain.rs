@@ -0,0 +1,172 @@
+//! EXPLAIN, which describes what a statement does instead of running it: a
+//! row for each step, with what the step works on. EXPLAIN QUERY PLAN shows
+//! the planner's decisions instead.
+
+use definitions::{RusqlStatement, SelectDef, FromClause, JoinOperator, JoinConstraint};
+use definitions::{ResultColumn, OrderingTerm, LimitDef, Expression, Order};
+use definitions::{InsertDataSource, UpsertAction, ConflictResolution, LiteralValue};
+use error::RusqlResult;
+use exec::query_plan;
+use planner;
+use rusql::Rusql;
+use table::{Table, TableRow};
+
+use std::sync::Arc;
+
+pub fn explain(stmt: &RusqlStatement) -> Table {
+    let mut steps: Vec<(&'static str, String)> = Vec::new();
+
+    match stmt {
+        &RusqlStatement::Delete(ref def) => {
+            steps.push(("SCAN", def.name.clone()));
+            filter_steps(&mut steps, &def.where_expr, &def.ordering_terms, &def.limit);
+            steps.push(("DELETE", def.name.clone()));
+            result_steps(&mut steps, &def.returning);
+        }
+        &RusqlStatement::Insert(ref def) => {
+            match def.data_source {
+                InsertDataSource::Values(ref rows) => steps.push(("VALUES", format!("{} rows", rows.len()))),
+                InsertDataSource::Select(ref select_def) => select_steps(&mut steps, select_def),
+                InsertDataSource::DefaultValues => steps.push(("VALUES", "DEFAULT VALUES".to_string())),
+                InsertDataSource::Error => {}
+            }
+
+            let target = match def.column_names {
+                Some(ref names) => format!("{}({})", def.table_name, names.connect(", ")),
+                None => def.table_name.clone(),
+            };
+            steps.push(match def.on_conflict {
+                ConflictResolution::Abort => ("INSERT", target),
+                ConflictResolution::Fail => ("INSERT OR FAIL", target),
+                ConflictResolution::Ignore => ("INSERT OR IGNORE", target),
+                ConflictResolution::Replace => ("INSERT OR REPLACE", target),
+                ConflictResolution::Rollback => ("INSERT OR ROLLBACK", target),
+            });
+
+            if let Some(ref upsert_def) = def.upsert {
+                match upsert_def.action {
+                    UpsertAction::Nothing => steps.push(("ON CONFLICT", "DO NOTHING".to_string())),
+                    UpsertAction::Update(ref set, ref where_expr) => {
+                        set_steps(&mut steps, set);
+                        if let &Some(ref expr) = where_expr {
+                            steps.push(("FILTER", format!("{}", expr)));
+                        }
+                    }
+                }
+            }
+            result_steps(&mut steps, &def.returning);
+        }
+        &RusqlStatement::Select(ref def) => select_steps(&mut steps, def),
+        &RusqlStatement::Update(ref def) => {
+            steps.push(("SCAN", def.name.clone()));
+            filter_steps(&mut steps, &def.where_expr, &def.ordering_terms, &def.limit);
+            set_steps(&mut steps, &def.set);
+            steps.push(("UPDATE", def.name.clone()));
+            result_steps(&mut steps, &def.returning);
+        }
+        _ => steps.push(("EXECUTE", format!("{:?}", stmt))),
+    }
+
+    let rows: Vec<TableRow> = steps.into_iter().enumerate().map(|(i, (operation, detail))| {
+        vec![LiteralValue::Integer(i as isize), LiteralValue::text(operation), LiteralValue::Text(Arc::new(detail))]
+    }).collect();
+
+    Table::from_rows(&["id", "operation", "detail"], rows)
+}
+
+pub fn explain_query_plan(db: &mut Rusql, stmt: &RusqlStatement) -> RusqlResult<Table> {
+    let plan = match stmt {
+        &RusqlStatement::Delete(ref def) => {
+            Some(planner::plan_write(try!(db.get_table(&def.name)), &def.where_expr, def.ordering_terms.is_some(),
+                                     db.stats.get(&def.name)))
+        }
+        &RusqlStatement::Insert(ref def) => match def.data_source {
+            InsertDataSource::Select(ref select_def) => Some(try!(query_plan(db, select_def))),
+            _ => None,
+        },
+        &RusqlStatement::Select(ref def) => Some(try!(query_plan(db, def))),
+        &RusqlStatement::Update(ref def) => {
+            Some(planner::plan_write(try!(db.get_table(&def.name)), &def.where_expr, def.ordering_terms.is_some(),
+                                     db.stats.get(&def.name)))
+        }
+        _ => None,
+    };
+    let lines = plan.map_or(Vec::new(), |plan| plan.describe());
+
+    let rows: Vec<TableRow> = lines.into_iter().enumerate().map(|(i, detail)| {
+        vec![LiteralValue::Integer(i as isize), LiteralValue::Text(Arc::new(detail))]
+    }).collect();
+
+    Ok(Table::from_rows(&["id", "detail"], rows))
+}
+
+fn select_steps(steps: &mut Vec<(&'static str, String)>, select_def: &SelectDef) {
+    match select_def.from_clause {
+        Some(FromClause::TableOrSubquery(ref names)) => {
+            for name in names.iter() {
+                steps.push(("SCAN", name.clone()));
+            }
+        }
+        Some(FromClause::JoinClause(ref name, ref join_clauses)) => {
+            steps.push(("SCAN", name.clone()));
+
+            if let &Some(ref join_clauses) = join_clauses {
+                for &(join_operator, ref name, ref constraint) in join_clauses.iter() {
+                    let operation = match join_operator {
+                        JoinOperator::Inner => "JOIN",
+                        JoinOperator::Natural => "NATURAL JOIN",
+                    };
+                    steps.push(match constraint {
+                        &Some(JoinConstraint::On(ref expr)) => (operation, format!("{} ON {}", name, expr)),
+                        &None => (operation, name.clone()),
+                    });
+                }
+            }
+        }
+        None => {}
+    }
+
+    filter_steps(steps, &select_def.where_expr, &select_def.ordering_terms, &select_def.limit);
+    result_steps(steps, &Some(select_def.result_column.clone()));
+}
+
+fn filter_steps(steps: &mut Vec<(&'static str, String)>, where_expr: &Option<Expression>,
+                ordering_terms: &Option<Vec<OrderingTerm>>, limit: &Option<LimitDef>) {
+    if let &Some(ref expr) = where_expr {
+        steps.push(("FILTER", format!("{}", expr)));
+    }
+
+    if let &Some(ref terms) = ordering_terms {
+        for term in terms.iter() {
+            steps.push(match term.order {
+                Order::Ascending => ("SORT", format!("{}", term.expr)),
+                Order::Descending => ("SORT", format!("{} DESC", term.expr)),
+            });
+        }
+    }
+
+    if let &Some(ref limit_def) = limit {
+        steps.push(match limit_def.offset {
+            Some(ref offset) => ("LIMIT", format!("{} OFFSET {}", limit_def.limit, offset)),
+            None => ("LIMIT", format!("{}", limit_def.limit)),
+        });
+    }
+}
+
+fn set_steps(steps: &mut Vec<(&'static str, String)>, set: &Vec<(String, Expression)>) {
+    for &(ref name, ref expr) in set.iter() {
+        steps.push(("SET", format!("{} = {}", name, expr)));
+    }
+}
+
+fn result_steps(steps: &mut Vec<(&'static str, String)>, result_column: &Option<ResultColumn>) {
+    match result_column {
+        &Some(ResultColumn::Expressions(ref exprs)) => {
+            for expr in exprs.iter() {
+                steps.push(("RESULT", format!("{}", expr)));
+            }
+        }
+        &Some(ResultColumn::Asterisk) => steps.push(("RESULT", "*".to_string())),
+        &None => {}
+    }
+}
@@ -1,7 +1,10 @@
 use definitions::{Expression, LiteralValue, BinaryOperator, UnaryOperator, ColumnDef};
+use error::{RusqlError, RusqlResult};
 use table::{Table, TableRow, TableHeader, get_column};
 
+use std::ascii::AsciiExt;
 use std::cell::Cell;
+use std::sync::Arc;
 
 #[derive(PartialEq, Clone)]
 pub enum ExpressionResult {
@@ -23,7 +26,7 @@ pub struct ExpressionEvaluator<'a, 'b> {
     // FIXME wtf am I doing?!?!?!
     row: &'a TableRow,
     head: &'a TableHeader,
-    tables: Option<Vec<&'b Table>>,
+    tables: Option<&'b [Arc<Table>]>,
     get_column_def: bool,
     as_column_alias: bool,
     order_pass: Cell<bool>,
@@ -46,7 +49,7 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
         self
     }
 
-    pub fn with_tables(&'a mut self, tables: Vec<&'b Table>) -> &mut ExpressionEvaluator<'a, 'b> {
+    pub fn with_tables(&'a mut self, tables: &'b [Arc<Table>]) -> &mut ExpressionEvaluator<'a, 'b> {
         self.tables = Some(tables);
         self
     }
@@ -56,6 +59,13 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
         self
     }
 
+    /// For expressions that have already been through order_of_operations,
+    /// like the ones conjuncts returns.
+    pub fn ordered(&'a mut self) -> &mut ExpressionEvaluator<'a, 'b> {
+        self.order_pass.set(true);
+        self
+    }
+
     pub fn order_of_operations(&'a self, expr: &Expression) -> Expression {
         let (b1, left1, right1) = expr.unwrap_binary_operator();
         let (b2, left2, right2) = right1.unwrap_binary_operator();
@@ -63,15 +73,15 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
         if b1.ord_val() < b2.ord_val() {
             let right2 = self.order_of_operations(&right2);
 
-            let new_expr_child = Expression::BinaryOperator((b1, box left2, box left1));
-            let new_expr_parent = Expression::BinaryOperator((b2, box right2, box new_expr_child));
+            let new_expr_child = Expression::BinaryOperator((b1, box left1, box left2));
+            let new_expr_parent = Expression::BinaryOperator((b2, box new_expr_child, box right2));
 
             return new_expr_parent;
         } else if b1.ord_val() == b2.ord_val() && b1 > b2{
             let right2 = self.order_of_operations(&right2);
 
             let new_expr_child = Expression::BinaryOperator((b1, box left1, box left2));
-            let new_expr_parent = Expression::BinaryOperator((b2, box right2, box new_expr_child));
+            let new_expr_parent = Expression::BinaryOperator((b2, box new_expr_child, box right2));
 
             return new_expr_parent;
         } else if b2 != BinaryOperator::Null {
@@ -102,6 +112,7 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
                 }
             }
             &Expression::UnaryOperator((u, ref exp)) => self.eval_unary_operator(u, &**exp),
+            &Expression::Function((ref name, ref args)) => self.eval_function(name, args),
             _ => ExpressionResult::Null,
         }
     }
@@ -164,10 +175,36 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
                 ExpressionResult::Value(left | right)
             }
             BinaryOperator::Equals => {
-                ExpressionResult::Value(LiteralValue::Boolean(self.eval_expr(expr1) == self.eval_expr(expr2)))
+                let left = result_to_literal(self.eval_expr(expr1));
+                let right = result_to_literal(self.eval_expr(expr2));
+                ExpressionResult::Value(LiteralValue::Boolean(left.equals(&right)))
             }
             BinaryOperator::NotEquals => {
-                ExpressionResult::Value(LiteralValue::Boolean(self.eval_expr(expr1) != self.eval_expr(expr2)))
+                let left = result_to_literal(self.eval_expr(expr1));
+                let right = result_to_literal(self.eval_expr(expr2));
+                ExpressionResult::Value(LiteralValue::Boolean(!left.equals(&right)))
+            }
+            BinaryOperator::Is => {
+                let left = result_to_literal(self.eval_expr(expr1));
+                let right = result_to_literal(self.eval_expr(expr2));
+                ExpressionResult::Value(LiteralValue::Boolean(left == right))
+            }
+            BinaryOperator::IsNot => {
+                let left = result_to_literal(self.eval_expr(expr1));
+                let right = result_to_literal(self.eval_expr(expr2));
+                ExpressionResult::Value(LiteralValue::Boolean(left != right))
+            }
+            BinaryOperator::Like | BinaryOperator::CaseSensitiveLike => {
+                let value = result_to_literal(self.eval_expr(expr1));
+                let pattern = result_to_literal(self.eval_expr(expr2));
+
+                if value.is_null() || pattern.is_null() {
+                    return ExpressionResult::Null;
+                }
+
+                let case_sensitive = operator == BinaryOperator::CaseSensitiveLike;
+                let matched = like(format!("{}", pattern).as_slice(), format!("{}", value).as_slice(), case_sensitive);
+                ExpressionResult::Value(LiteralValue::Boolean(matched))
             }
             BinaryOperator::And => {
                 let left = result_to_literal(self.eval_expr(expr1));
@@ -229,15 +266,37 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
         }
     }
 
+    // https://www.sqlite.org/lang_corefunc.html
+    fn eval_function(&'a self, name: &String, args: &Vec<Expression>) -> ExpressionResult {
+        let args: Vec<LiteralValue> = args.iter().map(|arg| result_to_literal(self.eval_expr(arg))).collect();
+
+        let value = match (name.as_slice().to_ascii_lowercase().as_slice(), args.as_slice()) {
+            ("lower", [LiteralValue::Text(ref t)]) => LiteralValue::Text(Arc::new(t.as_slice().to_ascii_lowercase())),
+            ("upper", [LiteralValue::Text(ref t)]) => LiteralValue::Text(Arc::new(t.as_slice().to_ascii_uppercase())),
+            ("length", [LiteralValue::Text(ref t)]) => LiteralValue::Integer(t.chars().count() as isize),
+            ("length", [LiteralValue::Blob(ref bytes)]) => LiteralValue::Integer(bytes.len() as isize),
+            ("length", [LiteralValue::Null]) => LiteralValue::Null,
+            ("length", [ref value]) => LiteralValue::Integer(format!("{}", value).len() as isize),
+            ("abs", [LiteralValue::Integer(i)]) => LiteralValue::Integer(i.abs()),
+            ("abs", [LiteralValue::Real(r)]) => LiteralValue::Real(r.abs()),
+            // e.g. lower(NULL) or upper(26)
+            ("lower", [ref value]) | ("upper", [ref value]) | ("abs", [ref value]) => value.clone(),
+            // Can't happen, statements go through check_function first.
+            _ => LiteralValue::Null,
+        };
+
+        ExpressionResult::Value(value)
+    }
+
     fn eval_column_name(&'a self, expr: &Expression, table: Option<&Table>, offset: Option<usize>) -> ExpressionResult {
         match expr {
             &Expression::TableName((ref name, ref expr)) => {
                 let mut table_opt: Option<&Table> = None;
                 let mut offset = 0us;
 
-                for table in self.tables.clone().unwrap().into_iter() {
+                for table in self.tables.unwrap().iter() {
                     if &table.name == name {
-                        table_opt = Some(table);
+                        table_opt = Some(&**table);
                         break;
                     }
                     offset = offset + table.header.len();
@@ -263,7 +322,7 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
             } else {
                 // FIXME what if there are _other_ columns with the same name
                 // further down?
-                if let Some(ref tables) = self.tables {
+                if let Some(tables) = self.tables {
                     for table in tables.iter() {
                         if let Some(column_def) = table.get_column_def_by_name(name) {
                             return ExpressionResult::ColumnDef(column_def.clone());
@@ -279,7 +338,7 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
             }
             if let Some(table) = table {
                 return ExpressionResult::Value(get_column(name, self.row, &table.header, offset));
-            } else if let Some(ref tables) = self.tables {
+            } else if let Some(tables) = self.tables {
                 let mut offset = 0us;
                 for table in tables.iter() {
                     if let Some(_) = table.get_column_def_by_name(name) {
@@ -304,11 +363,27 @@ impl<'a, 'b> ExpressionEvaluator<'a, 'b> {
             }
             &Expression::BinaryOperator((b, ref expr1, ref expr2)) => Expression::BinaryOperator((b.neg(), box self.neg(&**expr1), box self.neg(&**expr2))),
             &Expression::UnaryOperator((u, ref expr)) => Expression::UnaryOperator((u.neg(), expr.clone())),
+            &Expression::Function(..) => Expression::UnaryOperator((UnaryOperator::Minus, box expr.clone())),
             _ => expr.clone()
         }
     }
 }
 
+/// Checks that `name` is a function that takes `args` arguments.
+pub fn check_function(name: &String, args: usize) -> RusqlResult<()> {
+    let arity = match name.as_slice().to_ascii_lowercase().as_slice() {
+        "lower" | "upper" | "length" | "abs" => 1,
+        "last_insert_rowid" => 0,
+        _ => return Err(RusqlError::NoSuchFunction(name.clone())),
+    };
+
+    if args == arity {
+        Ok(())
+    } else {
+        Err(RusqlError::WrongArgumentCount(name.clone()))
+    }
+}
+
 pub fn result_to_literal(result: ExpressionResult) -> LiteralValue {
     match result {
         ExpressionResult::Value(v) => v,
@@ -322,3 +397,277 @@ pub fn expr_to_literal(expr: &Expression) -> LiteralValue {
         _ => LiteralValue::Null,
     }
 }
+
+/// Replaces the references to the OLD and NEW rows of a trigger with their
+/// values.
+pub fn bind_trigger_rows(expr: &mut Expression, header: &TableHeader,
+                         old_row: &Option<TableRow>, new_row: &Option<TableRow>) {
+    if let &Some(ref row) = old_row {
+        bind_row(expr, "OLD", header, row);
+    }
+    if let &Some(ref row) = new_row {
+        bind_row(expr, "NEW", header, row);
+    }
+}
+
+/// Replaces the references to the columns of a row going by a name that isn't
+/// a table, like NEW in a trigger or excluded in an upsert, with its values.
+pub fn bind_row(expr: &mut Expression, row_name: &str, header: &TableHeader, row: &TableRow) {
+    let value = match *expr {
+        Expression::TableName((ref qualifier, ref column)) => {
+            if qualifier.as_slice().to_ascii_uppercase() != row_name.to_ascii_uppercase() {
+                return;
+            }
+
+            match &**column {
+                &Expression::ColumnName(ref name) => {
+                    header.iter().position(|def| &def.name == name).map(|i| row[i].clone())
+                }
+                _ => None,
+            }
+        }
+        Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+            bind_row(&mut **left, row_name, header, row);
+            bind_row(&mut **right, row_name, header, row);
+            None
+        }
+        Expression::UnaryOperator((_, ref mut expr)) => {
+            bind_row(&mut **expr, row_name, header, row);
+            None
+        }
+        Expression::Function((_, ref mut args)) => {
+            for arg in args.iter_mut() {
+                bind_row(arg, row_name, header, row);
+            }
+            None
+        }
+        _ => None,
+    };
+
+    if let Some(value) = value {
+        *expr = Expression::LiteralValue(value);
+    }
+}
+
+/// Replaces the calls to a function without arguments with a value.
+pub fn bind_function(expr: &mut Expression, function_name: &str, value: &LiteralValue) {
+    let is_call = match *expr {
+        Expression::Function((ref name, ref args)) => {
+            args.is_empty() && name.as_slice().to_ascii_lowercase().as_slice() == function_name
+        }
+        _ => false,
+    };
+
+    if is_call {
+        *expr = Expression::LiteralValue(value.clone());
+        return;
+    }
+
+    match *expr {
+        Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+            bind_function(&mut **left, function_name, value);
+            bind_function(&mut **right, function_name, value);
+        }
+        Expression::UnaryOperator((_, ref mut expr)) => bind_function(&mut **expr, function_name, value),
+        Expression::Function((_, ref mut args)) => {
+            for arg in args.iter_mut() {
+                bind_function(arg, function_name, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Makes the LIKEs in `expr` case sensitive.
+pub fn bind_case_sensitive_like(expr: &mut Expression) {
+    match *expr {
+        Expression::BinaryOperator((ref mut b, ref mut left, ref mut right)) => {
+            if *b == BinaryOperator::Like {
+                *b = BinaryOperator::CaseSensitiveLike;
+            }
+            bind_case_sensitive_like(&mut **left);
+            bind_case_sensitive_like(&mut **right);
+        }
+        Expression::UnaryOperator((_, ref mut expr)) => bind_case_sensitive_like(&mut **expr),
+        Expression::Function((_, ref mut args)) => {
+            for arg in args.iter_mut() {
+                bind_case_sensitive_like(arg);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `value` matches a LIKE `pattern`, where % matches any number of
+/// characters and _ any single one.
+pub fn like(pattern: &str, value: &str, case_sensitive: bool) -> bool {
+    let fold = |c: char| if case_sensitive { c } else { c.to_lowercase() };
+    let pattern: Vec<char> = pattern.chars().map(|c| fold(c)).collect();
+    let value: Vec<char> = value.chars().map(|c| fold(c)).collect();
+
+    like_chars(pattern.as_slice(), value.as_slice())
+}
+
+fn like_chars(pattern: &[char], value: &[char]) -> bool {
+    match pattern.first() {
+        None => value.is_empty(),
+        Some(&'%') => range(0, value.len() + 1).any(|i| like_chars(pattern.slice_from(1), value.slice_from(i))),
+        Some(&'_') => !value.is_empty() && like_chars(pattern.slice_from(1), value.slice_from(1)),
+        Some(c) => value.first() == Some(c) && like_chars(pattern.slice_from(1), value.slice_from(1)),
+    }
+}
+
+/// Replaces the parameter called `name` with `value`.
+pub fn bind_parameter(expr: &mut Expression, name: &str, value: &LiteralValue) {
+    let is_parameter = match *expr {
+        Expression::Parameter(ref parameter) => parameter.as_slice() == name,
+        _ => false,
+    };
+
+    if is_parameter {
+        *expr = Expression::LiteralValue(value.clone());
+        return;
+    }
+
+    match *expr {
+        Expression::TableName((_, ref mut expr)) => bind_parameter(&mut **expr, name, value),
+        Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+            bind_parameter(&mut **left, name, value);
+            bind_parameter(&mut **right, name, value);
+        }
+        Expression::UnaryOperator((_, ref mut expr)) => bind_parameter(&mut **expr, name, value),
+        Expression::Function((_, ref mut args)) => {
+            for arg in args.iter_mut() {
+                bind_parameter(arg, name, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Gives the parameters of `expr` their indexes the way SQLite does, adding
+/// the name of every new one to `names`, where parameter N is at N - 1. A
+/// plain `?` is the one after the largest index so far, and is renamed to
+/// `?N`, as is any `?N` with the name of parameter N.
+pub fn number_parameters(expr: &mut Expression, names: &mut Vec<String>) {
+    match *expr {
+        Expression::Parameter(ref mut name) => {
+            if name.as_slice() == "?" {
+                *name = format!("?{}", names.len() + 1);
+                names.push(name.clone());
+            } else if name.as_slice().starts_with("?") {
+                if let Some(index) = name.as_slice().slice_from(1).parse::<usize>() {
+                    while names.len() < index {
+                        let next = format!("?{}", names.len() + 1);
+                        names.push(next);
+                    }
+                    if index > 0 {
+                        *name = names[index - 1].clone();
+                    }
+                }
+            } else if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        Expression::TableName((_, ref mut expr)) => number_parameters(&mut **expr, names),
+        Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+            number_parameters(&mut **left, names);
+            number_parameters(&mut **right, names);
+        }
+        Expression::UnaryOperator((_, ref mut expr)) => number_parameters(&mut **expr, names),
+        Expression::Function((_, ref mut args)) => {
+            for arg in args.iter_mut() {
+                number_parameters(arg, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Evaluates an expression that doesn't refer to any row.
+pub fn eval_constant(expr: &Expression) -> LiteralValue {
+    let empty_row: TableRow = Vec::new();
+    let empty_header: TableHeader = Vec::new();
+
+    result_to_literal(ExpressionEvaluator::new(&empty_row, &empty_header).eval_expr(expr))
+}
+
+/// Whether `expr` being true guarantees that `other` is true as well. Only
+/// the simple cases are caught: `other` being one of the terms ANDed together
+/// in `expr`, or `x IS NOT NULL` when a term compares x to a value, which
+/// can't be true for a NULL x.
+pub fn implies(expr: &Expression, other: &Expression) -> bool {
+    let empty_row: TableRow = Vec::new();
+    let empty_header: TableHeader = Vec::new();
+    let evaluator = ExpressionEvaluator::new(&empty_row, &empty_header);
+
+    let expr = evaluator.order_of_operations(expr);
+    let other = evaluator.order_of_operations(other);
+
+    terms(&expr).iter().any(|term| {
+        if *term == &other {
+            return true;
+        }
+
+        match (*term, &other) {
+            (&Expression::BinaryOperator((b, ref left, ref right)),
+             &Expression::BinaryOperator((BinaryOperator::IsNot, ref column, ref null)))
+                    if **null == Expression::LiteralValue(LiteralValue::Null) && b.is_comparison() => {
+                let is_value = |e: &Expression| match e {
+                    &Expression::LiteralValue(ref value) => !value.is_null(),
+                    _ => false,
+                };
+
+                (left == column && is_value(&**right)) || (right == column && is_value(&**left))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// The conditions ANDed together in `expr`, each of which has to be true for
+/// it to be, ready to be checked one at a time by an evaluator that's
+/// `ordered`.
+pub fn conjuncts(expr: &Expression) -> Vec<Expression> {
+    let empty_row: TableRow = Vec::new();
+    let empty_header: TableHeader = Vec::new();
+    let evaluator = ExpressionEvaluator::new(&empty_row, &empty_header);
+    let mut ordered = false;
+
+    let expr = in_order(&evaluator, expr, &mut ordered);
+
+    terms(&expr).into_iter().map(|term| term.clone()).collect()
+}
+
+// The expression as eval_expr sees it: the first binary operator it comes
+// to is put through order_of_operations, and nothing after that.
+fn in_order<'a, 'b>(evaluator: &'a ExpressionEvaluator<'a, 'b>, expr: &Expression, ordered: &mut bool) -> Expression {
+    if *ordered {
+        return expr.clone();
+    }
+
+    match expr {
+        &Expression::BinaryOperator(..) => {
+            *ordered = true;
+            evaluator.order_of_operations(expr)
+        }
+        &Expression::UnaryOperator((u, ref expr)) => {
+            Expression::UnaryOperator((u, box in_order(evaluator, &**expr, ordered)))
+        }
+        &Expression::Function((ref name, ref args)) => {
+            Expression::Function((name.clone(), args.iter().map(|arg| in_order(evaluator, arg, ordered)).collect()))
+        }
+        _ => expr.clone(),
+    }
+}
+
+fn terms(expr: &Expression) -> Vec<&Expression> {
+    match expr {
+        &Expression::BinaryOperator((BinaryOperator::And, ref left, ref right)) => {
+            let mut terms = self::terms(&**left);
+            terms.push_all(self::terms(&**right).as_slice());
+            terms
+        }
+        _ => vec![expr],
+    }
+}
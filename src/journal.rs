@@ -0,0 +1,116 @@
+//! The rollback journal of a paged file not in WAL mode, `<path>-journal`.
+//! The pages of a B-tree are never written over, see pager, but the header
+//! is: before a commit writes it, the header as it was goes to the journal,
+//! which is deleted once the new one reached the disk. Opening a file with
+//! a whole journal writes the pages it saved back first, so a crash during
+//! a commit leaves the file as it was after the last one. A journal that
+//! isn't whole is from a crash before anything was written over, and is
+//! ignored.
+//!
+//! The journal is the pages saved, each as its number and contents, then a
+//! checksum of them. Numbers are big-endian.
+
+use error::{RusqlError, RusqlResult};
+use logfile::checksum;
+use pager::{PageNo, be_bytes, be_uint};
+
+use std::io::{BufferedWriter, File, IoResult, SeekStyle};
+use std::io::fs::{self, PathExtensions};
+
+pub const MAGIC: &'static [u8] = b"rusql journal\0";
+pub const VERSION: u32 = 1;
+
+/// Where the journal of the paged file at `path` is.
+pub fn journal_path(path: &Path) -> Path {
+    Path::new(format!("{}-journal", path.display()))
+}
+
+/// Saves `pages`, as the file has them, to the journal at `path` and waits
+/// for it to reach the disk.
+pub fn write(path: &Path, pages: &[(PageNo, Vec<u8>)]) -> IoResult<()> {
+    let mut body = be_bytes(pages.len() as u64, 8);
+    for &(page, ref contents) in pages.iter() {
+        body.push_all(be_bytes(page, 8).as_slice());
+        body.push_all(be_bytes(contents.len() as u64, 8).as_slice());
+        body.push_all(contents.as_slice());
+    }
+
+    let mut w = BufferedWriter::new(try!(File::create(path)));
+    for byte in MAGIC.iter() {
+        try!(w.write_u8(*byte));
+    }
+    try!(w.write_be_u32(VERSION));
+    for byte in body.iter() {
+        try!(w.write_u8(*byte));
+    }
+    try!(w.write_be_u32(checksum(body.as_slice())));
+    try!(w.flush());
+    w.into_inner().fsync()
+}
+
+/// Deletes the journal at `path`, once the commit it's for is done.
+pub fn remove(path: &Path) -> IoResult<()> {
+    fs::unlink(path)
+}
+
+/// Writes the pages saved in the journal at `path`, if there's a whole one,
+/// back to `file`, which has pages of `page_size` bytes, then deletes the
+/// journal. Returns whether there was anything to roll back.
+pub fn roll_back(path: &Path, file: &mut File, page_size: usize) -> RusqlResult<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let bytes = try!(File::open(path).read_to_end());
+    let pages = match read_pages(bytes.as_slice()) {
+        Some(pages) => pages,
+        None => {
+            try!(remove(path));
+            return Ok(false);
+        }
+    };
+
+    for &(page, ref contents) in pages.iter() {
+        if contents.len() != page_size {
+            return Err(RusqlError::Corrupt("the journal doesn't match the database".to_string()));
+        }
+
+        try!(file.seek((page * page_size as u64) as i64, SeekStyle::SeekSet));
+        for byte in contents.iter() {
+            try!(file.write_u8(*byte));
+        }
+    }
+    try!(file.fsync());
+
+    try!(remove(path));
+    Ok(true)
+}
+
+// The pages of a whole journal, None for one cut short.
+fn read_pages(bytes: &[u8]) -> Option<Vec<(PageNo, Vec<u8>)>> {
+    let start = MAGIC.len() + 4;
+
+    if bytes.len() < start + 12 || &bytes[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    if be_uint(&bytes[MAGIC.len()..start]) > VERSION as u64 {
+        return None;
+    }
+
+    let body = &bytes[start..bytes.len() - 4];
+    if be_uint(&bytes[bytes.len() - 4..]) != checksum(body) as u64 {
+        return None;
+    }
+
+    let mut pages: Vec<(PageNo, Vec<u8>)> = Vec::new();
+    let mut offset = 8;
+    for _ in range(0, be_uint(&body[0..8])) {
+        let page = be_uint(&body[offset..offset + 8]);
+        let len = be_uint(&body[offset + 8..offset + 16]) as usize;
+
+        pages.push((page, body[offset + 16..offset + 16 + len].to_vec()));
+        offset += 16 + len;
+    }
+    Some(pages)
+}
+
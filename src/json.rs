@@ -0,0 +1,449 @@
+//! JSON, as in RFC 8259, for loading arrays of objects into tables, see
+//! Rusql::import_json, and writing tables out as them, see Table::to_json.
+//! https://www.rfc-editor.org/rfc/rfc8259
+//!
+//! Query results can also be written as JSON Lines, an object to a line with
+//! nothing around them, as the rows are read, see Rusql::export_json_lines.
+//! https://jsonlines.org
+
+use definitions::{ColumnDef, ColumnType, LiteralValue, TableDef};
+use error::{RusqlError, RusqlResult};
+use rusql::Rusql;
+use table::{Table, TableHeader, TableRow};
+
+use std::char;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::IoResult;
+use std::sync::Arc;
+
+/// A JSON value. Objects keep their members in order.
+#[derive(Show, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+    /// A number without a fraction or an exponent that fits.
+    Integer(isize),
+    Real(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The value stored for it: arrays and objects are stored as their JSON
+    /// text.
+    pub fn to_value(&self) -> LiteralValue {
+        match *self {
+            Json::Null => LiteralValue::Null,
+            Json::Boolean(b) => LiteralValue::Boolean(b),
+            Json::Integer(i) => LiteralValue::Integer(i),
+            Json::Real(r) => LiteralValue::Real(r),
+            Json::String(ref s) => LiteralValue::Text(Arc::new(s.clone())),
+            ref json => LiteralValue::Text(Arc::new(format!("{}", json))),
+        }
+    }
+
+    /// The JSON for `value`. Blobs are written as a string of hex digits,
+    /// and decimals as a string, so as not to lose digits.
+    pub fn from_value(value: &LiteralValue) -> Json {
+        match *value {
+            LiteralValue::Null => Json::Null,
+            LiteralValue::Boolean(b) => Json::Boolean(b),
+            LiteralValue::Integer(i) => Json::Integer(i),
+            LiteralValue::Real(r) => Json::Real(r),
+            LiteralValue::Text(ref text) => Json::String((**text).clone()),
+            ref value => Json::String(format!("{}", value)),
+        }
+    }
+
+    /// An object with a member for each column of `row`.
+    pub fn from_row(header: &TableHeader, row: &TableRow) -> Json {
+        Json::Object(header.iter().zip(row.iter()).map(|(column, value)| {
+            (column.name.clone(), Json::from_value(value))
+        }).collect())
+    }
+}
+
+impl fmt::String for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Boolean(b) => write!(f, "{}", b),
+            Json::Integer(i) => write!(f, "{}", i),
+            // JSON has no NaN or infinities, and a whole number is written
+            // with a fraction so that it's read back as a real.
+            Json::Real(r) if r.is_nan() || r.is_infinite() => write!(f, "null"),
+            Json::Real(r) => {
+                let number = format!("{}", r);
+                if number.as_slice().chars().any(|c| c == '.' || c == 'e' || c == 'E') {
+                    write!(f, "{}", number)
+                } else {
+                    write!(f, "{}.0", number)
+                }
+            }
+            Json::String(ref s) => write!(f, "{}", quote(s.as_slice())),
+            Json::Array(ref values) => {
+                try!(write!(f, "["));
+                for (i, value) in values.iter().enumerate() {
+                    try!(write!(f, "{}{}", if i > 0 { "," } else { "" }, value));
+                }
+                write!(f, "]")
+            }
+            Json::Object(ref members) => {
+                try!(write!(f, "{{"));
+                for (i, &(ref name, ref value)) in members.iter().enumerate() {
+                    try!(write!(f, "{}{}:{}", if i > 0 { "," } else { "" }, quote(name.as_slice()), value));
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// `s` as a JSON string.
+pub fn quote(s: &str) -> String {
+    let mut quoted = "\"".to_string();
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(format!("\\u{:04x}", c as u32).as_slice()),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Parses `text`, which holds one value, whitespace aside.
+pub fn parse(text: &str) -> RusqlResult<Json> {
+    let mut parser = Parser { text: text, pos: 0 };
+    let value = try!(parser.value());
+
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("text after the value"));
+    }
+    Ok(value)
+}
+
+/// Inserts the objects of the JSON array in `text` into the table called
+/// `name`, each member into the column it's named after; returns how many
+/// there were. If there's no such table, it's created with a column for
+/// each member of the first object, whose value gives the column's type.
+pub fn import(db: &mut Rusql, text: &str, name: &str) -> RusqlResult<usize> {
+    let objects = match try!(parse(text)) {
+        Json::Array(values) => values,
+        _ => return Err(RusqlError::TypeMismatch("expected a JSON array of objects".to_string())),
+    };
+
+    let mut records: Vec<BTreeMap<String, LiteralValue>> = Vec::new();
+    for object in objects.iter() {
+        match *object {
+            Json::Object(ref members) => {
+                records.push(members.iter().map(|&(ref column, ref value)| (column.clone(), value.to_value()))
+                                    .collect());
+            }
+            _ => return Err(RusqlError::TypeMismatch("expected a JSON array of objects".to_string())),
+        }
+    }
+
+    if db.find_table(&name.to_string()).is_none() {
+        let column_defs = match objects.first() {
+            Some(&Json::Object(ref members)) => members.iter().map(|&(ref column, ref value)| {
+                ColumnDef { name: column.clone(), column_type: column_type(value), column_constraints: Vec::new() }
+            }).collect(),
+            _ => return Err(RusqlError::NoSuchTable(name.to_string())),
+        };
+
+        try!(db.create_table(TableDef {
+            table_name: name.to_string(),
+            columns: column_defs,
+            constraints: Vec::new(),
+            if_not_exists: false,
+            temporary: false,
+            as_select: None,
+            strict: false,
+            columnar: false,
+        }));
+    }
+
+    db.insert_maps(name, records)
+}
+
+/// Writes the rows of `table` as a JSON array of objects, each with a
+/// member for each column, named after it. There's an object to a line.
+pub fn export<W: Writer>(table: &Table, w: &mut W) -> IoResult<()> {
+    try!(w.write_str("["));
+
+    for (i, row) in table.data.values().enumerate() {
+        try!(w.write_str(if i > 0 { ",\n" } else { "\n" }));
+        try!(w.write_str(format!("{}", Json::from_row(&table.header, row)).as_slice()));
+    }
+
+    w.write_str(if table.data.is_empty() { "]\n" } else { "\n]\n" })
+}
+
+/// Writes each of `rows` as a JSON object on a line of its own, as export
+/// does but without the array, so that none of them has to be kept; returns
+/// how many there were.
+pub fn write_lines<W: Writer, I: Iterator<Item=TableRow>>(header: &TableHeader, rows: I, w: &mut W)
+        -> IoResult<usize> {
+    let mut count = 0;
+
+    for row in rows {
+        try!(w.write_str(format!("{}\n", Json::from_row(header, &row)).as_slice()));
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// The type of a column made for `value`; none for null.
+fn column_type(value: &Json) -> Option<ColumnType> {
+    match *value {
+        Json::Boolean(..) => Some(ColumnType::Boolean),
+        Json::Integer(..) => Some(ColumnType::Integer),
+        Json::Real(..) => Some(ColumnType::Real),
+        Json::String(..) | Json::Array(..) | Json::Object(..) => Some(ColumnType::Text),
+        Json::Null => None,
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self) -> RusqlResult<Json> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(|s| Json::String(s)),
+            Some(b't') => self.literal("true", Json::Boolean(true)),
+            Some(b'f') => self.literal("false", Json::Boolean(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-') => self.number(),
+            Some(b) if (b as char).is_digit(10) => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self) -> RusqlResult<Json> {
+        let mut members: Vec<(String, Json)> = Vec::new();
+        self.pos += 1;
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = try!(self.string());
+
+            try!(self.expect(b':'));
+            members.push((name, try!(self.value())));
+
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => {}
+                Some(b'}') => return Ok(Json::Object(members)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> RusqlResult<Json> {
+        let mut values: Vec<Json> = Vec::new();
+        self.pos += 1;
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(try!(self.value()));
+
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => {}
+                Some(b']') => return Ok(Json::Array(values)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> RusqlResult<String> {
+        let mut s = String::new();
+        self.pos += 1;
+
+        loop {
+            // The quote and the backslash are ASCII, so the text between
+            // them is whole characters.
+            let start = self.pos;
+            while self.peek().map_or(false, |b| b != b'"' && b != b'\\' && b >= 0x20) {
+                self.pos += 1;
+            }
+            s.push_str(&self.text[start..self.pos]);
+
+            match self.next() {
+                Some(b'"') => return Ok(s),
+                Some(b'\\') => {}
+                Some(_) => return Err(self.error("control character in a string")),
+                None => return Err(self.error("unterminated string")),
+            }
+
+            let c = match self.next() {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'b') => '\x08',
+                Some(b'f') => '\x0c',
+                Some(b'n') => '\n',
+                Some(b'r') => '\r',
+                Some(b't') => '\t',
+                Some(b'u') => try!(self.unicode_escape()),
+                _ => return Err(self.error("bad escape")),
+            };
+            s.push(c);
+        }
+    }
+
+    // The character of a \u escape, which for one outside the BMP is a
+    // surrogate pair of them.
+    fn unicode_escape(&mut self) -> RusqlResult<char> {
+        let mut code = try!(self.hex4());
+
+        if code >= 0xD800 && code < 0xDC00 {
+            if !self.text[self.pos..].starts_with("\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+
+            let low = try!(self.hex4());
+            if low < 0xDC00 || low >= 0xE000 {
+                return Err(self.error("unpaired surrogate"));
+            }
+            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+        }
+
+        char::from_u32(code).ok_or(self.error("bad \\u escape"))
+    }
+
+    fn hex4(&mut self) -> RusqlResult<u32> {
+        let mut code = 0u32;
+
+        for _ in range(0, 4) {
+            match self.next().and_then(|b| (b as char).to_digit(16)) {
+                Some(digit) => code = code * 16 + digit as u32,
+                None => return Err(self.error("bad \\u escape")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> RusqlResult<Json> {
+        let start = self.pos;
+        let mut integer = true;
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if !self.digits() {
+            return Err(self.error("expected a digit"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            integer = false;
+            if !self.digits() {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        if self.peek() == Some(b'e') || self.peek() == Some(b'E') {
+            self.pos += 1;
+            integer = false;
+            if self.peek() == Some(b'+') || self.peek() == Some(b'-') {
+                self.pos += 1;
+            }
+            if !self.digits() {
+                return Err(self.error("expected a digit"));
+            }
+        }
+
+        let number = &self.text[start..self.pos];
+        let parsed = if integer { number.parse::<isize>().map(|i| Json::Integer(i)) } else { None };
+
+        match parsed {
+            Some(json) => Ok(json),
+            None => number.parse::<f64>().map(|r| Json::Real(r)).ok_or(self.error("bad number")),
+        }
+    }
+
+    // Skips digits; returns whether there were any.
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+
+        while self.peek().map_or(false, |b| (b as char).is_digit(10)) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> RusqlResult<Json> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> RusqlResult<()> {
+        self.skip_whitespace();
+
+        if self.next() == Some(b) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", b as char).as_slice()))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |b| b == b' ' || b == b'\t' || b == b'\n' || b == b'\r') {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).map(|b| *b)
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek();
+        if b.is_some() {
+            self.pos += 1;
+        }
+        b
+    }
+
+    fn error(&self, message: &str) -> RusqlError {
+        RusqlError::ParseError(format!("JSON at byte {}: {}", self.pos, message))
+    }
+}
@@ -1,16 +1,66 @@
-#![feature(plugin, box_syntax)]
+#![feature(plugin, box_syntax, unsafe_destructor)]
 #![allow(unstable)]
 
 #[macro_use] extern crate log;
 #[plugin] extern crate peg_syntax_ext;
+#[cfg(feature = "encryption")] extern crate crypto;
+#[cfg(feature = "serde")] extern crate serde;
+extern crate time;
 
-pub use exec::rusql_exec;
-pub use definitions::{ColumnDef, LiteralValue};
-pub use rusql::Rusql;
+pub use builder::{Select, ToExpression, col, val, func};
+pub use csv::{CsvOptions, Quoting};
+pub use exec::{rusql_exec, parse, QueryResult, Rows};
+pub use definitions::{RusqlStatement, Expression, ColumnDef, LiteralValue, Order};
+pub use error::{RusqlError, RusqlResult};
+pub use pragma::PragmaHandler;
+pub use row::{Row, FromRow, FromValue};
+pub use rusql::{Rusql, InterruptHandle};
+pub use snapshot::Snapshot;
+pub use statement::Statement;
+pub use storage::{Storage, TableData};
 pub use table::{TableRow, TableHeader, RowFormat};
+pub use visitor::Visitor;
 
+pub mod backup;
+pub mod btree;
+pub mod builder;
+pub mod cipher;
+pub mod columnar;
+pub mod csv;
+pub mod decimal;
 pub mod definitions;
+pub mod dump;
+pub mod error;
 pub mod table;
 pub mod exec;
+pub mod explain;
 pub mod expressions;
+pub mod journal;
+pub mod json;
+pub mod logfile;
+pub mod lz4;
+pub mod memory;
+pub mod page_cache;
+pub mod pager;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod parse_cache;
+pub mod persist;
+pub mod planner;
+pub mod pragma;
+#[cfg(feature = "arrow")]
+pub mod record_batch;
+pub mod row;
 pub mod rusql;
+pub mod script;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod snapshot;
+pub mod sqlite;
+pub mod statement;
+pub mod stats;
+pub mod storage;
+pub mod visitor;
+pub mod wal;
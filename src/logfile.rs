@@ -0,0 +1,424 @@
+//! The append-only log of Rusql::open_log. Every flush appends a frame with
+//! what changed since the one before: the rows changed in each table, by
+//! key, whole tables when they're new or their definition changed, and the
+//! rest of the schema when it changed. Opening the log replays the frames
+//! in order; a frame cut short by a crash is dropped.
+//!
+//! A frame is the length of its records, the records and a checksum of them.
+//! Numbers are big-endian, values are written as by persist.
+
+use error::{RusqlError, RusqlResult};
+use persist;
+use rusql::Rusql;
+use table::{Table, TableRow, PkType};
+
+use std::cmp::max;
+use std::error::FromError;
+use std::collections::BTreeMap;
+use std::io::{BufferedReader, BufferedWriter, File, FileAccess, FileMode, IoResult, MemReader, MemWriter};
+use std::io::SeekStyle;
+use std::io::fs::{self, PathExtensions};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread::Thread;
+
+pub const MAGIC: &'static [u8] = b"rusql log";
+// Version 2 can have compressed values, see persist.
+pub const VERSION: u32 = 2;
+
+/// The log is compacted in the background once it's grown to this many
+/// times the size it had after the last compaction...
+pub const COMPACT_RATIO: u64 = 4;
+/// ... and to at least this many bytes.
+pub const MIN_COMPACT_SIZE: u64 = 1 << 20;
+
+const TABLE: u8 = 1;
+const DROP: u8 = 2;
+const ROWS: u8 = 3;
+const SCHEMA: u8 = 4;
+
+// The length and the checksum around the records of a frame.
+const FRAME_OVERHEAD: u64 = 12;
+
+pub struct LogFile {
+    path: Path,
+    file: BufferedWriter<File>,
+    size: u64,
+    /// How long the log was after the last compaction.
+    compacted_size: u64,
+    /// The CREATE TABLE statement of every table as last written, by name.
+    tables: BTreeMap<String, String>,
+    /// The rest of the schema as last written, see persist::write_schema.
+    schema: Vec<u8>,
+    compaction: Option<Compaction>,
+}
+
+/// A compaction running in the background. It writes the database as it was
+/// when the log was `size` bytes long; the frames appended since are copied
+/// after it when it's done.
+struct Compaction {
+    size: u64,
+    done: Receiver<IoResult<()>>,
+}
+
+impl LogFile {
+    /// Replays the log at `path` into a new database, or creates the log if
+    /// there's no such file.
+    pub fn open(path: &Path) -> RusqlResult<(Rusql, LogFile)> {
+        let mut db = Rusql::new();
+        let mut size = (MAGIC.len() + 4) as u64;
+
+        if path.exists() {
+            let file_size = try!(path.stat()).size;
+            let mut r = BufferedReader::new(try!(File::open(path)));
+            let magic = try!(r.read_exact(MAGIC.len()));
+
+            if magic.as_slice() != MAGIC {
+                return Err(RusqlError::Corrupt("not a rusql log".to_string()));
+            }
+
+            let version = try!(r.read_be_u32());
+            if version > VERSION {
+                return Err(RusqlError::Corrupt(format!("unsupported log version {}", version)));
+            }
+
+            while let Some(records) = read_frame(&mut r, file_size - size) {
+                try!(replay(&mut db, records.as_slice()));
+                size += records.len() as u64 + FRAME_OVERHEAD;
+            }
+        } else {
+            let mut w = BufferedWriter::new(try!(File::create(path)));
+            try!(write_header(&mut w));
+            try!(w.flush());
+        }
+
+        // Whatever follows the last whole frame is from a write that didn't
+        // finish, and would hide the frames appended after it.
+        let mut file = try!(File::open_mode(path, FileMode::Append, FileAccess::Write));
+        try!(file.truncate(size as i64));
+
+        let mut log = LogFile {
+            path: path.clone(),
+            file: BufferedWriter::new(file),
+            size: size,
+            compacted_size: size,
+            tables: BTreeMap::new(),
+            schema: Vec::new(),
+            compaction: None,
+        };
+
+        let names: Vec<String> = db.map.keys().map(|name| name.clone()).collect();
+        for name in names.iter() {
+            let table = try!(db.get_mut_table(name));
+            table.data.track_changes();
+            log.tables.insert(name.clone(), table.to_sql());
+        }
+        log.schema = try!(schema_bytes(&db));
+
+        Ok((db, log))
+    }
+
+    /// Appends a frame with the changes made to `db` since the last one, if
+    /// there are any. A compaction that's done is put in place first, and a
+    /// new one started if the log has grown enough since.
+    pub fn append(&mut self, db: &mut Rusql) -> RusqlResult<()> {
+        try!(self.finish_compaction(false));
+
+        let mut w = MemWriter::new();
+        let mut schema_changed = false;
+
+        let dropped: Vec<String> = self.tables.keys().filter(|name| !db.map.contains_key(name.as_slice()))
+                                                     .map(|name| name.clone())
+                                                     .collect();
+        for name in dropped.iter() {
+            try!(w.write_u8(DROP));
+            try!(persist::write_string(&mut w, name.as_slice()));
+            self.tables.remove(name);
+            schema_changed = true;
+        }
+
+        let names: Vec<String> = db.map.keys().map(|name| name.clone()).collect();
+        for name in names.iter() {
+            let sql = db.map.get(name).unwrap().to_sql();
+
+            // A table that doesn't track its changes took the place of the
+            // one written, e.g. by a backup, even if it's defined the same.
+            if self.tables.get(name) != Some(&sql) || !db.map.get(name).unwrap().data.tracks_changes() {
+                let table = try!(db.get_mut_table(name));
+                table.data.track_changes();
+                table.data.take_changes();
+
+                try!(w.write_u8(TABLE));
+                try!(persist::write_string(&mut w, name.as_slice()));
+                try!(persist::write_table(&mut w, table));
+                self.tables.insert(name.clone(), sql);
+                schema_changed = true;
+            } else if db.map.get(name).unwrap().data.has_changes() {
+                let table = try!(db.get_mut_table(name));
+                try!(write_rows(&mut w, table));
+            }
+        }
+
+        // Replaying a TABLE or DROP takes the indexes and triggers of the
+        // table with it, so the schema is written again after one.
+        let schema = try!(schema_bytes(db));
+        if schema_changed || schema != self.schema {
+            try!(w.write_u8(SCHEMA));
+            try!(write_bytes(&mut w, schema.as_slice()));
+            self.schema = schema;
+        }
+
+        let records = w.into_inner();
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        try!(write_frame(&mut self.file, records.as_slice()));
+        try!(self.file.flush());
+        try!(self.file.get_mut().datasync());
+        self.size += records.len() as u64 + FRAME_OVERHEAD;
+
+        if self.compaction.is_none() && self.size >= max(MIN_COMPACT_SIZE, self.compacted_size * COMPACT_RATIO) {
+            try!(self.start_compaction(db));
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the log as the tables and schema it holds, dropping the rows
+    /// replaced or deleted since, and waits for it to be done.
+    pub fn compact(&mut self, db: &Rusql) -> RusqlResult<()> {
+        if self.compaction.is_none() {
+            try!(self.start_compaction(db));
+        }
+        self.finish_compaction(true)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // Writes the tables of `db`, which the thread shares with it until they
+    // change, to the file next to the log.
+    fn start_compaction(&mut self, db: &Rusql) -> RusqlResult<()> {
+        let tables: Vec<Arc<Table>> = db.map.values().map(|table| table.clone()).collect();
+        let schema = try!(schema_bytes(db));
+        let path = compact_path(&self.path);
+        let (sender, receiver) = channel();
+
+        Thread::spawn(move || {
+            let _ = sender.send(write_compacted(&path, tables, schema));
+        });
+
+        self.compaction = Some(Compaction { size: self.size, done: receiver });
+        Ok(())
+    }
+
+    // Puts the compacted log in place of this one, if the compaction is
+    // done. A failed compaction is dropped, the log is still whole; only
+    // `wait`ing for it reports why it failed.
+    fn finish_compaction(&mut self, wait: bool) -> RusqlResult<()> {
+        let result = match self.compaction {
+            Some(ref compaction) if wait => compaction.done.recv().ok(),
+            Some(ref compaction) => match compaction.done.try_recv() {
+                Ok(result) => Some(result),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => None,
+            },
+            None => return Ok(()),
+        };
+
+        let compaction = self.compaction.take().unwrap();
+        let path = compact_path(&self.path);
+
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(err)) => {
+                let _ = fs::unlink(&path);
+                return if wait { Err(FromError::from_error(err)) } else { Ok(()) };
+            }
+            None => {
+                let _ = fs::unlink(&path);
+                return if wait { Err(RusqlError::Io("the compaction stopped".to_string())) } else { Ok(()) };
+            }
+        }
+
+        let compacted_size = try!(path.stat()).size;
+        let mut file = try!(File::open_mode(&path, FileMode::Append, FileAccess::Write));
+
+        if self.size > compaction.size {
+            let mut log = try!(File::open(&self.path));
+            try!(log.seek(compaction.size as i64, SeekStyle::SeekSet));
+            let mut w = BufferedWriter::new(file);
+            for byte in try!(log.read_to_end()).iter() {
+                try!(w.write_u8(*byte));
+            }
+            try!(w.flush());
+            file = w.into_inner();
+        }
+
+        try!(file.fsync());
+        try!(fs::rename(&path, &self.path));
+
+        self.file = BufferedWriter::new(try!(File::open_mode(&self.path, FileMode::Append, FileAccess::Write)));
+        self.size = compacted_size + (self.size - compaction.size);
+        self.compacted_size = compacted_size;
+        Ok(())
+    }
+}
+
+fn compact_path(path: &Path) -> Path {
+    Path::new(format!("{}.compact", path.display()))
+}
+
+fn write_header<W: Writer>(w: &mut W) -> IoResult<()> {
+    for byte in MAGIC.iter() {
+        try!(w.write_u8(*byte));
+    }
+    w.write_be_u32(VERSION)
+}
+
+// A frame for each table and one for the rest of the schema; the file only
+// takes the place of the log once it's whole.
+fn write_compacted(path: &Path, tables: Vec<Arc<Table>>, schema: Vec<u8>) -> IoResult<()> {
+    let mut w = BufferedWriter::new(try!(File::create(path)));
+    try!(write_header(&mut w));
+
+    for table in tables.iter() {
+        let mut records = MemWriter::new();
+        try!(records.write_u8(TABLE));
+        try!(persist::write_string(&mut records, table.name.as_slice()));
+        try!(persist::write_table(&mut records, &**table));
+        try!(write_frame(&mut w, records.into_inner().as_slice()));
+    }
+
+    let mut records = MemWriter::new();
+    try!(records.write_u8(SCHEMA));
+    try!(write_bytes(&mut records, schema.as_slice()));
+    try!(write_frame(&mut w, records.into_inner().as_slice()));
+
+    try!(w.flush());
+    w.into_inner().fsync()
+}
+
+fn write_frame<W: Writer>(w: &mut W, records: &[u8]) -> IoResult<()> {
+    try!(w.write_be_u64(records.len() as u64));
+    for byte in records.iter() {
+        try!(w.write_u8(*byte));
+    }
+    w.write_be_u32(checksum(records))
+}
+
+// The records of the next frame, None if there's no whole frame left in the
+// `left` bytes of the log.
+fn read_frame<R: Reader>(r: &mut R, left: u64) -> Option<Vec<u8>> {
+    let len = match r.read_be_u64() {
+        Ok(len) if len + FRAME_OVERHEAD <= left => len,
+        _ => return None,
+    };
+
+    match (r.read_exact(len as usize), r.read_be_u32()) {
+        (Ok(records), Ok(sum)) if sum == checksum(records.as_slice()) => Some(records),
+        _ => None,
+    }
+}
+
+/// FNV-1a.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(2166136261u32, |hash, &byte| ((((hash ^ byte as u32) as u64) * 16777619) & 0xFFFFFFFF) as u32)
+}
+
+fn schema_bytes(db: &Rusql) -> RusqlResult<Vec<u8>> {
+    let mut w = MemWriter::new();
+    try!(persist::write_schema(&mut w, db));
+    Ok(w.into_inner())
+}
+
+fn write_bytes<W: Writer>(w: &mut W, bytes: &[u8]) -> IoResult<()> {
+    try!(w.write_be_u64(bytes.len() as u64));
+    for byte in bytes.iter() {
+        try!(w.write_u8(*byte));
+    }
+    Ok(())
+}
+
+// Each changed row by its key, with whether it's still there.
+fn write_rows<W: Writer>(w: &mut W, table: &mut Table) -> IoResult<()> {
+    let keys = table.data.take_changes();
+
+    try!(w.write_u8(ROWS));
+    try!(persist::write_string(w, table.name.as_slice()));
+    try!(w.write_be_u64(table.max_pk as u64));
+    try!(w.write_be_u64(keys.len() as u64));
+
+    for pk in keys.iter() {
+        try!(w.write_be_u64(*pk as u64));
+
+        match table.data.get(pk) {
+            Some(row) => {
+                try!(w.write_u8(1));
+                for value in row.iter() {
+                    try!(persist::write_value(w, value));
+                }
+            }
+            None => try!(w.write_u8(0)),
+        }
+    }
+
+    Ok(())
+}
+
+fn replay(db: &mut Rusql, records: &[u8]) -> RusqlResult<()> {
+    let mut r = MemReader::new(records.to_vec());
+
+    while !r.eof() {
+        match try!(r.read_u8()) {
+            TABLE => {
+                let name = try!(persist::read_string(&mut r));
+                if db.map.contains_key(name.as_slice()) {
+                    try!(db.drop_table(&name));
+                }
+                try!(persist::read_table(&mut r, db));
+            }
+            DROP => try!(db.drop_table(&try!(persist::read_string(&mut r)))),
+            ROWS => try!(replay_rows(&mut r, db)),
+            SCHEMA => {
+                let _ = try!(r.read_be_u64());
+                let index_names: Vec<String> = db.indexes.keys().map(|name| name.clone()).collect();
+                for name in index_names.iter() {
+                    try!(db.drop_index(name));
+                }
+                db.views.clear();
+                db.materialized_views.clear();
+                db.triggers.clear();
+
+                try!(persist::read_schema(&mut r, db));
+            }
+            tag => return Err(RusqlError::Corrupt(format!("unknown log record {}", tag))),
+        }
+    }
+
+    Ok(())
+}
+
+fn replay_rows<R: Reader>(r: &mut R, db: &mut Rusql) -> RusqlResult<()> {
+    let name = try!(persist::read_string(r));
+    let table = try!(db.get_mut_table(&name));
+    let max_pk = try!(r.read_be_u64()) as PkType;
+
+    for _ in range(0, try!(r.read_be_u64())) {
+        let pk = try!(r.read_be_u64()) as PkType;
+        table.remove_row(pk);
+
+        if try!(r.read_u8()) != 0 {
+            let mut row: TableRow = Vec::new();
+            for _ in range(0, table.header.len()) {
+                row.push(try!(persist::read_value(r)));
+            }
+            table.restore_rows(vec![(pk, row)]);
+        }
+    }
+
+    table.max_pk = max_pk;
+    Ok(())
+}
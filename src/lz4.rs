@@ -0,0 +1,167 @@
+//! The LZ4 block format, which persist compresses long text and blob values
+//! with when built with the `compression` feature. Values written that way
+//! can be read back whether the feature is on or not.
+//! https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+//!
+//! A block is a run of sequences, each a token with the number of literals
+//! and the length of the match after them, the literals, and where the match
+//! is, as how far back in the output it starts.
+
+use std::cmp::min;
+use std::iter::repeat;
+
+const MIN_MATCH: usize = 4;
+const HASH_LOG: usize = 12;
+const MAX_OFFSET: usize = 65535;
+// The format wants the last match to start at least this far from the end,
+// and the last bytes to be literals.
+const MF_LIMIT: usize = 12;
+const LAST_LITERALS: usize = 5;
+
+/// `input` as a block. Matches are found through a table of where each
+/// four bytes were last seen, which is fast rather than thorough.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut table: Vec<Option<usize>> = repeat(None).take(1 << HASH_LOG).collect();
+    let mut anchor = 0;
+    let mut i = 0;
+
+    if input.len() > MF_LIMIT {
+        let limit = input.len() - MF_LIMIT;
+
+        while i < limit {
+            let hash = hash(&input[i..i + MIN_MATCH]);
+            let candidate = table[hash];
+            table[hash] = Some(i);
+
+            match candidate {
+                Some(start) if i - start <= MAX_OFFSET
+                                && &input[start..start + MIN_MATCH] == &input[i..i + MIN_MATCH] => {
+                    let end = input.len() - LAST_LITERALS;
+                    let mut len = MIN_MATCH;
+                    while i + len < end && input[start + len] == input[i + len] {
+                        len += 1;
+                    }
+
+                    write_sequence(&mut out, &input[anchor..i], Some((i - start, len)));
+                    i += len;
+                    anchor = i;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// The `len` bytes compressed into `input`, None if it isn't a block that
+/// gives that many.
+pub fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut i = 0;
+
+    while i < input.len() {
+        let token = input[i] as usize;
+        i += 1;
+
+        let mut literals = token >> 4;
+        if literals == 15 {
+            literals += match read_length(input, &mut i) {
+                Some(more) => more,
+                None => return None,
+            };
+        }
+        if i + literals > input.len() || out.len() + literals > len {
+            return None;
+        }
+        out.push_all(&input[i..i + literals]);
+        i += literals;
+
+        // The last sequence has no match.
+        if i == input.len() {
+            break;
+        }
+        if i + 2 > input.len() {
+            return None;
+        }
+
+        let offset = input[i] as usize | (input[i + 1] as usize) << 8;
+        i += 2;
+
+        let mut match_len = token & 15;
+        if match_len == 15 {
+            match_len += match read_length(input, &mut i) {
+                Some(more) => more,
+                None => return None,
+            };
+        }
+        match_len += MIN_MATCH;
+
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return None;
+        }
+
+        // The match can overlap the bytes it produces, so it's copied a
+        // byte at a time.
+        let start = out.len() - offset;
+        for k in range(0, match_len) {
+            let byte = out[start + k];
+            out.push(byte);
+        }
+    }
+
+    if out.len() == len { Some(out) } else { None }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = bytes.iter().rev().fold(0u64, |value, &byte| (value << 8) | byte as u64);
+    (((value * 2654435761) & 0xFFFFFFFF) >> (32 - HASH_LOG)) as usize
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+
+    out.push(((min(literals.len(), 15) << 4) | min(match_len, 15)) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.push_all(literals);
+
+    if let Some((offset, _)) = matched {
+        out.push(offset as u8);
+        out.push((offset >> 8) as u8);
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+// Lengths past what the token holds are written as bytes of 255 and the
+// rest.
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn read_length(input: &[u8], i: &mut usize) -> Option<usize> {
+    let mut len = 0;
+
+    loop {
+        if *i >= input.len() {
+            return None;
+        }
+
+        let byte = input[*i];
+        *i += 1;
+        len += byte as usize;
+
+        if byte != 255 {
+            return Some(len);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Rough accounting of the memory taken by the rows of the tables and of the
+//! result sets, which is what lets a database stay under a soft limit: a
+//! statement that would go over it fails with OutOfMemory instead.
+
+use definitions::LiteralValue;
+use table::TableRow;
+
+use std::mem;
+
+/// The bytes a value takes, its text or blob included. Text shared between
+/// values, e.g. through a table's string pool, is counted for each of them.
+pub fn value_size(value: &LiteralValue) -> usize {
+    let heap = match *value {
+        LiteralValue::Text(ref text) => text.len(),
+        LiteralValue::Blob(ref bytes) => bytes.len(),
+        _ => 0,
+    };
+
+    mem::size_of::<LiteralValue>() + heap
+}
+
+/// The bytes a row takes, with its key.
+pub fn row_size(row: &TableRow) -> usize {
+    row.iter().fold(mem::size_of::<TableRow>() + mem::size_of::<usize>(), |size, value| size + value_size(value))
+}
+
+pub fn rows_size(rows: &Vec<TableRow>) -> usize {
+    rows.iter().fold(0, |size, row| size + row_size(row))
+}
@@ -0,0 +1,108 @@
+//! The pages of a paged file read or written most recently, so that reading
+//! the same pages again, e.g. the top of a B-tree every query goes through,
+//! doesn't go to the disk. See pager.
+
+use pager::PageNo;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// How many pages a file opened keeps in its cache.
+pub const DEFAULT_CAPACITY: usize = 2000;
+
+pub struct PageCache {
+    capacity: usize,
+    /// The contents of each page, with when it was last used.
+    pages: HashMap<PageNo, (Vec<u8>, u64)>,
+    /// The pages by when they were last used.
+    used: BTreeMap<u64, PageNo>,
+    clock: u64,
+    /// How many reads found their page in the cache.
+    pub hits: u64,
+    /// How many reads had to go to the file.
+    pub misses: u64,
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity: capacity,
+            pages: HashMap::new(),
+            used: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keeps at most `capacity` pages, forgetting the ones used least
+    /// recently. 0 turns the cache off.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        while self.pages.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.used.clear();
+    }
+
+    /// A copy of `page`, if it's kept, counting the read as a hit or a miss.
+    pub fn get(&mut self, page: PageNo) -> Option<Vec<u8>> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        match self.pages.get_mut(&page) {
+            Some(&mut (ref bytes, ref mut used)) => {
+                self.used.remove(&*used);
+                self.used.insert(clock, page);
+                *used = clock;
+                self.hits += 1;
+                Some(bytes.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Keeps `bytes` as the contents of `page`, in place of what was kept.
+    pub fn insert(&mut self, page: PageNo, bytes: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some((_, used)) = self.pages.remove(&page) {
+            self.used.remove(&used);
+        }
+        while self.pages.len() >= self.capacity {
+            self.evict();
+        }
+
+        self.clock += 1;
+        self.used.insert(self.clock, page);
+        self.pages.insert(page, (bytes, self.clock));
+    }
+
+    // Forgets the page used least recently.
+    fn evict(&mut self) {
+        let (used, page) = match self.used.iter().next() {
+            Some((&used, &page)) => (used, page),
+            None => return,
+        };
+
+        self.used.remove(&used);
+        self.pages.remove(&page);
+    }
+}
@@ -0,0 +1,660 @@
+//! The file of Rusql::open_paged, made of fixed-size pages. The first page
+//! is the header, which points to the catalog: the tables, each with the
+//! page its B-tree starts at (see btree), the free-list and the rest of the
+//! schema, in a chain of pages. A table's rows are only read as they're
+//! needed, so the file can be larger than memory. The indexes aren't kept in
+//! the file; they're built again from the rows when it's opened, which
+//! reads every page once to check it.
+//!
+//! Pages in use are never written over. A commit writes the B-trees that
+//! changed to free pages, then the catalog and last the header, saved to a
+//! rollback journal first (see journal), so a crash leaves the file as it
+//! was before the commit or after it. The pages the
+//! old B-trees used are free once the header no longer points to them and
+//! no snapshot reads them. The file doesn't shrink as pages are freed;
+//! VACUUM writes the tables to a new file that takes its place.
+//!
+//! Numbers are big-endian.
+
+use btree::{PagedStorage, Version};
+use cipher::{self, Cipher};
+use definitions::RusqlStatement;
+use error::{RusqlError, RusqlResult};
+use journal;
+use page_cache::{self, PageCache};
+use persist;
+use rusql::Rusql;
+use storage::{Storage, TableData};
+use wal::{self, Wal};
+
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufferedWriter, File, FileAccess, FileMode, IoResult, MemReader, MemWriter, SeekStyle};
+use std::io::fs::{self, PathExtensions};
+use std::mem::replace;
+use std::sync::{Arc, Mutex};
+
+pub type PageNo = u64;
+
+pub const MAGIC: &'static [u8] = b"rusql paged\0";
+// Version 2 added the bytes reserved at the end of a page, 3 compressed
+// values, see persist.
+pub const VERSION: u32 = 3;
+pub const PAGE_SIZE: usize = 4096;
+
+// The magic, the version, the page size, the page count, where the catalog
+// is and how long, and since version 2 how many bytes at the end of a page
+// the cipher takes.
+const HEADER_LEN: usize = 48;
+
+/// Reads and writes the pages of the file and hands out free ones.
+pub struct Pager {
+    file: BufferedWriter<File>,
+    /// How many bytes of a page can be used, which the file takes up with
+    /// `reserved` more.
+    pub page_size: usize,
+    pub reserved: usize,
+    /// The cipher of an encrypted file, see cipher.
+    cipher: Option<Cipher>,
+    /// How many pages the file has, the header included.
+    pub page_count: u64,
+    /// The pages nothing uses, which can be written over.
+    pub free: Vec<PageNo>,
+    /// The pages freed since the header was last written, which the file
+    /// still uses until it's written again.
+    pub pending: Vec<PageNo>,
+    /// The generation of the next B-tree written, see btree::Version.
+    generation: u64,
+    /// The generations of the B-trees something reads.
+    live: BTreeSet<u64>,
+    /// The pages of B-trees that were replaced, each with the generation of
+    /// the last B-tree that may use them.
+    retired: Vec<(u64, Vec<PageNo>)>,
+    pub cache: PageCache,
+    /// The log the pages are written to in WAL mode.
+    wal: Option<Wal>,
+}
+
+impl Pager {
+    pub fn read(&mut self, page: PageNo) -> IoResult<Vec<u8>> {
+        if let Some(bytes) = self.cache.get(page) {
+            return Ok(bytes);
+        }
+
+        let logged = match self.wal {
+            Some(ref mut wal) => try!(wal.read(page)),
+            None => None,
+        };
+        let stored = match logged {
+            Some(bytes) => bytes,
+            None => {
+                try!(self.file.flush());
+                let file = self.file.get_mut();
+                try!(file.seek((page * (self.page_size + self.reserved) as u64) as i64, SeekStyle::SeekSet));
+                try!(file.read_exact(self.page_size + self.reserved))
+            }
+        };
+
+        let bytes = match self.cipher {
+            Some(ref cipher) if page != 0 => try!(cipher.decrypt(page, stored.as_slice())),
+            _ => stored,
+        };
+        self.cache.insert(page, bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Writes `bytes` to `page`, padded with zeros to the page size; in WAL
+    /// mode to the log. Every page but the header is encrypted if the file
+    /// is.
+    pub fn write(&mut self, page: PageNo, bytes: &[u8]) -> IoResult<()> {
+        let size = if page == 0 { self.page_size + self.reserved } else { self.page_size };
+        let mut contents = bytes.to_vec();
+        for _ in range(bytes.len(), size) {
+            contents.push(0);
+        }
+
+        let stored = match self.cipher {
+            Some(ref cipher) if page != 0 => try!(cipher.encrypt(page, contents.as_slice())),
+            _ => contents.clone(),
+        };
+        match self.wal {
+            Some(ref mut wal) => try!(wal.write(page, stored.as_slice())),
+            None => try!(self.write_to_file(page, stored.as_slice())),
+        }
+
+        self.cache.insert(page, contents);
+        Ok(())
+    }
+
+    fn write_to_file(&mut self, page: PageNo, stored: &[u8]) -> IoResult<()> {
+        try!(self.file.flush());
+        try!(self.file.get_mut().seek((page * (self.page_size + self.reserved) as u64) as i64, SeekStyle::SeekSet));
+
+        for byte in stored.iter() {
+            try!(self.file.write_u8(*byte));
+        }
+        Ok(())
+    }
+
+    /// A free page, or a new one at the end of the file.
+    pub fn allocate(&mut self) -> PageNo {
+        match self.free.pop() {
+            Some(page) => page,
+            None => self.allocate_at_end(),
+        }
+    }
+
+    pub fn allocate_at_end(&mut self) -> PageNo {
+        self.page_count += 1;
+        self.page_count - 1
+    }
+
+    /// Writes `bytes` to a chain of `pages`, each starting with the number
+    /// of the next one, 0 for the last.
+    pub fn write_chain(&mut self, bytes: &[u8], pages: &[PageNo]) -> IoResult<()> {
+        let room = self.chain_room();
+
+        for (i, &page) in pages.iter().enumerate() {
+            let next = if i + 1 < pages.len() { pages[i + 1] } else { 0 };
+            let end = if i + 1 < pages.len() { (i + 1) * room } else { bytes.len() };
+            let mut contents = be_bytes(next, 8);
+
+            contents.push_all(&bytes[i * room..end]);
+            try!(self.write(page, contents.as_slice()));
+        }
+        Ok(())
+    }
+
+    /// The `len` bytes of the chain starting at `page`, and its pages.
+    pub fn read_chain(&mut self, mut page: PageNo, len: usize) -> RusqlResult<(Vec<u8>, Vec<PageNo>)> {
+        let room = self.chain_room();
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut pages: Vec<PageNo> = Vec::new();
+
+        while bytes.len() < len {
+            if page == 0 || page >= self.page_count || pages.len() as u64 > self.page_count {
+                return Err(RusqlError::Corrupt("broken page chain".to_string()));
+            }
+
+            let contents = try!(self.read(page));
+            let end = 8 + min(room, len - bytes.len());
+            bytes.push_all(&contents[8..end]);
+            pages.push(page);
+            page = be_uint(&contents[0..8]);
+        }
+
+        Ok((bytes, pages))
+    }
+
+    /// How many pages a chain of `len` bytes takes.
+    pub fn chain_length(&self, len: usize) -> usize {
+        (len + self.chain_room() - 1) / self.chain_room()
+    }
+
+    /// Counts a new B-tree as read; returns its generation.
+    pub fn register(&mut self) -> u64 {
+        self.generation += 1;
+        self.live.insert(self.generation);
+        self.generation
+    }
+
+    /// Counts the B-tree of `generation` as no longer read, along with the
+    /// `pages` it doesn't share with the ones after it. Pages are freed once
+    /// no B-tree as old as the last one that uses them is read anymore.
+    pub fn release(&mut self, generation: u64, pages: Vec<PageNo>) {
+        self.live.remove(&generation);
+        if !pages.is_empty() {
+            self.retired.push((generation, pages));
+        }
+
+        let oldest = self.live.iter().next().map(|generation| *generation);
+        let mut retired: Vec<(u64, Vec<PageNo>)> = Vec::new();
+
+        for (last, pages) in replace(&mut self.retired, Vec::new()).into_iter() {
+            if oldest.map_or(true, |oldest| last < oldest) {
+                self.pending.extend(pages.into_iter());
+            } else {
+                retired.push((last, pages));
+            }
+        }
+        self.retired = retired;
+    }
+
+    /// Writes out what's buffered and waits for it to reach the disk.
+    pub fn sync(&mut self) -> IoResult<()> {
+        if let Some(ref mut wal) = self.wal {
+            return wal.sync();
+        }
+
+        try!(self.file.flush());
+        self.file.get_mut().fsync()
+    }
+
+    /// Writes the pages in the log over the file and empties the log.
+    pub fn checkpoint(&mut self) -> IoResult<()> {
+        let mut wal = match self.wal.take() {
+            Some(wal) => wal,
+            None => return Ok(()),
+        };
+
+        let result = self.write_back(&mut wal);
+        self.wal = Some(wal);
+        result
+    }
+
+    fn write_back(&mut self, wal: &mut Wal) -> IoResult<()> {
+        for page in wal.pages().into_iter() {
+            let contents = try!(wal.read(page)).unwrap();
+            try!(self.write_to_file(page, contents.as_slice()));
+        }
+
+        try!(self.file.flush());
+        try!(self.file.get_mut().fsync());
+        wal.reset()
+    }
+
+    fn chain_room(&self) -> usize {
+        self.page_size - 8
+    }
+}
+
+/// The file of a database from open_paged.
+pub struct PagedFile {
+    path: Path,
+    /// The key of an encrypted file, which VACUUM writes the new one with.
+    key: Option<Vec<u8>>,
+    pager: Arc<Mutex<Pager>>,
+    /// The pages of the catalog the header points to.
+    catalog: Vec<PageNo>,
+    /// The tables and the schema as last written to the catalog, so that
+    /// it's not written again unchanged.
+    written: Vec<u8>,
+    /// The B-tree of every table as last written, by name.
+    versions: BTreeMap<String, Arc<Version>>,
+}
+
+impl PagedFile {
+    /// Opens the file at `path`, creating it if there's none, and reads the
+    /// schema from it. The rows stay in the file. If the file has a WAL, it's
+    /// opened in WAL mode. An encrypted file needs its `key`, see cipher, and
+    /// a new one is encrypted if a key is given.
+    pub fn open(path: &Path, key: Option<&[u8]>) -> RusqlResult<(Rusql, PagedFile)> {
+        let cipher = match key {
+            Some(key) => match Cipher::new(key) {
+                Some(cipher) => Some(cipher),
+                None => return Err(RusqlError::TypeMismatch(format!("a key is {} bytes long", cipher::KEY_LEN))),
+            },
+            None => None,
+        };
+
+        if !path.exists() {
+            let header = Header {
+                page_size: PAGE_SIZE,
+                page_count: 1,
+                catalog: 0,
+                catalog_len: 0,
+                reserved: if cipher.is_some() { cipher::RESERVED } else { 0 },
+            };
+            let mut w = MemWriter::new();
+            try!(header.write(&mut w));
+
+            let mut file = BufferedWriter::new(try!(File::create(path)));
+            let bytes = w.into_inner();
+            for byte in bytes.iter() {
+                try!(file.write_u8(*byte));
+            }
+            for _ in range(bytes.len(), PAGE_SIZE) {
+                try!(file.write_u8(0));
+            }
+            try!(file.flush());
+        }
+
+        let mut file = try!(File::open_mode(path, FileMode::Open, FileAccess::ReadWrite));
+        let header = try!(Header::read(try!(file.read_exact(HEADER_LEN)).as_slice()));
+
+        match (cipher.is_some(), header.reserved) {
+            (false, 0) | (true, cipher::RESERVED) => {}
+            (false, _) => return Err(RusqlError::Corrupt("the database is encrypted, it needs a key".to_string())),
+            (true, _) => return Err(RusqlError::Corrupt("the database isn't encrypted".to_string())),
+        }
+
+        try!(journal::roll_back(&journal::journal_path(path), &mut file, header.page_size));
+        let wal_path = wal::wal_path(path);
+        let wal = if wal_path.exists() { Some(try!(Wal::open(&wal_path, header.page_size))) } else { None };
+
+        let pager = Arc::new(Mutex::new(Pager {
+            file: BufferedWriter::new(file),
+            page_size: header.page_size - header.reserved,
+            reserved: header.reserved,
+            cipher: cipher,
+            page_count: 0,
+            free: Vec::new(),
+            pending: Vec::new(),
+            generation: 0,
+            live: BTreeSet::new(),
+            retired: Vec::new(),
+            cache: PageCache::new(page_cache::DEFAULT_CAPACITY),
+            wal: wal,
+        }));
+        let mut paged = PagedFile {
+            path: path.clone(),
+            key: key.map(|key| key.to_vec()),
+            pager: pager.clone(),
+            catalog: Vec::new(),
+            written: Vec::new(),
+            versions: BTreeMap::new(),
+        };
+        let mut db = Rusql::new();
+
+        // In WAL mode the latest header is in the log.
+        let header = try!(Header::read(try!(pager.lock().unwrap().read(0)).as_slice()));
+        pager.lock().unwrap().page_count = header.page_count;
+
+        if header.catalog != 0 {
+            let (bytes, pages) = try!(pager.lock().unwrap().read_chain(header.catalog, header.catalog_len as usize));
+            paged.written = try!(paged.read_catalog(&mut db, bytes));
+            paged.catalog = pages;
+        }
+
+        Ok((db, paged))
+    }
+
+    /// "wal" in WAL mode, see wal, "delete" otherwise.
+    pub fn journal_mode(&self) -> &'static str {
+        if self.pager.lock().unwrap().wal.is_some() { "wal" } else { "delete" }
+    }
+
+    /// Turns WAL mode on or off. Turning it off checkpoints the log and
+    /// deletes it.
+    pub fn set_wal_mode(&mut self, on: bool) -> RusqlResult<()> {
+        let mut pager = self.pager.lock().unwrap();
+
+        if on && pager.wal.is_none() {
+            try!(pager.sync());
+            let page_size = pager.page_size + pager.reserved;
+            pager.wal = Some(try!(Wal::open(&wal::wal_path(&self.path), page_size)));
+        } else if !on && pager.wal.is_some() {
+            try!(pager.checkpoint());
+            try!(pager.wal.take().unwrap().remove());
+        }
+        Ok(())
+    }
+
+    /// Writes the pages in the WAL over the file and empties it; nothing to
+    /// do unless in WAL mode.
+    pub fn checkpoint(&self) -> RusqlResult<()> {
+        Ok(try!(self.pager.lock().unwrap().checkpoint()))
+    }
+
+    /// How many pages are kept in the cache, see page_cache.
+    pub fn cache_size(&self) -> usize {
+        self.pager.lock().unwrap().cache.capacity()
+    }
+
+    pub fn set_cache_size(&self, pages: usize) {
+        self.pager.lock().unwrap().cache.set_capacity(pages);
+    }
+
+    /// How many page reads found their page in the cache and how many went
+    /// to the file, and how many pages are kept.
+    pub fn cache_stats(&self) -> (u64, u64, usize) {
+        let pager = self.pager.lock().unwrap();
+        (pager.cache.hits, pager.cache.misses, pager.cache.len())
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.pager.lock().unwrap().page_size
+    }
+
+    /// Writes the tables of `db` to a new file, whose pages are all in use,
+    /// which then takes the place of this one, see VACUUM. The tables whose
+    /// rows are still in this file are rebuilt in memory first, see
+    /// Table::rebuild. The WAL, if there's one, is folded back into this
+    /// file before, so there's never one next to the new file that isn't
+    /// its own.
+    pub fn vacuum(&mut self, db: &mut Rusql) -> RusqlResult<()> {
+        let wal_mode = self.pager.lock().unwrap().wal.is_some();
+        if wal_mode {
+            try!(self.set_wal_mode(false));
+        }
+
+        let names: Vec<String> = db.map.keys().map(|name| name.clone()).collect();
+        for name in names.iter() {
+            let table = try!(db.get_mut_table(name));
+            if table.data.paged().is_some() {
+                table.rebuild();
+            }
+        }
+
+        // A file left from a VACUUM a crash cut short is of no use.
+        let path = vacuum_path(&self.path);
+        if path.exists() {
+            try!(fs::unlink(&path));
+        }
+
+        let (_, mut vacuumed) = try!(PagedFile::open(&path, self.key.as_ref().map(|key| key.as_slice())));
+        try!(vacuumed.commit(db));
+        try!(fs::rename(&path, &self.path));
+
+        vacuumed.path = self.path.clone();
+        vacuumed.set_cache_size(self.cache_size());
+        if wal_mode {
+            try!(vacuumed.set_wal_mode(true));
+        }
+
+        *self = vacuumed;
+        Ok(())
+    }
+
+    /// Writes the changes made to `db` since the last commit to the file.
+    /// New tables, and those created again, e.g. by ALTER TABLE, are moved
+    /// into the file.
+    pub fn commit(&mut self, db: &mut Rusql) -> RusqlResult<()> {
+        let names: Vec<String> = db.map.keys().map(|name| name.clone()).collect();
+        let mut versions: BTreeMap<String, Arc<Version>> = BTreeMap::new();
+        let mut w = MemWriter::new();
+
+        try!(w.write_be_u64(names.len() as u64));
+        for name in names.iter() {
+            let table = try!(db.get_mut_table(name));
+
+            if table.data.paged().is_none() {
+                let storage = try!(PagedStorage::create(self.pager.clone(), table.data.iter()));
+                table.data.set_storage(box storage);
+            }
+
+            try!(persist::write_string(&mut w, name.as_slice()));
+            try!(persist::write_string(&mut w, table.to_sql().as_slice()));
+            try!(w.write_be_u64(table.max_pk as u64));
+            try!(w.write_be_u64(table.memory as u64));
+
+            let storage = table.data.paged().unwrap();
+            try!(storage.sync());
+            versions.insert(name.clone(), storage.version());
+            try!(w.write_be_u64(storage.root()));
+            try!(w.write_be_u64(storage.len() as u64));
+        }
+
+        // The B-trees of the tables dropped or created again are free once
+        // nothing reads them anymore.
+        for (name, version) in replace(&mut self.versions, BTreeMap::new()).into_iter() {
+            if versions.get(&name).map_or(true, |current| &**current as *const Version != &*version as *const Version) {
+                try!(version.abandon());
+            }
+        }
+        self.versions = versions;
+
+        try!(persist::write_schema(&mut w, db));
+        let written = w.into_inner();
+
+        let mut pager = self.pager.lock().unwrap();
+        if written == self.written && pager.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = written.clone();
+        let free: Vec<PageNo> = pager.free.iter().chain(pager.pending.iter()).chain(self.catalog.iter())
+                                                 .map(|page| *page)
+                                                 .collect();
+        bytes.push_all(be_bytes(free.len() as u64, 8).as_slice());
+        for page in free.iter() {
+            bytes.push_all(be_bytes(*page, 8).as_slice());
+        }
+
+        // The catalog goes at the end of the file, as the free pages it lists
+        // can't be taken for it.
+        let pages: Vec<PageNo> = range(0, pager.chain_length(bytes.len())).map(|_| pager.allocate_at_end()).collect();
+        try!(pager.write_chain(bytes.as_slice(), pages.as_slice()));
+        try!(pager.sync());
+
+        // Without a WAL the header is written over, so it's saved to the
+        // journal until the new one is on the disk.
+        let journal_path = journal::journal_path(&self.path);
+        if pager.wal.is_none() {
+            let old = try!(pager.read(0));
+            try!(journal::write(&journal_path, &[(0, old)]));
+        }
+
+        let header = Header {
+            page_size: pager.page_size + pager.reserved,
+            page_count: pager.page_count,
+            catalog: pages[0],
+            catalog_len: bytes.len() as u64,
+            reserved: pager.reserved,
+        };
+        let mut w = MemWriter::new();
+        try!(header.write(&mut w));
+        try!(pager.write(0, w.into_inner().as_slice()));
+        try!(pager.sync());
+
+        if pager.wal.is_none() {
+            try!(journal::remove(&journal_path));
+        }
+
+        if pager.wal.as_ref().map_or(false, |wal| wal.frames() >= wal::AUTOCHECKPOINT) {
+            try!(pager.checkpoint());
+        }
+
+        pager.free = free;
+        pager.pending.clear();
+        self.catalog = pages;
+        self.written = written;
+        Ok(())
+    }
+
+    // Creates the tables and the rest of the schema; returns the part of the
+    // catalog they're in.
+    fn read_catalog(&mut self, db: &mut Rusql, bytes: Vec<u8>) -> RusqlResult<Vec<u8>> {
+        let mut r = MemReader::new(bytes);
+
+        for _ in range(0, try!(r.read_be_u64())) {
+            let name = try!(persist::read_string(&mut r));
+            let table_def = match try!(persist::parse_one(try!(persist::read_string(&mut r)).as_slice())) {
+                RusqlStatement::CreateTable(table_def) => table_def,
+                _ => return Err(RusqlError::Corrupt(format!("bad definition of table {}", name))),
+            };
+            let max_pk = try!(r.read_be_u64()) as usize;
+            let memory = try!(r.read_be_u64()) as usize;
+            let root = try!(r.read_be_u64());
+            let count = try!(r.read_be_u64()) as usize;
+
+            try!(db.create_table(table_def));
+            let table = try!(db.get_mut_table(&name));
+            let storage = PagedStorage::open(self.pager.clone(), root, count);
+            let indexed = table.is_indexed();
+            let mut rows = 0us;
+
+            // Every page is read once now, so that a corrupt one is reported
+            // here rather than by a panic once the Storage reads it.
+            try!(storage.for_each_row(|pk, row| {
+                rows += 1;
+                if indexed {
+                    table.index_stored_row(pk, &row);
+                }
+            }));
+            if rows != count {
+                return Err(RusqlError::Corrupt(format!("wrong row count for table {}", name)));
+            }
+
+            self.versions.insert(name.clone(), storage.version());
+            table.data = TableData::with_storage(box storage);
+            table.max_pk = max_pk;
+            table.memory = memory;
+        }
+
+        try!(persist::read_schema(&mut r, db));
+        let written = r.get_ref()[..r.tell().unwrap() as usize].to_vec();
+
+        // CREATE INDEX read the rows it indexed, which needn't stay in memory.
+        for table in db.map.values_mut() {
+            if let Some(storage) = table.make_unique().data.paged() {
+                storage.unload();
+            }
+        }
+
+        let mut pager = self.pager.lock().unwrap();
+        for _ in range(0, try!(r.read_be_u64())) {
+            pager.free.push(try!(r.read_be_u64()));
+        }
+        Ok(written)
+    }
+}
+
+// Where VACUUM writes the new file before it takes the place of the one at
+// `path`.
+fn vacuum_path(path: &Path) -> Path {
+    Path::new(format!("{}-vacuum", path.display()))
+}
+
+// The first page of the file.
+struct Header {
+    /// How many bytes a page takes in the file.
+    page_size: usize,
+    page_count: u64,
+    catalog: PageNo,
+    catalog_len: u64,
+    reserved: usize,
+}
+
+impl Header {
+    fn read(bytes: &[u8]) -> RusqlResult<Header> {
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(RusqlError::Corrupt("not a paged rusql database".to_string()));
+        }
+
+        let fields = &bytes[MAGIC.len()..];
+        let version = be_uint(&fields[0..4]);
+
+        if version > VERSION as u64 {
+            return Err(RusqlError::Corrupt(format!("unsupported format version {}", version)));
+        }
+
+        Ok(Header {
+            page_size: be_uint(&fields[4..8]) as usize,
+            page_count: be_uint(&fields[8..16]),
+            catalog: be_uint(&fields[16..24]),
+            catalog_len: be_uint(&fields[24..32]),
+            reserved: if version >= 2 { be_uint(&fields[32..36]) as usize } else { 0 },
+        })
+    }
+
+    fn write<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        for byte in MAGIC.iter() {
+            try!(w.write_u8(*byte));
+        }
+        try!(w.write_be_u32(VERSION));
+        try!(w.write_be_u32(self.page_size as u32));
+        try!(w.write_be_u64(self.page_count));
+        try!(w.write_be_u64(self.catalog));
+        try!(w.write_be_u64(self.catalog_len));
+        w.write_be_u32(self.reserved as u32)
+    }
+}
+
+pub fn be_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+pub fn be_bytes(value: u64, len: usize) -> Vec<u8> {
+    range(0, len).rev().map(|i| (value >> (8 * i)) as u8).collect()
+}
@@ -0,0 +1,38 @@
+//! Splits the work on the rows of large tables between threads, one for each
+//! core, when built with the `parallel` feature.
+
+use error::{RusqlError, RusqlResult};
+
+use std::os;
+use std::thread::Thread;
+
+/// Tables with fewer rows than this are scanned on the calling thread alone,
+/// since starting the others would take longer than the scan.
+pub const MIN_ROWS: usize = 10000;
+
+/// Splits `items` into as many runs as there are cores, calls `f` on each of
+/// them on a thread of its own and puts the results back together in the
+/// order of the items. Fails if any of the threads panics.
+pub fn map_chunks<T, R, F>(items: &[T], f: F) -> RusqlResult<Vec<R>>
+    where T: Sync, R: Send, F: Fn(&[T]) -> Vec<R> + Sync
+{
+    let threads = os::num_cpus();
+
+    if threads < 2 || items.len() < 2 {
+        return Ok(f(items));
+    }
+
+    let size = (items.len() + threads - 1) / threads;
+    let f = &f;
+    let guards: Vec<_> = items.chunks(size).map(|chunk| Thread::scoped(move || f(chunk))).collect();
+    let mut results: Vec<R> = Vec::new();
+
+    for guard in guards.into_iter() {
+        match guard.join() {
+            Ok(part) => results.extend(part.into_iter()),
+            Err(_) => return Err(RusqlError::Internal("a thread scanning a table panicked".to_string())),
+        }
+    }
+
+    Ok(results)
+}
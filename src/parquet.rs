@@ -0,0 +1,348 @@
+//! Writing tables as Parquet files, behind the `parquet` feature, see
+//! Table::to_parquet. https://parquet.apache.org/docs/file-format/
+//!
+//! A file is "PAR1", the column chunks, the file metadata in the Thrift
+//! compact protocol, its length and "PAR1" again. Tables are written as a
+//! single row group with one uncompressed data page per column. Every column
+//! is OPTIONAL, with a definition level per row saying whether it's NULL,
+//! and its values are PLAIN encoded.
+//!
+//! Each column's type comes from its values, as Arrow readers would have
+//! it: Int64 if they're all integers, Float64 if they're all numbers,
+//! decimals included, Boolean if they're all booleans, Binary if they're
+//! all blobs and Utf8, with the values as text, otherwise.
+
+use definitions::LiteralValue;
+use table::{Table, TableRow};
+
+use std::io::{IoResult, MemWriter};
+
+const MAGIC: &'static str = "PAR1";
+
+// Physical types.
+const BOOLEAN: i32 = 0;
+const INT64: i32 = 2;
+const DOUBLE: i32 = 5;
+const BYTE_ARRAY: i32 = 6;
+
+const OPTIONAL: i32 = 1;
+const UTF8: i32 = 0;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const DATA_PAGE: i32 = 0;
+const UNCOMPRESSED: i32 = 0;
+
+// Thrift compact protocol field types.
+const T_I32: u8 = 5;
+const T_I64: u8 = 6;
+const T_BINARY: u8 = 8;
+const T_LIST: u8 = 9;
+const T_STRUCT: u8 = 12;
+
+// What a column is written as.
+#[derive(Copy, PartialEq)]
+enum Kind {
+    Boolean,
+    Int64,
+    Double,
+    Binary,
+    Utf8,
+}
+
+impl Kind {
+    fn physical_type(&self) -> i32 {
+        match *self {
+            Kind::Boolean => BOOLEAN,
+            Kind::Int64 => INT64,
+            Kind::Double => DOUBLE,
+            Kind::Binary | Kind::Utf8 => BYTE_ARRAY,
+        }
+    }
+}
+
+// Where a column chunk was written, for the file metadata.
+struct Chunk {
+    name: String,
+    kind: Kind,
+    offset: usize,
+    size: usize,
+}
+
+impl Table {
+    /// Writes the rows as a Parquet file, see the parquet module.
+    pub fn to_parquet<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        export(self, w)
+    }
+}
+
+/// Writes the rows of `table` as a Parquet file.
+pub fn export<W: Writer>(table: &Table, w: &mut W) -> IoResult<()> {
+    let rows: Vec<&TableRow> = table.data.values().collect();
+    let mut out = MemWriter::new();
+    let mut chunks: Vec<Chunk> = Vec::new();
+
+    try!(out.write_str(MAGIC));
+
+    for (i, column) in table.header.iter().enumerate() {
+        let values: Vec<&LiteralValue> = rows.iter().map(|row| &row[i]).collect();
+        let kind = kind(values.as_slice());
+        let offset = out.get_ref().len();
+
+        try!(write_page(&mut out, values.as_slice(), kind));
+        let size = out.get_ref().len() - offset;
+        chunks.push(Chunk { name: column.name.clone(), kind: kind, offset: offset, size: size });
+    }
+
+    let metadata = file_metadata(chunks.as_slice(), rows.len());
+    try!(out.write(metadata.as_slice()));
+    try!(out.write_le_u32(metadata.len() as u32));
+    try!(out.write_str(MAGIC));
+
+    w.write(out.get_ref())
+}
+
+fn kind(values: &[&LiteralValue]) -> Kind {
+    let mut kind: Option<Kind> = None;
+
+    for value in values.iter() {
+        let this = match **value {
+            LiteralValue::Null => continue,
+            LiteralValue::Boolean(..) => Kind::Boolean,
+            LiteralValue::Integer(..) => Kind::Int64,
+            LiteralValue::Real(..) | LiteralValue::Decimal(..) => Kind::Double,
+            LiteralValue::Blob(..) => Kind::Binary,
+            _ => return Kind::Utf8,
+        };
+
+        kind = Some(match kind {
+            None => this,
+            Some(seen) if seen == this => seen,
+            Some(Kind::Int64) | Some(Kind::Double) if this == Kind::Int64 || this == Kind::Double => Kind::Double,
+            _ => return Kind::Utf8,
+        });
+    }
+
+    kind.unwrap_or(Kind::Utf8)
+}
+
+// A data page: its header, then the definition levels and the values that
+// aren't NULL.
+fn write_page(out: &mut MemWriter, values: &[&LiteralValue], kind: Kind) -> IoResult<()> {
+    let mut data = MemWriter::new();
+
+    let levels: Vec<bool> = values.iter().map(|value| !value.is_null()).collect();
+    let encoded = bit_packed_run(levels.as_slice());
+    try!(data.write_le_u32(encoded.len() as u32));
+    try!(data.write(encoded.as_slice()));
+
+    let present: Vec<&&LiteralValue> = values.iter().filter(|value| !value.is_null()).collect();
+    match kind {
+        Kind::Boolean => {
+            let bits: Vec<bool> = present.iter().map(|value| match ***value {
+                LiteralValue::Boolean(b) => b,
+                _ => false,
+            }).collect();
+            try!(data.write(pack_bits(bits.as_slice()).as_slice()));
+        }
+        Kind::Int64 => for value in present.iter() {
+            try!(data.write_le_i64(value.to_int() as i64));
+        },
+        Kind::Double => for value in present.iter() {
+            let r = match ***value {
+                LiteralValue::Integer(i) => i as f64,
+                LiteralValue::Real(r) => r,
+                LiteralValue::Decimal(d) => d.to_real(),
+                _ => 0.0,
+            };
+            try!(data.write_le_f64(r));
+        },
+        Kind::Binary | Kind::Utf8 => for value in present.iter() {
+            let bytes = match ***value {
+                LiteralValue::Blob(ref bytes) if kind == Kind::Binary => bytes.clone(),
+                LiteralValue::Text(ref text) => text.as_bytes().to_vec(),
+                ref value => format!("{}", value).into_bytes(),
+            };
+            try!(data.write_le_u32(bytes.len() as u32));
+            try!(data.write(bytes.as_slice()));
+        },
+    }
+
+    let data = data.into_inner();
+    let mut header = Thrift::new();
+    header.i32(1, DATA_PAGE);
+    header.i32(2, data.len() as i32);
+    header.i32(3, data.len() as i32);
+    header.begin_struct(5);
+    header.i32(1, values.len() as i32);
+    header.i32(2, PLAIN);
+    header.i32(3, RLE);
+    header.i32(4, RLE);
+    header.end_struct();
+    header.end_struct();
+
+    try!(out.write(header.out.as_slice()));
+    out.write(data.as_slice())
+}
+
+// The definition levels, as a single bit-packed run of the RLE hybrid
+// encoding: a header with the number of groups of eight, then a bit each.
+fn bit_packed_run(bits: &[bool]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+
+    write_varint(&mut out, ((((bits.len() + 7) / 8) << 1) | 1) as u64);
+    out.push_all(pack_bits(bits).as_slice());
+    out
+}
+
+// Eight to a byte, the first in the lowest bit.
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8).map(|byte| {
+        byte.iter().enumerate().fold(0u8, |packed, (i, &bit)| if bit { packed | (1 << i) } else { packed })
+    }).collect()
+}
+
+fn file_metadata(chunks: &[Chunk], num_rows: usize) -> Vec<u8> {
+    let mut t = Thrift::new();
+
+    t.i32(1, 1);
+
+    // The schema is a root with a child for each column.
+    t.list(2, T_STRUCT, chunks.len() + 1);
+    t.begin_element();
+    t.binary(4, "schema".as_bytes());
+    t.i32(5, chunks.len() as i32);
+    t.end_struct();
+    for chunk in chunks.iter() {
+        t.begin_element();
+        t.i32(1, chunk.kind.physical_type());
+        t.i32(3, OPTIONAL);
+        t.binary(4, chunk.name.as_bytes());
+        if chunk.kind == Kind::Utf8 {
+            t.i32(6, UTF8);
+        }
+        t.end_struct();
+    }
+
+    t.i64(3, num_rows as i64);
+
+    t.list(4, T_STRUCT, 1);
+    t.begin_element();
+    t.list(1, T_STRUCT, chunks.len());
+    for chunk in chunks.iter() {
+        t.begin_element();
+        t.i64(2, chunk.offset as i64);
+        t.begin_struct(3);
+        t.i32(1, chunk.kind.physical_type());
+        t.list(2, T_I32, 2);
+        t.varint_i32(PLAIN);
+        t.varint_i32(RLE);
+        t.list(3, T_BINARY, 1);
+        t.bytes(chunk.name.as_bytes());
+        t.i32(4, UNCOMPRESSED);
+        t.i64(5, num_rows as i64);
+        t.i64(6, chunk.size as i64);
+        t.i64(7, chunk.size as i64);
+        t.i64(9, chunk.offset as i64);
+        t.end_struct();
+        t.end_struct();
+    }
+    t.i64(2, chunks.iter().fold(0, |total, chunk| total + chunk.size) as i64);
+    t.i64(3, num_rows as i64);
+    t.end_struct();
+
+    t.binary(6, "rusql".as_bytes());
+    t.end_struct();
+
+    t.out
+}
+
+// A struct in the Thrift compact protocol. Field headers hold the change in
+// field id since the last field of the struct, so that's kept for each
+// struct being written.
+struct Thrift {
+    out: Vec<u8>,
+    last_ids: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Thrift {
+        Thrift { out: Vec::new(), last_ids: vec![0] }
+    }
+
+    fn field(&mut self, id: i16, field_type: u8) {
+        let last = self.last_ids.pop().unwrap();
+        let delta = id - last;
+
+        if delta > 0 && delta <= 15 {
+            self.out.push(((delta as u8) << 4) | field_type);
+        } else {
+            self.out.push(field_type);
+            write_varint(&mut self.out, zigzag(id as i64));
+        }
+        self.last_ids.push(id);
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, T_I32);
+        self.varint_i32(value);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, T_I64);
+        write_varint(&mut self.out, zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, T_BINARY);
+        self.bytes(value);
+    }
+
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, T_STRUCT);
+        self.last_ids.push(0);
+    }
+
+    // A struct in a list, which has no field header.
+    fn begin_element(&mut self) {
+        self.last_ids.push(0);
+    }
+
+    fn end_struct(&mut self) {
+        self.out.push(0);
+        self.last_ids.pop();
+    }
+
+    // The header of a list of `len` elements, which are written after it.
+    fn list(&mut self, id: i16, element_type: u8, len: usize) {
+        self.field(id, T_LIST);
+        if len < 15 {
+            self.out.push(((len as u8) << 4) | element_type);
+        } else {
+            self.out.push(0xF0 | element_type);
+            write_varint(&mut self.out, len as u64);
+        }
+    }
+
+    fn varint_i32(&mut self, value: i32) {
+        write_varint(&mut self.out, zigzag(value as i64));
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        write_varint(&mut self.out, value.len() as u64);
+        self.out.push_all(value);
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+// Seven bits to a byte, lowest first, with the top bit set on all but the
+// last.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
@@ -0,0 +1,92 @@
+//! The statements parsed from the SQL run most recently, so that running the
+//! same SQL again skips the parser, which takes longer than running most
+//! small statements.
+
+use definitions::RusqlStatement;
+
+use std::collections::HashMap;
+
+/// How many SQL strings a new database keeps the statements of.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+pub struct ParseCache {
+    capacity: usize,
+    /// The statements parsed from each SQL string, with when they were last
+    /// used.
+    entries: HashMap<String, (Vec<RusqlStatement>, u64)>,
+    clock: u64,
+}
+
+impl ParseCache {
+    pub fn new(capacity: usize) -> ParseCache {
+        ParseCache { capacity: capacity, entries: HashMap::new(), clock: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keeps at most `capacity` SQL strings, forgetting the ones used least
+    /// recently. 0 turns the cache off.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// A copy of the statements parsed from `sql`, if they're kept.
+    pub fn get(&mut self, sql: &str) -> Option<Vec<RusqlStatement>> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        match self.entries.get_mut(sql) {
+            Some(&mut (ref stmts, ref mut used)) => {
+                *used = clock;
+                Some(stmts.clone())
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, sql: &str, stmts: Vec<RusqlStatement>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        while self.entries.len() >= self.capacity && !self.entries.contains_key(sql) {
+            self.evict();
+        }
+
+        self.clock += 1;
+        self.entries.insert(sql.to_string(), (stmts, self.clock));
+    }
+
+    // Forgets the SQL used least recently.
+    fn evict(&mut self) {
+        let sql = {
+            let mut oldest: Option<(&String, u64)> = None;
+
+            for (sql, &(_, used)) in self.entries.iter() {
+                if oldest.map_or(true, |(_, oldest_used)| used < oldest_used) {
+                    oldest = Some((sql, used));
+                }
+            }
+
+            match oldest {
+                Some((sql, _)) => sql.clone(),
+                None => return,
+            }
+        };
+        self.entries.remove(&sql);
+    }
+}
@@ -0,0 +1,359 @@
+//! The file format of Rusql::save and Rusql::open. A file starts with MAGIC
+//! and the version of the format, followed by the tables, each as its CREATE
+//! TABLE statement, its largest key and its rows, and then by the rest of
+//! the schema as the SQL that creates it. Numbers are big-endian.
+//!
+//! Temporary tables and the triggers on them aren't saved.
+//!
+//! Built with the `compression` feature, long text and blobs are written
+//! compressed with LZ4 when that makes them smaller. They're read back
+//! either way, see lz4.
+
+use decimal::Decimal;
+use definitions::{LiteralValue, RusqlStatement};
+use error::{RusqlError, RusqlResult};
+use exec;
+use lz4;
+use rusql::Rusql;
+use table::{Table, TableRow, PkType};
+
+use std::io::{BufferedReader, BufferedWriter, File, IoResult};
+use std::io::fs;
+use std::sync::Arc;
+
+pub const MAGIC: &'static [u8] = b"rusql db";
+
+/// The version of the format written by save. Files of an older version
+/// can still be opened. Version 2 added compressed values.
+pub const VERSION: u32 = 2;
+
+/// Shorter text and blobs aren't worth compressing.
+pub const MIN_COMPRESSED_LEN: usize = 64;
+
+const NULL: u8 = 0;
+const INTEGER: u8 = 1;
+const REAL: u8 = 2;
+const TEXT: u8 = 3;
+const BLOB: u8 = 4;
+const BOOLEAN: u8 = 5;
+const DECIMAL: u8 = 6;
+const COMPRESSED_TEXT: u8 = 7;
+const COMPRESSED_BLOB: u8 = 8;
+
+/// Writes the database to `path`. The file is written next to it first and
+/// then moved over it, so that a failed save leaves the previous one.
+pub fn save(db: &Rusql, path: &Path) -> RusqlResult<()> {
+    let tmp = Path::new(format!("{}.tmp", path.display()));
+
+    {
+        let mut w = BufferedWriter::new(try!(File::create(&tmp)));
+        try!(write_db(&mut w, db));
+        try!(w.flush());
+        // Otherwise a crash could leave the new name on a file that isn't
+        // whole yet.
+        try!(w.get_mut().fsync());
+    }
+
+    try!(fs::rename(&tmp, path));
+    Ok(())
+}
+
+pub fn open(path: &Path) -> RusqlResult<Rusql> {
+    let mut r = BufferedReader::new(try!(File::open(path)));
+    let magic = try!(r.read_exact(MAGIC.len()));
+
+    if magic.as_slice() != MAGIC {
+        return Err(RusqlError::Corrupt("not a rusql database".to_string()));
+    }
+
+    let version = try!(r.read_be_u32());
+    if version > VERSION {
+        return Err(RusqlError::Corrupt(format!("unsupported format version {}", version)));
+    }
+
+    read_db(&mut r)
+}
+
+fn write_db<W: Writer>(w: &mut W, db: &Rusql) -> IoResult<()> {
+    for byte in MAGIC.iter() {
+        try!(w.write_u8(*byte));
+    }
+    try!(w.write_be_u32(VERSION));
+
+    try!(w.write_be_u64(db.map.len() as u64));
+    for table in db.map.values() {
+        try!(write_table(w, &**table));
+    }
+
+    write_schema(w, db)
+}
+
+/// Writes the indexes, views, materialized views and triggers of `db`, the
+/// part of the schema that isn't in the tables.
+pub fn write_schema<W: Writer>(w: &mut W, db: &Rusql) -> IoResult<()> {
+    let indexes: Vec<String> = db.indexes.iter().filter_map(|(name, table_name)| {
+        db.map.get(table_name).and_then(|table| table.get_index(name)).map(|index| index.to_sql(table_name))
+    }).collect();
+    try!(write_strings(w, &indexes));
+
+    let views: Vec<String> = db.views.iter().map(|(name, select)| format!("CREATE VIEW {} AS {}", name, select))
+                                            .collect();
+    try!(write_strings(w, &views));
+
+    try!(w.write_be_u64(db.materialized_views.len() as u64));
+    for (name, select) in db.materialized_views.iter() {
+        try!(write_string(w, name.as_slice()));
+        try!(write_string(w, format!("{}", select).as_slice()));
+    }
+
+    let mut triggers: Vec<(&String, &String)> = Vec::new();
+    for (table_name, defs) in db.triggers.iter() {
+        if db.map.contains_key(table_name) || db.views.contains_key(table_name) {
+            triggers.extend(defs.iter().map(|def| (table_name, &def.sql)));
+        }
+    }
+
+    try!(w.write_be_u64(triggers.len() as u64));
+    for &(table_name, sql) in triggers.iter() {
+        try!(write_string(w, table_name.as_slice()));
+        try!(write_string(w, sql.as_slice()));
+    }
+
+    Ok(())
+}
+
+pub fn write_table<W: Writer>(w: &mut W, table: &Table) -> IoResult<()> {
+    try!(write_string(w, table.to_sql().as_slice()));
+    try!(w.write_be_u64(table.max_pk as u64));
+    try!(w.write_be_u64(table.data.len() as u64));
+
+    for (pk, row) in table.data.iter() {
+        try!(w.write_be_u64(pk as u64));
+        for value in row.iter() {
+            try!(write_value(w, value));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_value<W: Writer>(w: &mut W, value: &LiteralValue) -> IoResult<()> {
+    match *value {
+        LiteralValue::Null => w.write_u8(NULL),
+        LiteralValue::Integer(i) => {
+            try!(w.write_u8(INTEGER));
+            w.write_be_i64(i as i64)
+        }
+        LiteralValue::Real(f) => {
+            try!(w.write_u8(REAL));
+            w.write_be_f64(f)
+        }
+        LiteralValue::Text(ref text) => {
+            if let Some(compressed) = compress(text.as_bytes()) {
+                try!(w.write_u8(COMPRESSED_TEXT));
+                return write_compressed(w, text.len(), compressed.as_slice());
+            }
+            try!(w.write_u8(TEXT));
+            write_string(w, text.as_slice())
+        }
+        LiteralValue::Blob(ref bytes) => {
+            if let Some(compressed) = compress(bytes.as_slice()) {
+                try!(w.write_u8(COMPRESSED_BLOB));
+                return write_compressed(w, bytes.len(), compressed.as_slice());
+            }
+            try!(w.write_u8(BLOB));
+            try!(w.write_be_u64(bytes.len() as u64));
+            for byte in bytes.iter() {
+                try!(w.write_u8(*byte));
+            }
+            Ok(())
+        }
+        LiteralValue::Boolean(b) => {
+            try!(w.write_u8(BOOLEAN));
+            w.write_u8(b as u8)
+        }
+        LiteralValue::Decimal(d) => {
+            try!(w.write_u8(DECIMAL));
+            try!(w.write_be_i64(d.value as i64));
+            w.write_be_u64(d.scale as u64)
+        }
+    }
+}
+
+// The original length, then the compressed bytes.
+fn write_compressed<W: Writer>(w: &mut W, len: usize, compressed: &[u8]) -> IoResult<()> {
+    try!(w.write_be_u64(len as u64));
+    try!(w.write_be_u64(compressed.len() as u64));
+    for byte in compressed.iter() {
+        try!(w.write_u8(*byte));
+    }
+    Ok(())
+}
+
+// `bytes` compressed, if they're long enough and it saves anything.
+#[cfg(feature = "compression")]
+fn compress(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < MIN_COMPRESSED_LEN {
+        return None;
+    }
+
+    let compressed = lz4::compress(bytes);
+    if compressed.len() + 8 < bytes.len() { Some(compressed) } else { None }
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+pub fn write_string<W: Writer>(w: &mut W, s: &str) -> IoResult<()> {
+    try!(w.write_be_u64(s.len() as u64));
+    w.write_str(s)
+}
+
+fn write_strings<W: Writer>(w: &mut W, strings: &Vec<String>) -> IoResult<()> {
+    try!(w.write_be_u64(strings.len() as u64));
+    for s in strings.iter() {
+        try!(write_string(w, s.as_slice()));
+    }
+    Ok(())
+}
+
+fn read_db<R: Reader>(r: &mut R) -> RusqlResult<Rusql> {
+    let mut db = Rusql::new();
+
+    for _ in range(0, try!(r.read_be_u64())) {
+        try!(read_table(r, &mut db));
+    }
+
+    try!(read_schema(r, &mut db));
+    Ok(db)
+}
+
+/// Reads back what write_schema wrote into `db`, whose tables are in place.
+pub fn read_schema<R: Reader>(r: &mut R, db: &mut Rusql) -> RusqlResult<()> {
+    // The indexes are built on the rows, which are all in place by now.
+    for sql in try!(read_strings(r)).iter() {
+        try!(exec::exec_stmt(db, try!(parse_one(sql.as_slice()))));
+    }
+
+    for sql in try!(read_strings(r)).iter() {
+        try!(exec::exec_stmt(db, try!(parse_one(sql.as_slice()))));
+    }
+
+    for _ in range(0, try!(r.read_be_u64())) {
+        let name = try!(read_string(r));
+        let sql = try!(read_string(r));
+
+        match try!(parse_one(sql.as_slice())) {
+            RusqlStatement::Select(select) => { db.materialized_views.insert(name, select); }
+            _ => return Err(RusqlError::Corrupt(format!("bad materialized view {}", name))),
+        }
+    }
+
+    // Triggers keep the table they're on apart from their SQL, which still
+    // has the name the table had when they were created.
+    for _ in range(0, try!(r.read_be_u64())) {
+        let table_name = try!(read_string(r));
+        let sql = try!(read_string(r));
+
+        match try!(parse_one(sql.as_slice())) {
+            RusqlStatement::CreateTrigger(mut def) => {
+                def.table_name = table_name;
+                try!(db.create_trigger(def));
+            }
+            _ => return Err(RusqlError::Corrupt(format!("bad trigger on {}", table_name))),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_table<R: Reader>(r: &mut R, db: &mut Rusql) -> RusqlResult<()> {
+    let table_def = match try!(parse_one(try!(read_string(r)).as_slice())) {
+        RusqlStatement::CreateTable(table_def) => table_def,
+        _ => return Err(RusqlError::Corrupt("bad table definition".to_string())),
+    };
+    let name = table_def.table_name.clone();
+    try!(db.create_table(table_def));
+
+    let table = try!(db.get_mut_table(&name));
+    let max_pk = try!(r.read_be_u64()) as PkType;
+    let count = try!(r.read_be_u64());
+    let mut rows: Vec<(PkType, TableRow)> = Vec::new();
+
+    for _ in range(0, count) {
+        let pk = try!(r.read_be_u64()) as PkType;
+        let mut row: TableRow = Vec::new();
+
+        for _ in range(0, table.header.len()) {
+            row.push(try!(read_value(r)));
+        }
+        rows.push((pk, row));
+    }
+
+    table.restore_rows(rows);
+    table.max_pk = max_pk;
+    Ok(())
+}
+
+pub fn read_value<R: Reader>(r: &mut R) -> RusqlResult<LiteralValue> {
+    Ok(match try!(r.read_u8()) {
+        NULL => LiteralValue::Null,
+        INTEGER => LiteralValue::Integer(try!(r.read_be_i64()) as isize),
+        REAL => LiteralValue::Real(try!(r.read_be_f64())),
+        TEXT => LiteralValue::Text(Arc::new(try!(read_string(r)))),
+        BLOB => {
+            let len = try!(r.read_be_u64()) as usize;
+            LiteralValue::Blob(try!(r.read_exact(len)))
+        }
+        COMPRESSED_TEXT => {
+            let bytes = try!(read_compressed(r));
+            LiteralValue::Text(Arc::new(try!(String::from_utf8(bytes).map_err(|_| {
+                RusqlError::Corrupt("invalid UTF-8 text".to_string())
+            }))))
+        }
+        COMPRESSED_BLOB => LiteralValue::Blob(try!(read_compressed(r))),
+        BOOLEAN => LiteralValue::Boolean(try!(r.read_u8()) != 0),
+        DECIMAL => {
+            let value = try!(r.read_be_i64()) as isize;
+            LiteralValue::Decimal(Decimal::new(value, try!(r.read_be_u64()) as usize))
+        }
+        tag => return Err(RusqlError::Corrupt(format!("unknown value type {}", tag))),
+    })
+}
+
+fn read_compressed<R: Reader>(r: &mut R) -> RusqlResult<Vec<u8>> {
+    let len = try!(r.read_be_u64()) as usize;
+    let compressed = try!(r.read_exact(try!(r.read_be_u64()) as usize));
+
+    lz4::decompress(compressed.as_slice(), len).ok_or(RusqlError::Corrupt("bad compressed value".to_string()))
+}
+
+pub fn read_string<R: Reader>(r: &mut R) -> RusqlResult<String> {
+    let len = try!(r.read_be_u64()) as usize;
+
+    String::from_utf8(try!(r.read_exact(len))).map_err(|_| RusqlError::Corrupt("invalid UTF-8 text".to_string()))
+}
+
+fn read_strings<R: Reader>(r: &mut R) -> RusqlResult<Vec<String>> {
+    let mut strings: Vec<String> = Vec::new();
+
+    for _ in range(0, try!(r.read_be_u64())) {
+        strings.push(try!(read_string(r)));
+    }
+    Ok(strings)
+}
+
+/// Parses one of the statements saved, which are kept without a semicolon.
+pub fn parse_one(sql: &str) -> RusqlResult<RusqlStatement> {
+    match exec::parse(format!("{};", sql).as_slice()) {
+        Ok(mut stmts) => {
+            if stmts.len() != 1 {
+                return Err(RusqlError::Corrupt(format!("bad statement: {}", sql)));
+            }
+            Ok(stmts.remove(0))
+        }
+        Err(_) => Err(RusqlError::Corrupt(format!("bad statement: {}", sql))),
+    }
+}
@@ -0,0 +1,555 @@
+//! Decides how a statement reads its tables, which EXPLAIN QUERY PLAN
+//! reports. Every table is scanned in full, in the order of the FROM clause,
+//! but the WHERE clause and the join constraints are broken up into the
+//! conditions ANDed together in them, so that each is checked as early as
+//! it can be: one that only uses a single table filters its rows before
+//! they're joined, and one that uses several is checked as soon as they've
+//! all been joined, instead of on the whole product. A table joined on an
+//! equality with the tables before it is put in a hash table by its side of
+//! the equality, so its matching rows are looked up instead of all tried,
+//! unless both sides are INTEGER PRIMARY KEYs of the first table and this
+//! one: their rows are already in that order, so they're merged instead.
+//!
+//! A table whose INTEGER PRIMARY KEY or indexed column is compared to a
+//! value only has the rows found through it read, instead of all of them.
+//!
+//! The rows of a COLUMNAR table are scanned column by column instead, for
+//! the filters comparing a column to a value.
+//!
+//! Once ANALYZE has collected statistics on every table, they're used to
+//! guess how many rows each table gives: the search finding the fewest is
+//! picked, and the tables are joined starting from the one giving the
+//! fewest, each followed by the smallest of those joined on an equality with
+//! the tables already in.
+
+use definitions::{SelectDef, Expression, BinaryOperator, FromClause, JoinOperator, JoinConstraint};
+use expressions::{conjuncts, eval_constant};
+use stats::TableStats;
+use table::Table;
+
+use std::collections::BTreeMap;
+
+use std::mem::replace;
+
+/// How the rows of one table are read.
+#[derive(Show, Clone, PartialEq)]
+pub enum Access {
+    /// Every row, in the order of their keys.
+    Scan,
+    /// The rows whose `key` is equal to `probe`, which is evaluated on the
+    /// rows of the tables joined before.
+    Hash { key: Expression, probe: Expression },
+    /// The same rows as Hash, found by walking the rows in the order of `key`
+    /// alongside the rows joined before in the order of `probe`.
+    Merge { key: Expression, probe: Expression },
+}
+
+/// How the rows of a table are narrowed down, from its filters comparing a
+/// column to a value.
+#[derive(Show, Clone, PartialEq)]
+pub enum Search {
+    /// The rows whose INTEGER PRIMARY KEY is within the bounds, each with
+    /// whether it's included. For = both are the value.
+    PrimaryKey { lower: Option<(Expression, bool)>, upper: Option<(Expression, bool)> },
+    /// The rows the index on the column at `column` has under `value`.
+    Index { name: String, column: usize, value: Expression },
+    /// The rows of a COLUMNAR table whose values pass the comparisons, each
+    /// a column, how it compares and the value it's compared to. Unlike the
+    /// others, it finds exactly the rows passing the filters it's made from.
+    Columns(Vec<(usize, BinaryOperator, Expression)>),
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct TableAccess {
+    pub table_name: String,
+    /// Where the table is in the FROM clause.
+    pub position: usize,
+    pub access: Access,
+    pub search: Option<Search>,
+    /// The filters the search is made from, which are checked on the rows
+    /// it finds like the others.
+    pub searched: Vec<Expression>,
+    /// The conditions on this table alone, checked on its rows before
+    /// they're joined.
+    pub filters: Vec<Expression>,
+    /// The conditions on this table and the ones before it, checked as its
+    /// rows are joined to theirs.
+    pub join_filters: Vec<Expression>,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct QueryPlan {
+    /// The tables in the order they're joined.
+    pub tables: Vec<TableAccess>,
+    /// The conditions that don't use any of the tables, or use columns
+    /// they don't have, checked once everything is joined.
+    pub filters: Vec<Expression>,
+    /// Whether the results are sorted once they're all in, for ORDER BY.
+    pub sorted: bool,
+}
+
+impl QueryPlan {
+    /// A line for each step, in the spirit of SQLite's EXPLAIN QUERY PLAN.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+
+        for table_access in self.tables.iter() {
+            let mut line = match table_access.search {
+                None => format!("SCAN {}", table_access.table_name),
+                Some(Search::PrimaryKey { .. }) => format!("SEARCH {} USING INTEGER PRIMARY KEY ({})",
+                                                           table_access.table_name,
+                                                           connect_exprs(&table_access.searched)),
+                Some(Search::Index { ref name, .. }) => format!("SEARCH {} USING INDEX {} ({})",
+                                                                table_access.table_name, name,
+                                                                connect_exprs(&table_access.searched)),
+                Some(Search::Columns(..)) => format!("SCAN {} USING COLUMNS ({})", table_access.table_name,
+                                                     connect_exprs(&table_access.searched)),
+            };
+
+            if !table_access.filters.is_empty() {
+                line.push_str(format!(" WHERE {}", connect_exprs(&table_access.filters)).as_slice());
+            }
+            lines.push(line);
+
+            match table_access.access {
+                Access::Scan => {}
+                Access::Hash { ref key, ref probe } => lines.push(format!("HASH JOIN ON {} = {}", probe, key)),
+                Access::Merge { ref key, ref probe } => lines.push(format!("MERGE JOIN ON {} = {}", probe, key)),
+            }
+
+            if !table_access.join_filters.is_empty() {
+                lines.push(format!("JOIN ON {}", connect_exprs(&table_access.join_filters)));
+            }
+        }
+
+        if !self.filters.is_empty() {
+            lines.push(format!("FILTER {}", connect_exprs(&self.filters)));
+        }
+
+        if self.sorted {
+            lines.push("USE TEMP B-TREE FOR ORDER BY".to_string());
+        }
+
+        lines
+    }
+}
+
+fn connect_exprs(exprs: &Vec<Expression>) -> String {
+    let exprs: Vec<String> = exprs.iter().map(|expr| format!("{}", expr)).collect();
+    exprs.connect(" AND ")
+}
+
+/// The plan of a SELECT reading from `tables`, the inputs named in its FROM
+/// clause with any views already run, with the statistics collected on them.
+pub fn plan_select(select_def: &SelectDef, tables: &Vec<&Table>, stats: &BTreeMap<String, TableStats>)
+                   -> QueryPlan {
+    let mut accesses: Vec<TableAccess> = tables.iter().enumerate().map(|(i, table)| {
+        scan(table.name.clone(), i)
+    }).collect();
+    let mut plan = QueryPlan {
+        tables: Vec::new(),
+        filters: Vec::new(),
+        sorted: select_def.ordering_terms.is_some(),
+    };
+    // The conditions using several tables, with the positions of those.
+    let mut joins: Vec<(Expression, Vec<usize>)> = Vec::new();
+
+    let mut exprs = join_constraints(select_def, tables);
+    exprs.extend(select_def.where_expr.clone().into_iter());
+
+    for expr in exprs.iter() {
+        for term in conjuncts(expr).into_iter() {
+            match referenced_tables(&term, tables) {
+                Some(ref used) if used.len() == 1 => accesses[used[0]].filters.push(term),
+                Some(ref used) if used.len() > 1 => joins.push((term, used.clone())),
+                _ => plan.filters.push(term),
+            }
+        }
+    }
+
+    // Views have no statistics, nor do tables that weren't analyzed.
+    let table_stats: Vec<Option<&TableStats>> = tables.iter().map(|table| stats.get(&table.name)).collect();
+    let estimates: Vec<Option<f64>> = accesses.iter_mut().enumerate().map(|(i, table_access)| {
+        plan_search(table_access, i, tables, table_stats[i])
+    }).collect();
+
+    let order: Vec<usize> = if estimates.iter().all(|estimate| estimate.is_some()) {
+        let estimates = estimates.iter().map(|estimate| estimate.unwrap()).collect();
+        join_order(&estimates, &joins, tables)
+    } else {
+        range(0, tables.len()).collect()
+    };
+
+    let mut accesses: Vec<Option<TableAccess>> = accesses.into_iter().map(|table_access| Some(table_access)).collect();
+    plan.tables = order.iter().map(|&i| accesses[i].take().unwrap()).collect();
+
+    for (expr, used) in joins.into_iter() {
+        let last = order.iter().rposition(|i| used.contains(i)).unwrap();
+        plan.tables[last].join_filters.push(expr);
+    }
+
+    for (i, table_access) in plan.tables.iter_mut().enumerate() {
+        let position = table_access.position;
+        let before: Vec<usize> = order.iter().take(i).map(|&j| j).collect();
+        let hash = table_access.join_filters.iter().position(|expr| hash_key(expr, position, &before, tables).is_some());
+
+        if let Some(hash) = hash {
+            let expr = table_access.join_filters.remove(hash);
+            let (key, probe) = hash_key(&expr, position, &before, tables).unwrap();
+
+            table_access.access = if is_primary_key(&key, position, tables) && is_primary_key(&probe, order[0], tables) {
+                Access::Merge { key: key, probe: probe }
+            } else {
+                Access::Hash { key: key, probe: probe }
+            };
+        }
+    }
+
+    plan
+}
+
+// The order to join the tables in, given about how many rows each gives:
+// the one giving the fewest first, then each time the one giving the fewest
+// of those that can be joined on an equality with the tables already in, or
+// of all the rest if none can. Ties keep the order of the FROM clause.
+fn join_order(estimates: &Vec<f64>, joins: &Vec<(Expression, Vec<usize>)>, tables: &Vec<&Table>) -> Vec<usize> {
+    let mut order: Vec<usize> = Vec::new();
+
+    while order.len() < tables.len() {
+        let rest: Vec<usize> = range(0, tables.len()).filter(|i| !order.contains(i)).collect();
+        let joined: Vec<usize> = rest.iter().map(|&i| i).filter(|&i| {
+            joins.iter().any(|&(ref expr, _)| hash_key(expr, i, &order, tables).is_some())
+        }).collect();
+        let candidates = if joined.is_empty() { rest } else { joined };
+
+        let mut next = candidates[0];
+        for &i in candidates.iter() {
+            if estimates[i] < estimates[next] {
+                next = i;
+            }
+        }
+        order.push(next);
+    }
+
+    order
+}
+
+// The sides of `expr` if it's an equality between a column of the table at
+// `position` in `tables` and the tables at `before`, joined before it: the
+// side on the table first, then the side on the others.
+fn hash_key(expr: &Expression, position: usize, before: &Vec<usize>, tables: &Vec<&Table>)
+            -> Option<(Expression, Expression)> {
+    let (left, right) = match expr {
+        &Expression::BinaryOperator((BinaryOperator::Equals, ref left, ref right)) => (&**left, &**right),
+        _ => return None,
+    };
+    let only_this = |expr: &Expression| referenced_tables(expr, tables) == Some(vec![position]);
+    let only_before = |expr: &Expression| match referenced_tables(expr, tables) {
+        Some(ref used) => !used.is_empty() && used.iter().all(|i| before.contains(i)),
+        None => false,
+    };
+
+    if only_this(left) && only_before(right) {
+        Some((left.clone(), right.clone()))
+    } else if only_this(right) && only_before(left) {
+        Some((right.clone(), left.clone()))
+    } else {
+        None
+    }
+}
+
+// Whether `expr` is the INTEGER PRIMARY KEY of the table at `position` in
+// `tables`, which its rows are kept in the order of.
+fn is_primary_key(expr: &Expression, position: usize, tables: &Vec<&Table>) -> bool {
+    let column = column_index(expr, position, tables);
+    column.is_some() && column == tables[position].pk
+}
+
+// The position of the column `expr` is, if it's one of the table at
+// `position` in `tables`.
+fn column_index(expr: &Expression, position: usize, tables: &Vec<&Table>) -> Option<usize> {
+    let name = match expr {
+        &Expression::ColumnName(ref name) => name,
+        &Expression::TableName((_, box Expression::ColumnName(ref name))) => name,
+        _ => return None,
+    };
+
+    if referenced_tables(expr, tables) != Some(vec![position]) {
+        return None;
+    }
+
+    tables[position].get_column_index(name)
+}
+
+// Picks a search for the table at `position` from its filters: by its
+// INTEGER PRIMARY KEY being equal to a value, then by an indexed column being
+// equal to one, then by the key being within bounds. With statistics, the
+// one finding the fewest rows is picked instead, or none if no search finds
+// fewer than all of them, and about how many rows pass the table's filters
+// is returned. Without any, a COLUMNAR table is scanned by its columns for
+// the filters comparing one to a value.
+fn plan_search(table_access: &mut TableAccess, position: usize, tables: &Vec<&Table>,
+               stats: Option<&TableStats>) -> Option<f64> {
+    let table = tables[position];
+    let is_pk = |column: usize| table.pk == Some(column);
+    let row_count = stats.map_or(0.0, |stats| stats.row_count as f64);
+
+    // The filters comparing a column to a value: which filter, the column,
+    // how the column compares to the value, and the value.
+    let comparisons: Vec<(usize, usize, BinaryOperator, Expression)> =
+            table_access.filters.iter().enumerate().filter_map(|(i, expr)| {
+        column_comparison(expr, position, tables).map(|(column, op, value)| (i, column, op, value))
+    }).collect();
+
+    let pk_equal = comparisons.iter().find(|&&(_, column, op, _)| is_pk(column) && op == BinaryOperator::Equals);
+    let lower = comparisons.iter().find(|&&(_, column, op, _)| {
+        is_pk(column) && (op == BinaryOperator::Greater || op == BinaryOperator::GreaterEq)
+    });
+    let upper = comparisons.iter().find(|&&(_, column, op, _)| {
+        is_pk(column) && (op == BinaryOperator::Less || op == BinaryOperator::LessEq)
+    });
+
+    // Each search that can be made, with the filters it's made from and
+    // about how many rows it finds.
+    let mut searches: Vec<(Search, Vec<usize>, f64)> = Vec::new();
+
+    if let Some(&(i, _, _, ref value)) = pk_equal {
+        searches.push((Search::PrimaryKey { lower: Some((value.clone(), true)), upper: Some((value.clone(), true)) },
+                       vec![i], 1.0));
+    }
+
+    for &(i, column, op, ref value) in comparisons.iter() {
+        if op != BinaryOperator::Equals {
+            continue;
+        }
+
+        if let Some(index) = table.index_on(column) {
+            let rows = stats.map_or(0.0, |stats| stats.equal_rows(&table.header[column].name));
+            searches.push((Search::Index { name: index.name.clone(), column: column, value: value.clone() },
+                           vec![i], rows));
+        }
+    }
+
+    if lower.is_some() || upper.is_some() {
+        let bound = |comparison: Option<&(usize, usize, BinaryOperator, Expression)>| comparison.map(|&(_, _, op, ref value)| {
+            (value.clone(), op == BinaryOperator::GreaterEq || op == BinaryOperator::LessEq)
+        });
+        let used = lower.iter().chain(upper.iter()).map(|&&(i, _, _, _)| i).collect();
+        let rows = stats.map_or(0.0, |stats| {
+            let value = |comparison: Option<&(usize, usize, BinaryOperator, Expression)>| {
+                comparison.map(|&(_, _, _, ref value)| eval_constant(value))
+            };
+            let name = &table.header[table.pk.unwrap()].name;
+            stats.range_rows(name, value(lower).as_ref(), value(upper).as_ref())
+        });
+
+        searches.push((Search::PrimaryKey { lower: bound(lower), upper: bound(upper) }, used, rows));
+    }
+
+    let mut best: Option<usize> = None;
+    for (i, &(_, _, rows)) in searches.iter().enumerate() {
+        if stats.is_none() {
+            best = Some(0);
+            break;
+        } else if rows < best.map_or(row_count, |best| searches[best].2) {
+            best = Some(i);
+        }
+    }
+
+    let mut rows = row_count;
+    if let Some(best) = best {
+        let (search, used, search_rows) = searches.remove(best);
+        let filters = replace(&mut table_access.filters, Vec::new());
+
+        for (i, expr) in filters.into_iter().enumerate() {
+            if used.contains(&i) {
+                table_access.searched.push(expr);
+            } else {
+                table_access.filters.push(expr);
+            }
+        }
+        table_access.search = Some(search);
+        rows = search_rows;
+    }
+
+    let estimate = stats.map(|stats| {
+        // Each other filter comparing a column to a value is taken to let
+        // through the rows with one of its values for =, and a third of them
+        // otherwise.
+        table_access.filters.iter().fold(rows, |rows, expr| {
+            match column_comparison(expr, position, tables) {
+                Some((column, BinaryOperator::Equals, _)) if stats.row_count > 0 => {
+                    rows * stats.equal_rows(&table.header[column].name) / row_count
+                }
+                Some(_) => rows / 3.0,
+                None => rows,
+            }
+        })
+    });
+
+    if table_access.search.is_none() && table.columns.is_some() && !comparisons.is_empty() {
+        let filters = replace(&mut table_access.filters, Vec::new());
+
+        for (i, expr) in filters.into_iter().enumerate() {
+            if comparisons.iter().any(|&(j, _, _, _)| i == j) {
+                table_access.searched.push(expr);
+            } else {
+                table_access.filters.push(expr);
+            }
+        }
+        table_access.search = Some(Search::Columns(comparisons.iter().map(|&(_, column, op, ref value)| {
+            (column, op, value.clone())
+        }).collect()));
+    }
+
+    estimate
+}
+
+// The column, the way it compares to the value and the value, if `expr`
+// compares a column of the table at `position` to something that doesn't
+// depend on any row.
+fn column_comparison(expr: &Expression, position: usize, tables: &Vec<&Table>)
+                     -> Option<(usize, BinaryOperator, Expression)> {
+    let (op, left, right) = match expr {
+        &Expression::BinaryOperator((op, ref left, ref right)) => (op, &**left, &**right),
+        _ => return None,
+    };
+    let flipped = match op {
+        BinaryOperator::Equals => BinaryOperator::Equals,
+        BinaryOperator::Less => BinaryOperator::Greater,
+        BinaryOperator::LessEq => BinaryOperator::GreaterEq,
+        BinaryOperator::Greater => BinaryOperator::Less,
+        BinaryOperator::GreaterEq => BinaryOperator::LessEq,
+        _ => return None,
+    };
+    let is_constant = |expr: &Expression| referenced_tables(expr, tables) == Some(Vec::new());
+
+    match (column_index(left, position, tables), column_index(right, position, tables)) {
+        (Some(column), _) if is_constant(right) => Some((column, op, right.clone())),
+        (_, Some(column)) if is_constant(left) => Some((column, flipped, left.clone())),
+        _ => None,
+    }
+}
+
+/// The plan of a DELETE or UPDATE of `table` with `where_expr`, with or
+/// without ORDER BY, given the statistics collected on the table if any.
+pub fn plan_write(table: &Table, where_expr: &Option<Expression>, sorted: bool, stats: Option<&TableStats>)
+                  -> QueryPlan {
+    let tables = vec![table];
+    let mut table_access = scan(table.name.clone(), 0);
+
+    for expr in where_expr.iter() {
+        table_access.filters.push_all(conjuncts(expr).as_slice());
+    }
+    plan_search(&mut table_access, 0, &tables, stats);
+
+    QueryPlan {
+        tables: vec![table_access],
+        filters: Vec::new(),
+        sorted: sorted,
+    }
+}
+
+fn scan(table_name: String, position: usize) -> TableAccess {
+    TableAccess {
+        table_name: table_name,
+        position: position,
+        access: Access::Scan,
+        search: None,
+        searched: Vec::new(),
+        filters: Vec::new(),
+        join_filters: Vec::new(),
+    }
+}
+
+/// The ON constraints of the joins, and the equalities of the columns a
+/// NATURAL JOIN shares with the tables before it.
+pub fn join_constraints(select_def: &SelectDef, tables: &Vec<&Table>) -> Vec<Expression> {
+    let mut constraints: Vec<JoinConstraint> = Vec::new();
+
+    if let Some(FromClause::JoinClause(_, Some(ref join_clauses))) = select_def.from_clause {
+        for (i, &(ref join_operator, _, ref join_const)) in join_clauses.iter().enumerate() {
+            if let &Some(ref constraint) = join_const {
+                constraints.push(constraint.clone());
+            }
+
+            if let &JoinOperator::Natural = join_operator {
+                let joined: Vec<&Table> = tables.iter().take(i + 2).map(|table| *table).collect();
+                natural_join(&joined, &mut constraints);
+            }
+        }
+    }
+
+    constraints.into_iter().map(|constraint| {
+        match constraint {
+            JoinConstraint::On(expr) => expr,
+        }
+    }).collect()
+}
+
+fn natural_join(tables: &Vec<&Table>, constraints: &mut Vec<JoinConstraint>) {
+    // FIXME ...
+    let mut columns: Vec<(String, String, String)> = Vec::new();
+
+    for table in tables.iter() {
+        for other in tables.iter() {
+            if table == other {
+                continue;
+            }
+
+            for col in table.header.iter() {
+                for other_col in other.header.iter() {
+                    if col.name == other_col.name {
+                        columns.push((table.name.clone(), other.name.clone(), other_col.name.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    for (table1, table2, column_name) in columns.into_iter() {
+        constraints.push(JoinConstraint::On(
+                Expression::BinaryOperator((BinaryOperator::Equals,
+                    box Expression::TableName((table1, box Expression::ColumnName(column_name.clone()))),
+                    box Expression::TableName((table2, box Expression::ColumnName(column_name)))))));
+    }
+}
+
+// The positions in `tables` of the tables `expr` uses, found the same way
+// the evaluator looks its columns up: by name for Foo.Id, and the first table
+// with the column for a plain Id. None if one can't be found.
+fn referenced_tables(expr: &Expression, tables: &Vec<&Table>) -> Option<Vec<usize>> {
+    let mut used: Vec<usize> = Vec::new();
+
+    let found = match expr {
+        &Expression::TableName((ref name, _)) => {
+            tables.iter().position(|table| &table.name == name).map(|i| used.push(i)).is_some()
+        }
+        &Expression::ColumnName(ref name) => {
+            tables.iter().position(|table| table.get_column_def_by_name(name).is_some())
+                         .map(|i| used.push(i)).is_some()
+        }
+        &Expression::BinaryOperator((_, ref left, ref right)) => {
+            [&**left, &**right].iter().all(|expr| {
+                referenced_tables(*expr, tables).map(|positions| used.push_all(positions.as_slice())).is_some()
+            })
+        }
+        &Expression::UnaryOperator((_, ref expr)) => {
+            referenced_tables(&**expr, tables).map(|positions| used.push_all(positions.as_slice())).is_some()
+        }
+        &Expression::Function((_, ref args)) => {
+            args.iter().all(|arg| {
+                referenced_tables(arg, tables).map(|positions| used.push_all(positions.as_slice())).is_some()
+            })
+        }
+        _ => true,
+    };
+
+    if !found {
+        return None;
+    }
+
+    used.sort();
+    used.dedup();
+    Some(used)
+}
@@ -0,0 +1,176 @@
+//! The PRAGMAs, looked up by name when one runs. More can be added with
+//! Rusql::register_pragma.
+//! https://www.sqlite.org/pragma.html
+
+use definitions::LiteralValue;
+use error::{RusqlError, RusqlResult};
+use rusql::Rusql;
+use table::{Table, TableRow};
+
+use std::ascii::AsciiExt;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Runs a PRAGMA given its value, as in `PRAGMA name = value` or
+/// `PRAGMA name(value)`, returning its results if it has any.
+pub type PragmaHandler = fn(&mut Rusql, Option<String>) -> RusqlResult<Option<Table>>;
+
+pub fn builtin_pragmas() -> BTreeMap<String, PragmaHandler> {
+    let mut pragmas: BTreeMap<String, PragmaHandler> = BTreeMap::new();
+
+    pragmas.insert("cache_size".to_string(), cache_size as PragmaHandler);
+    pragmas.insert("cache_stats".to_string(), cache_stats as PragmaHandler);
+    pragmas.insert("case_sensitive_like".to_string(), case_sensitive_like as PragmaHandler);
+    pragmas.insert("foreign_keys".to_string(), foreign_keys as PragmaHandler);
+    pragmas.insert("journal_mode".to_string(), journal_mode as PragmaHandler);
+    pragmas.insert("memory_used".to_string(), memory_used as PragmaHandler);
+    pragmas.insert("soft_heap_limit".to_string(), soft_heap_limit as PragmaHandler);
+    pragmas.insert("table_info".to_string(), table_info as PragmaHandler);
+    pragmas.insert("wal_checkpoint".to_string(), wal_checkpoint as PragmaHandler);
+
+    pragmas
+}
+
+/// Reads a PRAGMA's value as a flag, like SQLite.
+pub fn parse_flag(value: &String) -> bool {
+    match value.as_slice() {
+        "ON" | "on" | "TRUE" | "true" | "YES" | "yes" | "1" => true,
+        _ => false,
+    }
+}
+
+fn flag_results(name: &str, flag: bool) -> Option<Table> {
+    Some(Table::from_rows(&[name], vec![vec![LiteralValue::Integer(flag as isize)]]))
+}
+
+/// Sets how many pages the cache of a paged file keeps, or with a negative
+/// value how many KiB, like SQLite. Gives back the number of pages, 0 for a
+/// database without a paged file.
+fn cache_size(db: &mut Rusql, value: Option<String>) -> RusqlResult<Option<Table>> {
+    let paged = match db.paged_file() {
+        Some(paged) => paged,
+        None => return Ok(Some(Table::from_rows(&["cache_size"], vec![vec![LiteralValue::Integer(0)]]))),
+    };
+
+    if let Some(ref value) = value {
+        match value.as_slice().trim().parse::<isize>() {
+            Some(size) if size < 0 => paged.set_cache_size((-size) as usize * 1024 / paged.page_size()),
+            Some(size) => paged.set_cache_size(size as usize),
+            None => return Err(RusqlError::TypeMismatch(format!("not a number of pages: {}", value))),
+        }
+    }
+
+    Ok(Some(Table::from_rows(&["cache_size"], vec![vec![LiteralValue::Integer(paged.cache_size() as isize)]])))
+}
+
+/// How many page reads of a paged file found their page in the cache, how
+/// many went to the file and how many pages are cached; no rows for a
+/// database without a paged file.
+fn cache_stats(db: &mut Rusql, _: Option<String>) -> RusqlResult<Option<Table>> {
+    let rows = match db.paged_file() {
+        Some(paged) => {
+            let (hits, misses, pages) = paged.cache_stats();
+            vec![vec![LiteralValue::Integer(hits as isize), LiteralValue::Integer(misses as isize),
+                      LiteralValue::Integer(pages as isize)]]
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Some(Table::from_rows(&["hits", "misses", "pages"], rows)))
+}
+
+fn case_sensitive_like(db: &mut Rusql, value: Option<String>) -> RusqlResult<Option<Table>> {
+    match value {
+        Some(ref value) => {
+            db.case_sensitive_like = parse_flag(value);
+            Ok(None)
+        }
+        None => Ok(flag_results("case_sensitive_like", db.case_sensitive_like)),
+    }
+}
+
+fn foreign_keys(db: &mut Rusql, value: Option<String>) -> RusqlResult<Option<Table>> {
+    match value {
+        Some(ref value) => {
+            db.foreign_keys = parse_flag(value);
+            Ok(None)
+        }
+        None => Ok(flag_results("foreign_keys", db.foreign_keys)),
+    }
+}
+
+/// Sets the journal mode of a paged file, WAL or DELETE, and gives it back;
+/// no rows for a database without a paged file.
+fn journal_mode(db: &mut Rusql, value: Option<String>) -> RusqlResult<Option<Table>> {
+    if db.paged_file().is_none() {
+        return Ok(Some(Table::from_rows(&["journal_mode"], Vec::new())));
+    }
+
+    if let Some(ref value) = value {
+        match value.as_slice().trim().to_ascii_lowercase().as_slice() {
+            "wal" => try!(db.set_wal_mode(true)),
+            "delete" => try!(db.set_wal_mode(false)),
+            _ => return Err(RusqlError::TypeMismatch(format!("not a journal mode: {}", value))),
+        }
+    }
+
+    let mode = db.paged_file().unwrap().journal_mode();
+    Ok(Some(Table::from_rows(&["journal_mode"], vec![vec![LiteralValue::text(mode)]])))
+}
+
+/// About how many bytes the rows of every table take.
+fn memory_used(db: &mut Rusql, _: Option<String>) -> RusqlResult<Option<Table>> {
+    Ok(Some(Table::from_rows(&["memory_used"], vec![vec![LiteralValue::Integer(db.memory_used() as isize)]])))
+}
+
+/// Sets the memory limit in bytes, 0 for none, same as SQLite. Gives back
+/// the limit either way.
+fn soft_heap_limit(db: &mut Rusql, value: Option<String>) -> RusqlResult<Option<Table>> {
+    if let Some(ref value) = value {
+        match value.as_slice().trim().parse::<usize>() {
+            Some(0) => db.set_memory_limit(None),
+            Some(limit) => db.set_memory_limit(Some(limit)),
+            None => return Err(RusqlError::TypeMismatch(format!("not a number of bytes: {}", value))),
+        }
+    }
+
+    let limit = db.memory_limit().unwrap_or(0);
+    Ok(Some(Table::from_rows(&["soft_heap_limit"], vec![vec![LiteralValue::Integer(limit as isize)]])))
+}
+
+/// Same as CHECKPOINT.
+fn wal_checkpoint(db: &mut Rusql, _: Option<String>) -> RusqlResult<Option<Table>> {
+    try!(db.checkpoint());
+    Ok(None)
+}
+
+/// A row for every column of the table: its position, name, type, whether
+/// it's NOT NULL, its default and its position in the primary key.
+fn table_info(db: &mut Rusql, value: Option<String>) -> RusqlResult<Option<Table>> {
+    let name = match value {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let table = try!(db.get_table(&name));
+    let pk_columns = table.get_pk_columns();
+
+    let rows: Vec<TableRow> = table.header.iter().enumerate().map(|(i, column_def)| {
+        let column_type = match column_def.column_type {
+            Some(column_type) => format!("{}", column_type),
+            None => "".to_string(),
+        };
+        let default = match column_def.get_default() {
+            Some(expr) => LiteralValue::Text(Arc::new(format!("{}", expr))),
+            None => LiteralValue::Null,
+        };
+        let pk = match pk_columns.iter().position(|&column| column == i) {
+            Some(position) => position as isize + 1,
+            None => 0,
+        };
+
+        vec![LiteralValue::Integer(i as isize), LiteralValue::text(column_def.name.as_slice()),
+             LiteralValue::Text(Arc::new(column_type)), LiteralValue::Integer(0), default, LiteralValue::Integer(pk)]
+    }).collect();
+
+    Ok(Some(Table::from_rows(&["cid", "name", "type", "notnull", "dflt_value", "pk"], rows)))
+}
@@ -0,0 +1,321 @@
+//! Arrow support, behind the `arrow` feature: tables convert to and from
+//! RecordBatches, which hold their columns in Arrow's columnar format, so
+//! results can go to Arrow-based tools like DataFusion or Polars without
+//! going through CSV. https://arrow.apache.org/docs/format/Columnar.html
+//!
+//! The buffers of an array are the ones Arrow has: a validity bitmap with a
+//! bit per value, set unless it's NULL, the first in the lowest bit; for
+//! Utf8 and Binary, a little-endian i32 offset into the bytes of the values
+//! for each of them and one past the last; and the values, 8 bytes each,
+//! little-endian, for Int64 and Float64 and a bit each for Boolean. A Null
+//! array has no buffers.
+//!
+//! A column's type comes from its values, as in the parquet module: Int64
+//! if they're all integers, Float64 if they're all numbers, decimals
+//! included, Boolean if they're all booleans, Binary if they're all blobs,
+//! Null if they're all NULL and Utf8, with the values as text, otherwise.
+
+use definitions::{ColumnDef, ColumnType, LiteralValue};
+use error::{RusqlError, RusqlResult};
+use table::{Table, TableHeader, TableRow};
+
+use std::mem;
+use std::str;
+
+#[derive(Show, Copy, Clone, PartialEq)]
+pub enum DataType {
+    Null,
+    Boolean,
+    Int64,
+    Float64,
+    Utf8,
+    Binary,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub data_type: DataType,
+    pub nullable: bool,
+}
+
+/// A column of a RecordBatch.
+#[derive(Show, Clone, PartialEq)]
+pub struct Array {
+    data_type: DataType,
+    len: usize,
+    validity: Vec<u8>,
+    offsets: Vec<u8>,
+    values: Vec<u8>,
+}
+
+impl Array {
+    /// An array of `len` values made from its buffers, see the module
+    /// documentation. `validity` is left empty if no value is NULL, and
+    /// `offsets` unless the values are Utf8 or Binary. Fails with
+    /// TypeMismatch if the buffers don't hold `len` values.
+    pub fn new(data_type: DataType, len: usize, validity: Vec<u8>, offsets: Vec<u8>,
+               values: Vec<u8>) -> RusqlResult<Array> {
+        let bitmap_len = (len + 7) / 8;
+        let fits = match data_type {
+            DataType::Null => validity.is_empty() && offsets.is_empty() && values.is_empty(),
+            DataType::Boolean => offsets.is_empty() && values.len() >= bitmap_len,
+            DataType::Int64 | DataType::Float64 => offsets.is_empty() && values.len() >= len * 8,
+            DataType::Utf8 | DataType::Binary => offsets.len() == (len + 1) * 4,
+        };
+
+        if !fits || !(validity.is_empty() || validity.len() >= bitmap_len) {
+            return Err(invalid(data_type, "buffers too short"));
+        }
+
+        let array = Array { data_type: data_type, len: len, validity: validity, offsets: offsets, values: values };
+
+        if data_type == DataType::Utf8 || data_type == DataType::Binary {
+            let offsets: Vec<i32> = range(0, len + 1).map(|i| array.offset(i)).collect();
+
+            if offsets[0] < 0 || offsets.windows(2).any(|pair| pair[0] > pair[1])
+                    || offsets[len] as usize > array.values.len() {
+                return Err(invalid(data_type, "offsets out of order or past the values"));
+            }
+            if data_type == DataType::Utf8 && range(0, len).any(|i| str::from_utf8(array.bytes(i)).is_err()) {
+                return Err(invalid(data_type, "invalid UTF-8 text"));
+            }
+        }
+
+        Ok(array)
+    }
+
+    pub fn data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn validity(&self) -> &[u8] {
+        self.validity.as_slice()
+    }
+
+    pub fn offsets(&self) -> &[u8] {
+        self.offsets.as_slice()
+    }
+
+    pub fn values(&self) -> &[u8] {
+        self.values.as_slice()
+    }
+
+    pub fn is_null(&self, i: usize) -> bool {
+        self.data_type == DataType::Null || (!self.validity.is_empty() && !bit(self.validity.as_slice(), i))
+    }
+
+    pub fn null_count(&self) -> usize {
+        range(0, self.len).filter(|&i| self.is_null(i)).count()
+    }
+
+    /// The `i`th value, NULL if its bit in the validity bitmap isn't set.
+    pub fn value(&self, i: usize) -> LiteralValue {
+        if self.is_null(i) {
+            return LiteralValue::Null;
+        }
+
+        match self.data_type {
+            DataType::Null => LiteralValue::Null,
+            DataType::Boolean => LiteralValue::Boolean(bit(self.values.as_slice(), i)),
+            DataType::Int64 => LiteralValue::Integer(le_uint(&self.values[i * 8..i * 8 + 8]) as i64 as isize),
+            DataType::Float64 => {
+                LiteralValue::Real(unsafe { mem::transmute::<u64, f64>(le_uint(&self.values[i * 8..i * 8 + 8])) })
+            }
+            DataType::Utf8 => LiteralValue::text(str::from_utf8(self.bytes(i)).unwrap()),
+            DataType::Binary => LiteralValue::Blob(self.bytes(i).to_vec()),
+        }
+    }
+
+    fn offset(&self, i: usize) -> i32 {
+        le_uint(&self.offsets[i * 4..i * 4 + 4]) as u32 as i32
+    }
+
+    // The bytes of the `i`th value of a Utf8 or Binary array.
+    fn bytes(&self, i: usize) -> &[u8] {
+        &self.values[self.offset(i) as usize..self.offset(i + 1) as usize]
+    }
+}
+
+/// Columns of the same length, with a field naming each of them.
+#[derive(Show, Clone, PartialEq)]
+pub struct RecordBatch {
+    fields: Vec<Field>,
+    columns: Vec<Array>,
+    num_rows: usize,
+}
+
+impl RecordBatch {
+    /// Fails with TypeMismatch unless there is a column of `num_rows` values
+    /// for each field, of its type, and only a nullable field has NULLs.
+    pub fn new(fields: Vec<Field>, columns: Vec<Array>, num_rows: usize) -> RusqlResult<RecordBatch> {
+        if fields.len() != columns.len() {
+            return Err(RusqlError::TypeMismatch(format!("{} fields for {} columns", fields.len(), columns.len())));
+        }
+
+        for (field, column) in fields.iter().zip(columns.iter()) {
+            if column.data_type != field.data_type || column.len != num_rows
+                    || (!field.nullable && column.null_count() > 0) {
+                return Err(RusqlError::TypeMismatch(format!("column {} doesn't match its field", field.name)));
+            }
+        }
+
+        Ok(RecordBatch { fields: fields, columns: columns, num_rows: num_rows })
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        self.fields.as_slice()
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn column(&self, i: usize) -> &Array {
+        &self.columns[i]
+    }
+}
+
+impl Table {
+    /// The rows as a RecordBatch with a nullable field for each column.
+    pub fn to_record_batch(&self) -> RusqlResult<RecordBatch> {
+        let rows: Vec<&TableRow> = self.data.values().collect();
+        let mut fields: Vec<Field> = Vec::new();
+        let mut columns: Vec<Array> = Vec::new();
+
+        for (i, column) in self.header.iter().enumerate() {
+            let values: Vec<&LiteralValue> = rows.iter().map(|row| &row[i]).collect();
+            let array = try!(to_array(values.as_slice()));
+
+            fields.push(Field { name: column.name.clone(), data_type: array.data_type, nullable: true });
+            columns.push(array);
+        }
+
+        RecordBatch::new(fields, columns, rows.len())
+    }
+
+    /// A result table with the rows of `batch`, with its columns typed after
+    /// the fields.
+    pub fn from_record_batch(batch: &RecordBatch) -> RusqlResult<Table> {
+        let header: TableHeader = batch.fields.iter().map(|field| {
+            ColumnDef {
+                name: field.name.clone(),
+                column_type: column_type(field.data_type),
+                column_constraints: Vec::new(),
+            }
+        }).collect();
+
+        let mut table = Table::new_result_table(header);
+        for i in range(0, batch.num_rows) {
+            try!(table.push_row(batch.columns.iter().map(|column| column.value(i)).collect()));
+        }
+
+        Ok(table)
+    }
+}
+
+fn data_type(values: &[&LiteralValue]) -> DataType {
+    let mut data_type = DataType::Null;
+
+    for value in values.iter() {
+        let this = match **value {
+            LiteralValue::Null => continue,
+            LiteralValue::Boolean(..) => DataType::Boolean,
+            LiteralValue::Integer(..) => DataType::Int64,
+            LiteralValue::Real(..) | LiteralValue::Decimal(..) => DataType::Float64,
+            LiteralValue::Blob(..) => DataType::Binary,
+            _ => return DataType::Utf8,
+        };
+
+        data_type = match data_type {
+            DataType::Null => this,
+            seen if seen == this => seen,
+            DataType::Int64 | DataType::Float64 if this == DataType::Int64 || this == DataType::Float64 => {
+                DataType::Float64
+            }
+            _ => return DataType::Utf8,
+        };
+    }
+
+    data_type
+}
+
+fn to_array(values: &[&LiteralValue]) -> RusqlResult<Array> {
+    let data_type = data_type(values);
+    let present: Vec<bool> = values.iter().map(|value| !value.is_null()).collect();
+    let validity = if present.iter().all(|&bit| bit) { Vec::new() } else { pack_bits(present.as_slice()) };
+    let mut offsets: Vec<u8> = Vec::new();
+    let mut bytes: Vec<u8> = Vec::new();
+
+    match data_type {
+        DataType::Null => return Array::new(data_type, values.len(), Vec::new(), Vec::new(), Vec::new()),
+        DataType::Boolean => {
+            let bits: Vec<bool> = values.iter().map(|value| **value == LiteralValue::Boolean(true)).collect();
+            bytes = pack_bits(bits.as_slice());
+        }
+        DataType::Int64 => for value in values.iter() {
+            bytes.push_all(le_bytes(value.to_int() as i64 as u64, 8).as_slice());
+        },
+        DataType::Float64 => for value in values.iter() {
+            let r = if value.is_null() { 0.0 } else { value.to_real() };
+            bytes.push_all(le_bytes(unsafe { mem::transmute::<f64, u64>(r) }, 8).as_slice());
+        },
+        DataType::Utf8 | DataType::Binary => {
+            offsets.push_all(le_bytes(0, 4).as_slice());
+
+            for value in values.iter() {
+                match **value {
+                    LiteralValue::Null => {}
+                    LiteralValue::Blob(ref blob) if data_type == DataType::Binary => bytes.push_all(blob.as_slice()),
+                    LiteralValue::Text(ref text) => bytes.push_all(text.as_bytes()),
+                    ref value => bytes.push_all(format!("{}", value).as_bytes()),
+                }
+                offsets.push_all(le_bytes(bytes.len() as u64, 4).as_slice());
+            }
+        }
+    }
+
+    Array::new(data_type, values.len(), validity, offsets, bytes)
+}
+
+fn column_type(data_type: DataType) -> Option<ColumnType> {
+    match data_type {
+        DataType::Null => None,
+        DataType::Boolean => Some(ColumnType::Boolean),
+        DataType::Int64 => Some(ColumnType::Integer),
+        DataType::Float64 => Some(ColumnType::Real),
+        DataType::Utf8 => Some(ColumnType::Text),
+        DataType::Binary => Some(ColumnType::Blob),
+    }
+}
+
+fn invalid(data_type: DataType, why: &str) -> RusqlError {
+    RusqlError::TypeMismatch(format!("invalid {:?} array: {}", data_type, why))
+}
+
+// Eight to a byte, the first in the lowest bit.
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8).map(|byte| {
+        byte.iter().enumerate().fold(0u8, |packed, (i, &bit)| if bit { packed | (1 << i) } else { packed })
+    }).collect()
+}
+
+fn bit(bitmap: &[u8], i: usize) -> bool {
+    bitmap[i / 8] & (1 << (i % 8)) != 0
+}
+
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn le_bytes(value: u64, len: usize) -> Vec<u8> {
+    range(0, len).map(|i| (value >> (8 * i)) as u8).collect()
+}
@@ -0,0 +1,165 @@
+use decimal::Decimal;
+use definitions::LiteralValue;
+use error::{RusqlError, RusqlResult};
+use table::{TableRow, TableHeader};
+
+/// Rust types a value can be read as, see Row::get.
+pub trait FromValue {
+    fn from_value(value: &LiteralValue) -> RusqlResult<Self>;
+}
+
+fn mismatch(value: &LiteralValue, type_name: &str) -> RusqlError {
+    RusqlError::TypeMismatch(format!("cannot read {} value as {}", value.type_name(), type_name))
+}
+
+impl FromValue for LiteralValue {
+    fn from_value(value: &LiteralValue) -> RusqlResult<LiteralValue> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for isize {
+    fn from_value(value: &LiteralValue) -> RusqlResult<isize> {
+        match value {
+            &LiteralValue::Integer(i) => Ok(i),
+            &LiteralValue::Boolean(b) => Ok(if b { 1 } else { 0 }),
+            _ => Err(mismatch(value, "isize")),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &LiteralValue) -> RusqlResult<i64> {
+        FromValue::from_value(value).map(|i: isize| i as i64).map_err(|_| mismatch(value, "i64"))
+    }
+}
+
+impl FromValue for usize {
+    fn from_value(value: &LiteralValue) -> RusqlResult<usize> {
+        match FromValue::from_value(value) {
+            Ok(i) if i >= 0is => Ok(i as usize),
+            _ => Err(mismatch(value, "usize")),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &LiteralValue) -> RusqlResult<f64> {
+        match value {
+            &LiteralValue::Real(r) => Ok(r),
+            &LiteralValue::Integer(i) => Ok(i as f64),
+            &LiteralValue::Decimal(d) => Ok(d.to_real()),
+            _ => Err(mismatch(value, "f64")),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &LiteralValue) -> RusqlResult<bool> {
+        match value {
+            &LiteralValue::Boolean(b) => Ok(b),
+            &LiteralValue::Integer(i) => Ok(i != 0),
+            _ => Err(mismatch(value, "bool")),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &LiteralValue) -> RusqlResult<String> {
+        match value {
+            &LiteralValue::Text(ref s) => Ok((**s).clone()),
+            _ => Err(mismatch(value, "String")),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &LiteralValue) -> RusqlResult<Vec<u8>> {
+        match value {
+            &LiteralValue::Blob(ref bytes) => Ok(bytes.clone()),
+            &LiteralValue::Text(ref s) => Ok(s.as_bytes().to_vec()),
+            _ => Err(mismatch(value, "Vec<u8>")),
+        }
+    }
+}
+
+impl FromValue for Decimal {
+    fn from_value(value: &LiteralValue) -> RusqlResult<Decimal> {
+        match value {
+            &LiteralValue::Decimal(d) => Ok(d),
+            &LiteralValue::Integer(i) => Ok(Decimal::from_int(i)),
+            _ => Err(mismatch(value, "Decimal")),
+        }
+    }
+}
+
+/// NULL reads as None, anything else the way T reads it.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &LiteralValue) -> RusqlResult<Option<T>> {
+        match value {
+            &LiteralValue::Null => Ok(None),
+            _ => FromValue::from_value(value).map(|v| Some(v)),
+        }
+    }
+}
+
+/// Types a whole row can be read as, e.g. by Rusql::query_as. The from_row!
+/// macro implements it for structs whose fields are named after columns.
+pub trait FromRow {
+    fn from_row(row: &Row) -> RusqlResult<Self>;
+}
+
+/// Implements FromRow for a struct, reading each of the listed fields from
+/// the column with the same name:
+///
+/// ```ignore
+/// struct Foo { Id: isize, Name: Option<String> }
+/// from_row!(Foo { Id, Name });
+/// ```
+#[macro_export]
+macro_rules! from_row {
+    ($name:ident { $($field:ident),* }) => {
+        impl $crate::FromRow for $name {
+            fn from_row(row: &$crate::Row) -> $crate::RusqlResult<$name> {
+                Ok($name {
+                    $($field: try!(row.get_by_name(stringify!($field)))),*
+                })
+            }
+        }
+    };
+}
+
+/// A row of a result set along with its header, to read its values as Rust
+/// types by position or by column name.
+pub struct Row<'a> {
+    pub header: &'a TableHeader,
+    pub values: &'a TableRow,
+}
+
+impl<'a> Row<'a> {
+    pub fn new(header: &'a TableHeader, values: &'a TableRow) -> Row<'a> {
+        Row {
+            header: header,
+            values: values,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// The value of column `i`, starting at 0, e.g. `row.get::<String>(1)`.
+    pub fn get<T: FromValue>(&self, i: usize) -> RusqlResult<T> {
+        match self.values.get(i) {
+            Some(value) => FromValue::from_value(value),
+            None => Err(RusqlError::NoSuchColumn(format!("{}", i))),
+        }
+    }
+
+    pub fn get_by_name<T: FromValue>(&self, name: &str) -> RusqlResult<T> {
+        match self.header.iter().position(|def| def.name.as_slice() == name) {
+            Some(i) => self.get(i),
+            None => Err(RusqlError::NoSuchColumn(name.to_string())),
+        }
+    }
+}
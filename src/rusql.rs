@@ -1,10 +1,97 @@
-use definitions::TableDef;
-use table::Table;
+use backup::{self, Progress};
+use builder::Select;
+use csv::{self, CsvOptions};
+use definitions::{TableDef, IndexDef, ViewDef, SelectDef, TriggerDef, TriggerTime};
+use definitions::{ForeignKeyAction, LiteralValue, Expression, RusqlStatement};
+use definitions::{InsertDef, InsertDataSource, ConflictResolution};
+use dump;
+use error::{RusqlError, RusqlResult};
+use exec::{self, QueryResult, Rows};
+use json;
+use logfile::{self, LogFile};
+use memory;
+use pager::{self, PagedFile};
+use parse_cache::{self, ParseCache};
+use persist;
+use pragma::{self, PragmaHandler};
+use row::{Row, FromRow, FromValue};
+use script;
+use snapshot::Snapshot;
+use sqlite;
+use statement::Statement;
+use stats::TableStats;
+use table::{Table, TableRow, TableHeader, PkType, get_key};
 
 use std::collections::BTreeMap;
+use std::i64;
+use std::io::File;
+use std::io::fs::PathExtensions;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
+use std::time::Duration;
+use time;
 
 pub struct Rusql {
-    pub map: BTreeMap<String, Table>,
+    /// The tables, which snapshots and savepoints share until one of them
+    /// is changed, see get_mut_table.
+    pub map: BTreeMap<String, Arc<Table>>,
+    pub temp: BTreeMap<String, Arc<Table>>,
+    /// Maps the name of every index to the table it belongs to. The index
+    /// itself lives on the table, which keeps it up to date on every write.
+    pub indexes: BTreeMap<String, String>,
+    pub views: BTreeMap<String, SelectDef>,
+    /// The selects of the materialized views, whose results are stored as
+    /// regular tables.
+    pub materialized_views: BTreeMap<String, SelectDef>,
+    /// The triggers of every table, by table name.
+    pub triggers: BTreeMap<String, Vec<TriggerDef>>,
+    /// The statistics ANALYZE collected on each table, by table name. They
+    /// aren't kept up to date as the tables change.
+    pub stats: BTreeMap<String, TableStats>,
+    /// The statements parsed from the SQL run most recently.
+    pub parse_cache: ParseCache,
+    /// The triggers currently running. A trigger doesn't fire again while
+    /// it's running, same as SQLite without recursive triggers.
+    pub trigger_stack: Vec<String>,
+    pub foreign_keys: bool,
+    pub case_sensitive_like: bool,
+    interrupt: InterruptHandle,
+    timeout: Option<Duration>,
+    /// When the running statement times out, in nanoseconds from
+    /// time::precise_time_ns.
+    deadline: Option<u64>,
+    memory_limit: Option<usize>,
+    /// The file a database from open_file is written to, see flush.
+    file: Option<Path>,
+    /// The log a database from open_log appends its changes to.
+    log: Option<LogFile>,
+    /// The file a database from open_paged keeps its tables in.
+    paged: Option<PagedFile>,
+    /// The databases ATTACHed, by the name their tables go by, as in
+    /// aux.Foo.
+    pub attached: BTreeMap<String, Rusql>,
+    pragmas: BTreeMap<String, PragmaHandler>,
+    changes: usize,
+    total_changes: usize,
+    last_insert_rowid: PkType,
+    /// The open transaction and the savepoints within it, innermost last,
+    /// each with what's needed to undo what happened since it started.
+    savepoints: Vec<Journal>,
+}
+
+/// The state a transaction or savepoint rolls back to. Tables are kept the
+/// first time they're touched, the rest of the schema is copied up front.
+struct Journal {
+    /// The savepoint's name, None for a transaction started with BEGIN.
+    name: Option<String>,
+    /// The tables as they were before, by name, with whether they're
+    /// temporary. None for names that weren't taken.
+    tables: BTreeMap<String, Option<(Arc<Table>, bool)>>,
+    indexes: BTreeMap<String, String>,
+    views: BTreeMap<String, SelectDef>,
+    materialized_views: BTreeMap<String, SelectDef>,
+    triggers: BTreeMap<String, Vec<TriggerDef>>,
+    stats: BTreeMap<String, TableStats>,
 }
 
 
@@ -12,33 +99,1486 @@ impl Rusql {
     pub fn new() -> Rusql {
         return Rusql {
             map: BTreeMap::new(),
+            temp: BTreeMap::new(),
+            indexes: BTreeMap::new(),
+            views: BTreeMap::new(),
+            materialized_views: BTreeMap::new(),
+            triggers: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            parse_cache: ParseCache::new(parse_cache::DEFAULT_CAPACITY),
+            trigger_stack: Vec::new(),
+            foreign_keys: true,
+            case_sensitive_like: false,
+            interrupt: InterruptHandle { interrupted: Arc::new(AtomicBool::new(false)) },
+            timeout: None,
+            deadline: None,
+            memory_limit: None,
+            file: None,
+            log: None,
+            paged: None,
+            attached: BTreeMap::new(),
+            pragmas: pragma::builtin_pragmas(),
+            changes: 0,
+            total_changes: 0,
+            last_insert_rowid: 0,
+            savepoints: Vec::new(),
         };
     }
 
-    pub fn rename_table(&mut self, old_name: &String, new_name: String) {
-        let table = self.map.remove(old_name.as_slice()).unwrap();
-        self.map.insert(new_name, table);
+    pub fn in_transaction(&self) -> bool {
+        !self.savepoints.is_empty()
+    }
+
+    pub fn begin(&mut self) -> RusqlResult<()> {
+        if self.in_transaction() {
+            return Err(RusqlError::InvalidTransaction(
+                    "cannot start a transaction within a transaction".to_string()));
+        }
+
+        let journal = self.journal(None);
+        self.savepoints.push(journal);
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> RusqlResult<()> {
+        if !self.in_transaction() {
+            return Err(RusqlError::InvalidTransaction(
+                    "cannot commit - no transaction is active".to_string()));
+        }
+
+        self.savepoints.clear();
+        self.flush()
+    }
+
+    /// Puts back every table the transaction touched and the schema as it
+    /// was at BEGIN.
+    pub fn rollback(&mut self) -> RusqlResult<()> {
+        if !self.in_transaction() {
+            return Err(RusqlError::InvalidTransaction(
+                    "cannot rollback - no transaction is active".to_string()));
+        }
+
+        while let Some(journal) = self.savepoints.pop() {
+            self.restore(journal);
+        }
+        Ok(())
+    }
+
+    /// Starts a savepoint, and a transaction with it if none is open.
+    pub fn savepoint(&mut self, name: String) {
+        let journal = self.journal(Some(name));
+        self.savepoints.push(journal);
+    }
+
+    /// Forgets the savepoint `name` and the ones started after it, keeping
+    /// their changes. Releasing the outermost one commits the transaction.
+    pub fn release(&mut self, name: &String) -> RusqlResult<()> {
+        let position = try!(self.find_savepoint(name));
+
+        while self.savepoints.len() > position {
+            let journal = self.savepoints.pop().unwrap();
+            self.keep(journal);
+        }
+
+        self.flush()
+    }
+
+    /// Undoes everything since the savepoint `name` started, which stays
+    /// open, dropping the savepoints started after it.
+    pub fn rollback_to(&mut self, name: &String) -> RusqlResult<()> {
+        let position = try!(self.find_savepoint(name));
+
+        while self.savepoints.len() > position {
+            let journal = self.savepoints.pop().unwrap();
+            self.restore(journal);
+        }
+
+        let journal = self.journal(Some(name.clone()));
+        self.savepoints.push(journal);
+        Ok(())
+    }
+
+    /// Runs `f` in a transaction, which is committed if it returns Ok and
+    /// rolled back if it returns an error or panics.
+    pub fn transaction<T, F>(&mut self, f: F) -> RusqlResult<T>
+        where F: FnOnce(&mut Rusql) -> RusqlResult<T> {
+        try!(self.begin());
+
+        let mut guard = TransactionGuard { db: self, finished: false };
+        let result = f(&mut *guard.db);
+
+        guard.finished = true;
+        match result {
+            Ok(value) => {
+                try!(guard.db.commit());
+                Ok(value)
+            }
+            Err(e) => {
+                try!(guard.db.rollback());
+                Err(e)
+            }
+        }
+    }
+
+    /// A read-only copy of the database as it is now, changes of the open
+    /// transaction included, that shares the tables with it.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut db = Rusql::new();
+
+        db.map = self.map.clone();
+        db.temp = self.temp.clone();
+        db.indexes = self.indexes.clone();
+        db.views = self.views.clone();
+        db.materialized_views = self.materialized_views.clone();
+        db.triggers = self.triggers.clone();
+        db.stats = self.stats.clone();
+        db.foreign_keys = self.foreign_keys;
+        db.case_sensitive_like = self.case_sensitive_like;
+        db.pragmas = self.pragmas.clone();
+        db.timeout = self.timeout;
+        db.memory_limit = self.memory_limit;
+
+        Snapshot::new(db)
+    }
+
+    /// Writes the tables, their rows and the rest of the schema to `path`,
+    /// from which open reads them back. Temporary tables aren't saved.
+    pub fn save(&self, path: &Path) -> RusqlResult<()> {
+        persist::save(self, path)
+    }
+
+    /// A database holding what save wrote to `path`.
+    pub fn open(path: &Path) -> RusqlResult<Rusql> {
+        persist::open(path)
+    }
+
+    /// Copies the tables and the rest of the schema into `dest`, in place of
+    /// what it held, calling `progress` after every step; see backup.
+    pub fn backup_to<F: FnMut(Progress)>(&self, dest: &mut Rusql, progress: F) -> RusqlResult<()> {
+        backup::backup(self, dest, progress)
+    }
+
+    /// Like backup_to, into a new file at `path` as save writes it.
+    pub fn backup_to_file<F: FnMut(Progress)>(&self, path: &Path, progress: F) -> RusqlResult<()> {
+        let mut dest = Rusql::new();
+
+        try!(backup::backup(self, &mut dest, progress));
+        dest.save(path)
+    }
+
+    /// Runs the SQL script in the file at `path`, see script::execute_file.
+    pub fn execute_file(&mut self, path: &Path) -> RusqlResult<usize> {
+        script::execute_file(self, path)
     }
 
-    pub fn get_table(&self, name: &String) -> &Table {
-        self.map.get(name.as_slice()).unwrap()
+    /// A SQL script that recreates the database, see dump::dump.
+    pub fn dump(&self) -> String {
+        dump::dump(self)
     }
 
-    pub fn get_mut_table(&mut self, name: &String) -> &mut Table {
-        self.map.get_mut(name.as_slice()).unwrap()
+    /// A database holding what's in the SQLite 3 file at `path`, see
+    /// sqlite::open_sqlite.
+    pub fn open_sqlite(path: &Path) -> RusqlResult<Rusql> {
+        sqlite::open_sqlite(path)
     }
 
-    pub fn create_table(&mut self, table_def: TableDef) {
+    /// Writes the database to a SQLite 3 file at `path`, see
+    /// sqlite::save_sqlite.
+    pub fn save_sqlite(&self, path: &Path) -> RusqlResult<()> {
+        sqlite::save_sqlite(self, path)
+    }
+
+    /// The database saved in `path`, or a new one if there's no such file,
+    /// which is written back to it every time a statement changes it outside
+    /// of a transaction and every time a transaction commits.
+    pub fn open_file(path: &Path) -> RusqlResult<Rusql> {
+        let mut db = if path.exists() { try!(Rusql::open(path)) } else { Rusql::new() };
+
+        db.file = Some(path.clone());
+        try!(db.flush());
+        Ok(db)
+    }
+
+    /// The file the database is written to, if it was opened with open_file.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_ref()
+    }
+
+    /// Writes the database to its file, if it has one and no transaction or
+    /// trigger is running; called after every change.
+    pub fn flush(&mut self) -> RusqlResult<()> {
+        if self.in_transaction() || !self.trigger_stack.is_empty() {
+            return Ok(());
+        }
+
+        for db in self.attached.values_mut() {
+            try!(db.flush());
+        }
+
+        if let Some(ref path) = self.file {
+            try!(persist::save(self, path));
+        }
+
+        if let Some(mut log) = self.log.take() {
+            let result = log.append(self);
+            self.log = Some(log);
+            try!(result);
+        }
+
+        if let Some(mut paged) = self.paged.take() {
+            let result = paged.commit(self);
+            self.paged = Some(paged);
+            try!(result);
+        }
+        Ok(())
+    }
+
+    /// The database kept in the append-only log at `path`, or a new one if
+    /// there's no such file. Where open_file writes the whole database,
+    /// this only appends what changed, see logfile.
+    pub fn open_log(path: &Path) -> RusqlResult<Rusql> {
+        let (mut db, log) = try!(LogFile::open(path));
+
+        db.log = Some(log);
+        Ok(db)
+    }
+
+    /// The database kept in the pages of the file at `path`, or a new one if
+    /// there's no such file. Unlike open and open_file, the rows aren't read
+    /// until they're needed, and only what changed is written back, on the
+    /// same occasions as open_file; see pager.
+    pub fn open_paged(path: &Path) -> RusqlResult<Rusql> {
+        let (mut db, paged) = try!(PagedFile::open(path, None));
+
+        db.paged = Some(paged);
+        Ok(db)
+    }
+
+    /// Like open_paged, for a file encrypted with `key`, of cipher::KEY_LEN
+    /// bytes; a new file is encrypted with it. Needs the `encryption`
+    /// feature, see cipher.
+    pub fn open_encrypted(path: &Path, key: &[u8]) -> RusqlResult<Rusql> {
+        let (mut db, paged) = try!(PagedFile::open(path, Some(key)));
+
+        db.paged = Some(paged);
+        Ok(db)
+    }
+
+    /// Attaches the database in the file at `path`, or a new one in memory
+    /// for :memory:, under `name`, so that its tables can be used as in
+    /// `name.Foo`. The file is opened with open_paged or open_log if it's
+    /// one of theirs, and with open_file otherwise. Only its tables are
+    /// reached this way, and its changes aren't part of the transactions of
+    /// this database.
+    pub fn attach(&mut self, path: &str, name: String) -> RusqlResult<()> {
+        if self.in_transaction() {
+            return Err(RusqlError::InvalidTransaction("cannot ATTACH a database within a transaction".to_string()));
+        }
+        if name.as_slice() == "main" || name.as_slice() == "temp" || self.attached.contains_key(&name) {
+            return Err(RusqlError::InvalidSchemaChange(format!("database {} is already in use", name)));
+        }
+
+        let db = if path == ":memory:" { Rusql::new() } else { try!(open_attached(&Path::new(path))) };
+        self.attached.insert(name, db);
+        Ok(())
+    }
+
+    pub fn detach(&mut self, name: &String) -> RusqlResult<()> {
+        if self.in_transaction() {
+            return Err(RusqlError::InvalidTransaction("cannot DETACH a database within a transaction".to_string()));
+        }
+
+        match self.attached.remove(name) {
+            Some(mut db) => db.flush(),
+            None => Err(RusqlError::InvalidSchemaChange(format!("no such database: {}", name))),
+        }
+    }
+
+    /// Folds the WAL of a database from open_paged in WAL mode back into its
+    /// file; does nothing for other databases. See wal.
+    pub fn checkpoint(&mut self) -> RusqlResult<()> {
+        match self.paged {
+            Some(ref paged) => paged.checkpoint(),
+            None => Ok(()),
+        }
+    }
+
+    /// Turns WAL mode on or off for a database from open_paged. Fails for
+    /// other databases.
+    pub fn set_wal_mode(&mut self, on: bool) -> RusqlResult<()> {
+        match self.paged {
+            Some(ref mut paged) => paged.set_wal_mode(on),
+            None => Err(RusqlError::InvalidTransaction("WAL mode needs a database from open_paged".to_string())),
+        }
+    }
+
+    /// The paged file of a database from open_paged.
+    pub fn paged_file(&self) -> Option<&PagedFile> {
+        self.paged.as_ref()
+    }
+
+    /// Rewrites the log of a database from open_log to hold only the rows
+    /// there are now. This happens in the background too as the log grows.
+    pub fn compact_log(&mut self) -> RusqlResult<()> {
+        if let Some(mut log) = self.log.take() {
+            let result = log.compact(self);
+            self.log = Some(log);
+            try!(result);
+        }
+        Ok(())
+    }
+
+    /// VACUUM: stores the rows of every table again and builds the indexes
+    /// anew, see Table::rebuild, then writes the database out. A database
+    /// from open_paged moves to a new file without free pages, see
+    /// PagedFile::vacuum, and the log of one from open_log is compacted.
+    /// With a `schema`, the database attached under that name is vacuumed.
+    pub fn vacuum(&mut self, schema: Option<&String>) -> RusqlResult<()> {
+        if self.in_transaction() {
+            return Err(RusqlError::InvalidTransaction("cannot VACUUM within a transaction".to_string()));
+        }
+
+        if let Some(schema) = schema {
+            if schema.as_slice() != "main" && schema.as_slice() != "temp" {
+                return match self.attached.get_mut(schema) {
+                    Some(db) => db.vacuum(None),
+                    None => Err(RusqlError::InvalidSchemaChange(format!("no such database: {}", schema))),
+                };
+            }
+        }
+
+        let names: Vec<String> = self.map.keys().chain(self.temp.keys()).map(|name| name.clone()).collect();
+        for name in names.iter() {
+            try!(self.get_mut_table(name)).rebuild();
+        }
+
+        if let Some(mut paged) = self.paged.take() {
+            let result = paged.vacuum(self);
+            self.paged = Some(paged);
+            try!(result);
+        }
+
+        try!(self.flush());
+        self.compact_log()
+    }
+
+    /// A handle another thread can use to stop the statement running on
+    /// this database.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Makes every statement fail with Timeout once it has run for longer
+    /// than `timeout`, None for no limit.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Called as each statement starts: forgets an interrupt that came
+    /// before it and starts its clock.
+    pub fn start_statement(&mut self) {
+        self.interrupt.reset();
+        self.deadline = self.timeout.map(|timeout| {
+            time::precise_time_ns() + timeout.num_nanoseconds().unwrap_or(i64::MAX) as u64
+        });
+    }
+
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// About how many bytes the rows of every table take.
+    pub fn memory_used(&self) -> usize {
+        self.map.values().chain(self.temp.values()).fold(0, |size, table| size + table.memory)
+    }
+
+    /// A soft limit on the memory taken by the tables and the results of
+    /// the running statement, past which statements fail with OutOfMemory.
+    /// None for no limit.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    /// Fails with OutOfMemory if `size` more bytes would go over the memory
+    /// limit.
+    pub fn reserve_memory(&self, size: usize) -> RusqlResult<()> {
+        match self.memory_limit {
+            Some(limit) if self.memory_used() + size > limit => Err(RusqlError::OutOfMemory),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the statements in `sql`, stopping at the first one that fails.
+    /// Every statement gives one result, in order.
+    pub fn execute(&mut self, sql: &str) -> RusqlResult<Vec<QueryResult>> {
+        exec::execute(self, sql)
+    }
+
+    /// Runs statements from parse, which may have been changed since.
+    pub fn execute_statements(&mut self, stmts: Vec<RusqlStatement>) -> RusqlResult<Vec<QueryResult>> {
+        exec::execute_statements(self, stmts)
+    }
+
+    /// Runs a single SELECT, returning an iterator over its rows that reads
+    /// the tables as it goes rather than building the result set first.
+    pub fn query(&mut self, sql: &str) -> RusqlResult<Rows> {
+        exec::query(self, sql)
+    }
+
+    /// Runs a single SELECT, reading every row as a T, e.g. a struct with a
+    /// field for each column.
+    pub fn query_as<T: FromRow>(&mut self, sql: &str) -> RusqlResult<Vec<T>> {
+        let mut rows = try!(self.query(sql));
+        let header = rows.header().clone();
+        let mut results: Vec<T> = Vec::new();
+
+        for values in rows.by_ref() {
+            results.push(try!(FromRow::from_row(&Row::new(&header, &values))));
+        }
+
+        match rows.error() {
+            Some(e) => Err(e.clone()),
+            None => Ok(results),
+        }
+    }
+
+    /// Makes `PRAGMA name` run `handler`, replacing any PRAGMA with that name.
+    pub fn register_pragma(&mut self, name: &str, handler: PragmaHandler) {
+        self.pragmas.insert(name.to_string(), handler);
+    }
+
+    pub fn find_pragma(&self, name: &String) -> Option<PragmaHandler> {
+        self.pragmas.get(name).map(|handler| *handler)
+    }
+
+    /// Runs a SELECT made with the query builder.
+    pub fn select(&mut self, select: Select) -> RusqlResult<Table> {
+        match try!(exec::exec_stmt(self, RusqlStatement::Select(select.build()))) {
+            QueryResult::Rows(table) => Ok(table),
+            QueryResult::Changes(_) => unreachable!(),
+        }
+    }
+
+    /// Runs `sql` with `params` bound to its parameters in order, mapping
+    /// every row of the result with `f`.
+    pub fn query_map<T, F>(&mut self, sql: &str, params: &[LiteralValue], mut f: F) -> RusqlResult<Vec<T>>
+        where F: FnMut(&Row) -> RusqlResult<T> {
+        let table = try!(self.query_with(sql, params));
+        let mut results: Vec<T> = Vec::new();
+
+        for row in table.rows().iter() {
+            results.push(try!(f(row)));
+        }
+
+        Ok(results)
+    }
+
+    /// Like query_map, for the first row only, failing if there is none.
+    pub fn query_one<T, F>(&mut self, sql: &str, params: &[LiteralValue], f: F) -> RusqlResult<T>
+        where F: FnOnce(&Row) -> RusqlResult<T> {
+        let table = try!(self.query_with(sql, params));
+
+        match table.rows().first() {
+            Some(row) => f(row),
+            None => Err(RusqlError::NoRows),
+        }
+    }
+
+    /// The first column of the first row, e.g. for `SELECT count(*) ...`.
+    pub fn query_scalar<T: FromValue>(&mut self, sql: &str, params: &[LiteralValue]) -> RusqlResult<T> {
+        self.query_one(sql, params, |row| row.get(0))
+    }
+
+    fn query_with(&mut self, sql: &str, params: &[LiteralValue]) -> RusqlResult<Table> {
+        let mut stmt = try!(self.prepare(sql));
+
+        for (i, value) in params.iter().enumerate() {
+            try!(stmt.bind(i + 1, value.clone()));
+        }
+
+        stmt.query()
+    }
+
+    /// Parses `sql` once so it can be run many times, with values bound to
+    /// its parameters instead of pasted into the SQL.
+    pub fn prepare(&mut self, sql: &str) -> RusqlResult<Statement> {
+        Statement::new(self, sql)
+    }
+
+    /// Runs a script of statements, e.g. a migration, all or nothing: if one
+    /// fails, everything the script did is rolled back. It runs in a
+    /// savepoint, so it can be part of a bigger transaction.
+    ///
+    /// A script with its own BEGIN, COMMIT or ROLLBACK runs as it is
+    /// instead, and a transaction it started is rolled back if one of its
+    /// statements fails.
+    pub fn execute_batch(&mut self, sql: &str) -> RusqlResult<()> {
+        let stmts = try!(exec::parse_cached(self, sql));
+        let own_transaction = stmts.iter().any(|stmt| match *stmt {
+            RusqlStatement::Begin | RusqlStatement::Commit | RusqlStatement::Rollback(None) => true,
+            _ => false,
+        });
+
+        if !own_transaction {
+            return self.atomically(|db| exec::execute_statements(db, stmts).map(|_| ()));
+        }
+
+        let in_transaction = self.in_transaction();
+        match exec::execute_statements(self, stmts) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                if !in_transaction && self.in_transaction() {
+                    try!(self.rollback());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Inserts rows given as maps from column names to values, e.g. records
+    /// read from JSON, all or nothing. Returns how many rows were inserted.
+    pub fn insert_maps(&mut self, table_name: &str, records: Vec<BTreeMap<String, LiteralValue>>)
+                       -> RusqlResult<usize> {
+        self.atomically(|db| {
+            let mut inserted = 0us;
+
+            for record in records.into_iter() {
+                let (names, values): (Vec<String>, Vec<Expression>) = record.into_iter().map(|(name, value)| {
+                    (name, Expression::LiteralValue(value))
+                }).unzip();
+
+                let insert_def = InsertDef {
+                    table_name: table_name.to_string(),
+                    column_names: Some(names),
+                    data_source: InsertDataSource::Values(vec![values]),
+                    on_conflict: ConflictResolution::Abort,
+                    upsert: None,
+                    returning: None,
+                };
+                try!(exec::exec_stmt(db, RusqlStatement::Insert(insert_def)));
+                inserted += db.changes();
+            }
+
+            Ok(inserted)
+        })
+    }
+
+    /// Loads the records of the CSV file at `path` into a table, creating it
+    /// if there's none, all or nothing, see csv::import. Returns how many
+    /// rows were inserted.
+    pub fn import_csv(&mut self, path: &Path, table_name: &str, options: &CsvOptions) -> RusqlResult<usize> {
+        self.atomically(|db| csv::import(db, path, table_name, options))
+    }
+
+    /// Loads the JSON array of objects `r` holds into a table, creating it if
+    /// there's none, all or nothing, see json::import. Returns how many rows
+    /// were inserted.
+    pub fn import_json<R: Reader>(&mut self, r: &mut R, table_name: &str) -> RusqlResult<usize> {
+        let text = try!(r.read_to_string());
+        self.atomically(|db| json::import(db, text.as_slice(), table_name))
+    }
+
+    /// Runs a single SELECT, writing its rows to `w` as JSON Lines as they're
+    /// read, see json::write_lines. Returns how many rows were written.
+    pub fn export_json_lines<W: Writer>(&mut self, sql: &str, w: &mut W) -> RusqlResult<usize> {
+        let mut rows = try!(self.query(sql));
+        let header = rows.header().clone();
+        let count = try!(json::write_lines(&header, rows.by_ref(), w));
+
+        match rows.error() {
+            Some(e) => Err(e.clone()),
+            None => Ok(count),
+        }
+    }
+
+    /// Loads rows into a table faster than INSERT, all or nothing, see
+    /// Table::insert_batch. Every row has a value for each column, NULL for
+    /// an INTEGER PRIMARY KEY to be given the next one. Tables with triggers
+    /// take the same path as INSERT, which fires them. Returns how many rows
+    /// were inserted.
+    pub fn copy_from<I: Iterator<Item=TableRow>>(&mut self, table_name: &str, rows: I) -> RusqlResult<usize> {
+        let name = table_name.to_string();
+        let rows: Vec<TableRow> = rows.collect();
+
+        if self.has_triggers(&name) || self.views.contains_key(&name) {
+            let insert_def = InsertDef {
+                table_name: name,
+                column_names: None,
+                data_source: InsertDataSource::Values(rows.into_iter().map(|row| {
+                    row.into_iter().map(|value| Expression::LiteralValue(value)).collect()
+                }).collect()),
+                on_conflict: ConflictResolution::Abort,
+                upsert: None,
+                returning: None,
+            };
+            try!(exec::exec_stmt(self, RusqlStatement::Insert(insert_def)));
+            return Ok(self.changes());
+        }
+
+        self.start_statement();
+        try!(self.check_foreign_keys(&name, &rows));
+        try!(self.reserve_memory(memory::rows_size(&rows)));
+
+        let keys = try!(try!(self.get_mut_table(&name)).insert_batch(rows.into_iter()));
+        if let Some(&rowid) = keys.last() {
+            self.record_insert(rowid);
+        }
+        self.record_changes(keys.len());
+        try!(self.flush());
+
+        Ok(keys.len())
+    }
+
+    // Runs `f` in a savepoint, rolling back everything it did if it fails.
+    fn atomically<T, F>(&mut self, f: F) -> RusqlResult<T>
+        where F: FnOnce(&mut Rusql) -> RusqlResult<T> {
+        let name = "rusql_atomically".to_string();
+        self.savepoint(name.clone());
+
+        match f(self) {
+            Ok(value) => {
+                // Unless `f` ended the transaction itself.
+                if self.find_savepoint(&name).is_ok() {
+                    try!(self.release(&name));
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                if self.rollback_to(&name).is_ok() {
+                    try!(self.release(&name));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // Runs `f`, putting back every table it changed if it fails, for the
+    // steps of a statement that mustn't be left half done. Unlike a
+    // savepoint, it neither starts nor ends a transaction.
+    fn all_or_nothing<T, F>(&mut self, f: F) -> RusqlResult<T>
+        where F: FnOnce(&mut Rusql) -> RusqlResult<T> {
+        let journal = self.journal(None);
+        self.savepoints.push(journal);
+
+        let result = f(self);
+        let journal = self.savepoints.pop().unwrap();
+
+        match result {
+            Ok(value) => {
+                self.keep(journal);
+                Ok(value)
+            }
+            Err(e) => {
+                self.restore(journal);
+                Err(e)
+            }
+        }
+    }
+
+    // Folds the changes of a journal that's done with into the one around
+    // it: what the outer one has to put back is what was there before
+    // either of them touched it.
+    fn keep(&mut self, journal: Journal) {
+        if let Some(outer) = self.savepoints.last_mut() {
+            for (table_name, original) in journal.tables.into_iter() {
+                if !outer.tables.contains_key(&table_name) {
+                    outer.tables.insert(table_name, original);
+                }
+            }
+        }
+    }
+
+    // The index of the innermost savepoint called `name`.
+    fn find_savepoint(&self, name: &String) -> RusqlResult<usize> {
+        match self.savepoints.iter().rposition(|journal| journal.name.as_ref() == Some(name)) {
+            Some(position) => Ok(position),
+            None => Err(RusqlError::InvalidTransaction(format!("no such savepoint: {}", name))),
+        }
+    }
+
+    fn journal(&self, name: Option<String>) -> Journal {
+        Journal {
+            name: name,
+            tables: BTreeMap::new(),
+            indexes: self.indexes.clone(),
+            views: self.views.clone(),
+            materialized_views: self.materialized_views.clone(),
+            triggers: self.triggers.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
+    fn restore(&mut self, journal: Journal) {
+        for (name, original) in journal.tables.into_iter() {
+            self.temp.remove(name.as_slice());
+            self.map.remove(name.as_slice());
+
+            if let Some((table, temporary)) = original {
+                if temporary {
+                    self.temp.insert(name, table);
+                } else {
+                    self.map.insert(name, table);
+                }
+            }
+        }
+
+        self.indexes = journal.indexes;
+        self.views = journal.views;
+        self.materialized_views = journal.materialized_views;
+        self.triggers = journal.triggers;
+        self.stats = journal.stats;
+    }
+
+    // Keeps the table called `name` as it is in the innermost journal before
+    // it's first changed there, or notes that there was none. The journal
+    // shares the table until it is changed.
+    fn journal_table(&mut self, name: &String) {
+        let journal = match self.savepoints.last_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
+
+        if journal.tables.contains_key(name) {
+            return;
+        }
+
+        let original = match self.temp.get(name.as_slice()) {
+            Some(table) => Some((table.clone(), true)),
+            None => self.map.get(name.as_slice()).map(|table| (table.clone(), false)),
+        };
+        journal.tables.insert(name.clone(), original);
+    }
+
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// The number of rows inserted, updated or deleted since the database
+    /// was created, including the ones made by triggers.
+    pub fn total_changes(&self) -> usize {
+        self.total_changes
+    }
+
+    /// The key of the row most recently inserted outside of a trigger, or 0
+    /// if there is none.
+    pub fn last_insert_rowid(&self) -> PkType {
+        self.last_insert_rowid
+    }
+
+    pub fn record_insert(&mut self, rowid: PkType) {
+        if self.trigger_stack.is_empty() {
+            self.last_insert_rowid = rowid;
+        }
+    }
+
+    pub fn record_changes(&mut self, changes: usize) {
+        if self.trigger_stack.is_empty() {
+            self.changes = changes;
+        }
+        self.total_changes += changes;
+    }
+
+    pub fn rename_table(&mut self, old_name: &String, new_name: String) -> RusqlResult<()> {
+        if !self.map.contains_key(old_name.as_slice()) && !self.temp.contains_key(old_name.as_slice()) {
+            return Err(RusqlError::NoSuchTable(old_name.clone()));
+        }
+
+        self.journal_table(old_name);
+        self.journal_table(&new_name);
+
+        // The foreign keys of other tables, and of the table itself, follow
+        // it to its new name.
+        let referencing: Vec<String> = self.map.iter().chain(self.temp.iter()).filter(|&(_, table)| {
+            table.foreign_keys.iter().any(|fk| &fk.clause.table_name == old_name)
+        }).map(|(name, _)| name.clone()).collect();
+        for table_name in referencing.iter() {
+            self.journal_table(table_name);
+        }
+        for table in self.map.values_mut().chain(self.temp.values_mut()) {
+            if table.foreign_keys.iter().any(|fk| &fk.clause.table_name == old_name) {
+                table.make_unique().rename_referenced_table(old_name, &new_name);
+            }
+        }
+
+        let map = if self.temp.contains_key(old_name.as_slice()) { &mut self.temp } else { &mut self.map };
+
+        match map.remove(old_name.as_slice()) {
+            Some(mut table) => {
+                table.make_unique().name = new_name.clone();
+
+                for table_name in self.indexes.values_mut() {
+                    if table_name == old_name {
+                        *table_name = new_name.clone();
+                    }
+                }
+
+                for select_def in self.views.values_mut().chain(self.materialized_views.values_mut()) {
+                    select_def.rename_table(old_name, &new_name);
+                }
+
+                // Triggers on other tables may use it in their bodies.
+                for triggers in self.triggers.values_mut() {
+                    for trigger in triggers.iter_mut() {
+                        trigger.rename_table(old_name, &new_name);
+                    }
+                }
+                if let Some(triggers) = self.triggers.remove(old_name) {
+                    self.triggers.insert(new_name.clone(), triggers);
+                }
+
+                if let Some(stats) = self.stats.remove(old_name) {
+                    self.stats.insert(new_name.clone(), stats);
+                }
+
+                map.insert(new_name, table);
+                Ok(())
+            }
+            None => Err(RusqlError::NoSuchTable(old_name.clone())),
+        }
+    }
+
+    pub fn rename_column(&mut self, name: &String, old_name: &String, new_name: String) -> RusqlResult<()> {
+        try!(try!(self.get_mut_table(name)).rename_column(old_name, new_name.clone()));
+
+        let names: Vec<String> = self.map.keys().chain(self.temp.keys()).map(|name| name.clone()).collect();
+        for table_name in names.iter() {
+            self.journal_table(table_name);
+        }
+
+        for table in self.map.values_mut().chain(self.temp.values_mut()) {
+            if table.foreign_keys.iter().any(|fk| &fk.clause.table_name == name) {
+                table.make_unique().rename_referenced_column(name, old_name, &new_name);
+            }
+        }
+
+        for select_def in self.views.values_mut().chain(self.materialized_views.values_mut()) {
+            select_def.rename_column(name, old_name, &new_name);
+        }
+
+        for triggers in self.triggers.values_mut() {
+            for trigger in triggers.iter_mut() {
+                trigger.rename_column(name, old_name, &new_name);
+            }
+        }
+
+        if let Some(stats) = self.stats.get_mut(name) {
+            if let Some(column) = stats.columns.remove(old_name) {
+                stats.columns.insert(new_name, column);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Temporary tables shadow regular ones with the same name. The tables
+    /// of attached databases are found by their qualified names.
+    pub fn find_table(&self, name: &String) -> Option<&Table> {
+        if let Some((schema, table_name)) = split_schema(name) {
+            return match self.attached.get(&schema) {
+                Some(db) => db.find_table(&table_name),
+                None if schema.as_slice() == "main" || schema.as_slice() == "temp" => self.find_table(&table_name),
+                None => None,
+            };
+        }
+
+        match self.temp.get(name.as_slice()) {
+            Some(table) => Some(&**table),
+            None => self.map.get(name.as_slice()).map(|table| &**table),
+        }
+    }
+
+    /// Like find_table, but the table is shared instead of borrowed, so it
+    /// can be held on to while the database is written to.
+    pub fn find_shared_table(&self, name: &String) -> Option<Arc<Table>> {
+        if let Some((schema, table_name)) = split_schema(name) {
+            return match self.attached.get(&schema) {
+                Some(db) => db.find_shared_table(&table_name),
+                None if schema.as_slice() == "main" || schema.as_slice() == "temp" => {
+                    self.find_shared_table(&table_name)
+                }
+                None => None,
+            };
+        }
+
+        match self.temp.get(name.as_slice()) {
+            Some(table) => Some(table.clone()),
+            None => self.map.get(name.as_slice()).map(|table| table.clone()),
+        }
+    }
+
+    pub fn get_table(&self, name: &String) -> RusqlResult<&Table> {
+        match self.find_table(name) {
+            Some(table) => Ok(table),
+            None => Err(RusqlError::NoSuchTable(name.clone())),
+        }
+    }
+
+    /// Inside a transaction, the table is kept in the journal first, as the
+    /// caller is about to change it. The table is copied if a journal or a
+    /// snapshot still shares it.
+    pub fn get_mut_table(&mut self, name: &String) -> RusqlResult<&mut Table> {
+        if let Some((schema, table_name)) = split_schema(name) {
+            if schema.as_slice() == "main" || schema.as_slice() == "temp" {
+                return self.get_mut_table(&table_name);
+            }
+
+            return match self.attached.get_mut(&schema) {
+                Some(db) => db.get_mut_table(&table_name),
+                None => Err(RusqlError::NoSuchTable(name.clone())),
+            };
+        }
+
+        self.journal_table(name);
+
+        match self.temp.get_mut(name.as_slice()) {
+            Some(table) => Ok(table.make_unique()),
+            None => match self.map.get_mut(name.as_slice()) {
+                Some(table) => Ok(table.make_unique()),
+                None => Err(RusqlError::NoSuchTable(name.clone())),
+            },
+        }
+    }
+
+    pub fn temp_table_names(&self) -> Vec<String> {
+        self.temp.keys().map(|name| name.clone()).collect()
+    }
+
+    /// The names of all the tables, temporary ones included, in order. The
+    /// tables holding materialized views aren't listed.
+    pub fn table_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.map.keys().chain(self.temp.keys())
+            .filter(|name| !self.materialized_views.contains_key(*name))
+            .map(|name| name.clone())
+            .collect();
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The names of all the views, materialized or not, in order.
+    pub fn view_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.views.keys().chain(self.materialized_views.keys())
+            .map(|name| name.clone())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    pub fn table_schema(&self, name: &str) -> RusqlResult<&TableHeader> {
+        self.get_table(&name.to_string()).map(|table| &table.header)
+    }
+
+    pub fn column_names(&self, name: &str) -> RusqlResult<Vec<String>> {
+        let header = try!(self.table_schema(name));
+        Ok(header.iter().map(|column_def| column_def.name.clone()).collect())
+    }
+
+    pub fn row_count(&self, name: &str) -> RusqlResult<usize> {
+        self.get_table(&name.to_string()).map(|table| table.data.len())
+    }
+
+    pub fn create_table(&mut self, mut table_def: TableDef) -> RusqlResult<()> {
+        if let Some((schema, table_name)) = split_schema(&table_def.table_name) {
+            table_def.table_name = table_name;
+            table_def.temporary = table_def.temporary || schema.as_slice() == "temp";
+
+            if schema.as_slice() != "main" && schema.as_slice() != "temp" {
+                return match self.attached.get_mut(&schema) {
+                    Some(db) => db.create_table(table_def),
+                    None => Err(RusqlError::InvalidSchemaChange(format!("no such database: {}", schema))),
+                };
+            }
+        }
+
+        self.journal_table(&table_def.table_name);
+
+        let map = if table_def.temporary { &mut self.temp } else { &mut self.map };
+
         if table_def.if_not_exists {
-            if self.map.contains_key(&table_def.table_name) {
-                return;
+            if map.contains_key(&table_def.table_name) {
+                return Ok(());
+            }
+        }
+        let table = try!(Table::new(table_def));
+        map.insert(table.name.clone(), Arc::new(table));
+        Ok(())
+    }
+
+    pub fn drop_table(&mut self, name: &String) -> RusqlResult<()> {
+        if let Some((schema, table_name)) = split_schema(name) {
+            if schema.as_slice() == "main" || schema.as_slice() == "temp" {
+                return self.drop_table(&table_name);
+            }
+
+            return match self.attached.get_mut(&schema) {
+                Some(db) => db.drop_table(&table_name),
+                None => Err(RusqlError::NoSuchTable(name.clone())),
+            };
+        }
+
+        self.journal_table(name);
+
+        if self.temp.remove(name.as_slice()).is_none() && self.map.remove(name.as_slice()).is_none() {
+            return Err(RusqlError::NoSuchTable(name.clone()));
+        }
+
+        let index_names: Vec<String> = self.indexes.iter()
+                                                   .filter(|&(_, table_name)| table_name == name)
+                                                   .map(|(index_name, _)| index_name.clone())
+                                                   .collect();
+
+        for index_name in index_names.iter() {
+            self.indexes.remove(index_name);
+        }
+
+        self.materialized_views.remove(name);
+        self.triggers.remove(name);
+        self.stats.remove(name);
+
+        Ok(())
+    }
+
+    /// Collects the statistics of the table called `name`, or of every
+    /// table if None, replacing the ones collected before.
+    pub fn analyze(&mut self, name: Option<&String>) -> RusqlResult<()> {
+        let names = match name {
+            Some(name) => vec![try!(self.get_table(name)).name.clone()],
+            None => self.map.keys().chain(self.temp.keys()).map(|name| name.clone()).collect(),
+        };
+
+        for name in names.iter() {
+            let stats = TableStats::collect(try!(self.get_table(name)));
+            self.stats.insert(name.clone(), stats);
+        }
+
+        Ok(())
+    }
+
+    pub fn create_index(&mut self, index_def: IndexDef) -> RusqlResult<()> {
+        if self.indexes.contains_key(&index_def.name) {
+            if index_def.if_not_exists {
+                return Ok(());
+            }
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("index {} already exists", index_def.name)));
+        }
+
+        let name = index_def.name.clone();
+        let table_name = index_def.table_name.clone();
+
+        try!(try!(self.get_mut_table(&table_name)).create_index(index_def));
+        self.indexes.insert(name, table_name);
+
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, name: &String) -> RusqlResult<()> {
+        match self.indexes.remove(name) {
+            Some(table_name) => {
+                try!(self.get_mut_table(&table_name)).drop_index(name);
+                Ok(())
+            }
+            None => Err(RusqlError::NoSuchIndex(name.clone())),
+        }
+    }
+
+    pub fn create_view(&mut self, view_def: ViewDef) -> RusqlResult<()> {
+        if self.views.contains_key(&view_def.name) {
+            if view_def.if_not_exists {
+                return Ok(());
+            }
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("view {} already exists", view_def.name)));
+        }
+
+        if self.find_table(&view_def.name).is_some() {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("table {} already exists", view_def.name)));
+        }
+
+        // A view that reads itself, directly or through other views, would
+        // be expanded forever.
+        let sources = view_def.select.table_names();
+        if sources.iter().any(|name| name == &view_def.name || self.view_reads(name, &view_def.name)) {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("view {} is circularly defined", view_def.name)));
+        }
+
+        self.views.insert(view_def.name, view_def.select);
+        Ok(())
+    }
+
+    // Whether the view `name` reads `source`, directly or through other views.
+    fn view_reads(&self, name: &String, source: &String) -> bool {
+        match self.views.get(name) {
+            Some(select) => {
+                select.table_names().iter().any(|name| name == source || self.view_reads(name, source))
+            }
+            None => false,
+        }
+    }
+
+    pub fn drop_view(&mut self, name: &String) -> RusqlResult<()> {
+        if self.views.remove(name).is_some() {
+            self.triggers.remove(name);
+            return Ok(());
+        }
+
+        if self.materialized_views.contains_key(name) {
+            return self.drop_table(name);
+        }
+
+        Err(RusqlError::NoSuchView(name.clone()))
+    }
+
+    pub fn has_view(&self, name: &String) -> bool {
+        self.views.contains_key(name) || self.materialized_views.contains_key(name)
+    }
+
+    pub fn create_trigger(&mut self, trigger_def: TriggerDef) -> RusqlResult<()> {
+        if self.find_trigger(&trigger_def.name).is_some() {
+            if trigger_def.if_not_exists {
+                return Ok(());
+            }
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("trigger {} already exists", trigger_def.name)));
+        }
+
+        // Views can only have INSTEAD OF triggers, and tables only the others.
+        if self.views.contains_key(&trigger_def.table_name) {
+            if trigger_def.time != TriggerTime::InsteadOf {
+                return Err(RusqlError::InvalidSchemaChange(
+                        format!("cannot create BEFORE or AFTER trigger on view: {}", trigger_def.table_name)));
+            }
+        } else {
+            try!(self.get_table(&trigger_def.table_name));
+
+            if trigger_def.time == TriggerTime::InsteadOf {
+                return Err(RusqlError::InvalidSchemaChange(
+                        format!("cannot create INSTEAD OF trigger on table: {}", trigger_def.table_name)));
+            }
+        }
+
+        if !self.triggers.contains_key(&trigger_def.table_name) {
+            self.triggers.insert(trigger_def.table_name.clone(), Vec::new());
+        }
+        self.triggers.get_mut(&trigger_def.table_name).unwrap().push(trigger_def);
+
+        Ok(())
+    }
+
+    pub fn drop_trigger(&mut self, name: &String) -> RusqlResult<()> {
+        for triggers in self.triggers.values_mut() {
+            if let Some(i) = triggers.iter().position(|trigger| &trigger.name == name) {
+                triggers.remove(i);
+                return Ok(());
+            }
+        }
+
+        Err(RusqlError::NoSuchTrigger(name.clone()))
+    }
+
+    pub fn find_trigger(&self, name: &String) -> Option<&TriggerDef> {
+        self.triggers.values().flat_map(|triggers| triggers.iter()).find(|trigger| &trigger.name == name)
+    }
+
+    pub fn has_triggers(&self, table_name: &String) -> bool {
+        self.triggers.get(table_name).map_or(false, |triggers| !triggers.is_empty())
+    }
+
+    /// The triggers of `table_name` that run at `time` and aren't running
+    /// already.
+    pub fn triggers_for(&self, table_name: &String, time: TriggerTime) -> Vec<TriggerDef> {
+        match self.triggers.get(table_name) {
+            Some(triggers) => triggers.iter()
+                                      .filter(|trigger| trigger.time == time && !self.trigger_stack.contains(&trigger.name))
+                                      .map(|trigger| trigger.clone())
+                                      .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether a foreign key of any table points at the column `column_name` of
+    /// the table `name`.
+    pub fn is_column_referenced(&self, name: &String, column_name: &String) -> bool {
+        let parent = match self.find_table(name) {
+            Some(parent) => parent,
+            None => return false,
+        };
+
+        for child in self.map.values().chain(self.temp.values()) {
+            for fk in child.foreign_keys.iter() {
+                if &fk.clause.table_name != name {
+                    continue;
+                }
+
+                if let Ok(columns) = fk.parent_columns(parent) {
+                    if columns.iter().any(|&i| &parent.header[i].name == column_name) {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Makes sure every row about to be written to `name` points at an existing
+    /// parent row.
+    pub fn check_foreign_keys(&self, name: &String, rows: &Vec<TableRow>) -> RusqlResult<()> {
+        if !self.foreign_keys {
+            return Ok(());
+        }
+
+        let table = try!(self.get_table(name));
+
+        for fk in table.foreign_keys.iter() {
+            let parent = match self.find_table(&fk.clause.table_name) {
+                Some(parent) => parent,
+                None => return Err(RusqlError::ConstraintViolation(
+                        format!("foreign key mismatch: no such table {}", fk.clause.table_name))),
+            };
+            let parent_columns = try!(fk.parent_columns(parent));
+
+            for row in rows.iter() {
+                if let Some(key) = fk.key(row) {
+                    if !parent.has_key(&parent_columns, &key) {
+                        return Err(foreign_key_failed());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the rows of `name` stored under `keys`, carrying out the
+    /// ON DELETE action of every foreign key that references them. If one
+    /// of those fails, nothing is deleted.
+    pub fn delete_rows(&mut self, name: &String, keys: Vec<PkType>) -> RusqlResult<()> {
+        let changes: Vec<RowChange> = {
+            let table = try!(self.get_table(name));
+            keys.iter().filter_map(|key| table.data.get(key))
+                       .map(|row| (row.clone(), None))
+                       .collect()
+        };
+        let actions = try!(self.foreign_key_actions(name, &changes));
+
+        self.all_or_nothing(|db| {
+            {
+                let table = try!(db.get_mut_table(name));
+
+                for key in keys.iter() {
+                    table.remove_row(*key);
+                }
+            }
+
+            db.apply_foreign_key_actions(actions)
+        })
+    }
+
+    /// Replaces rows of `name`, checking the new rows' own foreign keys and
+    /// carrying out the ON UPDATE action of every foreign key that references
+    /// a changed key. If one of those fails, nothing is updated.
+    pub fn update_rows(&mut self, name: &String, updates: Vec<(PkType, TableRow)>) -> RusqlResult<()> {
+        let changes: Vec<RowChange> = {
+            let table = try!(self.get_table(name));
+            updates.iter().map(|&(ref key, ref row)| {
+                (table.data.get(key).unwrap().clone(), Some(row.clone()))
+            }).collect()
+        };
+        let new_rows: Vec<TableRow> = updates.iter().map(|&(_, ref row)| row.clone()).collect();
+
+        try!(self.check_foreign_keys(name, &new_rows));
+        let actions = try!(self.foreign_key_actions(name, &changes));
+
+        self.all_or_nothing(|db| {
+            try!(try!(db.get_mut_table(name)).update_rows(updates));
+            db.apply_foreign_key_actions(actions)
+        })
+    }
+
+    // Works out what has to happen to child rows still pointing at a row of
+    // `name` that is being deleted (`None`) or whose referenced key changes.
+    fn foreign_key_actions(&self, name: &String, changes: &Vec<RowChange>) -> RusqlResult<Vec<ChildAction>> {
+        let mut actions: Vec<ChildAction> = Vec::new();
+
+        if !self.foreign_keys {
+            return Ok(actions);
+        }
+
+        let parent = try!(self.get_table(name));
+
+        for child in self.map.values().chain(self.temp.values()) {
+            for fk in child.foreign_keys.iter() {
+                if &fk.clause.table_name != name {
+                    continue;
+                }
+
+                let parent_columns = try!(fk.parent_columns(parent));
+                let defaults = child.default_row();
+
+                for &(ref old_row, ref new_row) in changes.iter() {
+                    let old_key = match get_key(old_row, &parent_columns) {
+                        Some(key) => key,
+                        None => continue,
+                    };
+
+                    if let &Some(ref new_row) = new_row {
+                        if get_key(new_row, &parent_columns) == Some(old_key.clone()) {
+                            continue;
+                        }
+                    }
+
+                    let keys = child.keys_where(|row| fk.key(row) == Some(old_key.clone()));
+
+                    if keys.len() == 0 {
+                        continue;
+                    }
+
+                    let action = if new_row.is_some() { fk.clause.on_update } else { fk.clause.on_delete };
+
+                    let values: Vec<LiteralValue> = match action {
+                        ForeignKeyAction::NoAction | ForeignKeyAction::Restrict => {
+                            return Err(foreign_key_failed());
+                        }
+                        ForeignKeyAction::Cascade => match new_row {
+                            &Some(ref new_row) => parent_columns.iter().map(|&i| new_row[i].clone()).collect(),
+                            &None => {
+                                actions.push(ChildAction::Delete(child.name.clone(), keys));
+                                continue;
+                            }
+                        },
+                        ForeignKeyAction::SetNull => fk.columns.iter().map(|_| LiteralValue::Null).collect(),
+                        ForeignKeyAction::SetDefault => fk.columns.iter().map(|&i| defaults[i].clone()).collect(),
+                    };
+
+                    let assignments: Vec<(usize, LiteralValue)> = fk.columns.iter().map(|i| *i)
+                                                                           .zip(values.into_iter())
+                                                                           .collect();
+                    actions.push(ChildAction::Update(child.name.clone(),
+                                                     keys.into_iter().map(|key| (key, assignments.clone())).collect()));
+                }
             }
         }
-        let table = Table::new(table_def);
-        self.map.insert(table.name.clone(), table);
+
+        Ok(actions)
     }
 
-    pub fn drop_table(&mut self, name: &String) {
-        self.map.remove(name.as_slice());
+    fn apply_foreign_key_actions(&mut self, actions: Vec<ChildAction>) -> RusqlResult<()> {
+        for action in actions.into_iter() {
+            match action {
+                ChildAction::Delete(name, keys) => {
+                    // Some of them may already be gone, e.g. through a
+                    // self-referencing table.
+                    let keys: Vec<PkType> = {
+                        let table = try!(self.get_table(&name));
+                        keys.into_iter().filter(|key| table.has_row(*key)).collect()
+                    };
+                    try!(self.delete_rows(&name, keys));
+                }
+                ChildAction::Update(name, assignments) => {
+                    let mut updates: Vec<(PkType, TableRow)> = Vec::new();
+
+                    for (key, values) in assignments.into_iter() {
+                        if let Some(row) = try!(self.get_table(&name)).data.get(&key) {
+                            let mut row = row.clone();
+
+                            for (i, value) in values.into_iter() {
+                                row[i] = value;
+                            }
+
+                            updates.push((key, row));
+                        }
+                    }
+
+                    try!(self.update_rows(&name, updates));
+                }
+            }
+        }
+
+        Ok(())
     }
 }
+
+/// Stops the statement running on a Rusql, from any thread: it fails with
+/// Interrupted before it reads its next row. An interrupt that comes while
+/// no statement is running is forgotten when the next one starts.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(atomic::Ordering::SeqCst)
+    }
+
+    fn reset(&self) {
+        self.interrupted.store(false, atomic::Ordering::SeqCst);
+    }
+}
+
+// Rolls back the transaction of Rusql::transaction when its closure panics.
+struct TransactionGuard<'a> {
+    db: &'a mut Rusql,
+    finished: bool,
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for TransactionGuard<'a> {
+    fn drop(&mut self) {
+        if !self.finished && self.db.in_transaction() {
+            self.db.rollback().ok();
+        }
+    }
+}
+
+type RowChange = (TableRow, Option<TableRow>);
+
+enum ChildAction {
+    Delete(String, Vec<PkType>),
+    Update(String, Vec<(PkType, Vec<(usize, LiteralValue)>)>),
+}
+
+fn foreign_key_failed() -> RusqlError {
+    RusqlError::ConstraintViolation("FOREIGN KEY constraint failed".to_string())
+}
+
+// The database and the table of a qualified name, as in aux.Foo.
+fn split_schema(name: &String) -> Option<(String, String)> {
+    let name = name.as_slice();
+
+    name.find('.').map(|i| (name.slice_to(i).to_string(), name.slice_from(i + 1).to_string()))
+}
+
+// Opens the file at `path` the way it was written, going by how it starts.
+fn open_attached(path: &Path) -> RusqlResult<Rusql> {
+    if path.exists() {
+        let mut start: Vec<u8> = Vec::new();
+        File::open(path).and_then(|mut file| file.push(16, &mut start)).ok();
+
+        if start.starts_with(pager::MAGIC) {
+            return Rusql::open_paged(path);
+        }
+        if start.starts_with(logfile::MAGIC) {
+            return Rusql::open_log(path);
+        }
+    }
+
+    Rusql::open_file(path)
+}
@@ -0,0 +1,64 @@
+//! Running SQL scripts, e.g. ones written by dump, from files too large to
+//! parse in one go.
+
+use error::{RusqlError, RusqlResult};
+use exec;
+use rusql::Rusql;
+
+use std::ascii::AsciiExt;
+use std::io::{BufferedReader, File};
+
+/// Runs the statements in the file at `path` one at a time, as they're read,
+/// until one fails. Returns how many were run.
+pub fn execute_file(db: &mut Rusql, path: &Path) -> RusqlResult<usize> {
+    let mut reader = BufferedReader::new(try!(File::open(path)));
+    let mut sql = String::new();
+    let mut quote: Option<char> = None;
+    let mut count = 0;
+
+    for line in reader.lines() {
+        for c in try!(line).chars() {
+            sql.push(c);
+
+            match (quote, c) {
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (Some(q), c) if c == q => quote = None,
+                (None, ';') => {
+                    if let Some(n) = try!(execute_complete(db, sql.as_slice())) {
+                        count += n;
+                        sql.clear();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !sql.trim().is_empty() {
+        return Err(RusqlError::ParseError(format!("incomplete statement at the end of the file: {}", sql.trim())));
+    }
+
+    Ok(count)
+}
+
+// Runs `sql`, which ends with a semicolon, unless it's the start of a
+// CREATE TRIGGER whose body goes on after it. Returns how many statements
+// were run, None if more of the trigger is to come.
+fn execute_complete(db: &mut Rusql, sql: &str) -> RusqlResult<Option<usize>> {
+    match exec::parse(sql) {
+        Ok(stmts) => {
+            let count = stmts.len();
+            try!(exec::execute_statements(db, stmts));
+            Ok(Some(count))
+        }
+        Err(e) => {
+            let words: Vec<String> = sql.words().take(4).map(|word| word.to_ascii_uppercase()).collect();
+
+            if words.len() > 1 && words[0] == "CREATE" && words.iter().any(|word| word.as_slice() == "TRIGGER") {
+                Ok(None)
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
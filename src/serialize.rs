@@ -0,0 +1,243 @@
+//! Serde support, behind the `serde` feature. Values serialize as the
+//! closest JSON-like type, rows as maps from column names to values and
+//! tables as sequences of rows. Values deserialize the other way around.
+//!
+//! Rusql::insert_records takes anything that serializes as a map or a
+//! struct, with an entry or a field for each column it sets.
+
+use definitions::LiteralValue;
+use error::{RusqlError, RusqlResult};
+use row::Row;
+use rusql::Rusql;
+use table::Table;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer, SeqVisitor, MapVisitor};
+use std::collections::BTreeMap;
+
+impl Serialize for LiteralValue {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        match self {
+            &LiteralValue::Integer(i) => serializer.visit_i64(i as i64),
+            &LiteralValue::Text(ref s) => serializer.visit_str(s.as_slice()),
+            &LiteralValue::Real(r) => serializer.visit_f64(r),
+            &LiteralValue::Boolean(b) => serializer.visit_bool(b),
+            &LiteralValue::Blob(ref bytes) => serializer.visit_bytes(bytes.as_slice()),
+            // As a string, so no digits are lost on the way.
+            &LiteralValue::Decimal(d) => serializer.visit_str(format!("{}", d).as_slice()),
+            &LiteralValue::Null => serializer.visit_none(),
+        }
+    }
+}
+
+impl<'a> Serialize for Row<'a> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.visit_map(RowVisitor { row: self, position: 0 })
+    }
+}
+
+// The columns of a row, one entry at a time.
+struct RowVisitor<'a, 'b: 'a> {
+    row: &'a Row<'b>,
+    position: usize,
+}
+
+impl<'a, 'b> MapVisitor for RowVisitor<'a, 'b> {
+    fn visit<S: Serializer>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error> {
+        match (self.row.header.get(self.position), self.row.values.get(self.position)) {
+            (Some(def), Some(value)) => {
+                self.position += 1;
+                Ok(Some(try!(serializer.visit_map_elt(&def.name, value))))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.row.values.len())
+    }
+}
+
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.visit_seq(TableVisitor { rows: self.rows(), position: 0 })
+    }
+}
+
+struct TableVisitor<'a> {
+    rows: Vec<Row<'a>>,
+    position: usize,
+}
+
+impl<'a> SeqVisitor for TableVisitor<'a> {
+    fn visit<S: Serializer>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error> {
+        match self.rows.get(self.position) {
+            Some(row) => {
+                self.position += 1;
+                Ok(Some(try!(serializer.visit_seq_elt(row))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.rows.len())
+    }
+}
+
+struct LiteralValueVisitor;
+
+impl Visitor for LiteralValueVisitor {
+    type Value = LiteralValue;
+
+    fn visit_bool<E: de::Error>(&mut self, b: bool) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Boolean(b))
+    }
+
+    fn visit_i64<E: de::Error>(&mut self, i: i64) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Integer(i as isize))
+    }
+
+    fn visit_u64<E: de::Error>(&mut self, i: u64) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Integer(i as isize))
+    }
+
+    fn visit_f64<E: de::Error>(&mut self, r: f64) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Real(r))
+    }
+
+    fn visit_str<E: de::Error>(&mut self, s: &str) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::text(s))
+    }
+
+    fn visit_bytes<E: de::Error>(&mut self, bytes: &[u8]) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Blob(bytes.to_vec()))
+    }
+
+    fn visit_none<E: de::Error>(&mut self) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Null)
+    }
+
+    fn visit_unit<E: de::Error>(&mut self) -> Result<LiteralValue, E> {
+        Ok(LiteralValue::Null)
+    }
+
+    fn visit_some<D: Deserializer>(&mut self, deserializer: &mut D) -> Result<LiteralValue, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+}
+
+impl Deserialize for LiteralValue {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<LiteralValue, D::Error> {
+        deserializer.visit(LiteralValueVisitor)
+    }
+}
+
+impl Rusql {
+    /// Inserts `records`, e.g. structs or maps from column names to values,
+    /// all or nothing. Returns how many rows were inserted.
+    pub fn insert_records<T: Serialize>(&mut self, table_name: &str, records: Vec<T>) -> RusqlResult<usize> {
+        let mut maps: Vec<BTreeMap<String, LiteralValue>> = Vec::new();
+
+        for record in records.iter() {
+            let mut serializer = RecordSerializer { record: BTreeMap::new() };
+            try!(record.serialize(&mut serializer));
+            maps.push(serializer.record);
+        }
+
+        self.insert_maps(table_name, maps)
+    }
+}
+
+// Collects the entries or fields of a record, a column each.
+struct RecordSerializer {
+    record: BTreeMap<String, LiteralValue>,
+}
+
+impl RecordSerializer {
+    fn not_a_record<T>(&self) -> RusqlResult<T> {
+        Err(RusqlError::TypeMismatch("a record must be a map or a struct".to_string()))
+    }
+}
+
+impl Serializer for RecordSerializer {
+    type Error = RusqlError;
+
+    fn visit_bool(&mut self, _: bool) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_i64(&mut self, _: i64) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_u64(&mut self, _: u64) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_f64(&mut self, _: f64) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_str(&mut self, _: &str) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_unit(&mut self) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_none(&mut self) -> RusqlResult<()> { self.not_a_record() }
+
+    fn visit_some<V: Serialize>(&mut self, value: V) -> RusqlResult<()> {
+        value.serialize(self)
+    }
+
+    fn visit_seq<V: SeqVisitor>(&mut self, _: V) -> RusqlResult<()> { self.not_a_record() }
+    fn visit_seq_elt<V: Serialize>(&mut self, _: V) -> RusqlResult<()> { self.not_a_record() }
+
+    fn visit_map<V: MapVisitor>(&mut self, mut visitor: V) -> RusqlResult<()> {
+        while let Some(()) = try!(visitor.visit(self)) {}
+        Ok(())
+    }
+
+    fn visit_map_elt<K: Serialize, V: Serialize>(&mut self, key: K, value: V) -> RusqlResult<()> {
+        let name = match try!(to_value(&key)) {
+            LiteralValue::Text(name) => (*name).clone(),
+            _ => return Err(RusqlError::TypeMismatch("column names must be strings".to_string())),
+        };
+
+        self.record.insert(name, try!(to_value(&value)));
+        Ok(())
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> RusqlResult<LiteralValue> {
+    let mut serializer = ValueSerializer { value: LiteralValue::Null };
+
+    try!(value.serialize(&mut serializer));
+    Ok(serializer.value)
+}
+
+// Turns what serializes as a single value into one.
+struct ValueSerializer {
+    value: LiteralValue,
+}
+
+impl ValueSerializer {
+    fn set(&mut self, value: LiteralValue) -> RusqlResult<()> {
+        self.value = value;
+        Ok(())
+    }
+
+    fn nested<T>(&self) -> RusqlResult<T> {
+        Err(RusqlError::TypeMismatch("sequences and maps can't be stored in a column".to_string()))
+    }
+}
+
+impl Serializer for ValueSerializer {
+    type Error = RusqlError;
+
+    fn visit_bool(&mut self, b: bool) -> RusqlResult<()> { self.set(LiteralValue::Boolean(b)) }
+    fn visit_i64(&mut self, i: i64) -> RusqlResult<()> { self.set(LiteralValue::Integer(i as isize)) }
+    fn visit_u64(&mut self, i: u64) -> RusqlResult<()> { self.set(LiteralValue::Integer(i as isize)) }
+    fn visit_f64(&mut self, r: f64) -> RusqlResult<()> { self.set(LiteralValue::Real(r)) }
+    fn visit_str(&mut self, s: &str) -> RusqlResult<()> { self.set(LiteralValue::text(s)) }
+    fn visit_bytes(&mut self, bytes: &[u8]) -> RusqlResult<()> { self.set(LiteralValue::Blob(bytes.to_vec())) }
+    fn visit_unit(&mut self) -> RusqlResult<()> { self.set(LiteralValue::Null) }
+    fn visit_none(&mut self) -> RusqlResult<()> { self.set(LiteralValue::Null) }
+
+    fn visit_some<V: Serialize>(&mut self, value: V) -> RusqlResult<()> {
+        value.serialize(self)
+    }
+
+    fn visit_seq<V: SeqVisitor>(&mut self, _: V) -> RusqlResult<()> { self.nested() }
+    fn visit_seq_elt<V: Serialize>(&mut self, _: V) -> RusqlResult<()> { self.nested() }
+    fn visit_map<V: MapVisitor>(&mut self, _: V) -> RusqlResult<()> { self.nested() }
+
+    fn visit_map_elt<K: Serialize, V: Serialize>(&mut self, _: K, _: V) -> RusqlResult<()> {
+        self.nested()
+    }
+}
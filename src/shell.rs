@@ -1,3 +1,4 @@
+#![feature(box_syntax)]
 #![allow(unstable)]
 
 extern crate rusql;
@@ -6,10 +7,17 @@ extern crate readline;
 
 #[cfg(not(feature = "no_readline"))]
 use readline::{readline, add_history};
-use rusql::{rusql_exec, Rusql};
+use rusql::{CsvOptions, Quoting, Rusql, QueryResult};
 
-#[cfg(feature = "no_readline")]
-use std::io;
+use std::io::{self, File};
+
+// How query results are printed, see .mode.
+#[derive(Copy, PartialEq)]
+enum Mode {
+    Table,
+    Csv,
+    Json,
+}
 
 #[cfg(feature = "no_readline")]
 fn rl(prompt: &str) -> String {
@@ -26,8 +34,39 @@ fn rl(prompt: &str) -> String {
     res
 }
 
+// Prints the rows of `results`, or writes them to the file `once` names.
+fn print_results(results: &Vec<QueryResult>, mode: Mode, options: &CsvOptions, once: Option<Path>) {
+    let mut out: Box<Writer> = match once {
+        Some(path) => match File::create(&path) {
+            Ok(file) => box file,
+            Err(e) => return println!("error: {}", e),
+        },
+        None => box io::stdout(),
+    };
+
+    for result in results.iter() {
+        if let &QueryResult::Rows(ref table) = result {
+            let written = match mode {
+                Mode::Table => write!(out, "{}", table),
+                Mode::Csv => table.to_csv(&mut out, options),
+                Mode::Json => table.to_json(&mut out),
+            };
+
+            if let Err(e) = written {
+                return println!("error: {}", e);
+            }
+        }
+    }
+}
+
 pub fn main() {
     let mut db = Rusql::new();
+    let mut mode = Mode::Table;
+    // How .import reads files and how .mode csv and tabs print.
+    let mut options = CsvOptions::new();
+    // Where the results of the next statement go, see .once.
+    let mut once: Option<Path> = None;
+
     loop {
         let mut input = rl("rusql> ");
 
@@ -40,19 +79,67 @@ pub fn main() {
             input.push_str(continuation.as_slice());
         }
 
-        match input.as_slice() {
+        match input.as_slice().trim() {
             ".make_foo" => {
-                rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER, Name TEXT);
-                                     INSERT INTO Foo VALUES
-                                            (1, \"Foo1\"), (2, \"Foo2\"), (3, \"Foo3\");
-                                     CREATE TABLE Qux(QuxId INTEGER PRIMARY KEY, Nick TEXT);
-                                     INSERT INTO Qux(Nick) VALUES
-                                            (\"Bar1\"), (\"Bar2\"), (\"Bar3\");",
-                           |_, _| ());
-            }
-            _ => {
-                if let Some(results) = rusql_exec(&mut db, input.as_slice(), |_, _| {}) {
-                    print!("{}", results);
+                db.execute("CREATE TABLE Foo(Id INTEGER, Name TEXT);
+                            INSERT INTO Foo VALUES
+                                   (1, \"Foo1\"), (2, \"Foo2\"), (3, \"Foo3\");
+                            CREATE TABLE Qux(QuxId INTEGER PRIMARY KEY, Nick TEXT);
+                            INSERT INTO Qux(Nick) VALUES
+                                   (\"Bar1\"), (\"Bar2\"), (\"Bar3\");").ok();
+            }
+            command if command.starts_with(".import") => {
+                let args: Vec<&str> = command.words().skip(1).collect();
+
+                if args.len() != 2 {
+                    println!("usage: .import FILE TABLE");
+                } else {
+                    match db.import_csv(&Path::new(args[0]), args[1], &options) {
+                        Ok(count) => println!("{} rows imported", count),
+                        Err(e) => println!("error: {}", e),
+                    }
+                }
+            }
+            command if command.starts_with(".mode") => {
+                match command.words().nth(1) {
+                    Some("table") => mode = Mode::Table,
+                    Some("csv") => {
+                        mode = Mode::Csv;
+                        options.delimiter = ',';
+                        options.quoting = Quoting::Minimal;
+                    }
+                    Some("tabs") => {
+                        mode = Mode::Csv;
+                        options.delimiter = '\t';
+                        options.quoting = Quoting::Never;
+                    }
+                    Some("json") => mode = Mode::Json,
+                    _ => println!("usage: .mode table|csv|tabs|json"),
+                }
+            }
+            command if command.starts_with(".separator") => {
+                match command.words().nth(1) {
+                    Some("\\t") => options.delimiter = '\t',
+                    Some(separator) if separator.chars().count() == 1 => {
+                        options.delimiter = separator.char_at(0);
+                    }
+                    _ => println!("usage: .separator CHAR"),
+                }
+            }
+            command if command.starts_with(".nullvalue") => {
+                options.null = command.words().nth(1).unwrap_or("").to_string();
+            }
+            command if command.starts_with(".once") => {
+                match command.words().nth(1) {
+                    Some(path) => once = Some(Path::new(path)),
+                    None => println!("usage: .once FILE"),
+                }
+            }
+            _ => match db.execute(input.as_slice()) {
+                Ok(results) => print_results(&results, mode, &options, once.take()),
+                Err(e) => {
+                    once = None;
+                    println!("error: {}", e);
                 }
             }
         }
@@ -0,0 +1,72 @@
+//! Read-only copies of a database, see Rusql::snapshot.
+
+use backup::Progress;
+use definitions::{LiteralValue, RusqlStatement};
+use error::{RusqlError, RusqlResult};
+use exec::{self, QueryResult, Rows};
+use row::{Row, FromValue};
+use rusql::Rusql;
+
+/// Every table and the rest of the schema as they were when the snapshot
+/// was taken. The tables are shared with the database, which only copies
+/// one when it changes it afterwards, so taking a snapshot is cheap. A
+/// snapshot can be sent to another thread and queried there while the
+/// database keeps changing; only statements that don't write can run on it.
+pub struct Snapshot {
+    db: Rusql,
+}
+
+impl Snapshot {
+    /// Reads from `db`, which nothing else can change.
+    pub fn new(db: Rusql) -> Snapshot {
+        Snapshot { db: db }
+    }
+
+    /// Like Rusql::execute, failing with ReadOnly before running anything
+    /// if one of the statements would write.
+    pub fn execute(&mut self, sql: &str) -> RusqlResult<Vec<QueryResult>> {
+        try!(self.check_read_only(sql));
+        self.db.execute(sql)
+    }
+
+    pub fn query(&mut self, sql: &str) -> RusqlResult<Rows> {
+        try!(self.check_read_only(sql));
+        self.db.query(sql)
+    }
+
+    pub fn query_map<T, F>(&mut self, sql: &str, params: &[LiteralValue], f: F) -> RusqlResult<Vec<T>>
+        where F: FnMut(&Row) -> RusqlResult<T> {
+        try!(self.check_read_only(sql));
+        self.db.query_map(sql, params, f)
+    }
+
+    pub fn query_scalar<T: FromValue>(&mut self, sql: &str, params: &[LiteralValue]) -> RusqlResult<T> {
+        try!(self.check_read_only(sql));
+        self.db.query_scalar(sql, params)
+    }
+
+    /// Rusql::backup_to, which can run on a snapshot in another thread while
+    /// the database it was taken of keeps changing.
+    pub fn backup_to<F: FnMut(Progress)>(&self, dest: &mut Rusql, progress: F) -> RusqlResult<()> {
+        self.db.backup_to(dest, progress)
+    }
+
+    pub fn table_names(&self) -> Vec<String> {
+        self.db.table_names()
+    }
+
+    pub fn row_count(&self, name: &str) -> RusqlResult<usize> {
+        self.db.row_count(name)
+    }
+
+    fn check_read_only(&mut self, sql: &str) -> RusqlResult<()> {
+        for stmt in try!(exec::parse_cached(&mut self.db, sql)).iter() {
+            match *stmt {
+                RusqlStatement::Select(..) | RusqlStatement::Explain(..) | RusqlStatement::ExplainQueryPlan(..) => {}
+                _ => return Err(RusqlError::ReadOnly),
+            }
+        }
+
+        Ok(())
+    }
+}
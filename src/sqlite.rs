@@ -0,0 +1,1087 @@
+//! Reading and writing SQLite 3 database files, whose format is described
+//! in https://www.sqlite.org/fileformat.html. Only the table b-trees are
+//! read: the indexes are built again from their CREATE INDEX statements,
+//! and tables WITHOUT ROWID, which are stored in index b-trees, can't be
+//! read. Both are written, so that SQLite can use the indexes.
+
+use definitions::{LiteralValue, ColumnType, ColumnConstraint, TableConstraint, TableDef, ColumnDef};
+use error::{RusqlError, RusqlResult};
+use exec;
+use rusql::Rusql;
+use table::{Table, TableRow, PkType};
+
+use std::ascii::AsciiExt;
+use std::cmp::{max, min, Ordering};
+use std::io::{BufferedWriter, File};
+use std::iter::repeat;
+use std::mem;
+use std::sync::Arc;
+
+/// What every SQLite 3 file starts with.
+pub const MAGIC: &'static [u8] = b"SQLite format 3\0";
+
+/// The root page of the sqlite_master table.
+const MASTER_ROOT: usize = 1;
+/// The bytes of the file header, which come first on page 1.
+const HEADER_SIZE: usize = 100;
+
+const INTERIOR_TABLE_PAGE: u8 = 0x05;
+const LEAF_TABLE_PAGE: u8 = 0x0D;
+
+/// A database holding the tables, rows, indexes, views and triggers of the
+/// SQLite file at `path`. Tables whose SQL rusql can't parse, e.g. because
+/// of VARCHAR(n) columns or quoted names, get only the columns and primary
+/// key their CREATE TABLE declares; the SQL of the rest of the schema must
+/// be understood by rusql.
+pub fn open_sqlite(path: &Path) -> RusqlResult<Rusql> {
+    let file = try!(SqliteFile::new(try!(File::open(path).read_to_end())));
+    let mut db = Rusql::new();
+    let mut schema: Vec<TableRow> = Vec::new();
+
+    for (_, payload) in try!(file.table_cells(MASTER_ROOT)).into_iter() {
+        schema.push(try!(record(payload.as_slice())));
+    }
+
+    // The tables come first, so that the rest of the schema finds them.
+    for entry in schema.iter() {
+        if let (Some("table"), Some(name), Some(sql)) = (text(entry, 0), text(entry, 1), text(entry, 4)) {
+            if !name.starts_with("sqlite_") {
+                try!(create_table(&mut db, name, sql));
+                try!(load_rows(&mut db, &file, name, try!(root_page(entry))));
+            }
+        }
+    }
+
+    // The keys AUTOINCREMENT handed out, by table.
+    for entry in schema.iter() {
+        if let (Some("table"), Some("sqlite_sequence")) = (text(entry, 0), text(entry, 1)) {
+            for (_, payload) in try!(file.table_cells(try!(root_page(entry)))).into_iter() {
+                let sequence = try!(record(payload.as_slice()));
+
+                if let (Some(name), Some(&LiteralValue::Integer(max_pk))) = (text(&sequence, 0), sequence.get(1)) {
+                    let table = try!(db.get_mut_table(&name.to_string()));
+                    table.max_pk = max(table.max_pk, max_pk as usize);
+                }
+            }
+        }
+    }
+
+    // Indexes made for UNIQUE and PRIMARY KEY constraints have no SQL.
+    for entry in schema.iter() {
+        if let (Some(kind), Some(sql)) = (text(entry, 0), text(entry, 4)) {
+            if kind != "table" {
+                try!(exec::execute(&mut db, format!("{};", sql).as_slice()));
+            }
+        }
+    }
+
+    Ok(db)
+}
+
+fn create_table(db: &mut Rusql, name: &str, sql: &str) -> RusqlResult<()> {
+    match exec::execute(db, format!("{};", sql).as_slice()) {
+        Err(RusqlError::ParseError(..)) => {}
+        result => return result.map(|_| ()),
+    }
+
+    let (columns, constraints) = try!(table_header(sql));
+    db.create_table(TableDef {
+        table_name: name.to_string(),
+        columns: columns,
+        constraints: constraints,
+        if_not_exists: false,
+        temporary: false,
+        as_select: None,
+        strict: false,
+        columnar: false,
+    })
+}
+
+// The columns a CREATE TABLE statement declares, with the types their
+// declared ones have the affinity of, and its primary key. The other
+// constraints are left out.
+fn table_header(sql: &str) -> RusqlResult<(Vec<ColumnDef>, Vec<TableConstraint>)> {
+    let tokens = tokenize(sql);
+    let start = match tokens.iter().position(|token| *token == Token::Punct('(')) {
+        Some(i) => i + 1,
+        None => return Err(corrupt("bad CREATE TABLE statement")),
+    };
+    let mut columns: Vec<ColumnDef> = Vec::new();
+    let mut constraints: Vec<TableConstraint> = Vec::new();
+
+    for definition in split_definitions(&tokens[start..]).into_iter() {
+        let words: Vec<String> = definition.iter().map(|token| token.keyword()).collect();
+
+        match words.first().map(|word| word.as_slice()) {
+            None => return Err(corrupt("bad CREATE TABLE statement")),
+            Some("PRIMARY") => {
+                let names = definition.iter().skip_while(|token| **token != Token::Punct('('))
+                                             .filter_map(|token| token.name()).collect();
+                constraints.push(TableConstraint::PrimaryKey(names));
+            }
+            Some("CONSTRAINT") | Some("UNIQUE") | Some("CHECK") | Some("FOREIGN") => {}
+            Some(_) => {
+                let name = match definition[0].name() {
+                    Some(name) => name,
+                    None => return Err(corrupt("bad CREATE TABLE statement")),
+                };
+                // The type is every word up to the first constraint.
+                let type_words: Vec<&str> = words[1..].iter().map(|word| word.as_slice())
+                                                     .take_while(|word| !is_constraint_keyword(*word))
+                                                     .filter(|word| !word.is_empty()).collect();
+                let mut column_constraints: Vec<ColumnConstraint> = Vec::new();
+
+                if words.iter().any(|word| word.as_slice() == "PRIMARY") {
+                    column_constraints.push(ColumnConstraint::PrimaryKey);
+                }
+                if words.iter().any(|word| word.as_slice() == "AUTOINCREMENT") {
+                    column_constraints.push(ColumnConstraint::Autoincrement);
+                }
+
+                columns.push(ColumnDef {
+                    name: name,
+                    column_type: affinity(type_words.connect(" ").as_slice(), &definition),
+                    column_constraints: column_constraints,
+                });
+            }
+        }
+    }
+
+    Ok((columns, constraints))
+}
+
+// The column type with the affinity SQLite gives a declared type, following
+// https://www.sqlite.org/datatype3.html#determination_of_column_affinity.
+// NUMERIC affinity is only kept with a precision and scale: without them the
+// values SQLite stored already have it, and are left the way they are.
+fn affinity(declared: &str, definition: &[Token]) -> Option<ColumnType> {
+    if declared.contains("INT") {
+        Some(ColumnType::Integer)
+    } else if declared.contains("CHAR") || declared.contains("CLOB") || declared.contains("TEXT") {
+        Some(ColumnType::Text)
+    } else if declared.contains("BLOB") || declared.is_empty() {
+        None
+    } else if declared.contains("REAL") || declared.contains("FLOA") || declared.contains("DOUB") {
+        Some(ColumnType::Real)
+    } else if declared.contains("BOOL") {
+        Some(ColumnType::Boolean)
+    } else {
+        let numbers: Vec<usize> = definition.iter().filter_map(|token| match *token {
+            Token::Word(ref word) => word.parse::<usize>(),
+            _ => None,
+        }).take(2).collect();
+
+        match (declared.contains("NUMERIC") || declared.contains("DECIMAL"), numbers.as_slice()) {
+            (true, [precision, scale]) => Some(ColumnType::Numeric(precision, scale)),
+            _ => None,
+        }
+    }
+}
+
+fn is_constraint_keyword(word: &str) -> bool {
+    match word {
+        "CONSTRAINT" | "PRIMARY" | "NOT" | "NULL" | "UNIQUE" | "CHECK" | "DEFAULT" | "COLLATE"
+            | "REFERENCES" | "GENERATED" | "AS" => true,
+        _ => false,
+    }
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Word(String),
+    /// A name in double quotes, backquotes or brackets.
+    Quoted(String),
+    /// A string literal.
+    Text(String),
+    Punct(char),
+}
+
+impl Token {
+    // Words in upper case, to be compared with keywords. Anything else,
+    // which can't be a keyword, gives an empty string.
+    fn keyword(&self) -> String {
+        match *self {
+            Token::Word(ref word) => word.as_slice().to_ascii_uppercase(),
+            _ => String::new(),
+        }
+    }
+
+    fn name(&self) -> Option<String> {
+        match *self {
+            Token::Word(ref word) | Token::Quoted(ref word) | Token::Text(ref word) => Some(word.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' || c == '`' || c == '[' || c == '\'' {
+            let close = if c == '[' { ']' } else { c };
+            let mut text = String::new();
+            i += 1;
+
+            // A doubled quote stands for itself.
+            while i < chars.len() {
+                if chars[i] == close {
+                    if close != ']' && i + 1 < chars.len() && chars[i + 1] == close {
+                        text.push(close);
+                        i += 2;
+                        continue;
+                    }
+                    break;
+                }
+                text.push(chars[i]);
+                i += 1;
+            }
+            i += 1;
+            tokens.push(if c == '\'' { Token::Text(text) } else { Token::Quoted(text) });
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().map(|c| *c).collect()));
+        } else {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+// The tokens of each definition between the parentheses of a CREATE TABLE,
+// from just after the opening one.
+fn split_definitions(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut definitions: Vec<&[Token]> = Vec::new();
+    let mut depth = 0us;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') if depth == 0 => {
+                definitions.push(&tokens[start..i]);
+                return definitions;
+            }
+            Token::Punct(')') => depth -= 1,
+            Token::Punct(',') if depth == 0 => {
+                definitions.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    definitions
+}
+
+fn load_rows(db: &mut Rusql, file: &SqliteFile, name: &str, root: usize) -> RusqlResult<()> {
+    let cells = try!(file.table_cells(root));
+    let table = try!(db.get_mut_table(&name.to_string()));
+    let defaults = table.default_row();
+    let mut rows: Vec<TableRow> = Vec::new();
+
+    // An INTEGER PRIMARY KEY is the rowid, which the record has a NULL for.
+    let rowid_column = table.pk.and_then(|i| {
+        if table.header[i].column_type == Some(ColumnType::Integer) { Some(i) } else { None }
+    });
+
+    for (rowid, payload) in cells.into_iter() {
+        let mut row = try!(record(payload.as_slice()));
+
+        // The columns added after the row was written aren't in its record.
+        row.truncate(defaults.len());
+        let len = row.len();
+        row.extend(defaults[len..].iter().map(|value| value.clone()));
+
+        if let Some(i) = rowid_column {
+            if row[i].is_null() {
+                row[i] = LiteralValue::Integer(rowid as isize);
+            }
+        }
+        rows.push(row);
+    }
+
+    try!(table.insert_batch(rows.into_iter()));
+    Ok(())
+}
+
+fn text(entry: &TableRow, i: usize) -> Option<&str> {
+    match entry.get(i) {
+        Some(&LiteralValue::Text(ref text)) => Some(text.as_slice()),
+        _ => None,
+    }
+}
+
+fn root_page(entry: &TableRow) -> RusqlResult<usize> {
+    match entry.get(3) {
+        Some(&LiteralValue::Integer(page)) if page > 0 => Ok(page as usize),
+        _ => Err(corrupt("bad root page")),
+    }
+}
+
+fn corrupt(message: &str) -> RusqlError {
+    RusqlError::Corrupt(message.to_string())
+}
+
+struct SqliteFile {
+    bytes: Vec<u8>,
+    page_size: usize,
+    /// The bytes of each page left for the b-trees, without the ones
+    /// reserved at the end of it.
+    usable_size: usize,
+}
+
+impl SqliteFile {
+    fn new(bytes: Vec<u8>) -> RusqlResult<SqliteFile> {
+        if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(corrupt("not a SQLite 3 database"));
+        }
+
+        // A page size of 1 stands for 65536, which doesn't fit.
+        let page_size = match be_uint(&bytes[16..18]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        let usable_size = page_size - bytes[20] as usize;
+
+        if page_size < 512 || usable_size < 480 {
+            return Err(corrupt("bad page size"));
+        }
+
+        if be_uint(&bytes[56..60]) > 1 {
+            return Err(corrupt("only UTF-8 databases can be read"));
+        }
+
+        Ok(SqliteFile { bytes: bytes, page_size: page_size, usable_size: usable_size })
+    }
+
+    // Pages are numbered from 1.
+    fn page(&self, number: usize) -> RusqlResult<&[u8]> {
+        if number == 0 || number * self.page_size > self.bytes.len() {
+            return Err(corrupt("page out of range"));
+        }
+        Ok(&self.bytes[(number - 1) * self.page_size..number * self.page_size])
+    }
+
+    /// The rowid and payload of every row of the table b-tree at `root`, in
+    /// rowid order.
+    fn table_cells(&self, root: usize) -> RusqlResult<Vec<(i64, Vec<u8>)>> {
+        let mut cells: Vec<(i64, Vec<u8>)> = Vec::new();
+        let mut pages: Vec<usize> = vec![root];
+        let mut visited = 0;
+
+        while let Some(number) = pages.pop() {
+            // Corrupt files could send us round in circles.
+            visited += 1;
+            if visited > self.bytes.len() / self.page_size {
+                return Err(corrupt("b-tree loop"));
+            }
+
+            let page = try!(self.page(number));
+            let start = if number == 1 { HEADER_SIZE } else { 0 };
+            let count = be_uint(try!(slice(page, start + 3, 2))) as usize;
+
+            match page[start] {
+                LEAF_TABLE_PAGE => {
+                    for i in range(0, count) {
+                        let offset = be_uint(try!(slice(page, start + 8 + 2 * i, 2))) as usize;
+                        let (size, pos) = try!(varint(page, offset));
+                        let (rowid, pos) = try!(varint(page, pos));
+                        cells.push((rowid as i64, try!(self.payload(page, pos, size as usize))));
+                    }
+                }
+                INTERIOR_TABLE_PAGE => {
+                    // Pushed in reverse, so that the leftmost child comes
+                    // off the stack first.
+                    pages.push(be_uint(try!(slice(page, start + 8, 4))) as usize);
+                    for i in range(0, count).rev() {
+                        let offset = be_uint(try!(slice(page, start + 12 + 2 * i, 2))) as usize;
+                        pages.push(be_uint(try!(slice(page, offset, 4))) as usize);
+                    }
+                }
+                _ => return Err(corrupt("not a table b-tree page")),
+            }
+        }
+
+        Ok(cells)
+    }
+
+    // The payload of `size` bytes starting at `pos` on `page`, with the part
+    // that didn't fit on it read from its overflow pages.
+    fn payload(&self, page: &[u8], pos: usize, size: usize) -> RusqlResult<Vec<u8>> {
+        let usable = self.usable_size;
+        let max_local = usable - 35;
+
+        if size <= max_local {
+            return Ok(try!(slice(page, pos, size)).to_vec());
+        }
+
+        let min_local = (usable - 12) * 32 / 255 - 23;
+        let local = match min_local + (size - min_local) % (usable - 4) {
+            local if local <= max_local => local,
+            _ => min_local,
+        };
+
+        let mut payload = try!(slice(page, pos, local)).to_vec();
+        let mut next = be_uint(try!(slice(page, pos + local, 4))) as usize;
+
+        while payload.len() < size {
+            let overflow = try!(self.page(next));
+            let len = min(size - payload.len(), usable - 4);
+
+            payload.push_all(try!(slice(overflow, 4, len)));
+            next = be_uint(&overflow[..4]) as usize;
+        }
+
+        Ok(payload)
+    }
+}
+
+fn slice(bytes: &[u8], pos: usize, len: usize) -> RusqlResult<&[u8]> {
+    if pos + len > bytes.len() {
+        return Err(corrupt("read past the end of a page"));
+    }
+    Ok(&bytes[pos..pos + len])
+}
+
+fn be_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+// A variable-length integer, and the position after it.
+fn varint(bytes: &[u8], mut pos: usize) -> RusqlResult<(u64, usize)> {
+    let mut value = 0u64;
+
+    for i in range(0, 9) {
+        let byte = match bytes.get(pos) {
+            Some(&byte) => byte as u64,
+            None => return Err(corrupt("read past the end of a page")),
+        };
+        pos += 1;
+
+        // The ninth byte counts whole.
+        if i == 8 {
+            return Ok(((value << 8) | byte, pos));
+        }
+
+        value = (value << 7) | (byte & 0x7f);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Ok((value, pos))
+}
+
+/// The values of a row stored in the record format.
+fn record(payload: &[u8]) -> RusqlResult<TableRow> {
+    let (header_size, mut pos) = try!(varint(payload, 0));
+    let mut serial_types: Vec<u64> = Vec::new();
+
+    while pos < header_size as usize {
+        let (serial_type, next) = try!(varint(payload, pos));
+        serial_types.push(serial_type);
+        pos = next;
+    }
+
+    let mut row: TableRow = Vec::new();
+    let mut pos = header_size as usize;
+
+    for &serial_type in serial_types.iter() {
+        let len = match serial_type {
+            0 | 8 | 9 => 0,
+            1 ... 4 => serial_type as usize,
+            5 => 6,
+            6 | 7 => 8,
+            n if n >= 12 => (n as usize - 12) / 2,
+            _ => return Err(corrupt("bad serial type")),
+        };
+        let bytes = try!(slice(payload, pos, len));
+        pos += len;
+
+        row.push(match serial_type {
+            0 => LiteralValue::Null,
+            1 ... 6 => {
+                // Sign-extended from the first byte.
+                let first: i64 = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+                LiteralValue::Integer(bytes.iter().fold(first, |value, &byte| (value << 8) | byte as i64) as isize)
+            }
+            7 => LiteralValue::Real(unsafe { mem::transmute::<u64, f64>(be_uint(bytes)) }),
+            8 => LiteralValue::Integer(0),
+            9 => LiteralValue::Integer(1),
+            n if n % 2 == 0 => LiteralValue::Blob(bytes.to_vec()),
+            _ => match String::from_utf8(bytes.to_vec()) {
+                Ok(text) => LiteralValue::Text(Arc::new(text)),
+                Err(..) => return Err(corrupt("invalid UTF-8 text")),
+            },
+        });
+    }
+
+    Ok(row)
+}
+
+/// The size of the pages save_sqlite writes.
+pub const PAGE_SIZE: usize = 4096;
+/// The page holding byte 2^30 of the file, which stays unused.
+const LOCK_PAGE: usize = (1 << 30) / PAGE_SIZE + 1;
+/// The version of SQLite the files are written as, 3.8.8.
+const SQLITE_VERSION: u64 = 3008008;
+
+const INTERIOR_INDEX_PAGE: u8 = 0x02;
+const LEAF_INDEX_PAGE: u8 = 0x0A;
+
+/// Writes the tables of `db`, with their rows, indexes, views and triggers,
+/// to a SQLite 3 file at `path`. The SQL is written the way rusql renders
+/// it, so SQLite has to understand the schema for the file to be usable.
+/// Temporary tables aren't written, and materialized views are written as
+/// the tables holding their rows.
+pub fn save_sqlite(db: &Rusql, path: &Path) -> RusqlResult<()> {
+    let mut w = SqliteWriter { pages: Vec::new() };
+    let mut master: Vec<TableRow> = Vec::new();
+    let mut sequence: Vec<TableRow> = Vec::new();
+
+    // Page 1 is kept for sqlite_master, which is written last.
+    w.allocate();
+
+    for (name, table) in db.map.iter() {
+        let root = w.table_tree(table_cells(&**table), None);
+        master.push(master_entry("table", name, name, root, Some(table_sql(&**table))));
+
+        // SQLite keeps UNIQUE and PRIMARY KEY constraints in indexes of its
+        // own, which it finds by these names.
+        for (i, columns) in autoindex_columns(&**table).into_iter().enumerate() {
+            let keys = table.data.iter().map(|(pk, row)| {
+                (columns.iter().map(|&c| row[c].clone()).collect(), pk)
+            }).collect();
+            let root = w.index_tree(keys);
+            master.push(master_entry("index", &format!("sqlite_autoindex_{}_{}", name, i + 1), name, root, None));
+        }
+
+        for index in table.indexes.iter() {
+            let keys = table.data.iter().filter(|&(_, row)| index.covers(row, &table.header))
+                                        .map(|(pk, row)| (index.key(row, &table.header), pk))
+                                        .collect();
+            let root = w.index_tree(keys);
+            master.push(master_entry("index", &index.name, name, root, Some(index.to_sql(name))));
+        }
+
+        if table.autoincrement {
+            sequence.push(vec![LiteralValue::text(name.as_slice()), LiteralValue::Integer(table.max_pk as isize)]);
+        }
+    }
+
+    if !sequence.is_empty() {
+        let cells = sequence.iter().enumerate().map(|(i, row)| ((i + 1) as i64, encode_record(row))).collect();
+        let root = w.table_tree(cells, None);
+        let name = "sqlite_sequence".to_string();
+        master.push(master_entry("table", &name, &name, root, Some("CREATE TABLE sqlite_sequence(name,seq)".to_string())));
+    }
+
+    for (name, select_def) in db.views.iter() {
+        master.push(master_entry("view", name, name, 0, Some(format!("CREATE VIEW {} AS {}", name, select_def))));
+    }
+
+    for (table_name, triggers) in db.triggers.iter() {
+        if db.map.contains_key(table_name) || db.views.contains_key(table_name) {
+            for trigger in triggers.iter() {
+                master.push(master_entry("trigger", &trigger.name, table_name, 0, Some(trigger.sql.clone())));
+            }
+        }
+    }
+
+    let cells = master.iter().enumerate().map(|(i, row)| ((i + 1) as i64, encode_record(row))).collect();
+    w.table_tree(cells, Some(MASTER_ROOT));
+    w.write_header();
+
+    let mut file = BufferedWriter::new(try!(File::create(path)));
+    for page in w.pages.iter() {
+        for byte in page.iter() {
+            try!(file.write_u8(*byte));
+        }
+    }
+    try!(file.flush());
+    Ok(())
+}
+
+fn master_entry(kind: &str, name: &String, table_name: &String, root: usize, sql: Option<String>) -> TableRow {
+    vec![LiteralValue::text(kind), LiteralValue::text(name.as_slice()), LiteralValue::text(table_name.as_slice()),
+         LiteralValue::Integer(root as isize), sql.map_or(LiteralValue::Null, |sql| LiteralValue::Text(Arc::new(sql)))]
+}
+
+// The CREATE TABLE statement, without what only rusql knows.
+fn table_sql(table: &Table) -> String {
+    let mut sql = table.to_sql();
+
+    if table.columns.is_some() {
+        let len = sql.len() - " COLUMNAR".len();
+        sql.truncate(len);
+    }
+    sql
+}
+
+// The column that's an alias for the rowid, which a single INTEGER PRIMARY
+// KEY is in SQLite.
+fn rowid_column(table: &Table) -> Option<usize> {
+    table.pk.and_then(|i| {
+        if table.header[i].column_type == Some(ColumnType::Integer) { Some(i) } else { None }
+    })
+}
+
+// The rowid and record of every row. The rowid alias is stored as NULL, and
+// VIRTUAL generated columns aren't stored at all.
+fn table_cells(table: &Table) -> Vec<(i64, Vec<u8>)> {
+    let rowid = rowid_column(table);
+    let stored: Vec<usize> = table.header.iter().enumerate().filter(|&(_, column_def)| {
+        !column_def.column_constraints.iter().any(|constraint| match *constraint {
+            ColumnConstraint::Generated(_, stored) => !stored,
+            _ => false,
+        })
+    }).map(|(i, _)| i).collect();
+
+    table.data.iter().map(|(pk, row)| {
+        let values: TableRow = stored.iter().map(|&i| {
+            if Some(i) == rowid { LiteralValue::Null } else { row[i].clone() }
+        }).collect();
+        (pk as i64, encode_record(&values))
+    }).collect()
+}
+
+// The columns of the indexes SQLite makes for the PRIMARY KEY and UNIQUE
+// constraints, in the order it numbers them: as they appear in the CREATE
+// TABLE statement, without repeating the same columns.
+fn autoindex_columns(table: &Table) -> Vec<Vec<usize>> {
+    let rowid = rowid_column(table);
+    let mut indexes: Vec<Vec<usize>> = Vec::new();
+    let mut constrained: Vec<Vec<usize>> = Vec::new();
+
+    for (i, column_def) in table.header.iter().enumerate() {
+        for constraint in column_def.column_constraints.iter() {
+            match *constraint {
+                ColumnConstraint::PrimaryKey | ColumnConstraint::Unique => constrained.push(vec![i]),
+                _ => {}
+            }
+        }
+    }
+
+    for constraint in table.constraints.iter() {
+        match *constraint {
+            TableConstraint::PrimaryKey(ref names) | TableConstraint::Unique(ref names) => {
+                if let Ok(columns) = table.column_indexes(names) {
+                    constrained.push(columns);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for columns in constrained.into_iter() {
+        if (columns.len() != 1 || Some(columns[0]) != rowid) && !indexes.contains(&columns) {
+            indexes.push(columns);
+        }
+    }
+    indexes
+}
+
+// The value as SQLite stores it, which has no booleans or decimals.
+fn storage_value(value: &LiteralValue) -> LiteralValue {
+    match *value {
+        LiteralValue::Boolean(b) => LiteralValue::Integer(b as isize),
+        LiteralValue::Decimal(..) => LiteralValue::Real(value.to_real()),
+        _ => value.clone(),
+    }
+}
+
+// How SQLite orders values in an index: NULLs, then numbers, then text and
+// then blobs.
+fn compare_values(left: &LiteralValue, right: &LiteralValue) -> Ordering {
+    let class = |value: &LiteralValue| match *value {
+        LiteralValue::Null => 0,
+        LiteralValue::Text(..) => 2,
+        LiteralValue::Blob(..) => 3,
+        _ => 1,
+    };
+
+    match (storage_value(left), storage_value(right)) {
+        (LiteralValue::Integer(x), LiteralValue::Integer(y)) => x.cmp(&y),
+        (LiteralValue::Text(x), LiteralValue::Text(y)) => x.as_bytes().cmp(y.as_bytes()),
+        (LiteralValue::Blob(x), LiteralValue::Blob(y)) => x.cmp(&y),
+        (x, y) => match class(&x).cmp(&class(&y)) {
+            Ordering::Equal if class(&x) == 1 => x.to_real().partial_cmp(&y.to_real()).unwrap_or(Ordering::Equal),
+            ordering => ordering,
+        },
+    }
+}
+
+fn encode_record(values: &TableRow) -> Vec<u8> {
+    let mut header: Vec<u8> = Vec::new();
+    let mut body: Vec<u8> = Vec::new();
+
+    for value in values.iter() {
+        let serial_type = match storage_value(value) {
+            LiteralValue::Integer(0) => 8,
+            LiteralValue::Integer(1) => 9,
+            LiteralValue::Integer(i) => {
+                let i = i as i64;
+                let (len, serial_type) = match i {
+                    -0x80 ... 0x7f => (1, 1),
+                    -0x8000 ... 0x7fff => (2, 2),
+                    -0x800000 ... 0x7fffff => (3, 3),
+                    -0x80000000 ... 0x7fffffff => (4, 4),
+                    -0x800000000000 ... 0x7fffffffffff => (6, 5),
+                    _ => (8, 6),
+                };
+                body.push_all(be_bytes(i as u64, len).as_slice());
+                serial_type
+            }
+            LiteralValue::Real(r) => {
+                body.push_all(be_bytes(unsafe { mem::transmute::<f64, u64>(r) }, 8).as_slice());
+                7
+            }
+            LiteralValue::Text(ref text) => {
+                body.push_all(text.as_bytes());
+                13 + 2 * text.len() as u64
+            }
+            LiteralValue::Blob(ref bytes) => {
+                body.push_all(bytes.as_slice());
+                12 + 2 * bytes.len() as u64
+            }
+            _ => 0,
+        };
+        header.push_all(varint_bytes(serial_type).as_slice());
+    }
+
+    // The size of the header counts the varint holding it.
+    let mut header_size = header.len() + 1;
+    while varint_bytes(header_size as u64).len() + header.len() != header_size {
+        header_size = varint_bytes(header_size as u64).len() + header.len();
+    }
+
+    let mut record = varint_bytes(header_size as u64);
+    record.push_all(header.as_slice());
+    record.push_all(body.as_slice());
+    record
+}
+
+fn be_bytes(value: u64, len: usize) -> Vec<u8> {
+    range(0, len).rev().map(|i| (value >> (8 * i)) as u8).collect()
+}
+
+fn varint_bytes(value: u64) -> Vec<u8> {
+    if value > 0x00ffffffffffffff {
+        let mut bytes: Vec<u8> = repeat(0u8).take(8).collect();
+        let mut rest = value >> 8;
+        for i in range(0, 8).rev() {
+            bytes[i] = (rest & 0x7f) as u8 | 0x80;
+            rest >>= 7;
+        }
+        bytes.push(value as u8);
+        return bytes;
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut rest = value;
+    loop {
+        bytes.insert(0, (rest & 0x7f) as u8 | if bytes.is_empty() { 0 } else { 0x80 });
+        rest >>= 7;
+        if rest == 0 {
+            return bytes;
+        }
+    }
+}
+
+fn put(page: &mut Vec<u8>, pos: usize, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+        page[pos + i] = *byte;
+    }
+}
+
+struct SqliteWriter {
+    pages: Vec<Vec<u8>>,
+}
+
+impl SqliteWriter {
+    fn allocate(&mut self) -> usize {
+        self.pages.push(repeat(0u8).take(PAGE_SIZE).collect());
+
+        // SQLite locks the page holding byte 2^30 of the file, which is
+        // left empty.
+        if self.pages.len() == LOCK_PAGE {
+            self.pages.push(repeat(0u8).take(PAGE_SIZE).collect());
+        }
+        self.pages.len()
+    }
+
+    // The bytes a b-tree page has for cells and their pointers, after its
+    // header, and the file header on page 1.
+    fn capacity(root: Option<usize>, header_len: usize) -> usize {
+        PAGE_SIZE - header_len - if root == Some(MASTER_ROOT) { HEADER_SIZE } else { 0 }
+    }
+
+    fn write_page(&mut self, number: usize, page_type: u8, cells: &Vec<Vec<u8>>, right: Option<usize>) {
+        let start = if number == MASTER_ROOT { HEADER_SIZE } else { 0 };
+        let header_len = if right.is_some() { 12 } else { 8 };
+        let page = &mut self.pages[number - 1];
+        let mut content = PAGE_SIZE;
+
+        page[start] = page_type;
+        put(page, start + 3, be_bytes(cells.len() as u64, 2).as_slice());
+
+        for (i, cell) in cells.iter().enumerate() {
+            content -= cell.len();
+            put(page, content, cell.as_slice());
+            put(page, start + header_len + 2 * i, be_bytes(content as u64, 2).as_slice());
+        }
+
+        put(page, start + 5, be_bytes(content as u64, 2).as_slice());
+        if let Some(right) = right {
+            put(page, start + 8, be_bytes(right as u64, 4).as_slice());
+        }
+    }
+
+    // What of `payload` goes in its cell: all of it if it's no longer than
+    // `max_local`, otherwise its start and the first of the overflow pages
+    // the rest is written to.
+    fn local_payload(&mut self, payload: Vec<u8>, max_local: usize) -> Vec<u8> {
+        if payload.len() <= max_local {
+            return payload;
+        }
+
+        let min_local = (PAGE_SIZE - 12) * 32 / 255 - 23;
+        let local = match min_local + (payload.len() - min_local) % (PAGE_SIZE - 4) {
+            local if local <= max_local => local,
+            _ => min_local,
+        };
+
+        let mut cell = payload[..local].to_vec();
+        let chunks: Vec<&[u8]> = payload[local..].chunks(PAGE_SIZE - 4).collect();
+        let numbers: Vec<usize> = chunks.iter().map(|_| self.allocate()).collect();
+        cell.push_all(be_bytes(numbers[0] as u64, 4).as_slice());
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let next = if i + 1 < chunks.len() { numbers[i + 1] } else { 0 };
+            let page = &mut self.pages[numbers[i] - 1];
+
+            put(page, 0, be_bytes(next as u64, 4).as_slice());
+            put(page, 4, *chunk);
+        }
+
+        cell
+    }
+
+    // Writes a level of a b-tree: a single page is the root, written to
+    // `root` if given, otherwise each page gets a number, which is returned.
+    fn write_level(&mut self, page_type: u8, pages: Vec<(Vec<Vec<u8>>, Option<usize>)>, root: Option<usize>)
+                   -> Vec<usize> {
+        if pages.len() == 1 {
+            let number = match root {
+                Some(number) => number,
+                None => self.allocate(),
+            };
+            self.write_page(number, page_type, &pages[0].0, pages[0].1);
+            return vec![number];
+        }
+
+        pages.iter().map(|&(ref cells, right)| {
+            let number = self.allocate();
+            self.write_page(number, page_type, cells, right);
+            number
+        }).collect()
+    }
+
+    /// Writes a table b-tree holding `rows`, rowids and records in rowid
+    /// order, and returns its root page.
+    fn table_tree(&mut self, rows: Vec<(i64, Vec<u8>)>, root: Option<usize>) -> usize {
+        let capacity = SqliteWriter::capacity(root, 8);
+        let mut pages: Vec<(Vec<Vec<u8>>, Option<usize>)> = Vec::new();
+        let mut keys: Vec<i64> = Vec::new();
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        let mut used = 0;
+        let mut last_rowid = 0;
+
+        for (rowid, payload) in rows.into_iter() {
+            let mut cell = varint_bytes(payload.len() as u64);
+            cell.push_all(varint_bytes(rowid as u64).as_slice());
+            cell.push_all(self.local_payload(payload, PAGE_SIZE - 35).as_slice());
+
+            if !cells.is_empty() && used + cell.len() + 2 > capacity {
+                pages.push((cells, None));
+                keys.push(last_rowid);
+                cells = Vec::new();
+                used = 0;
+            }
+
+            used += cell.len() + 2;
+            last_rowid = rowid;
+            cells.push(cell);
+        }
+        pages.push((cells, None));
+        keys.push(last_rowid);
+
+        let mut page_type = LEAF_TABLE_PAGE;
+        loop {
+            let numbers = self.write_level(page_type, pages, root);
+            if numbers.len() == 1 {
+                return numbers[0];
+            }
+
+            // Interior cells take at most 13 bytes, and 2 for the pointer.
+            let per_page = SqliteWriter::capacity(root, 12) / 15 + 1;
+            let children: Vec<(usize, i64)> = numbers.into_iter().zip(keys.into_iter()).collect();
+            let mut chunks: Vec<Vec<(usize, i64)>> = children.chunks(per_page).map(|chunk| chunk.to_vec()).collect();
+
+            // A page can't have only the right pointer.
+            let n = chunks.len();
+            if n > 1 && chunks[n - 1].len() == 1 {
+                let child = chunks[n - 2].pop().unwrap();
+                chunks[n - 1].insert(0, child);
+            }
+
+            pages = Vec::new();
+            keys = Vec::new();
+            for chunk in chunks.into_iter() {
+                let (right, key) = chunk[chunk.len() - 1];
+                let cells = chunk[..chunk.len() - 1].iter().map(|&(child, key)| {
+                    let mut cell = be_bytes(child as u64, 4);
+                    cell.push_all(varint_bytes(key as u64).as_slice());
+                    cell
+                }).collect();
+
+                pages.push((cells, Some(right)));
+                keys.push(key);
+            }
+            page_type = INTERIOR_TABLE_PAGE;
+        }
+    }
+
+    /// Writes an index b-tree holding each key with the rowid of its row,
+    /// and returns its root page.
+    fn index_tree(&mut self, mut keys: Vec<(TableRow, PkType)>) -> usize {
+        keys.sort_by(|&(ref x, x_pk), &(ref y, y_pk)| {
+            x.iter().zip(y.iter()).map(|(x, y)| compare_values(x, y))
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(x_pk.cmp(&y_pk))
+        });
+
+        let max_local = (PAGE_SIZE - 12) * 64 / 255 - 23;
+        let capacity = SqliteWriter::capacity(None, 8);
+        let count = keys.len();
+        let mut pages: Vec<(Vec<Vec<u8>>, Option<usize>)> = Vec::new();
+        // The entries between the pages, which are kept by their parent.
+        let mut dividers: Vec<Vec<u8>> = Vec::new();
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        let mut used = 0;
+
+        for (i, (mut key, pk)) in keys.into_iter().enumerate() {
+            key.push(LiteralValue::Integer(pk as isize));
+            let payload = encode_record(&key);
+            let mut cell = varint_bytes(payload.len() as u64);
+            cell.push_all(self.local_payload(payload, max_local).as_slice());
+
+            if !cells.is_empty() && used + cell.len() + 2 > capacity {
+                // The last entry can't be a divider, there'd be no page
+                // after it, so the one before it is.
+                if i + 1 == count {
+                    let divider = cells.pop().unwrap();
+                    pages.push((cells, None));
+                    dividers.push(divider);
+                    used = cell.len() + 2;
+                    cells = vec![cell];
+                } else {
+                    pages.push((cells, None));
+                    dividers.push(cell);
+                    used = 0;
+                    cells = Vec::new();
+                }
+                continue;
+            }
+
+            used += cell.len() + 2;
+            cells.push(cell);
+        }
+        pages.push((cells, None));
+
+        let capacity = SqliteWriter::capacity(None, 12);
+        let mut page_type = LEAF_INDEX_PAGE;
+        loop {
+            let children = self.write_level(page_type, pages, None);
+            if children.len() == 1 {
+                return children[0];
+            }
+
+            let count = dividers.len();
+            let mut parents: Vec<(Vec<usize>, usize)> = Vec::new();
+            let mut parent_dividers: Vec<usize> = Vec::new();
+            let mut entries: Vec<usize> = Vec::new();
+            let mut used = 0;
+
+            // Entry i is the divider between child i and i + 1, kept with
+            // a pointer to child i.
+            for i in range(0, count) {
+                let size = 4 + dividers[i].len() + 2;
+
+                if !entries.is_empty() && used + size > capacity {
+                    if i + 1 == count {
+                        let j = entries.pop().unwrap();
+                        parents.push((entries, children[j]));
+                        parent_dividers.push(j);
+                        used = size;
+                        entries = vec![i];
+                    } else {
+                        parents.push((entries, children[i]));
+                        parent_dividers.push(i);
+                        used = 0;
+                        entries = Vec::new();
+                    }
+                    continue;
+                }
+
+                used += size;
+                entries.push(i);
+            }
+            parents.push((entries, children[count]));
+
+            pages = parents.into_iter().map(|(entries, right)| {
+                let cells = entries.iter().map(|&i| {
+                    let mut cell = be_bytes(children[i] as u64, 4);
+                    cell.push_all(dividers[i].as_slice());
+                    cell
+                }).collect();
+                (cells, Some(right))
+            }).collect();
+            let promoted = parent_dividers.iter().map(|&i| dividers[i].clone()).collect();
+            dividers = promoted;
+            page_type = INTERIOR_INDEX_PAGE;
+        }
+    }
+
+    fn write_header(&mut self) {
+        let count = self.pages.len();
+        let page = &mut self.pages[0];
+
+        put(page, 0, MAGIC);
+        put(page, 16, be_bytes(PAGE_SIZE as u64, 2).as_slice());
+        // File format versions, reserved bytes and payload fractions.
+        put(page, 18, &[1, 1, 0, 64, 32, 32]);
+        // The change counter, which the "version valid for" at 92 matches.
+        put(page, 24, be_bytes(1, 4).as_slice());
+        put(page, 28, be_bytes(count as u64, 4).as_slice());
+        // The schema cookie and schema format.
+        put(page, 40, be_bytes(1, 4).as_slice());
+        put(page, 44, be_bytes(4, 4).as_slice());
+        // UTF-8.
+        put(page, 56, be_bytes(1, 4).as_slice());
+        put(page, 92, be_bytes(1, 4).as_slice());
+        put(page, 96, be_bytes(SQLITE_VERSION, 4).as_slice());
+    }
+}
@@ -0,0 +1,111 @@
+use definitions::{RusqlStatement, LiteralValue};
+use error::{RusqlError, RusqlResult};
+use exec::{self, QueryResult};
+use expressions::{bind_parameter, number_parameters};
+use rusql::Rusql;
+use table::Table;
+
+/// Statements parsed once, to be run any number of times with different
+/// values bound to their parameters: ?, ?N or :name.
+pub struct Statement<'a> {
+    db: &'a mut Rusql,
+    stmts: Vec<RusqlStatement>,
+    /// The name of every parameter, parameter N at N - 1.
+    names: Vec<String>,
+    values: Vec<LiteralValue>,
+}
+
+impl<'a> Statement<'a> {
+    pub fn new(db: &'a mut Rusql, sql: &str) -> RusqlResult<Statement<'a>> {
+        let mut stmts = try!(exec::parse_cached(db, sql));
+        let mut names: Vec<String> = Vec::new();
+
+        for stmt in stmts.iter_mut() {
+            for expr in stmt.expressions_mut().into_iter() {
+                number_parameters(expr, &mut names);
+            }
+        }
+
+        let values = names.iter().map(|_| LiteralValue::Null).collect();
+
+        Ok(Statement {
+            db: db,
+            stmts: stmts,
+            names: names,
+            values: values,
+        })
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.names.len()
+    }
+
+    /// The index of the parameter called `name`, e.g. ":id", starting at 1.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n.as_slice() == name).map(|i| i + 1)
+    }
+
+    /// Binds `value` to parameter `index`, starting at 1. It stays bound
+    /// until it's rebound or the bindings are cleared.
+    pub fn bind(&mut self, index: usize, value: LiteralValue) -> RusqlResult<()> {
+        if index == 0 || index > self.values.len() {
+            return Err(RusqlError::NoSuchParameter(format!("?{}", index)));
+        }
+
+        self.values[index - 1] = value;
+        Ok(())
+    }
+
+    pub fn bind_named(&mut self, name: &str, value: LiteralValue) -> RusqlResult<()> {
+        match self.parameter_index(name) {
+            Some(index) => self.bind(index, value),
+            None => Err(RusqlError::NoSuchParameter(name.to_string())),
+        }
+    }
+
+    /// Sets every parameter back to NULL.
+    pub fn clear_bindings(&mut self) {
+        for value in self.values.iter_mut() {
+            *value = LiteralValue::Null;
+        }
+    }
+
+    /// Runs the statements, returning the number of rows the last INSERT,
+    /// UPDATE or DELETE changed.
+    pub fn execute(&mut self) -> RusqlResult<usize> {
+        try!(self.run());
+        Ok(self.db.changes())
+    }
+
+    /// Runs the statements, returning the rows of the last one that gave
+    /// any, e.g. a SELECT.
+    pub fn query(&mut self) -> RusqlResult<Table> {
+        let mut rows = Table::new_result_table(Vec::new());
+
+        for result in try!(self.run()).into_iter() {
+            if let QueryResult::Rows(table) = result {
+                rows = table;
+            }
+        }
+
+        Ok(rows)
+    }
+
+    fn run(&mut self) -> RusqlResult<Vec<QueryResult>> {
+        let mut results: Vec<QueryResult> = Vec::new();
+
+        for stmt in self.stmts.iter() {
+            let mut stmt = stmt.clone();
+
+            for expr in stmt.expressions_mut().into_iter() {
+                for (name, value) in self.names.iter().zip(self.values.iter()) {
+                    bind_parameter(expr, name.as_slice(), value);
+                }
+            }
+
+            results.push(try!(exec::exec_stmt(&mut *self.db, stmt)));
+        }
+
+        Ok(results)
+    }
+}
@@ -0,0 +1,93 @@
+//! The statistics ANALYZE collects on a table, which the planner uses to
+//! guess how many rows each way of reading it would find.
+//! https://www.sqlite.org/lang_analyze.html
+
+use definitions::LiteralValue;
+use table::Table;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Show, Clone, PartialEq)]
+pub struct ColumnStats {
+    /// The number of different values in the column, NULL not counted.
+    pub distinct: usize,
+    /// The smallest and largest values, NULL if every value is NULL.
+    pub min: LiteralValue,
+    pub max: LiteralValue,
+}
+
+#[derive(Show, Clone, PartialEq)]
+pub struct TableStats {
+    pub row_count: usize,
+    /// The statistics of each column, by name.
+    pub columns: BTreeMap<String, ColumnStats>,
+}
+
+impl TableStats {
+    /// Reads every row of `table`.
+    pub fn collect(table: &Table) -> TableStats {
+        let mut columns: BTreeMap<String, ColumnStats> = BTreeMap::new();
+
+        for (i, column_def) in table.header.iter().enumerate() {
+            let mut values: BTreeSet<&LiteralValue> = BTreeSet::new();
+
+            for row in table.data.values() {
+                if !row[i].is_null() {
+                    values.insert(&row[i]);
+                }
+            }
+
+            // Values are ordered by how they compare, so these are the
+            // smallest and largest.
+            let min = values.iter().next().map_or(LiteralValue::Null, |value| (**value).clone());
+            let max = values.iter().next_back().map_or(LiteralValue::Null, |value| (**value).clone());
+
+            columns.insert(column_def.name.clone(), ColumnStats { distinct: values.len(), min: min, max: max });
+        }
+
+        TableStats { row_count: table.data.len(), columns: columns }
+    }
+
+    /// About how many rows have a given value in the column called `name`.
+    pub fn equal_rows(&self, name: &String) -> f64 {
+        match self.columns.get(name) {
+            Some(column) if column.distinct > 0 => self.row_count as f64 / column.distinct as f64,
+            Some(_) => 0.0,
+            None => self.row_count as f64,
+        }
+    }
+
+    /// About how many rows have a value between `lower` and `upper` in the
+    /// column called `name`, taking the values to be spread evenly between
+    /// its smallest and largest. A third of the rows if that can't be told.
+    pub fn range_rows(&self, name: &String, lower: Option<&LiteralValue>, upper: Option<&LiteralValue>) -> f64 {
+        let column = match self.columns.get(name) {
+            Some(column) => column,
+            None => return self.row_count as f64 / 3.0,
+        };
+        let is_number = |value: &LiteralValue| match value {
+            &LiteralValue::Integer(..) | &LiteralValue::Real(..) | &LiteralValue::Decimal(..) => true,
+            _ => false,
+        };
+
+        if column.distinct == 0 {
+            return 0.0;
+        }
+        if !is_number(&column.min) || !is_number(&column.max)
+                || !lower.iter().chain(upper.iter()).all(|value| is_number(*value)) {
+            return self.row_count as f64 / 3.0;
+        }
+
+        let (min, max) = (column.min.to_real(), column.max.to_real());
+        let from = lower.map_or(min, |value| if value.to_real() > min { value.to_real() } else { min });
+        let to = upper.map_or(max, |value| if value.to_real() < max { value.to_real() } else { max });
+
+        if from > to {
+            0.0
+        } else if min < max {
+            self.row_count as f64 * (to - from) / (max - min)
+        } else {
+            self.row_count as f64
+        }
+    }
+}
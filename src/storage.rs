@@ -0,0 +1,258 @@
+//! Where a table keeps its rows. Tables go through TableData, which holds
+//! any implementation of Storage, so that another engine can take the place
+//! of the BTreeMap kept in memory without the rest of the crate knowing.
+
+use btree::PagedStorage;
+use table::{TableRow, PkType};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::Bound::Included;
+
+/// The rows of a table by their keys. Scans give the rows in the order of
+/// their keys.
+pub trait Storage: Send + Sync {
+    fn get(&self, pk: PkType) -> Option<&TableRow>;
+
+    fn get_mut(&mut self, pk: PkType) -> Option<&mut TableRow>;
+
+    /// Stores `row` under `pk`, returning the row it replaces.
+    fn insert(&mut self, pk: PkType, row: TableRow) -> Option<TableRow>;
+
+    fn delete(&mut self, pk: PkType) -> Option<TableRow>;
+
+    fn len(&self) -> usize;
+
+    fn clear(&mut self);
+
+    fn scan<'a>(&'a self) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a>;
+
+    fn scan_mut<'a>(&'a mut self) -> Box<Iterator<Item=(PkType, &'a mut TableRow)> + 'a>;
+
+    /// The rows with keys from `from` to `to`, both included.
+    fn range<'a>(&'a self, from: PkType, to: PkType) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a>;
+
+    fn last_key(&self) -> Option<PkType> {
+        self.scan().last().map(|(pk, _)| pk)
+    }
+
+    /// Takes every row out, in the order of their keys.
+    fn into_rows(self: Box<Self>) -> Vec<(PkType, TableRow)>;
+
+    fn box_clone(&self) -> Box<Storage>;
+
+    /// The storage itself if it's the one of a paged file, which writes the
+    /// changes out through it.
+    fn paged(&mut self) -> Option<&mut PagedStorage> {
+        None
+    }
+}
+
+/// The default storage, every row in memory.
+#[derive(Clone)]
+pub struct BTreeStorage {
+    rows: BTreeMap<PkType, TableRow>,
+}
+
+impl BTreeStorage {
+    pub fn new() -> BTreeStorage {
+        BTreeStorage { rows: BTreeMap::new() }
+    }
+}
+
+impl Storage for BTreeStorage {
+    fn get(&self, pk: PkType) -> Option<&TableRow> {
+        self.rows.get(&pk)
+    }
+
+    fn get_mut(&mut self, pk: PkType) -> Option<&mut TableRow> {
+        self.rows.get_mut(&pk)
+    }
+
+    fn insert(&mut self, pk: PkType, row: TableRow) -> Option<TableRow> {
+        self.rows.insert(pk, row)
+    }
+
+    fn delete(&mut self, pk: PkType) -> Option<TableRow> {
+        self.rows.remove(&pk)
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    fn scan<'a>(&'a self) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a> {
+        box self.rows.iter().map(|(pk, row)| (*pk, row))
+    }
+
+    fn scan_mut<'a>(&'a mut self) -> Box<Iterator<Item=(PkType, &'a mut TableRow)> + 'a> {
+        box self.rows.iter_mut().map(|(pk, row)| (*pk, row))
+    }
+
+    fn range<'a>(&'a self, from: PkType, to: PkType) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a> {
+        box self.rows.range(Included(&from), Included(&to)).map(|(pk, row)| (*pk, row))
+    }
+
+    fn last_key(&self) -> Option<PkType> {
+        self.rows.keys().next_back().map(|pk| *pk)
+    }
+
+    fn into_rows(self: Box<Self>) -> Vec<(PkType, TableRow)> {
+        self.rows.into_iter().collect()
+    }
+
+    fn box_clone(&self) -> Box<Storage> {
+        box self.clone()
+    }
+}
+
+/// The rows of a table, in whichever Storage they're kept. Its methods are
+/// named after those of the BTreeMap it replaces.
+pub struct TableData {
+    storage: Box<Storage>,
+    /// The keys of the rows inserted, replaced or deleted since the last
+    /// take_changes, once track_changes was called.
+    changed: Option<BTreeSet<PkType>>,
+}
+
+impl TableData {
+    pub fn new() -> TableData {
+        TableData::with_storage(box BTreeStorage::new())
+    }
+
+    pub fn with_storage(storage: Box<Storage>) -> TableData {
+        TableData { storage: storage, changed: None }
+    }
+
+    pub fn get(&self, pk: &PkType) -> Option<&TableRow> {
+        self.storage.get(*pk)
+    }
+
+    pub fn get_mut(&mut self, pk: &PkType) -> Option<&mut TableRow> {
+        self.mark_changed(*pk);
+        self.storage.get_mut(*pk)
+    }
+
+    /// Puts `storage`, which holds the same rows, in place of the one that
+    /// keeps them now.
+    pub fn set_storage(&mut self, storage: Box<Storage>) {
+        self.storage = storage;
+    }
+
+    pub fn paged(&mut self) -> Option<&mut PagedStorage> {
+        self.storage.paged()
+    }
+
+    pub fn contains_key(&self, pk: &PkType) -> bool {
+        self.storage.get(*pk).is_some()
+    }
+
+    pub fn insert(&mut self, pk: PkType, row: TableRow) -> Option<TableRow> {
+        self.mark_changed(pk);
+        self.storage.insert(pk, row)
+    }
+
+    pub fn remove(&mut self, pk: &PkType) -> Option<TableRow> {
+        self.mark_changed(*pk);
+        self.storage.delete(*pk)
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        if self.changed.is_some() {
+            let keys: Vec<PkType> = self.keys().collect();
+            for pk in keys.into_iter() {
+                self.mark_changed(pk);
+            }
+        }
+
+        self.storage.clear();
+    }
+
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a> {
+        self.storage.scan()
+    }
+
+    /// Like iter, but the rows changed through it aren't counted as changed,
+    /// which is only right when the whole table is written out anyway, e.g.
+    /// after a column was added.
+    pub fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(PkType, &'a mut TableRow)> + 'a> {
+        self.storage.scan_mut()
+    }
+
+    pub fn keys<'a>(&'a self) -> Box<Iterator<Item=PkType> + 'a> {
+        box self.storage.scan().map(|(pk, _)| pk)
+    }
+
+    pub fn values<'a>(&'a self) -> Box<Iterator<Item=&'a TableRow> + 'a> {
+        box self.storage.scan().map(|(_, row)| row)
+    }
+
+    pub fn range<'a>(&'a self, from: PkType, to: PkType) -> Box<Iterator<Item=(PkType, &'a TableRow)> + 'a> {
+        self.storage.range(from, to)
+    }
+
+    pub fn last_key(&self) -> Option<PkType> {
+        self.storage.last_key()
+    }
+
+    pub fn into_rows(self) -> Vec<(PkType, TableRow)> {
+        self.storage.into_rows()
+    }
+
+    /// Starts keeping the keys of the rows that change, see take_changes.
+    pub fn track_changes(&mut self) {
+        if self.changed.is_none() {
+            self.changed = Some(BTreeSet::new());
+        }
+    }
+
+    pub fn tracks_changes(&self) -> bool {
+        self.changed.is_some()
+    }
+
+    /// Whether there are changes for take_changes to return.
+    pub fn has_changes(&self) -> bool {
+        self.changed.as_ref().map_or(false, |changed| !changed.is_empty())
+    }
+
+    /// The keys of the rows changed since the last call, in order.
+    pub fn take_changes(&mut self) -> Vec<PkType> {
+        match self.changed {
+            Some(ref mut changed) => {
+                let keys = changed.iter().map(|pk| *pk).collect();
+                changed.clear();
+                keys
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn mark_changed(&mut self, pk: PkType) {
+        if let Some(ref mut changed) = self.changed {
+            changed.insert(pk);
+        }
+    }
+}
+
+impl Clone for TableData {
+    fn clone(&self) -> TableData {
+        TableData { storage: self.storage.box_clone(), changed: self.changed.clone() }
+    }
+}
+
+impl PartialEq for TableData {
+    fn eq(&self, other: &TableData) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
@@ -1,10 +1,21 @@
-use definitions::{TableDef, LiteralValue, ColumnDef, ColumnConstraint};
+use definitions::{TableDef, LiteralValue, ColumnDef, ColumnConstraint, TableConstraint, Expression};
+use definitions::{ForeignKeyClause, IndexDef, ConflictResolution, rename_in};
+use error::{RusqlError, RusqlResult};
+use expressions::{ExpressionEvaluator, result_to_literal, implies};
+use columnar::ColumnStore;
+use csv::{self, CsvOptions};
+use json;
+use memory;
+use row::Row;
+use storage::TableData;
 
-use std::cell::Cell;
-use std::cmp::max;
+use std::cmp::{max, Ordering};
 use std::collections::BTreeMap;
+use std::collections::Bound::{Included, Excluded, Unbounded};
 use std::fmt;
-use std::iter::repeat;
+use std::io::IoResult;
+use std::mem::replace;
+use std::sync::{Arc, Mutex};
 
 pub type TableRow = Vec<LiteralValue>;
 pub type TableHeader = Vec<ColumnDef>;
@@ -13,50 +24,448 @@ pub type PkType = usize;
 pub struct RowFormat<'a>(pub &'a TableRow);
 pub struct HeaderFormat<'a>(pub &'a TableHeader);
 
-#[derive(PartialEq)]
+/// Maps the values of the indexed columns to the keys of every row holding
+/// them. Besides the ones created with CREATE INDEX, UNIQUE constraints and
+/// composite primary keys are enforced through unique indexes.
+#[derive(PartialEq, Clone)]
+pub struct Index {
+    pub name: String,
+    pub column_names: Vec<String>,
+    pub columns: Vec<usize>,
+    /// What the index is on, when it's on expressions rather than columns.
+    /// `columns` is empty then.
+    pub expressions: Option<Vec<Expression>>,
+    pub unique: bool,
+    /// Only rows matching it are indexed, for a partial index.
+    pub predicate: Option<Expression>,
+    pub keys: BTreeMap<TableRow, Vec<PkType>>,
+}
+
+impl Index {
+    pub fn new(name: String, column_names: Vec<String>, columns: Vec<usize>, unique: bool) -> Index {
+        Index {
+            name: name,
+            column_names: column_names,
+            columns: columns,
+            expressions: None,
+            unique: unique,
+            predicate: None,
+            keys: BTreeMap::new(),
+        }
+    }
+
+    pub fn key(&self, row: &TableRow, header: &TableHeader) -> TableRow {
+        match self.expressions {
+            Some(ref exprs) => exprs.iter().map(|expr| {
+                result_to_literal(ExpressionEvaluator::new(row, header).eval_expr(expr))
+            }).collect(),
+            None => self.columns.iter().map(|&i| row[i].clone()).collect(),
+        }
+    }
+
+    pub fn references_column(&self, i: usize, name: &String) -> bool {
+        self.columns.contains(&i)
+            || self.expressions.iter().flat_map(|exprs| exprs.iter()).any(|expr| expr.references_column(name))
+            || self.predicate.iter().any(|expr| expr.references_column(name))
+    }
+
+    /// Whether `row` belongs in the index, which for a partial index means
+    /// it matches the WHERE clause.
+    pub fn covers(&self, row: &TableRow, header: &TableHeader) -> bool {
+        match self.predicate {
+            Some(ref expr) => ExpressionEvaluator::new(row, header).eval_bool(expr),
+            None => true,
+        }
+    }
+
+    /// Whether the index holds every row a query with `where_expr` can
+    /// return, so it can be used to answer it.
+    pub fn usable_for(&self, where_expr: &Option<Expression>) -> bool {
+        match (&self.predicate, where_expr) {
+            (&None, _) => true,
+            (&Some(ref predicate), &Some(ref expr)) => implies(expr, predicate),
+            (&Some(_), &None) => false,
+        }
+    }
+
+    pub fn insert(&mut self, pk: PkType, row: &TableRow, header: &TableHeader) {
+        let key = self.key(row, header);
+
+        if let Some(keys) = self.keys.get_mut(&key) {
+            keys.push(pk);
+            return;
+        }
+
+        self.keys.insert(key, vec![pk]);
+    }
+
+    pub fn remove(&mut self, pk: PkType, row: &TableRow, header: &TableHeader) {
+        let key = self.key(row, header);
+        let empty = match self.keys.get_mut(&key) {
+            Some(keys) => {
+                keys.retain(|&k| k != pk);
+                keys.is_empty()
+            }
+            None => false,
+        };
+
+        if empty {
+            self.keys.remove(&key);
+        }
+    }
+
+    pub fn lookup(&self, key: &TableRow) -> Vec<PkType> {
+        match self.keys.get(key) {
+            Some(keys) => keys.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Finds the rows whose leading indexed columns hold `prefix`, so a
+    /// compound index also serves lookups on its first few columns.
+    pub fn lookup_prefix(&self, prefix: &TableRow) -> Vec<PkType> {
+        if prefix.len() == self.column_names.len() {
+            return self.lookup(prefix);
+        }
+
+        // Keys are sorted, so all the ones starting with the prefix are
+        // next to each other, right after the prefix itself.
+        let mut keys: Vec<PkType> = Vec::new();
+
+        for (_, pks) in self.keys.range(Included(prefix), Unbounded)
+                                 .take_while(|&(key, _)| key.starts_with(prefix.as_slice())) {
+            keys.push_all(pks.as_slice());
+        }
+
+        keys
+    }
+
+    /// Finds the rows whose first indexed column is equal to `value` the way
+    /// = compares them, so 1 also finds 1.0.
+    pub fn lookup_equal(&self, value: &LiteralValue) -> Vec<PkType> {
+        // Values that compare equal are next to each other whatever their
+        // type, which only breaks ties, so some of them may come before
+        // `value` itself and some after it.
+        let start = vec![value.clone()];
+        let mut keys: Vec<PkType> = Vec::new();
+
+        for (_, pks) in self.keys.range(Unbounded, Excluded(&start)).rev()
+                                 .take_while(|&(key, _)| key[0].compare(value) == Ordering::Equal) {
+            keys.push_all(pks.as_slice());
+        }
+        for (_, pks) in self.keys.range(Included(&start), Unbounded)
+                                 .take_while(|&(key, _)| key[0].compare(value) == Ordering::Equal) {
+            keys.push_all(pks.as_slice());
+        }
+
+        keys
+    }
+
+    /// How many of the leading indexed columns are in `columns`.
+    pub fn prefix_len(&self, columns: &Vec<usize>) -> usize {
+        self.columns.iter().take_while(|i| columns.contains(i)).count()
+    }
+
+    /// Finds another row holding the same key as `row` in a unique index.
+    pub fn find_conflict(&self, row: &TableRow, header: &TableHeader, ignore: Option<PkType>) -> Option<PkType> {
+        if !self.unique {
+            return None;
+        }
+
+        let key = self.key(row, header);
+
+        // NULLs are never equal to each other, so they can't collide.
+        if key.iter().any(|value| value.is_null()) {
+            return None;
+        }
+
+        self.lookup(&key).into_iter().find(|&pk| Some(pk) != ignore)
+    }
+
+    /// The CREATE INDEX statement for the index, on `table_name`.
+    pub fn to_sql(&self, table_name: &String) -> String {
+        let columns = match self.expressions {
+            Some(ref exprs) => exprs.iter().map(|expr| format!("{}", expr)).collect::<Vec<String>>().connect(", "),
+            None => self.column_names.connect(", "),
+        };
+        let mut sql = format!("CREATE {}INDEX {} ON {}({})", if self.unique { "UNIQUE " } else { "" },
+                              self.name, table_name, columns);
+
+        if let Some(ref predicate) = self.predicate {
+            sql.push_str(format!(" WHERE {}", predicate).as_slice());
+        }
+        sql
+    }
+}
+
+#[derive(PartialEq, Clone)]
+pub struct ForeignKey {
+    pub columns: Vec<usize>,
+    pub clause: ForeignKeyClause,
+}
+
+impl ForeignKey {
+    pub fn key(&self, row: &TableRow) -> Option<TableRow> {
+        get_key(row, &self.columns)
+    }
+
+    pub fn parent_columns(&self, parent: &Table) -> RusqlResult<Vec<usize>> {
+        match self.clause.column_names {
+            Some(ref names) => {
+                let mut columns: Vec<usize> = Vec::new();
+
+                for name in names.iter() {
+                    match parent.get_column_index(name) {
+                        Some(i) => columns.push(i),
+                        None => return Err(RusqlError::ConstraintViolation(
+                                format!("foreign key mismatch: {}", parent.name))),
+                    }
+                }
+
+                Ok(columns)
+            }
+            None => match parent.pk {
+                Some(i) => Ok(vec![i]),
+                None => Err(RusqlError::ConstraintViolation(
+                        format!("foreign key mismatch: {}", parent.name))),
+            },
+        }
+    }
+}
+
+/// The text stored in a table, so that the values with the same text share
+/// a single copy of it. Text no longer stored stays in the pool.
+pub struct StringPool {
+    strings: Mutex<BTreeMap<Arc<String>, Arc<String>>>,
+}
+
+impl StringPool {
+    pub fn new() -> StringPool {
+        StringPool { strings: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Makes a text value share its text with the values already stored,
+    /// adding it to the pool if there are none.
+    pub fn intern(&self, value: &mut LiteralValue) {
+        let text = match *value {
+            LiteralValue::Text(ref text) => text.clone(),
+            _ => return,
+        };
+        let mut strings = self.strings.lock().unwrap();
+
+        if !strings.contains_key(&text) {
+            strings.insert(text.clone(), text.clone());
+        }
+        *value = LiteralValue::Text(strings.get(&text).unwrap().clone());
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.lock().unwrap().len()
+    }
+}
+
+impl Clone for StringPool {
+    fn clone(&self) -> StringPool {
+        StringPool { strings: Mutex::new(self.strings.lock().unwrap().clone()) }
+    }
+}
+
+// Which copy of its text a value has doesn't make the table different.
+impl PartialEq for StringPool {
+    fn eq(&self, _: &StringPool) -> bool {
+        true
+    }
+}
+
+#[derive(PartialEq, Clone)]
 pub struct Table {
     pub name: String,
     pub header: TableHeader,
-    pub data: BTreeMap<PkType, TableRow>,
+    pub data: TableData,
     pub pk: Option<PkType>,
-    pub max_pk: Cell<PkType>,
+    pub max_pk: PkType,
+    pub autoincrement: bool,
+    pub pk_index: Option<Index>,
+    pub constraints: Vec<TableConstraint>,
+    pub unique: Vec<Index>,
+    pub checks: Vec<Expression>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub indexes: Vec<Index>,
+    pub strict: bool,
+    pub strings: StringPool,
+    /// The values of each column kept together, for the tables created
+    /// COLUMNAR.
+    pub columns: Option<ColumnStore>,
+    /// About how many bytes the rows take, see memory::row_size.
+    pub memory: usize,
 }
 
 impl Table {
-    pub fn new(table_def: TableDef) -> Table {
+    pub fn new(table_def: TableDef) -> RusqlResult<Table> {
         let mut table = Table {
             name: table_def.table_name,
             header: table_def.columns,
-            data: BTreeMap::new(),
+            data: TableData::new(),
             pk: None,
-            max_pk: Cell::new(0),
+            max_pk: 0,
+            autoincrement: false,
+            pk_index: None,
+            constraints: table_def.constraints,
+            unique: Vec::new(),
+            checks: Vec::new(),
+            foreign_keys: Vec::new(),
+            indexes: Vec::new(),
+            strict: table_def.strict,
+            strings: StringPool::new(),
+            columns: None,
+            memory: 0,
         };
+
+        for constraint in table.constraints.iter() {
+            match constraint {
+                &TableConstraint::PrimaryKey(ref names) | &TableConstraint::Unique(ref names) |
+                &TableConstraint::ForeignKey(ref names, _) => {
+                    try!(table.column_indexes(names));
+                }
+                &TableConstraint::Check(_) => {}
+            }
+        }
         table.process_constraints();
 
-        table
+        if table_def.columnar {
+            table.columns = Some(ColumnStore::new(&table.header));
+        }
+
+        Ok(table)
     }
 
     pub fn new_result_table(header: TableHeader) -> Table {
         Table {
             name: "".to_string(),
             header: header,
-            data: BTreeMap::new(),
+            data: TableData::new(),
             pk: None,
-            max_pk: Cell::new(0),
+            max_pk: 0,
+            autoincrement: false,
+            pk_index: None,
+            constraints: Vec::new(),
+            unique: Vec::new(),
+            checks: Vec::new(),
+            foreign_keys: Vec::new(),
+            indexes: Vec::new(),
+            strict: false,
+            strings: StringPool::new(),
+            columns: None,
+            memory: 0,
         }
     }
+
+    /// A result table with a column for each name, e.g. for the results of
+    /// a PRAGMA.
+    pub fn from_rows(names: &[&str], rows: Vec<TableRow>) -> Table {
+        let header: TableHeader = names.iter().map(|name| {
+            ColumnDef { name: name.to_string(), column_type: None, column_constraints: Vec::new() }
+        }).collect();
+        let mut table = Table::new_result_table(header);
+
+        for row in rows.into_iter() {
+            table.push_row(row).ok();
+        }
+
+        table
+    }
+
+    /// The CREATE TABLE statement for the table as it is now.
+    pub fn to_sql(&self) -> String {
+        let mut definitions: Vec<String> = self.header.iter().map(|column_def| format!("{}", column_def)).collect();
+
+        for constraint in self.constraints.iter() {
+            definitions.push(format!("{}", constraint));
+        }
+
+        format!("CREATE TABLE {}({}){}{}", self.name, definitions.connect(", "),
+                if self.strict { " STRICT" } else { "" }, if self.columns.is_some() { " COLUMNAR" } else { "" })
+    }
+
+    /// Writes the rows as CSV, see csv::export.
+    pub fn to_csv<W: Writer>(&self, w: &mut W, options: &CsvOptions) -> IoResult<()> {
+        csv::export(self, w, options)
+    }
+
+    /// Writes the rows as a JSON array of objects, see json::export.
+    pub fn to_json<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        json::export(self, w)
+    }
+
     pub fn get_column_def_by_name(&self, name: &String) -> Option<&ColumnDef> {
         self.header.iter().find(|&cols| &cols.name == name)
     }
 
+    /// The rows in key order, with typed accessors.
+    pub fn rows(&self) -> Vec<Row> {
+        self.data.values().map(|values| Row::new(&self.header, values)).collect()
+    }
+
     pub fn get_column_index(&self, name: &String) -> Option<usize> {
         self.header.iter().position(|ref cols| &cols.name == name)
     }
 
+    /// The positions of the columns called `names`, or an error naming the
+    /// first one that doesn't exist.
+    pub fn column_indexes(&self, names: &Vec<String>) -> RusqlResult<Vec<usize>> {
+        let mut columns: Vec<usize> = Vec::new();
+
+        for name in names.iter() {
+            match self.get_column_index(name) {
+                Some(i) => columns.push(i),
+                None => return Err(RusqlError::NoSuchColumn(format!("{}.{}", self.name, name))),
+            }
+        }
+
+        Ok(columns)
+    }
+
     pub fn has_row(&self, pk: PkType) -> bool {
         self.data.contains_key(&pk)
     }
 
+    /// Looks a row up by the values of its primary key columns, which also
+    /// works for composite keys.
+    pub fn get_row_by_key(&self, key: &TableRow) -> Option<&TableRow> {
+        match self.pk_index {
+            Some(ref index) => index.keys.get(key).and_then(|keys| keys.first())
+                                                  .and_then(|pk| self.data.get(pk)),
+            None => match self.pk {
+                Some(_) if key.len() == 1 => self.data.get(&key[0].to_uint()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Whether a row holds `key` in `columns`, looked up through the primary
+    /// key or an index on exactly those columns if there's one.
+    pub fn has_key(&self, columns: &Vec<usize>, key: &TableRow) -> bool {
+        if *columns == self.get_pk_columns() {
+            return self.get_row_by_key(key).is_some();
+        }
+
+        let index = self.unique.iter().chain(self.indexes.iter()).find(|index| {
+            index.columns == *columns && index.expressions.is_none() && index.predicate.is_none()
+        });
+
+        match index {
+            Some(index) => !index.lookup(key).is_empty(),
+            None => self.data.values().any(|row| get_key(row, columns).as_ref() == Some(key)),
+        }
+    }
+
+    pub fn get_pk_columns(&self) -> Vec<usize> {
+        match self.pk_index {
+            Some(ref index) => index.columns.clone(),
+            None => self.pk.iter().map(|i| *i).collect(),
+        }
+    }
+
     pub fn assert_size(&self) {
         let header_size = self.header.len();
 
@@ -67,10 +476,245 @@ impl Table {
 
     pub fn add_column(&mut self, column_def: ColumnDef) {
         self.header.push(column_def);
+        let default = self.default_row().pop().unwrap();
+
+        let header = &self.header;
+
+        for (_, row) in self.data.iter_mut() {
+            row.push(default.clone());
+            fill_generated(row, header);
+        }
+
+        self.rebuild_columns();
+        self.count_memory();
+    }
+
+    pub fn drop_column(&mut self, name: &String) -> RusqlResult<()> {
+        let i = match self.get_column_index(name) {
+            Some(i) => i,
+            None => return Err(RusqlError::NoSuchColumn(name.clone())),
+        };
+
+        if self.get_pk_columns().contains(&i) {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("cannot drop PRIMARY KEY column: {}", name)));
+        }
+        if self.unique.iter().any(|index| index.columns.contains(&i)) {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("cannot drop UNIQUE column: {}", name)));
+        }
+        if self.indexes.iter().any(|index| index.references_column(i, name)) {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("cannot drop indexed column: {}", name)));
+        }
+        if self.foreign_keys.iter().any(|fk| fk.columns.contains(&i))
+                || self.checks.iter().any(|expr| expr.references_column(name))
+                || self.header.iter().filter_map(|def| def.get_generated()).any(|expr| expr.references_column(name)) {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("cannot drop column referenced by a constraint: {}", name)));
+        }
+
+        self.header.remove(i);
 
         for (_, row) in self.data.iter_mut() {
-            row.push(LiteralValue::Null);
+            row.remove(i);
         }
+
+        self.rebuild_constraints();
+        self.count_memory();
+        Ok(())
+    }
+
+    pub fn rename_column(&mut self, old_name: &String, new_name: String) -> RusqlResult<()> {
+        let i = match self.get_column_index(old_name) {
+            Some(i) => i,
+            None => return Err(RusqlError::NoSuchColumn(old_name.clone())),
+        };
+
+        if self.get_column_index(&new_name).is_some() {
+            return Err(RusqlError::InvalidSchemaChange(
+                    format!("duplicate column name: {}", new_name)));
+        }
+
+        self.header[i].name = new_name.clone();
+
+        for column in self.header.iter_mut() {
+            for constraint in column.column_constraints.iter_mut() {
+                match constraint {
+                    &mut ColumnConstraint::Check(ref mut expr)
+                        | &mut ColumnConstraint::Generated(ref mut expr, _) => {
+                        expr.rename_column(old_name, &new_name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for constraint in self.constraints.iter_mut() {
+            match constraint {
+                &mut TableConstraint::PrimaryKey(ref mut names)
+                    | &mut TableConstraint::Unique(ref mut names)
+                    | &mut TableConstraint::ForeignKey(ref mut names, _) => {
+                    rename_in(names, old_name, &new_name);
+                }
+                &mut TableConstraint::Check(ref mut expr) => expr.rename_column(old_name, &new_name),
+            }
+        }
+
+        for index in self.indexes.iter_mut() {
+            if let Some(ref mut exprs) = index.expressions {
+                for expr in exprs.iter_mut() {
+                    expr.rename_column(old_name, &new_name);
+                }
+                index.column_names = exprs.iter().map(|expr| format!("{}", expr)).collect();
+            } else {
+                rename_in(&mut index.column_names, old_name, &new_name);
+            }
+
+            if let Some(ref mut expr) = index.predicate {
+                expr.rename_column(old_name, &new_name);
+            }
+        }
+
+        self.rebuild_constraints();
+        Ok(())
+    }
+
+    /// Follows a column rename in the table `parent` through the foreign keys
+    /// of this table that point at it.
+    pub fn rename_referenced_column(&mut self, parent: &String, old_name: &String, new_name: &String) {
+        for column in self.header.iter_mut() {
+            for constraint in column.column_constraints.iter_mut() {
+                if let &mut ColumnConstraint::References(ref mut clause) = constraint {
+                    if &clause.table_name == parent {
+                        clause.rename_column(old_name, new_name);
+                    }
+                }
+            }
+        }
+
+        for constraint in self.constraints.iter_mut() {
+            if let &mut TableConstraint::ForeignKey(_, ref mut clause) = constraint {
+                if &clause.table_name == parent {
+                    clause.rename_column(old_name, new_name);
+                }
+            }
+        }
+
+        self.rebuild_constraints();
+    }
+
+    /// Follows the rename of the table `old_name` through the foreign keys
+    /// of this table that point at it.
+    pub fn rename_referenced_table(&mut self, old_name: &String, new_name: &String) {
+        for column in self.header.iter_mut() {
+            for constraint in column.column_constraints.iter_mut() {
+                if let &mut ColumnConstraint::References(ref mut clause) = constraint {
+                    if &clause.table_name == old_name {
+                        clause.table_name = new_name.clone();
+                    }
+                }
+            }
+        }
+
+        for constraint in self.constraints.iter_mut() {
+            if let &mut TableConstraint::ForeignKey(_, ref mut clause) = constraint {
+                if &clause.table_name == old_name {
+                    clause.table_name = new_name.clone();
+                }
+            }
+        }
+
+        self.rebuild_constraints();
+    }
+
+    pub fn create_index(&mut self, index_def: IndexDef) -> RusqlResult<()> {
+        for expr in index_def.columns.iter().chain(index_def.where_expr.iter()) {
+            for column_name in expr.column_names().iter() {
+                if self.get_column_index(column_name).is_none() {
+                    return Err(RusqlError::NoSuchColumn(column_name.clone()));
+                }
+            }
+        }
+
+        let column_names: Vec<String> = index_def.columns.iter().map(|expr| format!("{}", expr)).collect();
+        let on_columns = index_def.columns.iter().all(|expr| {
+            match expr {
+                &Expression::ColumnName(..) => true,
+                _ => false,
+            }
+        });
+
+        let mut index = if on_columns {
+            let columns = try!(self.column_indexes(&column_names));
+            Index::new(index_def.name, column_names, columns, index_def.unique)
+        } else {
+            let mut index = Index::new(index_def.name, column_names, Vec::new(), index_def.unique);
+            index.expressions = Some(index_def.columns);
+            index
+        };
+        index.predicate = index_def.where_expr;
+
+        for (pk, row) in self.data.iter() {
+            if !index.covers(row, &self.header) {
+                continue;
+            }
+            if index.find_conflict(row, &self.header, None).is_some() {
+                return Err(self.unique_failed(&index));
+            }
+            index.insert(pk, row, &self.header);
+        }
+
+        self.indexes.push(index);
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, name: &String) -> Option<Index> {
+        match self.indexes.iter().position(|index| &index.name == name) {
+            Some(i) => Some(self.indexes.remove(i)),
+            None => None,
+        }
+    }
+
+    pub fn get_index(&self, name: &String) -> Option<&Index> {
+        self.indexes.iter().find(|index| &index.name == name)
+    }
+
+    /// An index whose first column is the one at `column`, holding every
+    /// row, to look rows up by its value.
+    pub fn index_on(&self, column: usize) -> Option<&Index> {
+        self.pk_index.iter().chain(self.unique.iter()).chain(self.indexes.iter()).find(|index| {
+            index.predicate.is_none() && index.expressions.is_none() && index.columns.first() == Some(&column)
+        })
+    }
+
+    /// Looks up rows by equality on some columns through the index covering
+    /// the most of them. The rows returned only match the columns that are
+    /// part of the index prefix, the others still have to be checked. None
+    /// if no index starts with one of the columns.
+    pub fn lookup_by_columns(&self, values: &Vec<(usize, LiteralValue)>) -> Option<Vec<PkType>> {
+        let columns: Vec<usize> = values.iter().map(|&(i, _)| i).collect();
+        let mut best: Option<(&Index, usize)> = None;
+
+        for index in self.pk_index.iter().chain(self.unique.iter()).chain(self.indexes.iter()) {
+            if index.predicate.is_some() {
+                continue;
+            }
+
+            let len = index.prefix_len(&columns);
+
+            if len > 0 && best.map_or(true, |(_, best_len)| len > best_len) {
+                best = Some((index, len));
+            }
+        }
+
+        best.map(|(index, len)| {
+            let prefix: TableRow = index.columns[..len].iter().map(|i| {
+                values.iter().find(|&&(j, _)| j == *i).unwrap().1.clone()
+            }).collect();
+
+            index.lookup_prefix(&prefix)
+        })
     }
 
     pub fn add_columns(&mut self, column_defs: Vec<ColumnDef>) {
@@ -80,58 +724,603 @@ impl Table {
     }
 
     pub fn insert(&mut self, column_data: Vec<TableRow>,
-                  specified_columns: &Option<Vec<String>>) {
+                  specified_columns: &Option<Vec<String>>) -> RusqlResult<()> {
+        let rows = try!(self.build_rows(column_data, specified_columns));
+        self.insert_rows(rows)
+    }
+
+    pub fn build_rows(&self, column_data: Vec<TableRow>,
+                      specified_columns: &Option<Vec<String>>) -> RusqlResult<Vec<TableRow>> {
+        let mut rows: Vec<TableRow> = Vec::new();
+        let mut next_pk = self.last_pk();
+        let columns = match specified_columns {
+            &Some(ref column_names) => Some(try!(self.column_indexes(column_names))),
+            &None => None,
+        };
+
         for column_data in column_data.into_iter() {
-            if let &Some(ref column_names) = specified_columns {
-                assert!(column_names.len() == column_data.len());
-                let mut row: TableRow = repeat(LiteralValue::Null).take(self.header.len()).collect();
+            let row = if let Some(ref columns) = columns {
+                if columns.len() != column_data.len() {
+                    return Err(RusqlError::ConstraintViolation(
+                            format!("{} values for {} columns", column_data.len(), columns.len())));
+                }
+                let mut row = self.default_row();
 
-                for (name, data) in column_names.iter().zip(column_data.into_iter()) {
-                    row[self.get_column_index(name).unwrap()] = data;
+                for (&i, data) in columns.iter().zip(column_data.into_iter()) {
+                    row[i] = data;
                 }
 
                 if let Some(i) = self.pk {
                     if row[i] == LiteralValue::Null {
-                        row[i] = LiteralValue::Integer((self.max_pk.get() + 1) as isize);
+                        row[i] = LiteralValue::Integer((next_pk + 1) as isize);
                     }
                 }
 
-                self.push_row(row);
+                row
+            } else if column_data.len() > self.header.len() {
+                return Err(RusqlError::ConstraintViolation(
+                        format!("table {} has {} columns but {} values were supplied",
+                                self.name, self.header.len(), column_data.len())));
+            } else if column_data.len() < self.header.len() {
+                // Generated columns can be left out of the values, but only they.
+                let stored = self.header.iter().filter(|def| def.get_generated().is_none()).count();
+                if column_data.len() != stored {
+                    return Err(RusqlError::ConstraintViolation(
+                            format!("table {} has {} columns but {} values were supplied",
+                                    self.name, stored, column_data.len())));
+                }
+                let mut data = column_data.into_iter();
+
+                self.header.iter().map(|def| {
+                    match def.get_generated() {
+                        Some(_) => LiteralValue::Null,
+                        None => data.next().unwrap(),
+                    }
+                }).collect()
             } else {
-                self.push_row(column_data);
+                column_data
+            };
+
+            if let Some(i) = self.pk {
+                next_pk = max(next_pk, row[i].to_uint());
+            }
+
+            rows.push(row);
+        }
+
+        Ok(rows)
+    }
+
+    // The last primary key handed out. With AUTOINCREMENT that's the largest key
+    // ever used, so the keys of deleted rows are never handed out again.
+    fn last_pk(&self) -> PkType {
+        if self.autoincrement {
+            self.max_pk
+        } else {
+            self.data.last_key().unwrap_or(0)
+        }
+    }
+
+    pub fn insert_rows(&mut self, rows: Vec<TableRow>) -> RusqlResult<()> {
+        self.insert_rows_with(rows, ConflictResolution::Abort).map(|_| ())
+    }
+
+    /// Returns the keys of the rows inserted, which leaves out the ignored
+    /// ones.
+    pub fn insert_rows_with(&mut self, rows: Vec<TableRow>, on_conflict: ConflictResolution)
+                            -> RusqlResult<Vec<PkType>> {
+        let mut inserted: Vec<PkType> = Vec::new();
+        let mut replaced: Vec<TableRow> = Vec::new();
+
+        for mut row in rows.into_iter() {
+            match on_conflict {
+                ConflictResolution::Replace => {
+                    self.prepare_row(&mut row);
+
+                    for pk in self.conflicting_rows(&row, None).into_iter() {
+                        replaced.push(self.remove_row(pk).unwrap());
+                    }
+                }
+                ConflictResolution::Ignore => {
+                    self.prepare_row(&mut row);
+
+                    if !self.conflicting_rows(&row, None).is_empty() {
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
+            match self.push_row(row) {
+                Ok(pk) => inserted.push(pk),
+                Err(_) if on_conflict == ConflictResolution::Ignore => continue,
+                // Keep what the statement did before the failing row.
+                Err(e) if on_conflict == ConflictResolution::Fail => return Err(e),
+                // ROLLBACK undoes the statement here, and the caller rolls
+                // back the transaction around it, if any.
+                Err(e) => {
+                    // Don't leave the statement half applied.
+                    for pk in inserted.iter() {
+                        self.remove_row(*pk);
+                    }
+                    for row in replaced.into_iter() {
+                        self.push_row(row).ok();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    /// Inserts many rows at once, e.g. loaded from a file, all of them or
+    /// none: every row is checked before the first one is stored, then each
+    /// index is updated for all of them in one go and the rows are stored in
+    /// key order. Returns the keys of the rows.
+    pub fn insert_batch<I: Iterator<Item=TableRow>>(&mut self, rows: I) -> RusqlResult<Vec<PkType>> {
+        let mut last_pk = self.last_pk();
+        let mut last_key = self.max_pk;
+        let mut batch: Vec<(PkType, TableRow)> = Vec::new();
+
+        for mut row in rows {
+            if row.len() != self.header.len() {
+                return Err(RusqlError::ConstraintViolation(
+                        format!("table {} has {} columns but {} values were supplied",
+                                self.name, self.header.len(), row.len())));
+            }
+
+            self.prepare_row(&mut row);
+
+            let pk = match self.pk {
+                Some(i) => {
+                    if row[i].is_null() {
+                        row[i] = LiteralValue::Integer((last_pk + 1) as isize);
+                    }
+                    try!(self.check_types(&row));
+                    last_pk = max(last_pk, row[i].to_uint());
+                    row[i].to_uint()
+                }
+                None => {
+                    try!(self.check_types(&row));
+                    last_key += 1;
+                    last_key
+                }
+            };
+
+            try!(self.check_checks(&row));
+            batch.push((pk, row));
+        }
+
+        batch.as_mut_slice().sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+        try!(self.check_unique_batch(&batch));
+
+        {
+            let header = &self.header;
+
+            for index in self.pk_index.iter_mut().chain(self.unique.iter_mut()).chain(self.indexes.iter_mut()) {
+                for &(pk, ref row) in batch.iter() {
+                    if index.covers(row, header) {
+                        index.insert(pk, row, header);
+                    }
+                }
+            }
+        }
+
+        if let Some(ref mut columns) = self.columns {
+            for &(pk, ref row) in batch.iter() {
+                columns.insert(pk, row);
             }
         }
+
+        let mut keys: Vec<PkType> = Vec::new();
+
+        for (pk, row) in batch.into_iter() {
+            self.max_pk = max(self.max_pk, pk);
+            self.memory += memory::row_size(&row);
+            self.data.insert(pk, row);
+            keys.push(pk);
+        }
+
+        Ok(keys)
     }
 
-    pub fn push_row(&mut self, row: TableRow) {
+    // Like check_unique for every row of a batch sorted by key, which must
+    // also not conflict with each other.
+    fn check_unique_batch(&self, batch: &Vec<(PkType, TableRow)>) -> RusqlResult<()> {
         if let Some(i) = self.pk {
-            let pk = row[i].clone().to_uint();
+            let mut last: Option<PkType> = None;
+
+            for &(pk, _) in batch.iter() {
+                if last == Some(pk) || self.data.contains_key(&pk) {
+                    return Err(RusqlError::ConstraintViolation(
+                            format!("UNIQUE constraint failed: {}.{}", self.name, self.header[i].name)));
+                }
+                last = Some(pk);
+            }
+        }
 
-            self.max_pk.set(max(self.max_pk.get(), pk));
+        for index in self.pk_index.iter().chain(self.unique.iter()).chain(self.indexes.iter()) {
+            if !index.unique {
+                continue;
+            }
+
+            let mut keys: BTreeMap<TableRow, ()> = BTreeMap::new();
+
+            for &(_, ref row) in batch.iter() {
+                if !index.covers(row, &self.header) {
+                    continue;
+                }
+
+                let key = index.key(row, &self.header);
+
+                // NULLs are never equal to each other, so they can't collide.
+                if key.iter().any(|value| value.is_null()) {
+                    continue;
+                }
+                if keys.contains_key(&key) || !index.lookup(&key).is_empty() {
+                    return Err(self.unique_failed(index));
+                }
+                keys.insert(key, ());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Puts back rows that were stored before under these keys, e.g. read
+    /// from a saved database, without checking them again.
+    pub fn restore_rows(&mut self, rows: Vec<(PkType, TableRow)>) {
+        for (pk, mut row) in rows.into_iter() {
+            for value in row.iter_mut() {
+                self.strings.intern(value);
+            }
+
+            self.max_pk = max(self.max_pk, pk);
+            self.index_row(pk, &row);
+            self.memory += memory::row_size(&row);
             self.data.insert(pk, row);
+        }
+    }
+
+    /// Stores the rows again, in new storage held in memory, and builds the
+    /// indexes and the string pool anew, see VACUUM. Rows without an INTEGER
+    /// PRIMARY KEY are numbered again from 1, in order.
+    pub fn rebuild(&mut self) {
+        let rows: Vec<(PkType, TableRow)> = self.data.iter().map(|(pk, row)| (pk, row.clone())).collect();
+        let renumber = self.pk.is_none() && !self.autoincrement;
+
+        for index in self.pk_index.iter_mut().chain(self.unique.iter_mut()).chain(self.indexes.iter_mut()) {
+            index.keys.clear();
+        }
+        if self.columns.is_some() {
+            self.columns = Some(ColumnStore::new(&self.header));
+        }
+
+        self.data = TableData::new();
+        self.strings = StringPool::new();
+        self.memory = 0;
+
+        if renumber {
+            self.max_pk = 0;
+            self.restore_rows(rows.into_iter().enumerate().map(|(i, (_, row))| (i + 1, row)).collect());
         } else {
-            self.max_pk.set(self.max_pk.get() + 1);
-            self.data.insert(self.max_pk.get(), row);
+            self.restore_rows(rows);
         }
     }
 
-    pub fn delete_where<F: Fn(&TableRow) -> bool>(&mut self, f: F) {
+    /// Indexes a row its storage already holds, e.g. one in the pages of a
+    /// database from open_paged.
+    pub fn index_stored_row(&mut self, pk: PkType, row: &TableRow) {
+        self.max_pk = max(self.max_pk, pk);
+        self.index_row(pk, row);
+    }
+
+    /// Whether anything is kept about each row besides the row itself, which
+    /// index_stored_row has to be called on.
+    pub fn is_indexed(&self) -> bool {
+        self.pk_index.is_some() || !self.unique.is_empty() || !self.indexes.is_empty() || self.columns.is_some()
+    }
+
+    /// The keys of the rows that share the primary key or the values of a
+    /// UNIQUE constraint with the row. With a target, only the constraint on
+    /// exactly those columns is checked.
+    pub fn conflicting_rows(&self, row: &TableRow, target: Option<&Vec<String>>) -> Vec<PkType> {
+        let mut keys: Vec<PkType> = Vec::new();
+        let is_target = |names: &Vec<String>| {
+            match target {
+                Some(target) => target.len() == names.len() && target.iter().all(|name| names.contains(name)),
+                None => true,
+            }
+        };
+
+        if let Some(i) = self.pk {
+            let pk = row[i].to_uint();
+
+            if self.data.contains_key(&pk) && is_target(&vec![self.header[i].name.clone()]) {
+                keys.push(pk);
+            }
+        }
+
+        for index in self.pk_index.iter().chain(self.unique.iter()).chain(self.indexes.iter()) {
+            if !index.covers(row, &self.header) || !is_target(&index.column_names) {
+                continue;
+            }
+
+            if let Some(pk) = index.find_conflict(row, &self.header, None) {
+                if !keys.contains(&pk) {
+                    keys.push(pk);
+                }
+            }
+        }
+
+        keys
+    }
+
+    pub fn default_row(&self) -> TableRow {
+        let empty_row: TableRow = Vec::new();
+
+        self.header.iter().map(|def| {
+            match def.get_default() {
+                Some(expr) => result_to_literal(ExpressionEvaluator::new(&empty_row, &self.header)
+                                                                    .eval_expr(expr)),
+                None => LiteralValue::Null,
+            }
+        }).collect()
+    }
+
+    /// Converts the values to the column types and computes the generated
+    /// columns, as done for every row that gets stored.
+    pub fn prepare_row(&self, row: &mut TableRow) {
+        apply_affinity(row, &self.header);
+        fill_generated(row, &self.header);
+
+        for value in row.iter_mut() {
+            self.strings.intern(value);
+        }
+    }
+
+    pub fn push_row(&mut self, mut row: TableRow) -> RusqlResult<PkType> {
+        self.prepare_row(&mut row);
+
+        let pk = if let Some(i) = self.pk {
+            row[i].to_uint()
+        } else {
+            self.max_pk + 1
+        };
+
+        try!(self.check_constraints(&row, None));
+
+        self.max_pk = max(self.max_pk, pk);
+        self.index_row(pk, &row);
+        self.memory += memory::row_size(&row);
+        self.data.insert(pk, row);
+
+        Ok(pk)
+    }
+
+    pub fn remove_row(&mut self, pk: PkType) -> Option<TableRow> {
+        let row = self.data.remove(&pk);
+
+        if let Some(ref row) = row {
+            self.unindex_row(pk, row);
+            self.memory -= memory::row_size(row);
+        }
+
+        row
+    }
+
+    pub fn update_rows(&mut self, updates: Vec<(PkType, TableRow)>) -> RusqlResult<()> {
+        let mut applied: Vec<(PkType, TableRow)> = Vec::new();
+
+        for (pk, mut row) in updates.into_iter() {
+            self.prepare_row(&mut row);
+
+            if let Err(e) = self.check_constraints(&row, Some(pk)) {
+                // Put back the rows we already changed before bailing out.
+                for (pk, old_row) in applied.into_iter().rev() {
+                    self.remove_row(pk);
+                    self.push_row(old_row).ok();
+                }
+                return Err(e);
+            }
+
+            let old_row = self.remove_row(pk).unwrap();
+            let new_pk = self.push_row(row).unwrap();
+            applied.push((new_pk, old_row));
+        }
+
+        Ok(())
+    }
+
+    pub fn keys_where<F: Fn(&TableRow) -> bool>(&self, f: F) -> Vec<PkType> {
         let mut keys: Vec<PkType> = Vec::new();
 
         for (key, row) in self.data.iter() {
             if !f(row) {
                 continue;
             }
-            keys.push(key.clone());
+            keys.push(key);
         }
 
+        keys
+    }
+
+    pub fn delete_where<F: Fn(&TableRow) -> bool>(&mut self, f: F) {
+        let keys = self.keys_where(f);
+
         for key in keys.iter() {
-            self.data.remove(key);
+            self.remove_row(*key);
+        }
+    }
+
+    fn check_constraints(&self, row: &TableRow, ignore: Option<PkType>) -> RusqlResult<()> {
+        try!(self.check_types(row));
+        try!(self.check_checks(row));
+        self.check_unique(row, ignore)
+    }
+
+    fn check_types(&self, row: &TableRow) -> RusqlResult<()> {
+        // Rows are stored by their INTEGER PRIMARY KEY, which can't be
+        // anything else, STRICT or not.
+        if let Some(i) = self.pk {
+            if !row[i].is_int() {
+                return Err(RusqlError::TypeMismatch(
+                        format!("datatype mismatch: {}.{} must be an integer", self.name, self.header[i].name)));
+            }
+        }
+
+        if !self.strict {
+            return Ok(());
+        }
+
+        for (def, value) in self.header.iter().zip(row.iter()) {
+            if let Some(column_type) = def.column_type {
+                if !column_type.accepts(value) {
+                    return Err(RusqlError::ConstraintViolation(
+                            format!("cannot store {} value in {} column {}.{}",
+                                    value.type_name(), column_type, self.name, def.name)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_checks(&self, row: &TableRow) -> RusqlResult<()> {
+        for expr in self.checks.iter() {
+            let result = result_to_literal(ExpressionEvaluator::new(row, &self.header).eval_expr(expr));
+
+            // Like SQLite, a CHECK that evaluates to NULL passes.
+            if !result.is_null() && !result.to_bool() {
+                return Err(RusqlError::ConstraintViolation(
+                        format!("CHECK constraint failed: {}", self.name)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_unique(&self, row: &TableRow, ignore: Option<PkType>) -> RusqlResult<()> {
+        if let Some(i) = self.pk {
+            let pk = row[i].to_uint();
+
+            if Some(pk) != ignore && self.data.contains_key(&pk) {
+                return Err(RusqlError::ConstraintViolation(
+                        format!("UNIQUE constraint failed: {}.{}", self.name, self.header[i].name)));
+            }
+        }
+
+        for index in self.pk_index.iter().chain(self.unique.iter()).chain(self.indexes.iter()) {
+            if index.covers(row, &self.header) && index.find_conflict(row, &self.header, ignore).is_some() {
+                return Err(self.unique_failed(index));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unique_failed(&self, index: &Index) -> RusqlError {
+        if index.expressions.is_some() {
+            return RusqlError::ConstraintViolation(format!("UNIQUE constraint failed: index '{}'", index.name));
+        }
+
+        let columns: Vec<String> = index.column_names.iter()
+                                                     .map(|name| format!("{}.{}", self.name, name))
+                                                     .collect();
+        RusqlError::ConstraintViolation(format!("UNIQUE constraint failed: {}", columns.connect(", ")))
+    }
+
+    fn index_row(&mut self, pk: PkType, row: &TableRow) {
+        let header = &self.header;
+
+        for index in self.pk_index.iter_mut().chain(self.unique.iter_mut()).chain(self.indexes.iter_mut()) {
+            if index.covers(row, header) {
+                index.insert(pk, row, header);
+            }
+        }
+
+        if let Some(ref mut columns) = self.columns {
+            columns.insert(pk, row);
+        }
+    }
+
+    fn unindex_row(&mut self, pk: PkType, row: &TableRow) {
+        let header = &self.header;
+
+        for index in self.pk_index.iter_mut().chain(self.unique.iter_mut()).chain(self.indexes.iter_mut()) {
+            index.remove(pk, row, header);
+        }
+
+        if let Some(ref mut columns) = self.columns {
+            columns.remove(pk);
         }
     }
 
     pub fn clear(&mut self) {
         self.data.clear();
+        self.memory = 0;
+
+        for index in self.pk_index.iter_mut().chain(self.unique.iter_mut()).chain(self.indexes.iter_mut()) {
+            index.keys.clear();
+        }
+
+        if self.columns.is_some() {
+            self.columns = Some(ColumnStore::new(&self.header));
+        }
+    }
+
+    // Counts the bytes of every row again, after they were changed in place.
+    fn count_memory(&mut self) {
+        self.memory = self.data.values().fold(0, |size, row| size + memory::row_size(row));
+    }
+
+    // Copies the rows into a new column store, for a COLUMNAR table whose
+    // columns changed.
+    fn rebuild_columns(&mut self) {
+        if self.columns.is_none() {
+            return;
+        }
+
+        let mut columns = ColumnStore::new(&self.header);
+        for (pk, row) in self.data.iter() {
+            columns.insert(pk, row);
+        }
+        self.columns = Some(columns);
+    }
+
+    /// Recomputes everything derived from the constraints, e.g. after the
+    /// header changed, and re-indexes the stored rows.
+    pub fn rebuild_constraints(&mut self) {
+        self.pk = None;
+        self.autoincrement = false;
+        self.pk_index = None;
+        self.unique.clear();
+        self.checks.clear();
+        self.foreign_keys.clear();
+
+        self.process_constraints();
+
+        let header = &self.header;
+
+        for index in self.indexes.iter_mut() {
+            if index.expressions.is_none() {
+                index.columns = index.column_names.iter()
+                                                  .map(|name| header.iter().position(|def| &def.name == name).unwrap())
+                                                  .collect();
+            }
+            index.keys.clear();
+        }
+
+        if self.columns.is_some() {
+            self.columns = Some(ColumnStore::new(&self.header));
+        }
+
+        let rows: Vec<(PkType, TableRow)> = self.data.iter().map(|(pk, row)| (pk, row.clone())).collect();
+
+        for &(pk, ref row) in rows.iter() {
+            self.index_row(pk, row);
+        }
     }
 
     pub fn process_constraints(&mut self) {
@@ -139,6 +1328,49 @@ impl Table {
             for constraint in column.column_constraints.iter() {
                 match constraint {
                     &ColumnConstraint::PrimaryKey => self.pk = Some(i),
+                    &ColumnConstraint::Autoincrement => self.autoincrement = true,
+                    &ColumnConstraint::Unique => {
+                        let name = format!("rusql_autoindex_{}_{}", self.name, self.unique.len() + 1);
+                        self.unique.push(Index::new(name, vec![column.name.clone()], vec![i], true));
+                    }
+                    &ColumnConstraint::Check(ref expr) => self.checks.push(expr.clone()),
+                    &ColumnConstraint::Default(..) | &ColumnConstraint::Generated(..) => {}
+                    &ColumnConstraint::References(ref clause) => {
+                        self.foreign_keys.push(ForeignKey { columns: vec![i], clause: clause.clone() });
+                    }
+                }
+            }
+        }
+
+        for constraint in self.constraints.iter() {
+            match constraint {
+                &TableConstraint::PrimaryKey(ref names) => {
+                    let columns: Vec<usize> = names.iter()
+                                                   .map(|name| self.get_column_index(name).unwrap())
+                                                   .collect();
+
+                    // A single column key is an alias for the row key, same as
+                    // a column constraint. Composite keys get their own index.
+                    if columns.len() == 1 {
+                        self.pk = Some(columns[0]);
+                    } else {
+                        let name = format!("rusql_autoindex_{}_pk", self.name);
+                        self.pk_index = Some(Index::new(name, names.clone(), columns, true));
+                    }
+                }
+                &TableConstraint::Unique(ref names) => {
+                    let columns: Vec<usize> = names.iter()
+                                                   .map(|name| self.get_column_index(name).unwrap())
+                                                   .collect();
+                    let name = format!("rusql_autoindex_{}_{}", self.name, self.unique.len() + 1);
+                    self.unique.push(Index::new(name, names.clone(), columns, true));
+                }
+                &TableConstraint::Check(ref expr) => self.checks.push(expr.clone()),
+                &TableConstraint::ForeignKey(ref names, ref clause) => {
+                    let columns: Vec<usize> = names.iter()
+                                                   .map(|name| self.get_column_index(name).unwrap())
+                                                   .collect();
+                    self.foreign_keys.push(ForeignKey { columns: columns, clause: clause.clone() });
                 }
             }
         }
@@ -175,6 +1407,40 @@ impl fmt::String for Table {
     }
 }
 
+/// Computes the generated columns of a row from the other columns. Rows only
+/// live in memory, so VIRTUAL columns are kept up to date on write just like
+/// STORED ones, which gives the same results as computing them on read.
+pub fn fill_generated(row: &mut TableRow, header: &TableHeader) {
+    for (i, def) in header.iter().enumerate() {
+        if let Some(expr) = def.get_generated() {
+            let value = result_to_literal(ExpressionEvaluator::new(row, header).eval_expr(expr));
+            row[i] = match def.column_type {
+                Some(column_type) => column_type.apply_affinity(value),
+                None => value,
+            };
+        }
+    }
+}
+
+pub fn apply_affinity(row: &mut TableRow, header: &TableHeader) {
+    for (value, def) in row.iter_mut().zip(header.iter()) {
+        if let Some(column_type) = def.column_type {
+            let converted = column_type.apply_affinity(replace(value, LiteralValue::Null));
+            *value = converted;
+        }
+    }
+}
+
+pub fn get_key(row: &TableRow, columns: &Vec<usize>) -> Option<TableRow> {
+    let key: TableRow = columns.iter().map(|&i| row[i].clone()).collect();
+
+    if key.iter().any(|value| value.is_null()) {
+        None
+    } else {
+        Some(key)
+    }
+}
+
 pub fn get_column(name: &String, row: &TableRow, head: &TableHeader, offset: Option<usize>) -> LiteralValue {
     let x = if let Some(x) = offset { x } else { 0 };
     row[head.iter().position(|ref def| def.name == *name).unwrap() + x].clone()
@@ -0,0 +1,162 @@
+//! Walks parsed statements, letting a Visitor inspect or rewrite them in
+//! place before they're run, e.g. to prefix table names or add a condition
+//! to every WHERE clause.
+//!
+//! Each visit_ method defaults to the matching walk_ function, which visits
+//! the children of the node, so an implementation that overrides one should
+//! call the walk_ function itself to keep going deeper.
+
+use definitions::{RusqlStatement, SelectDef, Expression, FromClause, ResultColumn};
+use definitions::{InsertDataSource, UpsertDef, UpsertAction};
+
+pub trait Visitor: Sized {
+    fn visit_statement(&mut self, stmt: &mut RusqlStatement) {
+        walk_statement(self, stmt)
+    }
+
+    fn visit_select(&mut self, select_def: &mut SelectDef) {
+        walk_select(self, select_def)
+    }
+
+    fn visit_expression(&mut self, expr: &mut Expression) {
+        walk_expression(self, expr)
+    }
+
+    /// Called for each table a statement reads or writes, including the
+    /// qualifier of a column like Foo.Id.
+    fn visit_table_name(&mut self, _name: &mut String) {}
+}
+
+/// Visits every statement in `stmts`, e.g. the result of parse.
+pub fn walk_statements<V: Visitor>(visitor: &mut V, stmts: &mut Vec<RusqlStatement>) {
+    for stmt in stmts.iter_mut() {
+        visitor.visit_statement(stmt);
+    }
+}
+
+pub fn walk_statement<V: Visitor>(visitor: &mut V, stmt: &mut RusqlStatement) {
+    match stmt {
+        &mut RusqlStatement::AlterTable(ref mut def) => visitor.visit_table_name(&mut def.name),
+        &mut RusqlStatement::Analyze(Some(ref mut name)) => visitor.visit_table_name(name),
+        &mut RusqlStatement::CreateIndex(ref mut def) => {
+            visitor.visit_table_name(&mut def.table_name);
+            for expr in def.columns.iter_mut() {
+                visitor.visit_expression(expr);
+            }
+            if let Some(ref mut expr) = def.where_expr {
+                visitor.visit_expression(expr);
+            }
+        }
+        &mut RusqlStatement::CreateTable(ref mut def) => {
+            visitor.visit_table_name(&mut def.table_name);
+            if let Some(ref mut select_def) = def.as_select {
+                visitor.visit_select(select_def);
+            }
+        }
+        &mut RusqlStatement::CreateTrigger(ref mut def) => {
+            visitor.visit_table_name(&mut def.table_name);
+            if let Some(ref mut expr) = def.when {
+                visitor.visit_expression(expr);
+            }
+            walk_statements(visitor, &mut def.body);
+        }
+        &mut RusqlStatement::CreateView(ref mut def) => visitor.visit_select(&mut def.select),
+        &mut RusqlStatement::Delete(ref mut def) => {
+            visitor.visit_table_name(&mut def.name);
+            if let Some(ref mut expr) = def.where_expr {
+                visitor.visit_expression(expr);
+            }
+            walk_returning(visitor, &mut def.returning);
+        }
+        &mut RusqlStatement::DropTable(ref mut def) => visitor.visit_table_name(&mut def.name),
+        &mut RusqlStatement::Explain(ref mut stmt) | &mut RusqlStatement::ExplainQueryPlan(ref mut stmt) => {
+            visitor.visit_statement(&mut **stmt)
+        }
+        &mut RusqlStatement::Insert(ref mut def) => {
+            visitor.visit_table_name(&mut def.table_name);
+            match def.data_source {
+                InsertDataSource::Values(ref mut rows) => {
+                    for row in rows.iter_mut() {
+                        for expr in row.iter_mut() {
+                            visitor.visit_expression(expr);
+                        }
+                    }
+                }
+                InsertDataSource::Select(ref mut select_def) => visitor.visit_select(select_def),
+                _ => {}
+            }
+            if let Some(UpsertDef { action: UpsertAction::Update(ref mut set, ref mut where_expr), .. }) = def.upsert {
+                for &mut (_, ref mut expr) in set.iter_mut() {
+                    visitor.visit_expression(expr);
+                }
+                if let Some(ref mut expr) = *where_expr {
+                    visitor.visit_expression(expr);
+                }
+            }
+            walk_returning(visitor, &mut def.returning);
+        }
+        &mut RusqlStatement::Select(ref mut def) => visitor.visit_select(def),
+        &mut RusqlStatement::Update(ref mut def) => {
+            visitor.visit_table_name(&mut def.name);
+            for &mut (_, ref mut expr) in def.set.iter_mut() {
+                visitor.visit_expression(expr);
+            }
+            if let Some(ref mut expr) = def.where_expr {
+                visitor.visit_expression(expr);
+            }
+            walk_returning(visitor, &mut def.returning);
+        }
+        _ => {}
+    }
+}
+
+pub fn walk_select<V: Visitor>(visitor: &mut V, select_def: &mut SelectDef) {
+    match select_def.from_clause {
+        Some(FromClause::TableOrSubquery(ref mut names)) => {
+            for name in names.iter_mut() {
+                visitor.visit_table_name(name);
+            }
+        }
+        Some(FromClause::JoinClause(ref mut name, ref mut join_clauses)) => {
+            visitor.visit_table_name(name);
+            if let &mut Some(ref mut join_clauses) = join_clauses {
+                for &mut (_, ref mut name, _) in join_clauses.iter_mut() {
+                    visitor.visit_table_name(name);
+                }
+            }
+        }
+        None => {}
+    }
+
+    for expr in select_def.expressions_mut().into_iter() {
+        visitor.visit_expression(expr);
+    }
+}
+
+pub fn walk_expression<V: Visitor>(visitor: &mut V, expr: &mut Expression) {
+    match expr {
+        &mut Expression::TableName((ref mut name, ref mut expr)) => {
+            visitor.visit_table_name(name);
+            visitor.visit_expression(&mut **expr);
+        }
+        &mut Expression::BinaryOperator((_, ref mut left, ref mut right)) => {
+            visitor.visit_expression(&mut **left);
+            visitor.visit_expression(&mut **right);
+        }
+        &mut Expression::UnaryOperator((_, ref mut expr)) => visitor.visit_expression(&mut **expr),
+        &mut Expression::Function((_, ref mut args)) => {
+            for arg in args.iter_mut() {
+                visitor.visit_expression(arg);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn walk_returning<V: Visitor>(visitor: &mut V, returning: &mut Option<ResultColumn>) {
+    if let &mut Some(ResultColumn::Expressions(ref mut exprs)) = returning {
+        for expr in exprs.iter_mut() {
+            visitor.visit_expression(expr);
+        }
+    }
+}
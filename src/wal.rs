@@ -0,0 +1,187 @@
+//! The write-ahead log of a paged file in WAL mode, `<path>-wal`. The pages a
+//! commit writes are appended to it instead of being written over the file,
+//! each as a frame: the page number, the page and a checksum of both. The
+//! header of the file, page 0, is written last by every commit, so its frame
+//! marks where a commit ends; opening the log drops the frames after the
+//! last one, which are from a commit a crash cut short.
+//!
+//! Reads look for a page in the log first. A checkpoint writes the latest
+//! frame of every page over the file and empties the log. As the pages a
+//! B-tree uses aren't written again while anything reads it, see pager, a
+//! snapshot keeps reading the same rows whether their pages were folded
+//! back into the file or not.
+//!
+//! Numbers are big-endian.
+
+use error::{RusqlError, RusqlResult};
+use logfile::checksum;
+use pager::{PageNo, be_bytes, be_uint};
+
+use std::collections::BTreeMap;
+use std::io::{BufferedReader, BufferedWriter, File, FileAccess, FileMode, IoResult, SeekStyle};
+use std::io::fs::{self, PathExtensions};
+
+pub const MAGIC: &'static [u8] = b"rusql wal\0";
+pub const VERSION: u32 = 1;
+
+/// A commit checkpoints the log once it has this many frames.
+pub const AUTOCHECKPOINT: u64 = 1000;
+
+// The page number and the checksum around a page.
+const FRAME_OVERHEAD: u64 = 12;
+
+pub struct Wal {
+    path: Path,
+    file: BufferedWriter<File>,
+    page_size: usize,
+    /// Where the latest frame of each page starts.
+    index: BTreeMap<PageNo, u64>,
+    size: u64,
+}
+
+impl Wal {
+    /// Opens the log at `path` of a file with pages of `page_size` bytes,
+    /// creating it if there's none.
+    pub fn open(path: &Path, page_size: usize) -> RusqlResult<Wal> {
+        let header_len = (MAGIC.len() + 8) as u64;
+        let frame_len = page_size as u64 + FRAME_OVERHEAD;
+        let mut index: BTreeMap<PageNo, u64> = BTreeMap::new();
+        let mut size = header_len;
+
+        if path.exists() {
+            let file_size = try!(path.stat()).size;
+            let mut r = BufferedReader::new(try!(File::open(path)));
+            let magic = try!(r.read_exact(MAGIC.len()));
+
+            if magic.as_slice() != MAGIC {
+                return Err(RusqlError::Corrupt("not a rusql WAL".to_string()));
+            }
+
+            let version = try!(r.read_be_u32());
+            if version > VERSION {
+                return Err(RusqlError::Corrupt(format!("unsupported WAL version {}", version)));
+            }
+            if try!(r.read_be_u32()) as usize != page_size {
+                return Err(RusqlError::Corrupt("the WAL doesn't match the database".to_string()));
+            }
+
+            // The frames of the commit being read, which only count once its
+            // header is.
+            let mut frames: Vec<(PageNo, u64)> = Vec::new();
+            let mut end = size;
+
+            while end + frame_len <= file_size {
+                match read_frame(&mut r, page_size) {
+                    Some(page) => frames.push((page, end)),
+                    None => break,
+                }
+                end += frame_len;
+
+                if frames.last().unwrap().0 == 0 {
+                    index.extend(frames.iter().map(|&frame| frame));
+                    frames.clear();
+                    size = end;
+                }
+            }
+        } else {
+            let mut w = BufferedWriter::new(try!(File::create(path)));
+            try!(write_header(&mut w, page_size));
+            try!(w.flush());
+        }
+
+        let mut file = try!(File::open_mode(path, FileMode::Open, FileAccess::ReadWrite));
+        try!(file.truncate(size as i64));
+
+        Ok(Wal { path: path.clone(), file: BufferedWriter::new(file), page_size: page_size, index: index, size: size })
+    }
+
+    /// The latest contents of `page`, if the log has it.
+    pub fn read(&mut self, page: PageNo) -> IoResult<Option<Vec<u8>>> {
+        let offset = match self.index.get(&page) {
+            Some(&offset) => offset,
+            None => return Ok(None),
+        };
+
+        try!(self.file.flush());
+        let file = self.file.get_mut();
+        try!(file.seek((offset + 8) as i64, SeekStyle::SeekSet));
+        Ok(Some(try!(file.read_exact(self.page_size))))
+    }
+
+    /// Appends a frame with `bytes`, a whole page, as the contents of `page`.
+    pub fn write(&mut self, page: PageNo, bytes: &[u8]) -> IoResult<()> {
+        try!(self.file.flush());
+        try!(self.file.get_mut().seek(self.size as i64, SeekStyle::SeekSet));
+
+        let mut frame = be_bytes(page, 8);
+        frame.push_all(bytes);
+        for byte in frame.iter() {
+            try!(self.file.write_u8(*byte));
+        }
+        try!(self.file.write_be_u32(checksum(frame.as_slice())));
+
+        self.index.insert(page, self.size);
+        self.size += frame.len() as u64 + 4;
+        Ok(())
+    }
+
+    /// Writes out what's buffered and waits for it to reach the disk.
+    pub fn sync(&mut self) -> IoResult<()> {
+        try!(self.file.flush());
+        self.file.get_mut().datasync()
+    }
+
+    /// How many frames the log has.
+    pub fn frames(&self) -> u64 {
+        (self.size - (MAGIC.len() + 8) as u64) / (self.page_size as u64 + FRAME_OVERHEAD)
+    }
+
+    /// The pages the log has frames of, in order.
+    pub fn pages(&self) -> Vec<PageNo> {
+        self.index.keys().map(|page| *page).collect()
+    }
+
+    /// Empties the log, once its pages were written to the file.
+    pub fn reset(&mut self) -> IoResult<()> {
+        self.size = (MAGIC.len() + 8) as u64;
+        self.index.clear();
+
+        try!(self.file.flush());
+        try!(self.file.get_mut().truncate(self.size as i64));
+        self.file.get_mut().datasync()
+    }
+
+    /// Deletes the log, which should be empty.
+    pub fn remove(self) -> IoResult<()> {
+        let path = self.path.clone();
+
+        drop(self);
+        fs::unlink(&path)
+    }
+}
+
+/// Where the log of the paged file at `path` is.
+pub fn wal_path(path: &Path) -> Path {
+    Path::new(format!("{}-wal", path.display()))
+}
+
+fn write_header<W: Writer>(w: &mut W, page_size: usize) -> IoResult<()> {
+    for byte in MAGIC.iter() {
+        try!(w.write_u8(*byte));
+    }
+    try!(w.write_be_u32(VERSION));
+    w.write_be_u32(page_size as u32)
+}
+
+// The page number of the next frame, None if it's not whole.
+fn read_frame<R: Reader>(r: &mut R, page_size: usize) -> Option<PageNo> {
+    let frame = match r.read_exact(8 + page_size) {
+        Ok(frame) => frame,
+        Err(_) => return None,
+    };
+
+    match r.read_be_u32() {
+        Ok(sum) if sum == checksum(frame.as_slice()) => Some(be_uint(&frame[0..8])),
+        _ => None,
+    }
+}
@@ -1,8 +1,21 @@
-#![allow(unstable)]
+#![allow(unstable, deprecated)]
 
-extern crate rusql;
+#[macro_use] extern crate rusql;
 
-use rusql::{rusql_exec, Rusql, LiteralValue};
+use rusql::{rusql_exec, parse, Rusql, LiteralValue, RusqlError, RusqlStatement, QueryResult, Select, Order, col, val};
+use rusql::{TableRow, Visitor};
+use rusql::decimal::Decimal;
+use rusql::definitions::{SelectDef, ResultColumn, ColumnType};
+use rusql::visitor::{walk_select, walk_statements};
+
+use std::time::Duration;
+
+struct Person {
+    name: String,
+    age: Option<isize>,
+}
+
+from_row!(Person { name, age });
 
 fn init_db_with_table() -> Rusql {
     let mut db = rusql::Rusql::new();
@@ -140,7 +153,7 @@ fn test_delete_all() {
 
     rusql_exec(&mut db, "DELETE FROM Foo;", |_,_| {});
 
-    let table = db.get_table(&"Foo".to_string());
+    let table = db.get_table(&"Foo".to_string()).unwrap();
     assert!(table.data.len() == 0);
 }
 
@@ -171,12 +184,26 @@ fn test_insert_with_select() {
 
     rusql_exec(&mut db, sql_str, |_,_| {});
 
-    let foo = db.get_table(&"Foo".to_string());
-    let foo2 = db.get_table(&"Foo2".to_string());
+    let foo = db.get_table(&"Foo".to_string()).unwrap();
+    let foo2 = db.get_table(&"Foo2".to_string()).unwrap();
 
     assert!(foo.data == foo2.data);
 }
 
+#[test]
+fn test_insert_with_select_columns() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE TABLE Copy(Id INTEGER PRIMARY KEY, Name TEXT, Kind TEXT DEFAULT 'copy');
+                INSERT INTO Copy(Name) SELECT Name FROM Foo WHERE Id > 2;").unwrap();
+
+    let rows: Vec<(isize, String, String)> = db.query_map("SELECT Id, Name, Kind FROM Copy;", &[], |row| {
+        Ok((try!(row.get(0)), try!(row.get(1)), try!(row.get(2))))
+    }).unwrap();
+    assert_eq!(rows, vec![(1, "Bar3".to_string(), "copy".to_string()), (2, "Bar4".to_string(), "copy".to_string())]);
+
+    assert!(db.execute("INSERT INTO Copy(Name) SELECT Id, Name FROM Foo;").is_err());
+}
+
 #[test]
 fn test_update() {
     let mut db = init_db_and_insert_into_table();
@@ -184,7 +211,7 @@ fn test_update() {
                    SELECT * FROM Foo;";
 
     rusql_exec(&mut db, sql_str, |row, _| {
-        assert!(row[1] == LiteralValue::Text("Qux".to_string()));
+        assert!(row[1] == LiteralValue::text("Qux"));
     });
 }
 
@@ -193,7 +220,7 @@ fn test_update_where() {
     let mut db = init_db_and_insert_into_table();
     let sql_str = "UPDATE Foo SET Name=\"Qux\" WHERE Id=3; \
                    SELECT * FROM Foo WHERE Id=3;";
-    let expected = vec![LiteralValue::Text("Qux".to_string())];
+    let expected = vec![LiteralValue::text("Qux")];
     let mut results: Vec<LiteralValue> = Vec::new();
 
     rusql_exec(&mut db, sql_str, |row, _| {
@@ -294,7 +321,7 @@ fn test_select_multiple_tables_with_specified_result_columns() {
 fn test_select_header_length_specified_table_and_columns() {
     let mut db = init_db_and_insert_into_table();
 
-    let results = rusql_exec(&mut db, "SELECT Foo.Id, Foo.Name FROM Foo;", |_,_| {}).unwrap();
+    let results = rusql_exec(&mut db, "SELECT Foo.Id, Foo.Name FROM Foo;", |_,_| {}).remove(0);
     assert_eq!(results.header.len(), 2);
 }
 
@@ -302,7 +329,7 @@ fn test_select_header_length_specified_table_and_columns() {
 fn test_select_header_length_specified_columns() {
     let mut db = init_db_and_insert_into_table();
 
-    let results = rusql_exec(&mut db, "SELECT Id, Name FROM Foo;", |_,_| {}).unwrap();
+    let results = rusql_exec(&mut db, "SELECT Id, Name FROM Foo;", |_,_| {}).remove(0);
     assert_eq!(results.header.len(), 2);
 }
 
@@ -310,7 +337,7 @@ fn test_select_header_length_specified_columns() {
 fn test_select_header_length_asterisk() {
     let mut db = init_db_and_insert_into_table();
 
-    let results = rusql_exec(&mut db, "SELECT * FROM Foo;", |_,_| {}).unwrap();
+    let results = rusql_exec(&mut db, "SELECT * FROM Foo;", |_,_| {}).remove(0);
     assert_eq!(results.header.len(), 2);
 }
 
@@ -362,7 +389,7 @@ fn test_select_order_by_desc() {
 fn test_single_quote() {
     let mut db = init_db_with_table();
     let mut results: Vec<LiteralValue> = Vec::new();
-    let expected = vec![LiteralValue::Text("Bar".to_string())];
+    let expected = vec![LiteralValue::text("Bar")];
     let sql_str = "INSERT INTO Foo VALUES(1, 'Bar'); \
                    SELECT * FROM Foo;";
 
@@ -478,3 +505,2697 @@ fn test_select_multiple_tables_with_natural_join() {
 
     assert_eq!(results, expected);
 }
+
+#[test]
+fn test_unique_constraint() {
+    let mut db = Rusql::new();
+    let sql_str = "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT UNIQUE); \
+                   INSERT INTO Foo VALUES(1, \"Bar1\"), (2, \"Bar2\");";
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(3, \"Bar1\");", |_,_| {});
+    rusql_exec(&mut db, "UPDATE Foo SET Name=\"Bar2\" WHERE Id=1;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::text("Bar1"));
+}
+
+#[test]
+fn test_check_constraint() {
+    let mut db = Rusql::new();
+    let sql_str = "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Qty INTEGER CHECK(Qty < 100), \
+                                    Cap INTEGER, CHECK(Qty <= Cap)); \
+                   INSERT INTO Foo VALUES(1, 5, 10);";
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(2, 200, 500);", |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(3, 11, 10);", |_,_| {});
+    rusql_exec(&mut db, "UPDATE Foo SET Qty=20;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 1);
+    assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::Integer(5));
+}
+
+#[test]
+fn test_default_values() {
+    let mut db = Rusql::new();
+    let expected = vec![LiteralValue::Integer(1),
+                        LiteralValue::text("None"),
+                        LiteralValue::Integer(6)];
+    let sql_str = "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT DEFAULT 'None', \
+                                    Qty INTEGER DEFAULT (2 * 3)); \
+                   INSERT INTO Foo(Id) VALUES(1);";
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.get(&1).unwrap(), &expected);
+}
+
+fn init_db_with_foreign_key() -> Rusql {
+    let mut db = init_db_and_insert_into_table();
+    let sql_str = "CREATE TABLE Qux(Id INTEGER PRIMARY KEY, FooId INTEGER REFERENCES Foo(Id)); \
+                   INSERT INTO Qux VALUES(1, 1), (2, 2);";
+    rusql_exec(&mut db, sql_str, |_,_| {});
+
+    db
+}
+
+#[test]
+fn test_foreign_key_insert() {
+    let mut db = init_db_with_foreign_key();
+
+    rusql_exec(&mut db, "INSERT INTO Qux VALUES(3, 5);", |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Qux(Id) VALUES(4);", |_,_| {});
+
+    let table = db.get_table(&"Qux".to_string()).unwrap();
+    assert!(!table.has_row(3));
+    assert!(table.has_row(4));
+}
+
+#[test]
+fn test_foreign_key_delete_parent() {
+    let mut db = init_db_with_foreign_key();
+
+    rusql_exec(&mut db, "DELETE FROM Foo WHERE Id=1;", |_,_| {});
+    rusql_exec(&mut db, "DELETE FROM Foo WHERE Id=3;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert!(table.has_row(1));
+    assert!(!table.has_row(3));
+}
+
+#[test]
+fn test_foreign_key_pragma_off() {
+    let mut db = init_db_with_foreign_key();
+
+    rusql_exec(&mut db, "PRAGMA foreign_keys = OFF; \
+                         INSERT INTO Qux VALUES(3, 5);", |_,_| {});
+
+    let table = db.get_table(&"Qux".to_string()).unwrap();
+    assert!(table.has_row(3));
+}
+
+#[test]
+fn test_foreign_key_on_delete_actions() {
+    let mut db = init_db_and_insert_into_table();
+    let sql_str = "CREATE TABLE Qux(Id INTEGER PRIMARY KEY, \
+                                    FooId INTEGER REFERENCES Foo(Id) ON DELETE CASCADE); \
+                   CREATE TABLE Baz(Id INTEGER PRIMARY KEY, \
+                                    FooId INTEGER REFERENCES Foo(Id) ON DELETE SET NULL); \
+                   INSERT INTO Qux VALUES(1, 1), (2, 2); \
+                   INSERT INTO Baz VALUES(1, 1), (2, 2); \
+                   DELETE FROM Foo WHERE Id=1;";
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+
+    let qux = db.get_table(&"Qux".to_string()).unwrap();
+    assert!(!qux.has_row(1));
+    assert!(qux.has_row(2));
+
+    let baz = db.get_table(&"Baz".to_string()).unwrap();
+    assert_eq!(baz.data.get(&1).unwrap()[1], LiteralValue::Null);
+    assert_eq!(baz.data.get(&2).unwrap()[1], LiteralValue::Integer(2));
+}
+
+#[test]
+fn test_foreign_key_failed_action() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE TABLE Qux(Id INTEGER PRIMARY KEY, \
+                                 FooId INTEGER CHECK(FooId IS NOT NULL) \
+                                       REFERENCES Foo(Id) ON DELETE SET NULL ON UPDATE SET NULL);
+                CREATE TABLE Baz(Id INTEGER PRIMARY KEY, \
+                                 FooId INTEGER REFERENCES Foo(Id) ON DELETE CASCADE ON UPDATE CASCADE);
+                INSERT INTO Baz VALUES(1, 1);
+                INSERT INTO Qux VALUES(1, 1);").unwrap();
+
+    // The CHECK of Qux fails the delete, after Baz's rows went.
+    assert!(db.execute("DELETE FROM Foo WHERE Id = 1;").is_err());
+    assert!(db.get_table(&"Foo".to_string()).unwrap().has_row(1));
+    assert!(db.get_table(&"Baz".to_string()).unwrap().has_row(1));
+
+    assert!(db.execute("UPDATE Foo SET Id = 5 WHERE Id = 1;").is_err());
+    assert!(db.get_table(&"Foo".to_string()).unwrap().has_row(1));
+    assert_eq!(db.get_table(&"Baz".to_string()).unwrap().data.get(&1).unwrap()[1], LiteralValue::Integer(1));
+}
+
+#[test]
+fn test_foreign_key_rename_parent() {
+    let mut db = init_db_with_foreign_key();
+
+    db.execute("ALTER TABLE Foo RENAME TO Foo2;").unwrap();
+    assert!(db.execute("INSERT INTO Qux VALUES(3, 3);").is_ok());
+    assert!(db.execute("INSERT INTO Qux VALUES(4, 5);").is_err());
+
+    // A parent looked up through a unique index rather than its key.
+    db.execute("CREATE TABLE Tag(Id INTEGER PRIMARY KEY, Name TEXT UNIQUE);
+                CREATE TABLE Post(Id INTEGER PRIMARY KEY, TagName TEXT REFERENCES Tag(Name));
+                INSERT INTO Tag(Name) VALUES('a'), ('b');").unwrap();
+    assert!(db.execute("INSERT INTO Post(TagName) VALUES('b');").is_ok());
+    assert!(db.execute("INSERT INTO Post(TagName) VALUES('c');").is_err());
+}
+
+#[test]
+fn test_pk_autoincrement_keyword() {
+    let mut db = Rusql::new();
+    let mut results: Vec<isize> = Vec::new();
+    let expected = vec![1, 2, 4, 3];
+    let sql_str = "CREATE TABLE Foo(Id INTEGER PRIMARY KEY AUTOINCREMENT, Name TEXT); \
+                   CREATE TABLE Bar(Id INTEGER PRIMARY KEY, Name TEXT); \
+                   INSERT INTO Foo(Name) VALUES(\"Foo1\"), (\"Foo2\"), (\"Foo3\"); \
+                   INSERT INTO Bar(Name) VALUES(\"Bar1\"), (\"Bar2\"), (\"Bar3\"); \
+                   DELETE FROM Foo WHERE Id=3; \
+                   DELETE FROM Bar WHERE Id=3; \
+                   INSERT INTO Foo(Name) VALUES(\"Foo4\"); \
+                   INSERT INTO Bar(Name) VALUES(\"Bar4\");";
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+    rusql_exec(&mut db, "SELECT Id FROM Foo;", |row, _| {
+        results.push(row[0].to_int());
+    });
+    rusql_exec(&mut db, "SELECT Id FROM Bar WHERE Name=\"Bar4\";", |row, _| {
+        results.push(row[0].to_int());
+    });
+
+    assert_eq!(expected, results);
+}
+
+#[test]
+fn test_composite_primary_key() {
+    let mut db = Rusql::new();
+    let sql_str = "CREATE TABLE Foo(A INTEGER, B INTEGER, Name TEXT, PRIMARY KEY(A, B)); \
+                   INSERT INTO Foo VALUES(1, 1, \"Foo1\"), (1, 2, \"Foo2\");";
+    let key = vec![LiteralValue::Integer(1), LiteralValue::Integer(2)];
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(1, 2, \"Foo3\");", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.get_row_by_key(&key).unwrap()[2], LiteralValue::text("Foo2"));
+}
+
+#[test]
+fn test_table_constraints() {
+    let mut db = init_db_and_insert_into_table();
+    let sql_str = "CREATE TABLE Qux(Id INTEGER, FooId INTEGER, Nick TEXT, \
+                                    CONSTRAINT pk PRIMARY KEY(Id), \
+                                    UNIQUE(FooId, Nick), \
+                                    CHECK(Id < 10), \
+                                    FOREIGN KEY(FooId) REFERENCES Foo(Id)); \
+                   INSERT INTO Qux VALUES(1, 1, \"Qux1\"), (2, 1, \"Qux2\");";
+
+    rusql_exec(&mut db, sql_str, |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Qux VALUES(3, 1, \"Qux1\");", |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Qux VALUES(11, 2, \"Qux1\");", |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Qux VALUES(4, 7, \"Qux1\");", |_,_| {});
+
+    let table = db.get_table(&"Qux".to_string()).unwrap();
+    assert_eq!(table.pk, Some(0));
+    assert_eq!(table.data.len(), 2);
+}
+
+#[test]
+fn test_create_table_as_select() {
+    let mut db = init_db_and_insert_into_table();
+    let expected = vec![LiteralValue::text("Bar3"), LiteralValue::Integer(30)];
+
+    rusql_exec(&mut db, "CREATE TABLE Snapshot AS SELECT Name, Id * 10 FROM Foo WHERE Id > 2;", |_,_| {});
+
+    let table = db.get_table(&"Snapshot".to_string()).unwrap();
+    assert_eq!(table.header.len(), 2);
+    assert_eq!(table.header[0].name, "Name".to_string());
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&1).unwrap(), &expected);
+}
+
+#[test]
+fn test_temp_table() {
+    let mut db = init_db_and_insert_into_table();
+    let mut results: Vec<LiteralValue> = Vec::new();
+    let sql_str = "CREATE TEMP TABLE Scratch(Id INTEGER PRIMARY KEY, Name TEXT); \
+                   INSERT INTO Scratch SELECT * FROM Foo WHERE Id=2; \
+                   SELECT Name FROM Scratch;";
+
+    rusql_exec(&mut db, sql_str, |row, _| {
+        results.push(row[0].clone());
+    });
+
+    assert_eq!(results, vec![LiteralValue::text("Bar2")]);
+    assert_eq!(db.temp_table_names(), vec!["Scratch".to_string()]);
+    assert!(!db.map.contains_key("Scratch".as_slice()));
+
+    rusql_exec(&mut db, "DROP TABLE Scratch;", |_,_| {});
+    assert_eq!(db.temp_table_names().len(), 0);
+    assert!(db.map.contains_key("Foo".as_slice()));
+}
+
+#[test]
+fn test_drop_table_if_exists() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "DROP TABLE IF EXISTS Nope; CREATE TABLE Bar(Id INTEGER);", |_,_| {});
+    assert!(db.map.contains_key("Bar".as_slice()));
+
+    rusql_exec(&mut db, "DROP TABLE Nope; CREATE TABLE Baz(Id INTEGER);", |_,_| {});
+    assert!(!db.map.contains_key("Baz".as_slice()));
+
+    assert_eq!(db.drop_table(&"Nope".to_string()), Err(RusqlError::NoSuchTable("Nope".to_string())));
+    assert!(db.get_table(&"Nope".to_string()).is_err());
+}
+
+#[test]
+fn test_alter_table_drop_column() {
+    let mut db = init_db_and_insert_into_table();
+
+    rusql_exec(&mut db, "ALTER TABLE Foo ADD COLUMN Hodor TEXT;", |_,_| {});
+    rusql_exec(&mut db, "ALTER TABLE Foo DROP COLUMN Name;", |_,_| {});
+    rusql_exec(&mut db, "ALTER TABLE Foo DROP COLUMN Id;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert!(table.get_column_def_by_name(&"Name".to_string()).is_none());
+    assert!(table.get_column_def_by_name(&"Id".to_string()).is_some());
+    assert_eq!(table.header.len(), 2);
+    table.assert_size();
+}
+
+#[test]
+fn test_alter_table_rename_column() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT CHECK(Name != ''));
+                         CREATE TABLE Bar(Id INTEGER PRIMARY KEY, FooId INTEGER REFERENCES Foo(Id));
+                         INSERT INTO Foo VALUES(1, 'Foo');
+                         ALTER TABLE Foo RENAME COLUMN Id TO FooKey;
+                         ALTER TABLE Foo RENAME Name TO Title;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert!(table.get_column_def_by_name(&"FooKey".to_string()).is_some());
+    assert!(table.get_column_def_by_name(&"Title".to_string()).is_some());
+    assert_eq!(table.pk, Some(0));
+
+    // The CHECK and the foreign key in Bar follow the new names.
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(2, '');", |_,_| {});
+    rusql_exec(&mut db, "INSERT INTO Bar VALUES(1, 1);", |_,_| {});
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 1);
+    assert_eq!(db.get_table(&"Bar".to_string()).unwrap().data.len(), 1);
+}
+
+#[test]
+fn test_create_and_drop_index() {
+    let mut db = init_db_and_insert_into_table();
+
+    rusql_exec(&mut db, "CREATE INDEX FooName ON Foo(Name);
+                         INSERT INTO Foo VALUES(5, \"Bar1\");
+                         DELETE FROM Foo WHERE Id=2;
+                         UPDATE Foo SET Name=\"Bar5\" WHERE Id=3;", |_,_| {});
+
+    {
+        let table = db.get_table(&"Foo".to_string()).unwrap();
+        let index = table.get_index(&"FooName".to_string()).unwrap();
+
+        assert_eq!(db.indexes.get("FooName".as_slice()), Some(&"Foo".to_string()));
+        assert_eq!(index.lookup(&vec![LiteralValue::text("Bar1")]), vec![1, 5]);
+        assert_eq!(index.lookup(&vec![LiteralValue::text("Bar2")]), vec![]);
+        assert_eq!(index.lookup(&vec![LiteralValue::text("Bar3")]), vec![]);
+        assert_eq!(index.lookup(&vec![LiteralValue::text("Bar5")]), vec![3]);
+    }
+
+    rusql_exec(&mut db, "DROP INDEX FooName;", |_,_| {});
+    assert!(db.indexes.is_empty());
+    assert!(db.get_table(&"Foo".to_string()).unwrap().get_index(&"FooName".to_string()).is_none());
+}
+
+#[test]
+fn test_unique_index() {
+    let mut db = init_db_and_insert_into_table();
+
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(5, \"Bar1\");
+                         CREATE UNIQUE INDEX FooName ON Foo(Name);", |_,_| {});
+    assert!(db.indexes.is_empty());
+
+    rusql_exec(&mut db, "DELETE FROM Foo WHERE Id=5;
+                         CREATE UNIQUE INDEX FooName ON Foo(Name);
+                         INSERT INTO Foo VALUES(6, \"Bar2\");", |_,_| {});
+    assert!(db.indexes.contains_key("FooName".as_slice()));
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 4);
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    let row = vec![LiteralValue::Integer(6), LiteralValue::text("Bar2")];
+    assert_eq!(table.get_index(&"FooName".to_string()).unwrap().find_conflict(&row, &table.header, None), Some(2));
+}
+
+#[test]
+fn test_multi_column_index() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Age INTEGER);
+                         INSERT INTO Foo VALUES(1, \"Bar\", 10), (2, \"Bar\", 20), (3, \"Baz\", 10);
+                         CREATE INDEX FooNameAge ON Foo(Name, Age);", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    let bar = LiteralValue::text("Bar");
+
+    let index = table.get_index(&"FooNameAge".to_string()).unwrap();
+    assert_eq!(index.lookup_prefix(&vec![bar.clone()]), vec![1, 2]);
+    assert_eq!(index.lookup_prefix(&vec![bar.clone(), LiteralValue::Integer(20)]), vec![2]);
+
+    assert_eq!(table.lookup_by_columns(&vec![(1, bar.clone())]), Some(vec![1, 2]));
+    assert_eq!(table.lookup_by_columns(&vec![(2, LiteralValue::Integer(10)), (1, bar)]), Some(vec![1]));
+    // Age alone isn't a prefix of the index.
+    assert_eq!(table.lookup_by_columns(&vec![(2, LiteralValue::Integer(10))]), None);
+}
+
+#[test]
+fn test_partial_index() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                         INSERT INTO Foo VALUES(1, \"Bar\"), (2, NULL), (3, NULL);
+                         CREATE UNIQUE INDEX FooName ON Foo(Name) WHERE Name IS NOT NULL;
+                         INSERT INTO Foo VALUES(4, NULL);", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    let index = table.get_index(&"FooName".to_string()).unwrap();
+
+    assert_eq!(table.data.len(), 4);
+    assert_eq!(index.keys.len(), 1);
+    assert_eq!(index.lookup(&vec![LiteralValue::Null]), vec![]);
+
+    let name_is_bar = rusql::definitions::Expression::BinaryOperator((
+            rusql::definitions::BinaryOperator::Equals,
+            Box::new(rusql::definitions::Expression::ColumnName("Name".to_string())),
+            Box::new(rusql::definitions::Expression::LiteralValue(LiteralValue::text("Bar")))));
+    assert!(index.usable_for(&Some(name_is_bar)));
+    assert!(!index.usable_for(&None));
+}
+
+#[test]
+fn test_expression_index() {
+    let mut db = init_db_and_insert_into_table();
+
+    rusql_exec(&mut db, "CREATE UNIQUE INDEX FooLowerName ON Foo(lower(Name));
+                         INSERT INTO Foo VALUES(5, \"BAR1\");", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    let index = table.get_index(&"FooLowerName".to_string()).unwrap();
+
+    assert_eq!(table.data.len(), 4);
+    assert_eq!(index.column_names, vec!["lower(Name)".to_string()]);
+    assert_eq!(index.lookup(&vec![LiteralValue::text("bar1")]), vec![1]);
+}
+
+#[test]
+fn test_create_and_drop_view() {
+    let mut db = init_db_and_insert_into_table();
+
+    rusql_exec(&mut db, "CREATE VIEW SomeFoo AS SELECT Id, Name FROM Foo WHERE Id > 2;", |_,_| {});
+    assert!(db.views.contains_key("SomeFoo".as_slice()));
+
+    let results = rusql_exec(&mut db, "SELECT Name FROM SomeFoo WHERE Id < 4;", |_,_| {}).remove(0);
+    assert_eq!(results.data.len(), 1);
+    assert_eq!(results.data.values().next().unwrap()[0], LiteralValue::text("Bar3"));
+
+    // The view follows the column being renamed.
+    rusql_exec(&mut db, "ALTER TABLE Foo RENAME COLUMN Name TO Title;", |_,_| {});
+    let results = rusql_exec(&mut db, "SELECT * FROM SomeFoo;", |_,_| {}).remove(0);
+    assert_eq!(results.data.len(), 2);
+    assert_eq!(results.header[1].name, "Title".to_string());
+
+    rusql_exec(&mut db, "DROP VIEW SomeFoo;", |_,_| {});
+    assert!(db.views.is_empty());
+}
+
+#[test]
+fn test_circular_views() {
+    let mut db = init_db_and_insert_into_table();
+
+    assert_eq!(db.execute("CREATE VIEW Loop AS SELECT * FROM Loop;").err(),
+               Some(RusqlError::InvalidSchemaChange("view Loop is circularly defined".to_string())));
+
+    // Through another view, which reads one that's been replaced.
+    rusql_exec(&mut db, "CREATE VIEW First AS SELECT Id FROM Foo;
+                         CREATE VIEW Second AS SELECT Id FROM First;
+                         DROP VIEW First;", |_,_| {});
+    assert_eq!(db.execute("CREATE VIEW First AS SELECT Id FROM Second;").err(),
+               Some(RusqlError::InvalidSchemaChange("view First is circularly defined".to_string())));
+    assert!(!db.views.contains_key("First".as_slice()));
+}
+
+#[test]
+fn test_materialized_view() {
+    let mut db = init_db_and_insert_into_table();
+
+    rusql_exec(&mut db, "CREATE MATERIALIZED VIEW SomeFoo AS SELECT Id, Name FROM Foo WHERE Id > 2;
+                         INSERT INTO Foo VALUES(5, \"Bar5\");", |_,_| {});
+    assert!(db.materialized_views.contains_key("SomeFoo".as_slice()));
+    assert_eq!(db.get_table(&"SomeFoo".to_string()).unwrap().data.len(), 2);
+
+    rusql_exec(&mut db, "REFRESH MATERIALIZED VIEW SomeFoo;", |_,_| {});
+    assert_eq!(db.get_table(&"SomeFoo".to_string()).unwrap().data.len(), 3);
+
+    rusql_exec(&mut db, "DROP MATERIALIZED VIEW SomeFoo;", |_,_| {});
+    assert!(db.materialized_views.is_empty());
+    assert!(db.find_table(&"SomeFoo".to_string()).is_none());
+}
+
+#[test]
+fn test_triggers() {
+    let mut db = init_db_with_table();
+
+    rusql_exec(&mut db, "CREATE TABLE Log(Id INTEGER PRIMARY KEY, FooId INTEGER, Action TEXT);
+                         CREATE TRIGGER FooInsert AFTER INSERT ON Foo
+                         BEGIN
+                             INSERT INTO Log(FooId, Action) VALUES(NEW.Id, \"insert\");
+                         END;
+                         CREATE TRIGGER FooRename BEFORE UPDATE OF Name ON Foo WHEN OLD.Name != NEW.Name
+                         BEGIN
+                             INSERT INTO Log(FooId, Action) VALUES(OLD.Id, NEW.Name);
+                         END;
+                         CREATE TRIGGER FooDelete AFTER DELETE ON Foo FOR EACH ROW
+                         BEGIN
+                             DELETE FROM Log WHERE FooId = OLD.Id;
+                         END;
+                         INSERT INTO Foo VALUES(1, \"Bar1\"), (2, \"Bar2\");
+                         UPDATE Foo SET Name = \"Bar3\" WHERE Id = 2;
+                         UPDATE Foo SET Name = \"Bar1\" WHERE Id = 1;", |_,_| {});
+
+    {
+        let log = db.get_table(&"Log".to_string()).unwrap();
+        assert_eq!(log.data.len(), 3);
+        assert_eq!(log.data.values().last().unwrap()[2], LiteralValue::text("Bar3"));
+    }
+
+    rusql_exec(&mut db, "DELETE FROM Foo WHERE Id = 2;", |_,_| {});
+    assert_eq!(db.get_table(&"Log".to_string()).unwrap().data.len(), 1);
+
+    rusql_exec(&mut db, "DROP TRIGGER FooInsert;
+                         INSERT INTO Foo VALUES(3, \"Bar3\");", |_,_| {});
+    assert_eq!(db.get_table(&"Log".to_string()).unwrap().data.len(), 1);
+}
+
+#[test]
+fn test_triggers_follow_column_rename() {
+    let mut db = init_db_with_table();
+
+    db.execute("CREATE TABLE Log(Id INTEGER PRIMARY KEY, FooId INTEGER, Action TEXT);
+                CREATE TRIGGER FooRename BEFORE UPDATE OF Name ON Foo WHEN OLD.Name != NEW.Name
+                BEGIN
+                    INSERT INTO Log(FooId, Action) VALUES(OLD.Id, NEW.Name);
+                END;
+                ALTER TABLE Foo RENAME COLUMN Name TO Title;
+                ALTER TABLE Log RENAME COLUMN Action TO Kind;
+                INSERT INTO Foo VALUES(1, 'Bar1');
+                UPDATE Foo SET Title = 'Bar2' WHERE Id = 1;").unwrap();
+    assert_eq!(db.query_scalar::<String>("SELECT Kind FROM Log;", &[]), Ok("Bar2".to_string()));
+
+    // The saved SQL follows too, so the trigger reads back the same.
+    let trigger = db.triggers.get("Foo").unwrap()[0].clone();
+    assert!(trigger.sql.as_slice().contains("UPDATE OF Title ON Foo WHEN OLD.Title != NEW.Title"));
+    match parse(format!("{};", trigger.sql).as_slice()).unwrap().pop() {
+        Some(RusqlStatement::CreateTrigger(def)) => {
+            assert_eq!(def.when, trigger.when);
+            assert_eq!(def.body, trigger.body);
+        }
+        _ => panic!("expected a trigger"),
+    }
+}
+
+#[test]
+fn test_after_insert_trigger_rows() {
+    let mut db = Rusql::new();
+
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT UNIQUE, Twice INTEGER AS (Id * 2));
+                CREATE TABLE Log(Id INTEGER PRIMARY KEY, FooId INTEGER, Twice INTEGER);
+                CREATE TRIGGER FooInsert AFTER INSERT ON Foo
+                BEGIN
+                    INSERT INTO Log(FooId, Twice) VALUES(NEW.Id, NEW.Twice);
+                END;
+                INSERT INTO Foo(Name) VALUES('a');
+                INSERT OR IGNORE INTO Foo(Name) VALUES('a'), ('b');").unwrap();
+
+    // The trigger saw the keys and generated columns, and not the ignored row.
+    let foo_ids: Vec<isize> = db.query_map("SELECT FooId FROM Log;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(foo_ids, vec![1, 2]);
+    assert_eq!(db.query_scalar::<isize>("SELECT Twice FROM Log WHERE FooId = 2;", &[]), Ok(4));
+
+    // It follows the table it inserts into being renamed.
+    db.execute("ALTER TABLE Log RENAME TO History;
+                INSERT INTO Foo(Name) VALUES('c');").unwrap();
+    let foo_ids: Vec<isize> = db.query_map("SELECT FooId FROM History;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(foo_ids, vec![1, 2, 3]);
+    assert!(db.triggers.get("Foo").unwrap()[0].sql.as_slice().contains("INSERT INTO History(FooId, Twice)"));
+}
+
+#[test]
+fn test_instead_of_triggers() {
+    let mut db = init_db_with_table();
+
+    rusql_exec(&mut db, "CREATE VIEW FooView AS SELECT Id, Name FROM Foo;
+                         INSERT INTO FooView VALUES(1, \"Bar1\");", |_,_| {});
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 0);
+
+    rusql_exec(&mut db, "CREATE TRIGGER FooViewInsert INSTEAD OF INSERT ON FooView
+                         BEGIN
+                             INSERT INTO Foo VALUES(NEW.Id, NEW.Name);
+                         END;
+                         CREATE TRIGGER FooViewDelete INSTEAD OF DELETE ON FooView
+                         BEGIN
+                             DELETE FROM Foo WHERE Id = OLD.Id;
+                         END;
+                         INSERT INTO FooView VALUES(1, \"Bar1\"), (2, \"Bar2\");", |_,_| {});
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 2);
+
+    rusql_exec(&mut db, "DELETE FROM FooView WHERE Name = \"Bar1\";
+                         UPDATE FooView SET Name = \"Bar3\";", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 1);
+    assert_eq!(table.data.values().next().unwrap()[1], LiteralValue::text("Bar2"));
+}
+
+#[test]
+fn test_generated_columns() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Item(Id INTEGER PRIMARY KEY, Price INTEGER, Quantity INTEGER,
+                                           Total INTEGER GENERATED ALWAYS AS (Price * Quantity) STORED,
+                                           Label TEXT AS (upper(\"item\")) VIRTUAL);
+                         INSERT INTO Item VALUES(1, 3, 4);
+                         INSERT INTO Item(Id, Price, Quantity) VALUES(2, 5, 1);
+                         UPDATE Item SET Quantity = 10 WHERE Id = 2;", |_,_| {});
+
+    let table = db.get_table(&"Item".to_string()).unwrap();
+    assert_eq!(table.data.get(&1).unwrap()[3], LiteralValue::Integer(12));
+    assert_eq!(table.data.get(&1).unwrap()[4], LiteralValue::text("ITEM"));
+    assert_eq!(table.data.get(&2).unwrap()[3], LiteralValue::Integer(50));
+}
+
+#[test]
+fn test_short_rows() {
+    let mut db = init_db_with_table();
+    db.execute("CREATE TABLE Item(Id INTEGER PRIMARY KEY, Price INTEGER, Quantity INTEGER,
+                                  Total INTEGER AS (Price * Quantity));").unwrap();
+
+    // Only the generated columns can be left out.
+    assert_eq!(db.execute("INSERT INTO Item VALUES(1, 5);").err(),
+               Some(RusqlError::ConstraintViolation("table Item has 3 columns but 2 values were supplied".to_string())));
+    assert_eq!(db.execute("INSERT INTO Foo VALUES(1);").err(),
+               Some(RusqlError::ConstraintViolation("table Foo has 2 columns but 1 values were supplied".to_string())));
+    assert_eq!(db.execute("INSERT INTO Item VALUES(1, 5, 2);").map(|_| ()), Ok(()));
+}
+
+#[test]
+fn test_strict_table() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Loose(Id INTEGER PRIMARY KEY, Amount INTEGER);
+                         CREATE TABLE Strict(Id INTEGER PRIMARY KEY, Amount INTEGER, Note) STRICT;
+                         INSERT INTO Loose VALUES(1, \"ten\");
+                         INSERT INTO Strict VALUES(1, 10, \"ok\");
+                         INSERT INTO Strict VALUES(2, 20, 3);", |_,_| {});
+
+    assert!(rusql_exec(&mut db, "INSERT INTO Strict VALUES(3, \"thirty\", NULL);", |_,_| {}).is_empty());
+    assert!(rusql_exec(&mut db, "UPDATE Strict SET Amount = \"ten\" WHERE Id = 1;", |_,_| {}).is_empty());
+
+    assert_eq!(db.get_table(&"Loose".to_string()).unwrap().data.len(), 1);
+    let table = db.get_table(&"Strict".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::Integer(10));
+}
+
+#[test]
+fn test_boolean_column() {
+    let mut db = Rusql::new();
+    let mut names: Vec<LiteralValue> = Vec::new();
+
+    rusql_exec(&mut db, "CREATE TABLE User(Id INTEGER PRIMARY KEY, Name TEXT, Active BOOLEAN);
+                         INSERT INTO User VALUES(1, \"Foo\", TRUE), (2, \"Bar\", FALSE), (3, \"Baz\", 1);
+                         SELECT Name FROM User WHERE Active = TRUE;", |row, _| {
+        names.push(row[0].clone());
+    });
+
+    assert_eq!(names, vec![LiteralValue::text("Foo"), LiteralValue::text("Baz")]);
+}
+
+#[test]
+fn test_type_affinity() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Amount INTEGER, Name TEXT, Data);
+                         INSERT INTO Foo VALUES(\"1\", \" 42 \", 26, \"7\");
+                         INSERT INTO Foo VALUES(2, \"many\", \"Bar\", 7);", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.get(&1).unwrap(), &vec![LiteralValue::Integer(1), LiteralValue::Integer(42),
+                                                  LiteralValue::text("26"),
+                                                  LiteralValue::text("7")]);
+    assert_eq!(table.data.get(&2).unwrap()[1], LiteralValue::text("many"));
+}
+
+#[test]
+fn test_numeric_column() {
+    let mut db = Rusql::new();
+    let mut results: Vec<String> = Vec::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Payment(Id INTEGER PRIMARY KEY, Amount NUMERIC(10,2), Rate REAL);
+                         INSERT INTO Payment VALUES(1, 19.999, 2), (2, \"0.10\", 0.5), (3, 5, NULL);
+                         SELECT Amount + Amount, Amount * 3, 2 - Amount, Amount / 4, Amount = 0.1
+                         FROM Payment WHERE Id = 2;", |row, _| {
+        results.extend(row.iter().map(|value| format!("{}", value)));
+    });
+
+    let table = db.get_table(&"Payment".to_string()).unwrap();
+    assert_eq!(format!("{}", table.data.get(&1).unwrap()[1]), "20.00");
+    assert_eq!(format!("{}", table.data.get(&3).unwrap()[1]), "5.00");
+    assert_eq!(results, vec!["0.20".to_string(), "0.30".to_string(), "1.90".to_string(),
+                             "0.025000".to_string(), "true".to_string()]);
+
+    // Only NUMERIC columns make decimals, the rest of the numbers with a
+    // fraction are reals.
+    assert_eq!(table.data.get(&1).unwrap()[2], LiteralValue::Real(2.0));
+    assert_eq!(table.data.get(&2).unwrap()[2], LiteralValue::Real(0.5));
+    assert_eq!(db.query_scalar::<f64>("SELECT Rate * 3 FROM Payment WHERE Id = 2;", &[]), Ok(1.5));
+}
+
+#[test]
+fn test_numeric_overflow() {
+    let mut db = Rusql::new();
+
+    // Values with too many digits for a decimal are kept as reals.
+    db.execute("CREATE TABLE Payment(Id INTEGER PRIMARY KEY, Amount NUMERIC(30,2));
+                INSERT INTO Payment VALUES(1, \"99999999999999999999.5\"), (2, 9999999999.99), (3, 0.1);").unwrap();
+    let table = db.get_table(&"Payment".to_string()).unwrap().clone();
+    assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::Real(1e20));
+    assert_eq!(table.data.get(&2).unwrap()[1], LiteralValue::Decimal(Decimal::new(999999999999, 2)));
+
+    let sql = "SELECT Amount * Amount FROM Payment WHERE Id = 2;";
+    assert!(db.query_scalar::<Decimal>(sql, &[]).is_err());
+    let square = db.query_scalar::<f64>(sql, &[]).unwrap();
+    assert!(square > 9.9e19 && square < 1e20);
+    assert_eq!(db.query_scalar::<Decimal>("SELECT Amount * Amount FROM Payment WHERE Id = 3;", &[]),
+               Ok(Decimal::new(1, 2)));
+
+    assert_eq!(Decimal::from_str("99999999999999999999.5"), None);
+    assert_eq!(Decimal::new(5, 1).rescale(30), None);
+    assert_eq!(Decimal::new(std::isize::MAX, 0).checked_add(&Decimal::from_int(1)), None);
+    assert_eq!(format!("{}", Decimal::new(-5, 3)), "-0.005".to_string());
+}
+
+#[test]
+fn test_insert_or_replace() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT UNIQUE);
+                         INSERT INTO Foo VALUES(1, \"Bar1\"), (2, \"Bar2\");
+                         INSERT OR REPLACE INTO Foo VALUES(1, \"Baz1\");
+                         REPLACE INTO Foo VALUES(3, \"Bar2\");", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::text("Baz1"));
+    assert!(table.data.get(&2).is_none());
+    assert_eq!(table.data.get(&3).unwrap()[1], LiteralValue::text("Bar2"));
+}
+
+#[test]
+fn test_insert_conflict_resolution() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT UNIQUE);
+                         INSERT INTO Foo VALUES(1, \"Bar1\");
+                         INSERT OR IGNORE INTO Foo VALUES(1, \"Baz1\"), (2, \"Bar1\"), (3, \"Bar3\");", |_,_| {});
+
+    {
+        let table = db.get_table(&"Foo".to_string()).unwrap();
+        assert_eq!(table.data.len(), 2);
+        assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::text("Bar1"));
+    }
+
+    assert!(rusql_exec(&mut db, "INSERT OR FAIL INTO Foo VALUES(4, \"Bar4\"), (5, \"Bar1\"), (6, \"Bar6\");",
+                       |_,_| {}).is_empty());
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 3);
+
+    assert!(rusql_exec(&mut db, "INSERT OR ABORT INTO Foo VALUES(7, \"Bar7\"), (8, \"Bar1\");",
+                       |_,_| {}).is_empty());
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 3);
+}
+
+#[test]
+fn test_upsert() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Counter(Id INTEGER PRIMARY KEY, Name TEXT UNIQUE, Hits INTEGER);
+                         INSERT INTO Counter VALUES(1, \"Foo\", 1);
+                         INSERT INTO Counter(Name, Hits) VALUES(\"Foo\", 5), (\"Bar\", 1)
+                             ON CONFLICT(Name) DO UPDATE SET Hits = Hits + excluded.Hits;
+                         INSERT INTO Counter VALUES(1, \"Baz\", 10) ON CONFLICT DO NOTHING;", |_,_| {});
+
+    let table = db.get_table(&"Counter".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&1).unwrap(), &vec![LiteralValue::Integer(1), LiteralValue::text("Foo"),
+                                                  LiteralValue::Integer(6)]);
+    assert_eq!(table.data.values().last().unwrap()[1], LiteralValue::text("Bar"));
+}
+
+#[test]
+fn test_insert_default_values() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT DEFAULT \"Bar\", Age INTEGER);
+                         INSERT INTO Foo DEFAULT VALUES;
+                         INSERT INTO Foo DEFAULT VALUES;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&2).unwrap(), &vec![LiteralValue::Integer(2), LiteralValue::text("Bar"),
+                                                  LiteralValue::Null]);
+}
+
+#[test]
+fn test_returning() {
+    let mut db = init_db_with_table();
+    let mut ids: Vec<LiteralValue> = Vec::new();
+
+    rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\") RETURNING Id;", |row, _| {
+        ids.push(row[0].clone());
+    });
+    assert_eq!(ids, vec![LiteralValue::Integer(1), LiteralValue::Integer(2)]);
+
+    // Only the rows stored are returned.
+    let results = rusql_exec(&mut db, "INSERT OR IGNORE INTO Foo VALUES(2, \"Bar2\"), (3, \"Bar3\") RETURNING *;",
+                             |_,_| {}).remove(0);
+    assert_eq!(results.data.values().map(|row| row.clone()).collect::<Vec<TableRow>>(),
+               vec![vec![LiteralValue::Integer(3), LiteralValue::text("Bar3")]]);
+    rusql_exec(&mut db, "DELETE FROM Foo WHERE Id = 3;", |_,_| {});
+
+    let results = rusql_exec(&mut db, "UPDATE Foo SET Name = \"Baz\" WHERE Id = 2 RETURNING *;", |_,_| {}).remove(0);
+    assert_eq!(results.data.values().next().unwrap(), &vec![LiteralValue::Integer(2),
+                                                            LiteralValue::text("Baz")]);
+
+    let results = rusql_exec(&mut db, "DELETE FROM Foo WHERE Id = 1 RETURNING Name;", |_,_| {}).remove(0);
+    assert_eq!(results.data.values().next().unwrap(), &vec![LiteralValue::text("Bar1")]);
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 1);
+}
+
+#[test]
+fn test_update_with_expressions() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Counter(Id INTEGER PRIMARY KEY, Hits INTEGER, Previous INTEGER);
+                         INSERT INTO Counter VALUES(1, 5, 0), (2, 10, 0);
+                         UPDATE Counter SET Hits = Hits + 1, Previous = Hits WHERE Id = 1;", |_,_| {});
+
+    let table = db.get_table(&"Counter".to_string()).unwrap();
+    assert_eq!(table.data.get(&1).unwrap(), &vec![LiteralValue::Integer(1), LiteralValue::Integer(6),
+                                                  LiteralValue::Integer(5)]);
+    assert_eq!(table.data.get(&2).unwrap()[1], LiteralValue::Integer(10));
+}
+
+#[test]
+fn test_delete_and_update_with_limit() {
+    let mut db = Rusql::new();
+
+    rusql_exec(&mut db, "CREATE TABLE Job(Id INTEGER PRIMARY KEY, Priority INTEGER, Done INTEGER);
+                         INSERT INTO Job VALUES(1, 3, 0), (2, 1, 0), (3, 2, 0), (4, 5, 0);
+                         UPDATE Job SET Done = 1 ORDER BY Priority DESC LIMIT 2;
+                         DELETE FROM Job WHERE Done = 0 ORDER BY Priority LIMIT 1 OFFSET 1;", |_,_| {});
+
+    let table = db.get_table(&"Job".to_string()).unwrap();
+    let done: Vec<LiteralValue> = table.data.values().map(|row| row[2].clone()).collect();
+    assert_eq!(table.data.keys().collect::<Vec<usize>>(), vec![1, 2, 4]);
+    assert_eq!(done, vec![LiteralValue::Integer(1), LiteralValue::Integer(0), LiteralValue::Integer(1)]);
+}
+
+#[test]
+fn test_duplicate_primary_key() {
+    let mut db = init_db_with_table();
+
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(1, \"Bar1\"), (2, \"Bar2\");
+                         INSERT INTO Foo VALUES(3, \"Bar3\"), (1, \"Baz1\");", |_,_| {});
+    rusql_exec(&mut db, "UPDATE Foo SET Id = 2 WHERE Id = 1;", |_,_| {});
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.get(&1).unwrap()[1], LiteralValue::text("Bar1"));
+    assert_eq!(table.data.get(&2).unwrap()[1], LiteralValue::text("Bar2"));
+}
+
+#[test]
+fn test_changes() {
+    let mut db = init_db_with_table();
+
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(1, \"Bar1\"), (2, \"Bar2\"), (3, \"Bar3\");", |_,_| {});
+    assert_eq!(db.changes(), 3);
+
+    rusql_exec(&mut db, "UPDATE Foo SET Name = \"Baz\" WHERE Id > 1;", |_,_| {});
+    assert_eq!(db.changes(), 2);
+
+    rusql_exec(&mut db, "DELETE FROM Foo WHERE Id = 1;", |_,_| {});
+    assert_eq!(db.changes(), 1);
+
+    rusql_exec(&mut db, "CREATE TABLE Log(Id INTEGER PRIMARY KEY, FooId INTEGER);
+                         CREATE TRIGGER FooInsert AFTER INSERT ON Foo
+                         BEGIN
+                             INSERT INTO Log(FooId) VALUES(NEW.Id);
+                         END;
+                         INSERT INTO Foo VALUES(4, \"Bar4\");", |_,_| {});
+    assert_eq!(db.changes(), 1);
+    assert_eq!(db.total_changes(), 8);
+}
+
+#[test]
+fn test_last_insert_rowid() {
+    let mut db = init_db_with_table();
+    assert_eq!(db.last_insert_rowid(), 0);
+
+    rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\");", |_,_| {});
+    assert_eq!(db.last_insert_rowid(), 2);
+
+    let results = rusql_exec(&mut db, "INSERT INTO Foo VALUES(10, \"Bar10\");
+                                       SELECT last_insert_rowid();", |_,_| {}).remove(0);
+    assert_eq!(results.data.values().next().unwrap(), &vec![LiteralValue::Integer(10)]);
+}
+
+#[test]
+fn test_unknown_functions() {
+    let mut db = init_db_with_table();
+    rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar\");", |_,_| {});
+
+    assert_eq!(db.execute("SELECT lowr(Name) FROM Foo;").err(),
+               Some(RusqlError::NoSuchFunction("lowr".to_string())));
+    assert_eq!(db.execute("SELECT Id FROM Foo WHERE length(Name, 2) = 3;").err(),
+               Some(RusqlError::WrongArgumentCount("length".to_string())));
+    assert_eq!(db.execute("UPDATE Foo SET Name = upper();").err(),
+               Some(RusqlError::WrongArgumentCount("upper".to_string())));
+    assert_eq!(db.execute("CREATE VIEW Bar AS SELECT lowr(Name) FROM Foo;").err(),
+               Some(RusqlError::NoSuchFunction("lowr".to_string())));
+
+    assert_eq!(db.query_scalar::<String>("SELECT UPPER(Name) FROM Foo;", &[]), Ok("BAR".to_string()));
+}
+
+#[test]
+fn test_transactions() {
+    let mut db = init_db_with_table();
+
+    rusql_exec(&mut db, "BEGIN;
+                         INSERT INTO Foo(Name) VALUES(\"Bar1\");
+                         COMMIT;", |_,_| {});
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 1);
+
+    rusql_exec(&mut db, "BEGIN TRANSACTION;
+                         INSERT INTO Foo(Name) VALUES(\"Bar2\");
+                         UPDATE Foo SET Name = \"Baz\";
+                         CREATE TABLE Bar(Id INTEGER PRIMARY KEY);
+                         DROP TABLE Foo;
+                         ROLLBACK;", |_,_| {});
+    assert!(db.find_table(&"Bar".to_string()).is_none());
+    assert!(!db.in_transaction());
+
+    let results = rusql_exec(&mut db, "SELECT Name FROM Foo;", |_,_| {}).remove(0);
+    assert_eq!(results.data.len(), 1);
+    assert_eq!(results.data.values().next().unwrap(), &vec![LiteralValue::text("Bar1")]);
+
+    assert!(db.commit().is_err());
+}
+
+#[test]
+fn test_savepoints() {
+    let mut db = init_db_with_table();
+
+    rusql_exec(&mut db, "BEGIN;
+                         INSERT INTO Foo(Name) VALUES(\"Bar1\");
+                         SAVEPOINT a;
+                         INSERT INTO Foo(Name) VALUES(\"Bar2\");
+                         SAVEPOINT b;
+                         INSERT INTO Foo(Name) VALUES(\"Bar3\");
+                         ROLLBACK TO a;
+                         INSERT INTO Foo(Name) VALUES(\"Bar4\");
+                         SAVEPOINT c;
+                         DELETE FROM Foo;
+                         RELEASE c;", |_,_| {});
+    assert!(db.get_table(&"Foo".to_string()).unwrap().data.is_empty());
+
+    rusql_exec(&mut db, "ROLLBACK TO SAVEPOINT a;
+                         RELEASE a;
+                         COMMIT;", |_,_| {});
+    assert!(!db.in_transaction());
+
+    let results = rusql_exec(&mut db, "SELECT Name FROM Foo;", |_,_| {}).remove(0);
+    assert_eq!(results.data.values().map(|row| row[0].clone()).collect::<Vec<LiteralValue>>(),
+               vec![LiteralValue::text("Bar1")]);
+
+    assert_eq!(db.release(&"a".to_string()),
+               Err(RusqlError::InvalidTransaction("no such savepoint: a".to_string())));
+}
+
+#[test]
+fn test_transaction_closure() {
+    let mut db = init_db_with_table();
+
+    let result = db.transaction(|tx| {
+        rusql_exec(tx, "INSERT INTO Foo(Name) VALUES(\"Bar1\");", |_,_| {});
+        Ok(tx.last_insert_rowid())
+    });
+    assert_eq!(result, Ok(1));
+
+    let result: Result<(), RusqlError> = db.transaction(|tx| {
+        rusql_exec(tx, "INSERT INTO Foo(Name) VALUES(\"Bar2\");", |_,_| {});
+        Err(RusqlError::ConstraintViolation("failed".to_string()))
+    });
+    assert!(result.is_err());
+    assert!(!db.in_transaction());
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 1);
+}
+
+#[test]
+fn test_execute_batch() {
+    let mut db = init_db_with_table();
+
+    assert!(db.execute_batch("INSERT INTO Foo(Name) VALUES(\"Bar1\");
+                              CREATE TABLE Bar(Id INTEGER PRIMARY KEY);").is_ok());
+    assert!(!db.in_transaction());
+
+    assert_eq!(db.execute_batch("INSERT INTO Foo(Name) VALUES(\"Bar2\");
+                                 DROP TABLE Bar;
+                                 INSERT INTO Baz VALUES(1);"),
+               Err(RusqlError::NoSuchTable("Baz".to_string())));
+    assert!(!db.in_transaction());
+    assert!(db.find_table(&"Bar".to_string()).is_some());
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 1);
+
+    // A script may have its own transaction.
+    assert!(db.execute_batch("BEGIN;
+                              INSERT INTO Foo(Name) VALUES(\"Bar2\");
+                              COMMIT;").is_ok());
+    assert!(db.execute_batch("BEGIN;
+                              INSERT INTO Foo(Name) VALUES(\"Bar3\");
+                              INSERT INTO Baz VALUES(1);
+                              COMMIT;").is_err());
+    assert!(!db.in_transaction());
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 2);
+
+    // Failing to write the file at the end is an error too.
+    let dir = std::os::tmpdir().join("rusql_test_execute_batch");
+    std::io::fs::mkdir(&dir, std::io::USER_RWX).unwrap();
+    let mut db = Rusql::open_file(&dir.join("test.db")).unwrap();
+    std::io::fs::rmdir_recursive(&dir).unwrap();
+    assert!(db.execute_batch("CREATE TABLE Foo(Id INTEGER PRIMARY KEY);").is_err());
+}
+
+#[test]
+fn test_execute() {
+    let mut db = Rusql::new();
+
+    let results = db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                              INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\");
+                              SELECT Name FROM Foo WHERE Id = 2;").unwrap();
+    assert_eq!(results.len(), 3);
+
+    match (&results[0], &results[1], &results[2]) {
+        (&QueryResult::Changes(0), &QueryResult::Changes(2), &QueryResult::Rows(ref table)) => {
+            assert_eq!(table.data.values().next().unwrap(), &vec![LiteralValue::text("Bar2")]);
+        }
+        _ => panic!("unexpected results"),
+    }
+
+    assert!(match db.execute("SELECT * FROM Bar;") {
+        Err(RusqlError::NoSuchTable(ref name)) => name.as_slice() == "Bar",
+        _ => false,
+    });
+    assert!(match db.execute("SELEC * FROM Foo;") {
+        Err(RusqlError::ParseError(_)) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn test_errors() {
+    let mut db = init_db_with_table();
+
+    let errors: Vec<(&str, RusqlError)> = vec![
+        ("SELECT Nope FROM Foo;", RusqlError::NoSuchColumn("Nope".to_string())),
+        ("SELECT Name FROM Foo ORDER BY Id;", RusqlError::NoSuchColumn("Id".to_string())),
+        ("DELETE FROM Foo WHERE Nope = 1;", RusqlError::NoSuchColumn("Nope".to_string())),
+        ("INSERT INTO Foo(Nope) VALUES(1);", RusqlError::NoSuchColumn("Foo.Nope".to_string())),
+        ("INSERT INTO Foo(Id, Name) VALUES(1);",
+         RusqlError::ConstraintViolation("1 values for 2 columns".to_string())),
+        ("INSERT INTO Foo VALUES(1, \"Bar\", 3);",
+         RusqlError::ConstraintViolation("table Foo has 2 columns but 3 values were supplied".to_string())),
+        ("INSERT INTO Foo VALUES(\"Bar\", \"Bar\");",
+         RusqlError::TypeMismatch("datatype mismatch: Foo.Id must be an integer".to_string())),
+        ("CREATE TABLE Bar(Id INTEGER, UNIQUE(Nope));", RusqlError::NoSuchColumn("Bar.Nope".to_string())),
+        ("CREATE INDEX Idx ON Foo(Nope);", RusqlError::NoSuchColumn("Foo.Nope".to_string())),
+    ];
+
+    for (sql, error) in errors.into_iter() {
+        assert_eq!(db.execute(sql).err(), Some(error));
+    }
+}
+
+#[test]
+fn test_prepared_statements() {
+    let mut db = init_db_with_table();
+
+    {
+        let mut stmt = db.prepare("INSERT INTO Foo VALUES(?, ?);").unwrap();
+        assert_eq!(stmt.parameter_count(), 2);
+
+        for i in range(1, 4) {
+            stmt.bind(1, LiteralValue::Integer(i)).unwrap();
+            stmt.bind(2, LiteralValue::text(format!("Bar{}", i).as_slice())).unwrap();
+            assert_eq!(stmt.execute(), Ok(1));
+        }
+
+        assert_eq!(stmt.bind(3, LiteralValue::Null), Err(RusqlError::NoSuchParameter("?3".to_string())));
+    }
+
+    let mut stmt = db.prepare("SELECT Name FROM Foo WHERE Id >= :min AND Id < ?3 + 1;").unwrap();
+    assert_eq!(stmt.parameter_count(), 3);
+    assert_eq!(stmt.parameter_index(":min"), Some(1));
+
+    stmt.bind_named(":min", LiteralValue::Integer(2)).unwrap();
+    stmt.bind(3, LiteralValue::Integer(2)).unwrap();
+
+    let results = stmt.query().unwrap();
+    assert_eq!(results.data.len(), 1);
+    assert_eq!(results.data.values().next().unwrap(), &vec![LiteralValue::text("Bar2")]);
+}
+
+#[test]
+fn test_streaming_query() {
+    let mut db = init_db_with_table();
+    rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\"), (\"Bar3\"), (\"Bar4\");
+                         CREATE TABLE Baz(FooId INTEGER, Value INTEGER);
+                         INSERT INTO Baz VALUES(1, 10), (2, 20), (2, 21), (4, 40);", |_,_| {});
+
+    let rows: Vec<Vec<LiteralValue>> = db.query("SELECT Name, Value FROM Foo JOIN Baz ON Id = FooId
+                                                 WHERE Value > 10 LIMIT 2 OFFSET 1;").unwrap().collect();
+    assert_eq!(rows, vec![vec![LiteralValue::text("Bar2"), LiteralValue::Integer(21)],
+                          vec![LiteralValue::text("Bar4"), LiteralValue::Integer(40)]]);
+
+    // The join stops once LIMIT has its rows, whether it's iterated over or not.
+    let names: Vec<String> = db.query_map("SELECT Name FROM Foo, Baz WHERE Value < 40 LIMIT 4 OFFSET 1;", &[],
+                                          |row| row.get(0)).unwrap();
+    assert_eq!(names, vec!["Bar1".to_string(), "Bar1".to_string(), "Bar2".to_string(), "Bar2".to_string()]);
+
+    {
+        let mut rows = db.query("SELECT * FROM Foo;").unwrap();
+        assert_eq!(rows.header().len(), 2);
+        assert_eq!(rows.next(), Some(vec![LiteralValue::Integer(1), LiteralValue::text("Bar1")]));
+    }
+
+    assert!(db.query("SELECT Nope FROM Foo;").is_err());
+}
+
+#[test]
+fn test_typed_row_accessors() {
+    let mut db = init_db_with_table();
+    rusql_exec(&mut db, "INSERT INTO Foo VALUES(1, \"Bar1\"), (2, NULL);", |_,_| {});
+
+    let table = rusql_exec(&mut db, "SELECT * FROM Foo;", |_,_| {}).remove(0);
+    let rows = table.rows();
+
+    assert_eq!(rows[0].get::<isize>(0), Ok(1));
+    assert_eq!(rows[0].get::<f64>(0), Ok(1.0));
+    assert_eq!(rows[0].get_by_name::<String>("Name"), Ok("Bar1".to_string()));
+    assert_eq!(rows[1].get_by_name::<Option<String>>("Name"), Ok(None));
+
+    assert_eq!(rows[0].get::<isize>(1),
+               Err(RusqlError::TypeMismatch("cannot read TEXT value as isize".to_string())));
+    assert_eq!(rows[1].get::<String>(1),
+               Err(RusqlError::TypeMismatch("cannot read NULL value as String".to_string())));
+    assert_eq!(rows[0].get::<isize>(2), Err(RusqlError::NoSuchColumn("2".to_string())));
+    assert_eq!(rows[0].get_by_name::<isize>("Nope"), Err(RusqlError::NoSuchColumn("Nope".to_string())));
+}
+
+#[test]
+fn test_query_as() {
+    let mut db = Rusql::new();
+    rusql_exec(&mut db, "CREATE TABLE People(id INTEGER PRIMARY KEY, name TEXT, age INTEGER);
+                         INSERT INTO People(name, age) VALUES(\"Ann\", 31), (\"Bob\", NULL);", |_,_| {});
+
+    let people: Vec<Person> = db.query_as("SELECT name, age FROM People;").unwrap();
+    assert_eq!(people.len(), 2);
+    assert_eq!(people[0].name.as_slice(), "Ann");
+    assert_eq!(people[0].age, Some(31));
+    assert_eq!(people[1].age, None);
+
+    assert!(db.query_as::<Person>("SELECT name FROM People;").is_err());
+}
+
+#[test]
+fn test_insert_maps() {
+    use std::collections::BTreeMap;
+
+    let mut db = init_db_with_table();
+
+    let mut record = BTreeMap::new();
+    record.insert("Name".to_string(), LiteralValue::text("Bar1"));
+    assert_eq!(db.insert_maps("Foo", vec![record.clone(), record.clone()]), Ok(2));
+
+    let mut bad_record = BTreeMap::new();
+    bad_record.insert("Nope".to_string(), LiteralValue::Integer(1));
+    assert!(db.insert_maps("Foo", vec![record, bad_record]).is_err());
+
+    assert_eq!(db.get_table(&"Foo".to_string()).unwrap().data.len(), 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_insert_records() {
+    use std::collections::BTreeMap;
+
+    let mut db = init_db_with_table();
+
+    let mut record = BTreeMap::new();
+    record.insert("Name", "Bar1");
+    assert_eq!(db.insert_records("Foo", vec![record.clone(), record]), Ok(2));
+
+    let mut record = BTreeMap::new();
+    record.insert("Name", Some(LiteralValue::text("Bar2")));
+    assert_eq!(db.insert_records("Foo", vec![record]), Ok(1));
+
+    assert!(db.insert_records("Foo", vec![1is, 2]).is_err());
+    assert!(db.insert_records("Foo", vec![vec![("Name", "Bar3")]]).is_err());
+
+    assert_eq!(db.query_map("SELECT Name FROM Foo;", &[], |row| row.get(0)).unwrap(),
+               vec!["Bar1".to_string(), "Bar1".to_string(), "Bar2".to_string()]);
+}
+
+#[test]
+fn test_query_map() {
+    let mut db = init_db_with_table();
+    rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\"), (\"Bar3\");", |_,_| {});
+
+    let names = db.query_map("SELECT Name FROM Foo WHERE Id > ?;", &[LiteralValue::Integer(1)],
+                             |row| row.get::<String>(0)).unwrap();
+    assert_eq!(names, vec!["Bar2".to_string(), "Bar3".to_string()]);
+
+    let id = db.query_one("SELECT Id, Name FROM Foo WHERE Name = :name;", &[LiteralValue::text("Bar2")],
+                          |row| row.get_by_name::<isize>("Id"));
+    assert_eq!(id, Ok(2));
+
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = ?1;", &[LiteralValue::Integer(3)]),
+               Ok("Bar3".to_string()));
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 4;", &[]), Err(RusqlError::NoRows));
+}
+
+#[test]
+fn test_multiple_result_sets() {
+    let mut db = init_db_with_table();
+    let mut rows_seen = 0us;
+
+    let results = rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\");
+                                       SELECT Name FROM Foo WHERE Id = 1;
+                                       UPDATE Foo SET Name = \"Baz\" WHERE Id = 2;
+                                       SELECT Name FROM Foo WHERE Id = 2;", |_,_| rows_seen += 1);
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(rows_seen, 2);
+    assert_eq!(results[0].data.values().next().unwrap(), &vec![LiteralValue::text("Bar1")]);
+    assert_eq!(results[1].data.values().next().unwrap(), &vec![LiteralValue::text("Baz")]);
+}
+
+#[test]
+fn test_query_builder() {
+    let mut db = init_db_with_table();
+    rusql_exec(&mut db, "INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\"), (\"Bar3\");
+                         CREATE TABLE Baz(Id INTEGER PRIMARY KEY, FooId INTEGER, Label TEXT);
+                         INSERT INTO Baz VALUES(1, 2, \"Two\"), (2, 3, \"Three\");", |_,_| {});
+
+    let table = db.select(Select::from("Foo").column("Name").filter(col("Id").eq(1))).unwrap();
+    assert_eq!(table.data.values().collect::<Vec<_>>(), vec![&vec![LiteralValue::text("Bar1")]]);
+
+    let select = Select::from("Foo")
+                     .column("Foo.Name")
+                     .column("Baz.Label")
+                     .join("Baz", col("Foo.Id").eq(col("Baz.FooId")))
+                     .filter(col("Foo.Id").gt(1))
+                     .filter(col("Baz.Label").ne(val("Three")));
+    let table = db.select(select).unwrap();
+    assert_eq!(table.data.values().collect::<Vec<_>>(),
+               vec![&vec![LiteralValue::text("Bar2"), LiteralValue::text("Two")]]);
+
+    let table = db.select(Select::from("Foo").order_by(col("Id"), Order::Descending).limit(2)).unwrap();
+    assert_eq!(table.data.len(), 2);
+    assert_eq!(table.data.values().next().unwrap()[0], LiteralValue::Integer(3));
+}
+
+#[test]
+fn test_parse() {
+    let stmts = parse("SELECT Name FROM Foo WHERE Id = 1; DELETE FROM Foo;").unwrap();
+    assert_eq!(stmts.len(), 2);
+
+    match stmts[0] {
+        RusqlStatement::Select(ref select_def) => {
+            assert_eq!(select_def.table_names(), vec!["Foo".to_string()]);
+            assert_eq!(select_def.where_expr, Some(col("Id").eq(1)));
+        }
+        _ => panic!("expected a SELECT"),
+    }
+    match stmts[1] {
+        RusqlStatement::Delete(ref delete_def) => assert_eq!(delete_def.name, "Foo".to_string()),
+        _ => panic!("expected a DELETE"),
+    }
+
+    assert!(match parse("SELEKT 1;") { Err(RusqlError::ParseError(_)) => true, _ => false });
+}
+
+struct Tenant {
+    prefix: &'static str,
+}
+
+impl Visitor for Tenant {
+    fn visit_select(&mut self, select_def: &mut SelectDef) {
+        walk_select(self, select_def);
+        let visible = col("Hidden").eq(0);
+        select_def.where_expr = match select_def.where_expr.take() {
+            Some(expr) => Some(expr.and(visible)),
+            None => Some(visible),
+        };
+    }
+
+    fn visit_table_name(&mut self, name: &mut String) {
+        *name = format!("{}{}", self.prefix, name);
+    }
+}
+
+#[test]
+fn test_visitor() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE a_Foo(Id INTEGER PRIMARY KEY, Name TEXT, Hidden INTEGER);
+                INSERT INTO a_Foo VALUES(1, \"Bar1\", 0), (2, \"Bar2\", 1), (3, \"Bar3\", 0);").unwrap();
+
+    let mut stmts = parse("SELECT Foo.Name FROM Foo WHERE Id > 1;").unwrap();
+    walk_statements(&mut Tenant { prefix: "a_" }, &mut stmts);
+
+    match stmts[0] {
+        RusqlStatement::Select(ref select_def) => {
+            assert_eq!(select_def.table_names(), vec!["a_Foo".to_string()]);
+            assert_eq!(select_def.result_column, ResultColumn::Expressions(vec![col("a_Foo.Name")]));
+        }
+        _ => panic!("expected a SELECT"),
+    }
+
+    match db.execute_statements(stmts).unwrap().pop() {
+        Some(QueryResult::Rows(table)) => {
+            assert_eq!(table.data.values().collect::<Vec<_>>(), vec![&vec![LiteralValue::text("Bar3")]]);
+        }
+        _ => panic!("expected rows"),
+    }
+}
+
+#[test]
+fn test_introspection() {
+    let mut db = init_db_with_table();
+    db.execute("INSERT INTO Foo(Name) VALUES(\"Bar1\"), (\"Bar2\");
+                CREATE TEMP TABLE Scratch(Id INTEGER PRIMARY KEY);
+                CREATE VIEW FooView AS SELECT Name FROM Foo;
+                CREATE MATERIALIZED VIEW FooNames AS SELECT Name FROM Foo;").unwrap();
+
+    assert_eq!(db.table_names(), vec!["Foo".to_string(), "Scratch".to_string()]);
+    assert_eq!(db.view_names(), vec!["FooNames".to_string(), "FooView".to_string()]);
+    assert_eq!(db.table_schema("Foo").unwrap().len(), 2);
+    assert_eq!(db.column_names("Foo"), Ok(vec!["Id".to_string(), "Name".to_string()]));
+    assert_eq!(db.row_count("Foo"), Ok(2));
+    assert_eq!(db.row_count("Scratch"), Ok(0));
+    assert_eq!(db.row_count("Nope"), Err(RusqlError::NoSuchTable("Nope".to_string())));
+}
+
+#[test]
+fn test_master_table() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT DEFAULT 'x', UNIQUE(Name)) STRICT;
+                CREATE TABLE Bar(Id INTEGER PRIMARY KEY, FooId INTEGER REFERENCES Foo(Id) ON DELETE CASCADE);
+                CREATE INDEX BarFoo ON Bar(FooId) WHERE FooId > 1;
+                CREATE VIEW FooNames AS SELECT Name FROM Foo WHERE Id > 1 ORDER BY Name DESC;").unwrap();
+
+    let rows: Vec<(String, String, String)> = db.query_map("SELECT type, name, sql FROM rusql_master;", &[],
+                                                            |row| Ok((try!(row.get(0)), try!(row.get(1)), try!(row.get(2)))))
+                                                 .unwrap();
+    assert_eq!(rows.iter().map(|&(ref t, ref name, _)| format!("{} {}", t, name)).collect::<Vec<String>>(),
+               vec!["table Bar".to_string(), "index BarFoo".to_string(), "table Foo".to_string(),
+                    "view FooNames".to_string()]);
+    assert_eq!(rows[1].2, "CREATE INDEX BarFoo ON Bar(FooId) WHERE FooId > 1".to_string());
+    assert_eq!(rows[3].2, "CREATE VIEW FooNames AS SELECT Name FROM Foo WHERE Id > 1 ORDER BY Name DESC".to_string());
+
+    // The SQL recreates the same schema.
+    let mut copy = Rusql::new();
+    for &(_, _, ref sql) in rows.iter() {
+        copy.execute(format!("{};", sql).as_slice()).unwrap();
+    }
+    assert_eq!(copy.query_scalar::<String>("SELECT sql FROM rusql_master WHERE name = 'Foo';", &[]),
+               Ok(rows[2].2.clone()));
+}
+
+fn user_version(_: &mut Rusql, _: Option<String>) -> rusql::RusqlResult<Option<rusql::table::Table>> {
+    Ok(Some(rusql::table::Table::from_rows(&["user_version"], vec![vec![LiteralValue::Integer(7)]])))
+}
+
+#[test]
+fn test_pragmas() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT DEFAULT 'x', Data);
+                INSERT INTO Foo VALUES(1, \"Bar\", NULL), (2, \"bar\", NULL), (3, \"Baz\", NULL);").unwrap();
+
+    let info: Vec<(String, String, Option<String>, isize)> =
+        db.query_map("PRAGMA table_info(Foo);", &[], |row| {
+            Ok((try!(row.get_by_name("name")), try!(row.get_by_name("type")),
+                try!(row.get_by_name("dflt_value")), try!(row.get_by_name("pk"))))
+        }).unwrap();
+    assert_eq!(info, vec![("Id".to_string(), "INTEGER".to_string(), None, 1),
+                          ("Name".to_string(), "TEXT".to_string(), Some("'x'".to_string()), 0),
+                          ("Data".to_string(), "".to_string(), None, 0)]);
+
+    assert_eq!(db.query_scalar::<bool>("PRAGMA foreign_keys;", &[]), Ok(true));
+    db.execute("PRAGMA foreign_keys = OFF;").unwrap();
+    assert_eq!(db.query_scalar::<bool>("PRAGMA foreign_keys;", &[]), Ok(false));
+
+    let ids = |db: &mut Rusql, sql: &str| db.query_map(sql, &[], |row| row.get::<isize>(0)).unwrap();
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Name LIKE 'BA_';"), vec![1, 2, 3]);
+    db.execute("PRAGMA case_sensitive_like = ON;").unwrap();
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Name LIKE 'Ba%';"), vec![1, 3]);
+
+    db.register_pragma("user_version", user_version);
+    assert_eq!(db.query_scalar::<isize>("PRAGMA user_version;", &[]), Ok(7));
+    assert!(db.execute("PRAGMA no_such_pragma;").is_ok());
+}
+
+#[test]
+fn test_explain() {
+    let mut db = init_db_with_table();
+    db.execute("CREATE TABLE Baz(Id INTEGER PRIMARY KEY, FooId INTEGER);").unwrap();
+
+    let steps: Vec<(String, String)> =
+        db.query_map("EXPLAIN SELECT Foo.Name FROM Foo JOIN Baz ON Foo.Id = Baz.FooId
+                      WHERE Foo.Id > 1 ORDER BY Foo.Name DESC LIMIT 5;", &[],
+                     |row| Ok((try!(row.get(1)), try!(row.get(2))))).unwrap();
+    let expected = vec![("SCAN", "Foo"), ("JOIN", "Baz ON Foo.Id = Baz.FooId"), ("FILTER", "Foo.Id > 1"),
+                        ("SORT", "Foo.Name DESC"), ("LIMIT", "5"), ("RESULT", "Foo.Name")];
+    assert_eq!(steps, expected.iter().map(|&(a, b)| (a.to_string(), b.to_string())).collect::<Vec<_>>());
+
+    let steps: Vec<String> = db.query_map("EXPLAIN UPDATE Foo SET Name = 'x' WHERE Id = 1;", &[],
+                                          |row| row.get(1)).unwrap();
+    assert_eq!(steps, vec!["SCAN".to_string(), "FILTER".to_string(), "SET".to_string(), "UPDATE".to_string()]);
+
+    // Nothing was run.
+    db.execute("EXPLAIN INSERT INTO Foo(Name) VALUES('Bar1'), ('Bar2');").unwrap();
+    assert_eq!(db.row_count("Foo"), Ok(0));
+}
+
+#[test]
+fn test_explain_query_plan() {
+    let mut db = init_db_with_table();
+    db.execute("CREATE TABLE Baz(Id INTEGER PRIMARY KEY, FooId INTEGER);").unwrap();
+
+    let plan = |db: &mut Rusql, sql: &str| db.query_map(sql, &[], |row| row.get::<String>(1)).unwrap();
+    assert_eq!(plan(&mut db, "EXPLAIN QUERY PLAN SELECT * FROM Foo JOIN Baz ON Foo.Id = Baz.FooId ORDER BY Foo.Name;"),
+               vec!["SCAN Foo".to_string(), "SCAN Baz".to_string(), "HASH JOIN ON Foo.Id = Baz.FooId".to_string(),
+                    "USE TEMP B-TREE FOR ORDER BY".to_string()]);
+    assert_eq!(plan(&mut db, "EXPLAIN QUERY PLAN DELETE FROM Baz WHERE FooId = 1;"), vec!["SCAN Baz WHERE FooId = 1".to_string()]);
+    assert!(plan(&mut db, "EXPLAIN QUERY PLAN INSERT INTO Foo(Name) VALUES('Bar');").is_empty());
+}
+
+#[test]
+fn test_predicate_pushdown() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE TABLE Baz(Id INTEGER PRIMARY KEY, FooId INTEGER, Amount INTEGER);
+                INSERT INTO Baz(FooId, Amount) VALUES(1, 10), (2, 20), (2, 30), (3, 40), (4, 50);").unwrap();
+
+    let sql = "SELECT Baz.Id FROM Foo, Baz WHERE Foo.Id > 1 AND Baz.Amount < 50 AND Foo.Id = Baz.FooId AND 1 = 1;";
+    let ids: Vec<isize> = db.query_map(sql, &[], |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![2, 3, 4]);
+
+    let plan: Vec<String> = db.query_map(format!("EXPLAIN QUERY PLAN {}", sql).as_slice(), &[],
+                                         |row| row.get(1)).unwrap();
+    assert_eq!(plan, vec!["SEARCH Foo USING INTEGER PRIMARY KEY (Foo.Id > 1)".to_string(),
+                          "SCAN Baz WHERE Baz.Amount < 50".to_string(),
+                          "HASH JOIN ON Foo.Id = Baz.FooId".to_string(), "FILTER 1 = 1".to_string()]);
+
+    // A NATURAL JOIN's equalities are applied as the tables are joined too.
+    let names: Vec<String> = db.query_map("SELECT Name FROM Foo NATURAL JOIN Baz WHERE Amount > 20;", &[],
+                                          |row| row.get(0)).unwrap();
+    assert_eq!(names, vec!["Bar3".to_string(), "Bar4".to_string()]);
+}
+
+#[test]
+fn test_hash_join() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE TABLE Score(Id INTEGER PRIMARY KEY, Points REAL, FooId INTEGER);
+                INSERT INTO Score(Points, FooId) VALUES(1.0, 3), (2.5, 3), (4.0, NULL), (3.0, 1);").unwrap();
+
+    let sql = "SELECT Foo.Name, Score.Id FROM Foo JOIN Score ON Score.Points = Foo.Id AND Score.Id > 1;";
+    let rows: Vec<(String, isize)> = db.query_map(sql, &[], |row| Ok((try!(row.get(0)), try!(row.get(1))))).unwrap();
+    assert_eq!(rows, vec![("Bar3".to_string(), 4), ("Bar4".to_string(), 3)]);
+
+    let plan: Vec<String> = db.query_map(format!("EXPLAIN QUERY PLAN {}", sql).as_slice(), &[],
+                                         |row| row.get(1)).unwrap();
+    assert_eq!(plan, vec!["SCAN Foo".to_string(), "SEARCH Score USING INTEGER PRIMARY KEY (Score.Id > 1)".to_string(),
+                          "HASH JOIN ON Foo.Id = Score.Points".to_string()]);
+
+    let names: Vec<String> = db.query_map("SELECT Foo.Name FROM Score, Foo WHERE Foo.Id = Score.FooId;", &[],
+                                          |row| row.get(0)).unwrap();
+    assert_eq!(names, vec!["Bar3".to_string(), "Bar3".to_string(), "Bar1".to_string()]);
+}
+
+#[test]
+fn test_merge_join() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE TABLE Bar(Id INTEGER PRIMARY KEY, Amount INTEGER);
+                INSERT INTO Bar VALUES(2, 20), (3, 30), (5, 50);").unwrap();
+
+    let sql = "SELECT Foo.Name, Bar.Amount FROM Foo JOIN Bar ON Bar.Id = Foo.Id;";
+    let rows: Vec<(String, isize)> = db.query_map(sql, &[], |row| Ok((try!(row.get(0)), try!(row.get(1))))).unwrap();
+    assert_eq!(rows, vec![("Bar2".to_string(), 20), ("Bar3".to_string(), 30)]);
+
+    let plan: Vec<String> = db.query_map(format!("EXPLAIN QUERY PLAN {}", sql).as_slice(), &[],
+                                         |row| row.get(1)).unwrap();
+    assert_eq!(plan, vec!["SCAN Foo".to_string(), "SCAN Bar".to_string(), "MERGE JOIN ON Foo.Id = Bar.Id".to_string()]);
+
+    // With a table in between, the same keys of Foo are looked up again for
+    // each of its rows.
+    db.execute("CREATE TABLE Qux(Id INTEGER PRIMARY KEY); INSERT INTO Qux VALUES(1), (2);").unwrap();
+    let amounts: Vec<isize> = db.query_map("SELECT Bar.Amount FROM Foo, Qux, Bar WHERE Foo.Id = Bar.Id;", &[],
+                                           |row| row.get(0)).unwrap();
+    assert_eq!(amounts, vec![20, 20, 30, 30]);
+}
+
+#[test]
+fn test_index_lookups() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE INDEX FooName ON Foo(Name);").unwrap();
+
+    let plan = |db: &mut Rusql, sql: &str| db.query_map(sql, &[], |row| row.get::<String>(1)).unwrap();
+    let ids = |db: &mut Rusql, sql: &str| db.query_map(sql, &[], |row| row.get::<isize>(0)).unwrap();
+
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Id = 3;"), vec![3]);
+    assert_eq!(plan(&mut db, "EXPLAIN QUERY PLAN SELECT Id FROM Foo WHERE Id = 3;"),
+               vec!["SEARCH Foo USING INTEGER PRIMARY KEY (Id = 3)".to_string()]);
+
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE 2 <= Id AND Id < 4;"), vec![2, 3]);
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Id > 2.5;"), vec![3, 4]);
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Id <= 2.5 AND Id > -1;"), vec![1, 2]);
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Id > 10;"), Vec::<isize>::new());
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Id < 0;"), Vec::<isize>::new());
+
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Name = 'Bar2' AND Id > 0;"), vec![2]);
+    assert_eq!(plan(&mut db, "EXPLAIN QUERY PLAN SELECT Id FROM Foo WHERE Name = 'Bar2' AND Id > 0;"),
+               vec!["SEARCH Foo USING INDEX FooName (Name = 'Bar2') WHERE Id > 0".to_string()]);
+
+    db.execute("UPDATE Foo SET Name = 'Baz' WHERE Name = 'Bar4'; DELETE FROM Foo WHERE Id = 1;").unwrap();
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo WHERE Name = 'Baz';"), vec![4]);
+    assert_eq!(ids(&mut db, "SELECT Id FROM Foo;"), vec![2, 3, 4]);
+
+    db.execute("CREATE TABLE Mixed(Id INTEGER PRIMARY KEY, Amount);
+                CREATE INDEX MixedAmount ON Mixed(Amount);
+                INSERT INTO Mixed VALUES(1, 1), (2, 1.0), (3, 0.5), (4, 2), (5, 1), (6, '1');").unwrap();
+    assert_eq!(ids(&mut db, "SELECT Id FROM Mixed WHERE Amount = 1;"), vec![1, 2, 5]);
+    assert_eq!(ids(&mut db, "SELECT Id FROM Mixed WHERE Amount = 1.0;"), vec![1, 2, 5]);
+}
+
+#[test]
+fn test_analyze() {
+    let mut db = rusql::Rusql::new();
+    let values: Vec<String> = range(1, 21).map(|i| format!("({}, {})", i, i % 2)).collect();
+    db.execute(format!("CREATE TABLE Big(Id INTEGER PRIMARY KEY, Kind INTEGER);
+                        CREATE INDEX BigKind ON Big(Kind);
+                        INSERT INTO Big VALUES{};
+                        CREATE TABLE Small(Id INTEGER PRIMARY KEY, BigId INTEGER);
+                        INSERT INTO Small VALUES(1, 4), (2, 7);", values.connect(", ")).as_slice()).unwrap();
+
+    let plan = |db: &mut Rusql, sql: &str| {
+        db.query_map(format!("EXPLAIN QUERY PLAN {}", sql).as_slice(), &[], |row| row.get::<String>(1)).unwrap()
+    };
+    let join = "SELECT Big.Id, Small.Id FROM Big, Small WHERE Big.Id = Small.BigId;";
+    let search = "SELECT Id FROM Big WHERE Kind = 1 AND Id < 3;";
+
+    assert_eq!(plan(&mut db, join), vec!["SCAN Big".to_string(), "SCAN Small".to_string(),
+                                         "HASH JOIN ON Big.Id = Small.BigId".to_string()]);
+    assert_eq!(plan(&mut db, search), vec!["SEARCH Big USING INDEX BigKind (Kind = 1) WHERE Id < 3".to_string()]);
+
+    db.execute("ANALYZE;").unwrap();
+
+    let stats = db.stats.get(&"Big".to_string()).unwrap();
+    assert_eq!(stats.row_count, 20);
+    let kind = stats.columns.get(&"Kind".to_string()).unwrap();
+    assert_eq!((kind.distinct, kind.min.clone(), kind.max.clone()),
+               (2, LiteralValue::Integer(0), LiteralValue::Integer(1)));
+
+    // The smaller table is joined first, and the key narrows the rows down
+    // more than the index does.
+    assert_eq!(plan(&mut db, join), vec!["SCAN Small".to_string(), "SCAN Big".to_string(),
+                                         "HASH JOIN ON Small.BigId = Big.Id".to_string()]);
+    assert_eq!(plan(&mut db, search),
+               vec!["SEARCH Big USING INTEGER PRIMARY KEY (Id < 3) WHERE Kind = 1".to_string()]);
+
+    // The columns stay in the order of the FROM clause.
+    let rows: Vec<(isize, isize)> = db.query_map(join, &[], |row| Ok((try!(row.get(0)), try!(row.get(1))))).unwrap();
+    assert_eq!(rows, vec![(4, 1), (7, 2)]);
+    let ids: Vec<isize> = db.query_map(search, &[], |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![1]);
+
+    db.execute("DROP TABLE Small;").unwrap();
+    assert!(db.stats.get(&"Small".to_string()).is_none());
+    assert!(db.execute("ANALYZE Small;").is_err());
+}
+
+#[test]
+fn test_text_interning() {
+    let mut db = init_db_with_table();
+    db.execute("INSERT INTO Foo(Name) VALUES('Red'), ('Blue'), ('Red'); UPDATE Foo SET Name = 'Red' WHERE Id = 2;")
+      .unwrap();
+
+    {
+        let table = db.get_table(&"Foo".to_string()).unwrap();
+        assert_eq!(table.strings.len(), 2);
+
+        match (&table.data.get(&1).unwrap()[1], &table.data.get(&2).unwrap()[1]) {
+            (&LiteralValue::Text(ref a), &LiteralValue::Text(ref b)) => {
+                assert_eq!(&**a as *const String, &**b as *const String);
+            }
+            values => panic!("expected text, got {:?}", values),
+        }
+    }
+
+    let ids: Vec<isize> = db.query_map("SELECT Id FROM Foo WHERE Name = 'Red';", &[], |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_columnar_table() {
+    let mut db = rusql::Rusql::new();
+    db.execute("CREATE TABLE Sales(Id INTEGER PRIMARY KEY, Region TEXT, Amount INTEGER) COLUMNAR;
+                INSERT INTO Sales(Region, Amount) VALUES('East', 10), ('West', 20), ('East', 30), ('East', NULL);")
+      .unwrap();
+
+    let ids = |db: &mut Rusql, sql: &str| db.query_map(sql, &[], |row| row.get::<isize>(0)).unwrap();
+    let sql = "SELECT Id FROM Sales WHERE Region = 'East' AND Amount >= 20;";
+
+    assert_eq!(ids(&mut db, sql), vec![3]);
+    let plan: Vec<String> = db.query_map(format!("EXPLAIN QUERY PLAN {}", sql).as_slice(), &[],
+                                         |row| row.get(1)).unwrap();
+    assert_eq!(plan, vec!["SCAN Sales USING COLUMNS (Region = 'East' AND Amount >= 20)".to_string()]);
+
+    // Text in the INTEGER column compares the same as it would row by row.
+    db.execute("INSERT INTO Sales(Region, Amount) VALUES('East', 'n/a');
+                UPDATE Sales SET Amount = 40 WHERE Id = 1;
+                DELETE FROM Sales WHERE Id = 3;").unwrap();
+    assert_eq!(ids(&mut db, sql), vec![1, 5]);
+
+    db.execute("ALTER TABLE Sales ADD COLUMN Year INTEGER DEFAULT 2015;").unwrap();
+    assert_eq!(ids(&mut db, "SELECT Id FROM Sales WHERE Year = 2015 AND Id < 3;"), vec![1, 2]);
+
+    let sql: String = db.query_scalar("SELECT sql FROM rusql_master WHERE name = 'Sales';", &[]).unwrap();
+    assert!(sql.as_slice().ends_with(" COLUMNAR"));
+}
+
+#[test]
+fn test_large_table_scan() {
+    let mut db = rusql::Rusql::new();
+    // Enough rows for the scan to be split between threads in a parallel build.
+    let values: Vec<String> = range(0, 25000is).map(|i| format!("({}, {})", i, i % 7)).collect();
+    db.execute(format!("CREATE TABLE Big(Id INTEGER PRIMARY KEY, Kind INTEGER);
+                        INSERT INTO Big VALUES {};", values.connect(", ")).as_slice()).unwrap();
+
+    let ids: Vec<isize> = db.query_map("SELECT Id FROM Big WHERE Kind = 3 AND Id % 1000 < 20;", &[],
+                                       |row| row.get(0)).unwrap();
+    let expected: Vec<isize> = range(0, 25000is).filter(|i| i % 7 == 3 && i % 1000 < 20).collect();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn test_large_join() {
+    let mut db = rusql::Rusql::new();
+    let values: Vec<String> = range(0, 5000is).map(|i| format!("({}, 'Name{}', {})", i, i, i % 10)).collect();
+    db.execute(format!("CREATE TABLE Big(Id INTEGER PRIMARY KEY, Name TEXT, Kind INTEGER);
+                        CREATE TABLE Kind(KindId INTEGER PRIMARY KEY, Label TEXT);
+                        INSERT INTO Big VALUES {};
+                        INSERT INTO Kind VALUES(3, 'three'), (7, 'seven');", values.connect(", ")).as_slice()).unwrap();
+
+    // The rows are the same whether they're streamed or put in a result set.
+    let rows = |db: &mut Rusql, sql: &str| {
+        let mut streamed: Vec<TableRow> = db.query(sql).unwrap().collect();
+        let mut results: Vec<TableRow> = match db.execute(sql).unwrap().pop() {
+            Some(QueryResult::Rows(table)) => table.data.values().map(|row| row.clone()).collect(),
+            _ => panic!("expected rows"),
+        };
+        streamed.sort();
+        results.sort();
+        assert_eq!(streamed, results);
+        streamed
+    };
+    let big = |i: isize| vec![LiteralValue::Integer(i), LiteralValue::text(format!("Name{}", i).as_slice()),
+                              LiteralValue::Integer(i % 10)];
+
+    let expected: Vec<TableRow> = range(0, 5000is).filter(|i| *i != 10).map(|i| big(i)).collect();
+    assert_eq!(rows(&mut db, "SELECT * FROM Big WHERE Name <> 'Name10';"), expected);
+
+    let joined = |i: &isize| i % 10 == 3 || i % 10 == 7;
+    let label = |i: isize| LiteralValue::text(if i % 10 == 3 { "three" } else { "seven" });
+
+    let mut expected: Vec<TableRow> = range(0, 5000is).filter(|i| joined(i)).map(|i| {
+        let mut row = big(i);
+        row.push(LiteralValue::Integer(i % 10));
+        row.push(label(i));
+        row
+    }).collect();
+    expected.sort();
+    assert_eq!(rows(&mut db, "SELECT * FROM Big JOIN Kind ON Kind = KindId;"), expected);
+
+    // Conditions on both tables are left for the joined rows.
+    let mut expected: Vec<TableRow> = range(0, 5000is).filter(|i| joined(i) && i % 3 == i % 10 % 3).map(|i| {
+        vec![LiteralValue::text(format!("Name{}", i).as_slice()), label(i)]
+    }).collect();
+    expected.sort();
+    assert_eq!(rows(&mut db, "SELECT Name, Label FROM Kind, Big WHERE Kind = KindId AND Id % 3 = Kind % 3;"),
+               expected);
+
+    // The rows keep reading the tables as they were when the query ran.
+    let mut streamed = db.query("SELECT Id FROM Big WHERE Id < 3;").unwrap();
+    assert_eq!(streamed.next(), Some(vec![LiteralValue::Integer(0)]));
+    db.execute("DELETE FROM Big WHERE Id < 3;").unwrap();
+    let rest: Vec<TableRow> = streamed.collect();
+    assert_eq!(rest, vec![vec![LiteralValue::Integer(1)], vec![LiteralValue::Integer(2)]]);
+    assert_eq!(db.query("SELECT Id FROM Big WHERE Id < 3;").unwrap().count(), 0);
+}
+
+#[test]
+fn test_interrupt() {
+    let mut db = init_db_and_insert_into_table();
+    db.execute("CREATE TABLE Baz(Value INTEGER); INSERT INTO Baz VALUES(1), (2), (3);").unwrap();
+    let handle = db.interrupt_handle();
+
+    {
+        let mut rows = db.query("SELECT Name, Value FROM Foo, Baz;").unwrap();
+        assert!(rows.next().is_some());
+
+        let other = handle.clone();
+        std::thread::Thread::scoped(move || other.interrupt()).join().ok();
+
+        assert_eq!(rows.next(), None);
+        assert_eq!(rows.error(), Some(&RusqlError::Interrupted));
+    }
+
+    // An interrupt is forgotten when the next statement starts.
+    let ids: Vec<isize> = db.query_map("SELECT Id FROM Foo;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![1, 2, 3, 4]);
+
+    handle.interrupt();
+    assert_eq!(db.query("SELECT Name FROM Foo;").unwrap().count(), 4);
+}
+
+#[test]
+fn test_statement_timeout() {
+    let mut db = init_db_and_insert_into_table();
+    let values: Vec<String> = range(0, 200is).map(|i| format!("({})", i)).collect();
+    db.execute(format!("CREATE TABLE Baz(Value INTEGER); INSERT INTO Baz VALUES {};", values.connect(", ")).as_slice())
+      .unwrap();
+    let sql = "SELECT Name FROM Foo, Baz WHERE Value < 0;";
+
+    db.set_timeout(Some(Duration::seconds(60)));
+    assert_eq!(db.execute(sql).map(|results| results.len()), Ok(1));
+
+    db.set_timeout(Some(Duration::zero()));
+    assert_eq!(db.execute(sql).map(|results| results.len()), Err(RusqlError::Timeout));
+    let mut rows = db.query(sql).unwrap();
+    assert_eq!(rows.next(), None);
+    assert_eq!(rows.error(), Some(&RusqlError::Timeout));
+}
+
+#[test]
+fn test_parse_cache() {
+    let mut db = init_db_and_insert_into_table();
+    db.parse_cache.clear();
+    db.parse_cache.set_capacity(2);
+
+    let select = "SELECT Name FROM Foo WHERE Id = 1;";
+    for _ in range(0, 3us) {
+        assert_eq!(db.query_scalar::<String>(select, &[]), Ok("Bar1".to_string()));
+    }
+    assert_eq!(db.parse_cache.len(), 1);
+
+    // Bound parameters don't change the cached statements.
+    let by_id = "SELECT Name FROM Foo WHERE Id = ?;";
+    assert_eq!(db.query_scalar::<String>(by_id, &[LiteralValue::Integer(2)]), Ok("Bar2".to_string()));
+    assert_eq!(db.query_scalar::<String>(by_id, &[LiteralValue::Integer(3)]), Ok("Bar3".to_string()));
+
+    // The least recently used SQL makes room.
+    db.execute("DELETE FROM Foo WHERE Id = 4;").unwrap();
+    assert_eq!(db.parse_cache.len(), 2);
+    assert_eq!(db.parse_cache.get(select), None);
+    assert!(db.parse_cache.get(by_id).is_some());
+
+    db.parse_cache.set_capacity(0);
+    assert_eq!(db.parse_cache.len(), 0);
+    db.execute(select).unwrap();
+    assert_eq!(db.parse_cache.len(), 0);
+}
+
+#[test]
+fn test_memory_limit() {
+    let mut db = init_db_and_insert_into_table();
+    let used = db.memory_used();
+    assert!(used > 0);
+    assert_eq!(db.query_scalar::<isize>("PRAGMA memory_used;", &[]), Ok(used as isize));
+
+    db.execute("INSERT INTO Foo(Name) VALUES('Bar5');").unwrap();
+    assert!(db.memory_used() > used);
+    db.execute("DELETE FROM Foo WHERE Id = 5;").unwrap();
+    assert_eq!(db.memory_used(), used);
+
+    db.execute(format!("PRAGMA soft_heap_limit = {};", used + 200).as_slice()).unwrap();
+    assert_eq!(db.memory_limit(), Some(used + 200));
+
+    let long_name: String = range(0, 500us).map(|_| 'x').collect();
+    assert_eq!(db.execute(format!("INSERT INTO Foo(Name) VALUES('{}');", long_name).as_slice()).map(|_| ()),
+               Err(RusqlError::OutOfMemory));
+    assert_eq!(db.execute(format!("UPDATE Foo SET Name = '{}' WHERE Id = 1;", long_name).as_slice()).map(|_| ()),
+               Err(RusqlError::OutOfMemory));
+    assert_eq!(db.memory_used(), used);
+
+    // The result set of a join has to fit too, but its rows can still be
+    // streamed one at a time.
+    db.execute("PRAGMA soft_heap_limit = 0;
+                CREATE TABLE Baz(Value INTEGER);
+                INSERT INTO Baz VALUES(1), (2), (3), (4), (5), (6), (7), (8);").unwrap();
+    let used = db.memory_used();
+    db.set_memory_limit(Some(used + 500));
+    let sql = "SELECT Name, Value FROM Foo, Baz;";
+    assert_eq!(db.execute(sql).map(|_| ()), Err(RusqlError::OutOfMemory));
+    assert_eq!(db.query(sql).unwrap().count(), 32);
+
+    db.execute("PRAGMA soft_heap_limit = 0;").unwrap();
+    assert_eq!(db.memory_limit(), None);
+    assert_eq!(db.execute(sql).map(|results| results.len()), Ok(1));
+}
+
+#[test]
+fn test_copy_from() {
+    let mut db = rusql::Rusql::new();
+    db.execute("CREATE TABLE Item(Id INTEGER PRIMARY KEY, Code TEXT UNIQUE, Price INTEGER CHECK(Price >= 0));
+                CREATE INDEX ItemPrice ON Item(Price);").unwrap();
+
+    let rows = range(0, 1000is).map(|i| {
+        vec![LiteralValue::Null, LiteralValue::text(format!("C{}", i).as_slice()), LiteralValue::Integer(i % 10)]
+    });
+    assert_eq!(db.copy_from("Item", rows), Ok(1000));
+    assert_eq!(db.changes(), 1000);
+    assert_eq!(db.last_insert_rowid(), 1000);
+
+    let codes: Vec<String> = db.query_map("SELECT Code FROM Item WHERE Price = 9 AND Id < 30;", &[],
+                                          |row| row.get(0)).unwrap();
+    assert_eq!(codes, vec!["C9".to_string(), "C19".to_string(), "C29".to_string()]);
+
+    // Nothing is inserted unless every row can be.
+    let row = |id: isize, code: &str, price: isize| {
+        vec![LiteralValue::Integer(id), LiteralValue::text(code), LiteralValue::Integer(price)]
+    };
+    assert!(db.copy_from("Item", vec![row(2000, "New1", 1), row(2001, "New1", 1)].into_iter()).is_err());
+    assert!(db.copy_from("Item", vec![row(2000, "New1", 1), row(2000, "New2", 1)].into_iter()).is_err());
+    assert!(db.copy_from("Item", vec![row(2000, "New1", 1), row(2001, "C5", 1)].into_iter()).is_err());
+    assert!(db.copy_from("Item", vec![row(2000, "New1", 1), row(2001, "New2", -1)].into_iter()).is_err());
+    assert!(db.copy_from("Item", vec![row(2000, "New1", 1), row(5, "New2", 1)].into_iter()).is_err());
+    assert_eq!(db.row_count("Item"), Ok(1000));
+
+    assert_eq!(db.copy_from("Item", vec![row(2000, "New1", 1)].into_iter()), Ok(1));
+    assert_eq!(db.query_scalar::<String>("SELECT Code FROM Item WHERE Id = 2000;", &[]), Ok("New1".to_string()));
+}
+
+#[test]
+fn test_snapshot() {
+    let mut db = init_db_and_insert_into_table();
+    let mut snapshot = db.snapshot();
+
+    db.execute("DELETE FROM Foo WHERE Id > 2;
+                INSERT INTO Foo(Name) VALUES('Bar5');
+                CREATE TABLE Baz(Id INTEGER);").unwrap();
+
+    let reader = std::thread::Thread::scoped(move || {
+        let names: Vec<String> = snapshot.query_map("SELECT Name FROM Foo;", &[], |row| row.get(0)).unwrap();
+        (names, snapshot.table_names(), snapshot.execute("DELETE FROM Foo;").map(|_| ()))
+    });
+    let (names, table_names, deleted) = reader.join().ok().unwrap();
+
+    assert_eq!(names, vec!["Bar1".to_string(), "Bar2".to_string(), "Bar3".to_string(), "Bar4".to_string()]);
+    assert_eq!(table_names, vec!["Foo".to_string()]);
+    assert_eq!(deleted, Err(RusqlError::ReadOnly));
+
+    let names: Vec<String> = db.query_map("SELECT Name FROM Foo;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(names, vec!["Bar1".to_string(), "Bar2".to_string(), "Bar5".to_string()]);
+}
+
+#[test]
+fn test_save_and_open() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY AUTOINCREMENT, Name TEXT, Data BLOB);
+                CREATE TABLE Log(Name TEXT);
+                CREATE INDEX FooName ON Foo(Name);
+                CREATE VIEW Names AS SELECT Name FROM Foo;
+                CREATE TRIGGER LogFoo AFTER INSERT ON Foo BEGIN INSERT INTO Log VALUES(NEW.Name); END;
+                INSERT INTO Foo(Name, Data) VALUES('Bar1', X'01FF'), ('Bar2', NULL), ('Bar3', NULL);
+                DELETE FROM Foo WHERE Id = 3;
+                CREATE TEMP TABLE Scratch(Id INTEGER);").unwrap();
+
+    let path = std::os::tmpdir().join("rusql_test_save_and_open.db");
+    db.save(&path).unwrap();
+    let mut db = Rusql::open(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+
+    assert_eq!(db.table_names(), vec!["Foo".to_string(), "Log".to_string()]);
+    assert!(db.has_view(&"Names".to_string()));
+
+    db.execute("INSERT INTO Foo(Name) VALUES('Bar4');").unwrap();
+    let rows: Vec<(isize, String)> = db.query_map("SELECT Id, Name FROM Foo;", &[], |row| {
+        Ok((try!(row.get(0)), try!(row.get(1))))
+    }).unwrap();
+    assert_eq!(rows, vec![(1, "Bar1".to_string()), (2, "Bar2".to_string()), (4, "Bar4".to_string())]);
+
+    let data: Vec<u8> = db.query_scalar("SELECT Data FROM Foo WHERE Id = 1;", &[]).unwrap();
+    assert_eq!(data, vec![0x01, 0xFF]);
+    assert_eq!(db.row_count("Log").unwrap(), 4);
+    assert_eq!(db.indexes.get(&"FooName".to_string()), Some(&"Foo".to_string()));
+}
+
+#[test]
+fn test_open_file() {
+    let path = std::os::tmpdir().join("rusql_test_open_file.db");
+    std::io::fs::unlink(&path).ok();
+
+    {
+        let mut db = Rusql::open_file(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                    INSERT INTO Foo(Name) VALUES('Bar1');
+                    BEGIN;
+                    INSERT INTO Foo(Name) VALUES('Bar2');").unwrap();
+        assert_eq!(Rusql::open(&path).unwrap().row_count("Foo").unwrap(), 1);
+
+        db.execute("COMMIT;").unwrap();
+        assert_eq!(Rusql::open(&path).unwrap().row_count("Foo").unwrap(), 2);
+    }
+
+    let db = Rusql::open_file(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.row_count("Foo").unwrap(), 2);
+}
+
+#[test]
+fn test_dump() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Score REAL, Data BLOB);
+                CREATE INDEX FooName ON Foo(Name);
+                CREATE VIEW Names AS SELECT Name FROM Foo WHERE Name != 'it''s';
+                INSERT INTO Foo VALUES(1, 'it''s \"quoted\"', 2.0, X'00FF'), (2, NULL, 1.5, NULL);").unwrap();
+
+    let sql = db.dump();
+    assert!(sql.starts_with("BEGIN TRANSACTION;\nCREATE TABLE Foo("));
+    assert!(sql.contains("INSERT INTO Foo VALUES(1,'it''s \"quoted\"',2.0,X'00FF');\n"));
+
+    let mut copy = Rusql::new();
+    copy.execute(sql.as_slice()).unwrap();
+    assert_eq!(copy.dump(), sql);
+    assert_eq!(copy.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 1;", &[]),
+               Ok("it's \"quoted\"".to_string()));
+
+    db.execute("CREATE TABLE Item(Id INTEGER PRIMARY KEY, Price REAL, Total REAL AS (Price * 2));
+                INSERT INTO Item(Id, Price) VALUES(1, -1.5), (2, 1e20), (3, 0.000001);").unwrap();
+    let sql = db.dump();
+    assert!(sql.contains("INSERT INTO Item(Id,Price) VALUES(1,-1.5);\n"));
+
+    let mut copy = Rusql::new();
+    copy.execute(sql.as_slice()).unwrap();
+    assert_eq!(copy.dump(), sql);
+    assert_eq!(copy.query_scalar::<f64>("SELECT Total FROM Item WHERE Id = 1;", &[]), Ok(-3.0));
+}
+
+#[test]
+fn test_execute_file() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                CREATE TABLE Log(Name TEXT);
+                CREATE TRIGGER LogFoo AFTER INSERT ON Foo BEGIN INSERT INTO Log VALUES(NEW.Name); END;
+                INSERT INTO Foo(Name) VALUES('a;b'), ('it''s');").unwrap();
+
+    let path = std::os::tmpdir().join("rusql_test_execute_file.sql");
+    std::io::File::create(&path).unwrap().write_str(db.dump().as_slice()).unwrap();
+
+    let mut copy = Rusql::new();
+    let count = copy.execute_file(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+
+    assert_eq!(count, 9);
+    assert_eq!(copy.dump(), db.dump());
+}
+
+#[test]
+fn test_open_sqlite() {
+    let mut db = Rusql::open_sqlite(&Path::new("tests/data/people.sqlite")).unwrap();
+
+    assert_eq!(db.table_names(), vec!["Notes".to_string(), "People".to_string()]);
+    assert_eq!(db.row_count("People").unwrap(), 299);
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM People WHERE Id = 42;", &[]), Ok("Person42".to_string()));
+    assert_eq!(db.query_scalar::<Vec<u8>>("SELECT Photo FROM People WHERE Id = 7;", &[]), Ok(vec![7, 255]));
+    assert_eq!(db.query_scalar::<f64>("SELECT Score FROM People WHERE Id = 2;", &[]), Ok(0.5));
+    assert_eq!(db.indexes.get(&"PeopleName".to_string()), Some(&"People".to_string()));
+    assert!(db.has_view(&"Adults".to_string()));
+
+    // The body overflows its page, and the tag was added after the rows.
+    let notes: Vec<(String, String)> = db.query_map("SELECT Body, Tag FROM Notes;", &[], |row| {
+        Ok((try!(row.get(0)), try!(row.get(1))))
+    }).unwrap();
+    assert_eq!(notes, vec![(std::iter::repeat('x').take(2000).collect(), "none".to_string()),
+                           ("it's".to_string(), "none".to_string())]);
+
+    // The key deleted last isn't handed out again.
+    db.execute("INSERT INTO People(Name) VALUES('Person301');").unwrap();
+    assert_eq!(db.query_scalar::<isize>("SELECT Id FROM People WHERE Name = 'Person301';", &[]), Ok(301));
+
+    // Quoted names and types rusql doesn't parse: the columns get the types
+    // with the same affinity.
+    let db = Rusql::open_sqlite(&Path::new("tests/data/types.sqlite")).unwrap();
+    let table = db.get_table(&"Order Items".to_string()).unwrap();
+    let types: Vec<Option<ColumnType>> = table.header.iter().map(|def| def.column_type).collect();
+    assert_eq!(types, vec![Some(ColumnType::Integer), Some(ColumnType::Text), Some(ColumnType::Real),
+                           Some(ColumnType::Real), Some(ColumnType::Numeric(10, 2)), None]);
+    assert_eq!(table.pk, Some(0));
+    assert_eq!(table.data.get(&2).unwrap()[1], LiteralValue::text("Nut"));
+    assert_eq!(table.data.get(&2).unwrap()[2], LiteralValue::Real(2.0));
+    assert_eq!(table.data.get(&1).unwrap()[5], LiteralValue::text("2015-01-09"));
+}
+
+#[test]
+fn test_save_sqlite() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY AUTOINCREMENT, Name TEXT UNIQUE, Flag BOOLEAN, Data BLOB);
+                CREATE TABLE Log(Name TEXT);
+                CREATE INDEX FooFlag ON Foo(Flag, Name);
+                CREATE VIEW Flagged AS SELECT Name FROM Foo WHERE Flag = TRUE;
+                CREATE TRIGGER LogFoo AFTER DELETE ON Foo BEGIN INSERT INTO Log VALUES(OLD.Name); END;").unwrap();
+
+    // Enough rows to need interior pages, and a name that overflows its page.
+    let rows = range(0, 2000is).map(|i| {
+        vec![LiteralValue::Null, LiteralValue::text(format!("Bar{}", i).as_slice()), LiteralValue::Boolean(i % 3 == 0),
+             LiteralValue::Blob(vec![i as u8, 0xFF])]
+    });
+    db.copy_from("Foo", rows).unwrap();
+    db.execute("INSERT INTO Foo(Name) VALUES('Long');").unwrap();
+    let long_name: String = std::iter::repeat('x').take(10000).collect();
+    db.execute(format!("UPDATE Foo SET Name = '{}' WHERE Name = 'Long';", long_name).as_slice()).unwrap();
+    db.execute("DELETE FROM Foo WHERE Id = 5;").unwrap();
+
+    let path = std::os::tmpdir().join("rusql_test_save_sqlite.db");
+    db.save_sqlite(&path).unwrap();
+    let bytes = std::io::File::open(&path).read_to_end().unwrap();
+    let copy = Rusql::open_sqlite(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+
+    assert!(bytes.starts_with(b"SQLite format 3\0"));
+    assert_eq!(bytes.len() % 4096, 0);
+    assert_eq!(copy.dump(), db.dump());
+}
+
+#[test]
+fn test_storage_range() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                INSERT INTO Foo VALUES(1, 'a'), (2, 'b'), (4, 'c'), (7, 'd');").unwrap();
+
+    let table = db.get_table(&"Foo".to_string()).unwrap();
+    let names: Vec<LiteralValue> = table.data.range(2, 6).map(|(_, row)| row[1].clone()).collect();
+    assert_eq!(names, vec![LiteralValue::text("b"), LiteralValue::text("c")]);
+    assert_eq!(table.data.last_key(), Some(7));
+}
+
+#[test]
+fn test_open_log() {
+    let path = std::os::tmpdir().join("rusql_test_open_log.db");
+    std::io::fs::unlink(&path).ok();
+
+    let expected = {
+        let mut db = Rusql::open_log(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY AUTOINCREMENT, Name TEXT);
+                    CREATE TABLE Old(Id INTEGER);
+                    CREATE INDEX FooName ON Foo(Name);
+                    INSERT INTO Foo(Name) VALUES('Bar1'), ('Bar2'), ('Bar3');
+                    UPDATE Foo SET Name = 'Baz' WHERE Id = 2;
+                    DELETE FROM Foo WHERE Id = 3;
+                    DROP TABLE Old;
+                    BEGIN;
+                    INSERT INTO Foo(Name) VALUES('Gone');
+                    ROLLBACK;").unwrap();
+        db.dump()
+    };
+
+    // A frame cut short by a crash is dropped.
+    {
+        let mut file = std::io::File::open_mode(&path, std::io::FileMode::Append, std::io::FileAccess::Write).unwrap();
+        file.write_be_u64(1000).unwrap();
+        file.write_u8(3).unwrap();
+    }
+
+    {
+        let mut db = Rusql::open_log(&path).unwrap();
+        assert_eq!(db.dump(), expected);
+
+        let size = std::io::fs::stat(&path).unwrap().size;
+        db.compact_log().unwrap();
+        assert!(std::io::fs::stat(&path).unwrap().size < size);
+    }
+
+    let db = Rusql::open_log(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.dump(), expected);
+}
+
+#[test]
+fn test_open_paged() {
+    let path = std::os::tmpdir().join("rusql_test_open_paged.db");
+    std::io::fs::unlink(&path).ok();
+
+    let long: String = range(0, 10000).map(|_| 'x').collect();
+    let expected = {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY AUTOINCREMENT, Name TEXT);
+                    CREATE TABLE Old(Id INTEGER);
+                    CREATE INDEX FooName ON Foo(Name);").unwrap();
+        db.execute("BEGIN;").unwrap();
+        for i in range(0, 2000) {
+            db.execute(format!("INSERT INTO Foo(Name) VALUES('Bar{}');", i).as_slice()).unwrap();
+        }
+        db.execute("COMMIT;").unwrap();
+        db.execute(format!("INSERT INTO Foo(Name) VALUES('{}');", long).as_slice()).unwrap();
+        db.execute("UPDATE Foo SET Name = 'Baz' WHERE Id = 2;
+                    DELETE FROM Foo WHERE Id > 100 AND Id < 200;
+                    DROP TABLE Old;").unwrap();
+        db.dump()
+    };
+
+    {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        assert_eq!(db.dump(), expected);
+        assert_eq!(db.query_scalar::<isize>("SELECT Id FROM Foo WHERE Name = 'Baz';", &[]).unwrap(), 2);
+
+        let size = std::io::fs::stat(&path).unwrap().size;
+        db.execute("DELETE FROM Foo WHERE Id = 2;").unwrap();
+        db.execute("INSERT INTO Foo(Name) VALUES('Bar');").unwrap();
+        // Pages no longer used are reused instead of growing the file.
+        assert!(std::io::fs::stat(&path).unwrap().size <= size + 4 * 4096);
+    }
+
+    let mut db = Rusql::open_paged(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert!(db.query_scalar::<isize>("SELECT Id FROM Foo WHERE Name = 'Baz';", &[]).is_err());
+    let bars: Vec<isize> = db.query_map("SELECT Id FROM Foo WHERE Name = 'Bar';", &[], |row| row.get(0)).unwrap();
+    assert_eq!(bars.len(), 1);
+}
+
+#[test]
+fn test_open_corrupt_paged() {
+    let path = std::os::tmpdir().join("rusql_test_open_corrupt_paged.db");
+    std::io::fs::unlink(&path).ok();
+
+    {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                    INSERT INTO Foo VALUES(1, 'Bar1'), (2, 'Bar2');").unwrap();
+    }
+
+    // Too many cells for the leaf holding the rows.
+    let mut bytes = std::io::File::open(&path).read_to_end().unwrap();
+    let offset = range(0, bytes.len() - 4).find(|&i| bytes.slice(i, i + 4) == b"Bar1").unwrap();
+    let page = offset / 4096 * 4096;
+    bytes[page + 1] = 0xFF;
+    bytes[page + 2] = 0xFF;
+    std::io::File::create(&path).write(bytes.as_slice()).unwrap();
+
+    match Rusql::open_paged(&path) {
+        Err(RusqlError::Corrupt(..)) => {}
+        _ => panic!("expected a corrupt file"),
+    }
+    std::io::fs::unlink(&path).unwrap();
+}
+
+#[test]
+fn test_page_cache() {
+    let path = std::os::tmpdir().join("rusql_test_page_cache.db");
+    std::io::fs::unlink(&path).ok();
+
+    {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);").unwrap();
+        db.execute("BEGIN;").unwrap();
+        for i in range(0, 1000) {
+            db.execute(format!("INSERT INTO Foo VALUES({}, 'Bar{}');", i, i).as_slice()).unwrap();
+        }
+        db.execute("COMMIT;").unwrap();
+    }
+
+    let mut db = Rusql::open_paged(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    let stats = |db: &mut Rusql| {
+        db.query_one("PRAGMA cache_stats;", &[], |row| Ok((try!(row.get::<isize>(0)), try!(row.get::<isize>(1)))))
+          .unwrap()
+    };
+    let select = "SELECT Name FROM Foo WHERE Id = 500;";
+
+    assert_eq!(db.query_scalar::<String>(select, &[]), Ok("Bar500".to_string()));
+    let (hits, misses) = stats(&mut db);
+    // The pages above the leaf are read again from the cache.
+    assert_eq!(db.query_scalar::<String>(select, &[]), Ok("Bar500".to_string()));
+    let (more_hits, more_misses) = stats(&mut db);
+    assert!(more_hits > hits);
+    assert_eq!(more_misses, misses);
+
+    db.execute("PRAGMA cache_size = 0;").unwrap();
+    assert_eq!(db.query_scalar::<isize>("PRAGMA cache_size;", &[]), Ok(0));
+    assert_eq!(db.query_scalar::<String>(select, &[]), Ok("Bar500".to_string()));
+    assert!(stats(&mut db).1 > more_misses);
+
+    db.execute("PRAGMA cache_size = -400;").unwrap();
+    assert_eq!(db.query_scalar::<isize>("PRAGMA cache_size;", &[]), Ok(100));
+    assert_eq!(Rusql::new().query_scalar::<isize>("PRAGMA cache_size;", &[]), Ok(0));
+}
+
+#[test]
+fn test_wal_mode() {
+    let path = std::os::tmpdir().join("rusql_test_wal_mode.db");
+    let wal_path = std::os::tmpdir().join("rusql_test_wal_mode.db-wal");
+    std::io::fs::unlink(&path).ok();
+    std::io::fs::unlink(&wal_path).ok();
+
+    let expected = {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        assert_eq!(db.query_scalar::<String>("PRAGMA journal_mode = WAL;", &[]), Ok("wal".to_string()));
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                    INSERT INTO Foo VALUES(1, 'Bar1'), (2, 'Bar2');").unwrap();
+
+        // A snapshot reads the rows as they were, whatever is checkpointed.
+        let mut snapshot = db.snapshot();
+        db.execute("UPDATE Foo SET Name = 'Baz' WHERE Id = 1;").unwrap();
+        db.checkpoint().unwrap();
+        assert_eq!(snapshot.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 1;", &[]),
+                   Ok("Bar1".to_string()));
+
+        db.execute("INSERT INTO Foo VALUES(3, 'Bar3');").unwrap();
+        db.dump()
+    };
+    assert!(std::io::fs::stat(&wal_path).unwrap().size > 4096);
+
+    // The commits still in the WAL are read back from it.
+    let mut db = Rusql::open_paged(&path).unwrap();
+    assert_eq!(db.query_scalar::<String>("PRAGMA journal_mode;", &[]), Ok("wal".to_string()));
+    assert_eq!(db.dump(), expected);
+
+    db.execute("CHECKPOINT;").unwrap();
+    assert!(std::io::fs::stat(&wal_path).unwrap().size < 4096);
+
+    assert_eq!(db.query_scalar::<String>("PRAGMA journal_mode = DELETE;", &[]), Ok("delete".to_string()));
+    assert!(std::io::fs::stat(&wal_path).is_err());
+    drop(db);
+
+    let db = Rusql::open_paged(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.dump(), expected);
+}
+
+#[test]
+fn test_rollback_journal() {
+    let path = std::os::tmpdir().join("rusql_test_rollback_journal.db");
+    let journal_path = std::os::tmpdir().join("rusql_test_rollback_journal.db-journal");
+    std::io::fs::unlink(&path).ok();
+
+    let expected = {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                    INSERT INTO Foo VALUES(1, 'Bar1'), (2, 'Bar2');").unwrap();
+        db.dump()
+    };
+    assert!(std::io::fs::stat(&journal_path).is_err());
+
+    // A crash while the header was written over, with the old one saved.
+    {
+        let mut file = std::io::File::open_mode(&path, std::io::FileMode::Open, std::io::FileAccess::ReadWrite)
+                                     .unwrap();
+        let header = file.read_exact(4096).unwrap();
+        rusql::journal::write(&journal_path, &[(0, header)]).unwrap();
+
+        // Where the catalog is.
+        file.seek(28, std::io::SeekStyle::SeekSet).unwrap();
+        file.write_be_u64(0).unwrap();
+    }
+
+    {
+        let db = Rusql::open_paged(&path).unwrap();
+        assert_eq!(db.dump(), expected);
+        assert!(std::io::fs::stat(&journal_path).is_err());
+    }
+
+    // A journal cut short is from before anything was written over.
+    {
+        let mut file = std::io::File::create(&journal_path).unwrap();
+        file.write_str("rusql journal").unwrap();
+    }
+
+    let db = Rusql::open_paged(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.dump(), expected);
+    assert!(std::io::fs::stat(&journal_path).is_err());
+}
+
+#[test]
+fn test_backup_to() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                CREATE INDEX FooName ON Foo(Name);
+                CREATE VIEW Names AS SELECT Name FROM Foo;
+                CREATE TABLE Empty(Id INTEGER);").unwrap();
+    db.execute("BEGIN;").unwrap();
+    for i in range(0, 2500) {
+        db.execute(format!("INSERT INTO Foo VALUES({}, 'Bar{}');", i, i).as_slice()).unwrap();
+    }
+    db.execute("COMMIT;").unwrap();
+
+    let mut dest = Rusql::new();
+    dest.execute("CREATE TABLE Old(Id INTEGER);").unwrap();
+
+    let mut steps = Vec::new();
+    db.snapshot().backup_to(&mut dest, |progress| steps.push(progress.copied)).unwrap();
+    // A step for every STEP rows and one for the rest of each table.
+    assert_eq!(steps, vec![0, 1000, 2000, 2500]);
+    assert_eq!(dest.dump(), db.dump());
+    assert_eq!(dest.query_scalar::<String>("SELECT Name FROM Names WHERE Name = 'Bar7';", &[]),
+               Ok("Bar7".to_string()));
+
+    let path = std::os::tmpdir().join("rusql_test_backup_to.db");
+    db.backup_to_file(&path, |_| {}).unwrap();
+    let copy = Rusql::open(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(copy.dump(), db.dump());
+}
+
+#[test]
+fn test_attach() {
+    let path = std::os::tmpdir().join("rusql_test_attach.db");
+    std::io::fs::unlink(&path).ok();
+
+    let mut db = init_db_and_insert_into_table();
+    db.execute(format!("ATTACH DATABASE '{}' AS aux;", path.display()).as_slice()).unwrap();
+    db.execute("ATTACH ':memory:' AS mem;").unwrap();
+    assert!(db.execute("ATTACH ':memory:' AS mem;").is_err());
+
+    db.execute("CREATE TABLE aux.Ages(Id INTEGER PRIMARY KEY, Age INTEGER);
+                INSERT INTO aux.Ages VALUES(1, 30), (2, 40);
+                CREATE TABLE mem.Foo AS SELECT * FROM Foo WHERE Id > 2;").unwrap();
+    assert_eq!(db.row_count("mem.Foo"), Ok(2));
+    assert!(db.find_table(&"Ages".to_string()).is_none());
+
+    let ages: Vec<(String, isize)> = db.query_map("SELECT Name, Age FROM Foo, aux.Ages WHERE Foo.Id = Ages.Id;", &[],
+                                                  |row| Ok((try!(row.get(0)), try!(row.get(1))))).unwrap();
+    assert_eq!(ages, vec![("Bar1".to_string(), 30), ("Bar2".to_string(), 40)]);
+
+    db.execute("UPDATE aux.Ages SET Age = 31 WHERE Id = 1;
+                DELETE FROM mem.Foo;
+                DETACH DATABASE aux;").unwrap();
+    assert_eq!(db.row_count("mem.Foo"), Ok(0));
+    assert!(db.execute("SELECT * FROM aux.Ages;").is_err());
+
+    let mut aux = Rusql::open_file(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(aux.query_scalar::<isize>("SELECT Age FROM Ages WHERE Id = 1;", &[]), Ok(31));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_open_encrypted() {
+    let path = std::os::tmpdir().join("rusql_test_open_encrypted.db");
+    std::io::fs::unlink(&path).ok();
+    let key: Vec<u8> = range(0, 32u8).collect();
+
+    {
+        let mut db = Rusql::open_encrypted(&path, key.as_slice()).unwrap();
+        db.execute("CREATE TABLE Secrets(Id INTEGER PRIMARY KEY, Secret TEXT);
+                    INSERT INTO Secrets VALUES(1, 'hunter2');").unwrap();
+    }
+
+    let contents = std::io::File::open(&path).read_to_end().unwrap();
+    assert!(!contents.windows(7).any(|bytes| bytes == b"hunter2"));
+    assert!(!contents.windows(7).any(|bytes| bytes == b"Secrets"));
+
+    assert!(Rusql::open_paged(&path).is_err());
+    let other_key: Vec<u8> = range(1, 33u8).collect();
+    assert!(Rusql::open_encrypted(&path, other_key.as_slice()).is_err());
+    assert!(Rusql::open_encrypted(&path, &key[..16]).is_err());
+
+    let mut db = Rusql::open_encrypted(&path, key.as_slice()).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.query_scalar::<String>("SELECT Secret FROM Secrets WHERE Id = 1;", &[]),
+               Ok("hunter2".to_string()));
+}
+
+#[test]
+fn test_lz4() {
+    let inputs: Vec<Vec<u8>> = vec![Vec::new(), b"abc".to_vec(), b"abcabcabcabcabcabcabcabcabcabc".to_vec(),
+                                    range(0, 1000).map(|i| (i % 7) as u8).collect(),
+                                    range(0, 1000).map(|i| (i * 7919 % 251) as u8).collect()];
+
+    for input in inputs.iter() {
+        let compressed = rusql::lz4::compress(input.as_slice());
+        assert_eq!(rusql::lz4::decompress(compressed.as_slice(), input.len()), Some(input.clone()));
+    }
+
+    let compressed = rusql::lz4::compress(inputs[3].as_slice());
+    assert!(compressed.len() < 100);
+    assert_eq!(rusql::lz4::decompress(compressed.as_slice(), 999), None);
+    assert_eq!(rusql::lz4::decompress(&compressed[..compressed.len() - 1], 1000), None);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_compressed_values() {
+    let text: String = range(0, 500).map(|i| format!("row {} of the table ", i % 10)).collect();
+    let blob: String = range(0, 1000).map(|_| "AB").collect();
+
+    let mut db = Rusql::new();
+    db.execute(format!("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Data BLOB);
+                        INSERT INTO Foo(Name, Data) VALUES('{}', X'{}'), ('short', NULL);", text, blob).as_slice()).unwrap();
+
+    let path = std::os::tmpdir().join("rusql_test_compressed_values.db");
+    db.save(&path).unwrap();
+    assert!(std::io::fs::stat(&path).unwrap().size < 1000);
+
+    let mut db = Rusql::open(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 1;", &[]), Ok(text));
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 2;", &[]), Ok("short".to_string()));
+    assert_eq!(db.query_scalar::<Vec<u8>>("SELECT Data FROM Foo WHERE Id = 1;", &[]),
+               Ok(range(0, 1000).map(|_| 0xAB).collect()));
+}
+
+#[test]
+fn test_vacuum() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Name TEXT);
+                CREATE INDEX FooName ON Foo(Name);
+                INSERT INTO Foo VALUES('Bar1'), ('Bar2'), ('Bar3'), ('Bar4');
+                DELETE FROM Foo WHERE Name = 'Bar2' OR Name = 'Bar3';
+                VACUUM;").unwrap();
+
+    // The rows were numbered again.
+    db.execute("INSERT INTO Foo VALUES('Bar5');").unwrap();
+    assert_eq!(db.last_insert_rowid(), 3);
+    let names: Vec<String> = db.query_map("SELECT Name FROM Foo;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(names, vec!["Bar1".to_string(), "Bar4".to_string(), "Bar5".to_string()]);
+    assert!(db.execute("BEGIN; VACUUM;").is_err());
+    db.execute("ROLLBACK;").unwrap();
+
+    let path = std::os::tmpdir().join("rusql_test_vacuum.db");
+    std::io::fs::unlink(&path).ok();
+    let long: String = range(0, 1000).map(|_| 'x').collect();
+
+    let expected = {
+        let mut db = Rusql::open_paged(&path).unwrap();
+        db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);").unwrap();
+        db.execute("BEGIN;").unwrap();
+        for _ in range(0, 1000) {
+            db.execute(format!("INSERT INTO Foo(Name) VALUES('{}');", long).as_slice()).unwrap();
+        }
+        db.execute("COMMIT;").unwrap();
+        db.execute("DELETE FROM Foo WHERE Id > 10;").unwrap();
+
+        let size = std::io::fs::stat(&path).unwrap().size;
+        db.execute("VACUUM;").unwrap();
+        assert!(std::io::fs::stat(&path).unwrap().size < size / 10);
+        db.dump()
+    };
+
+    let mut db = Rusql::open_paged(&path).unwrap();
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.dump(), expected);
+    let ids: Vec<isize> = db.query_map("SELECT Id FROM Foo;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(ids, range(1, 11).collect::<Vec<isize>>());
+}
+
+#[test]
+fn test_import_csv() {
+    use rusql::CsvOptions;
+    use rusql::definitions::ColumnType;
+
+    let path = std::os::tmpdir().join("rusql_test_import_csv.csv");
+    std::io::File::create(&path).write_str("Id,Name,Score\r\n1,Bar,1.5\n2,\"Baz, \"\"the\"\"\nsecond\",\n\n3,\"42\",2\n").unwrap();
+
+    let mut db = Rusql::new();
+    assert_eq!(db.import_csv(&path, "People", &CsvOptions::new()), Ok(3));
+    assert_eq!(db.column_names("People").unwrap(), vec!["Id".to_string(), "Name".to_string(), "Score".to_string()]);
+    let types: Vec<Option<ColumnType>> = db.table_schema("People").unwrap().iter().map(|c| c.column_type).collect();
+    assert_eq!(types, vec![Some(ColumnType::Integer), Some(ColumnType::Text), None]);
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM People WHERE Id = 2;", &[]),
+               Ok("Baz, \"the\"\nsecond".to_string()));
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM People WHERE Id = 3;", &[]), Ok("42".to_string()));
+    let ids: Vec<isize> = db.query_map("SELECT Id FROM People WHERE Score IS NULL;", &[], |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![2]);
+
+    // The header names the columns of the table, so it's skipped.
+    std::io::File::create(&path).write_str("Id,Name,Score\n4,Qux,3\n").unwrap();
+    assert_eq!(db.import_csv(&path, "People", &CsvOptions::new()), Ok(1));
+
+    // Without a header the columns are numbered.
+    std::io::File::create(&path).write_str("1;x\n2;y\n").unwrap();
+    let options = CsvOptions { delimiter: ';', ..CsvOptions::new() };
+    assert_eq!(db.import_csv(&path, "Pairs", &options), Ok(2));
+    assert_eq!(db.column_names("Pairs").unwrap(), vec!["c1".to_string(), "c2".to_string()]);
+
+    std::io::File::create(&path).write_str("5,Quux,1\n6,\"Corge,2\n").unwrap();
+    assert!(db.import_csv(&path, "People", &CsvOptions::new()).is_err());
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.row_count("People"), Ok(4));
+}
+
+#[test]
+fn test_to_csv() {
+    use rusql::CsvOptions;
+
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Score INTEGER);
+                INSERT INTO Foo(Name, Score) VALUES('Bar, \"the\" first', 1), ('42', NULL), ('', 3);").unwrap();
+
+    let mut w = std::io::MemWriter::new();
+    match db.execute("SELECT * FROM Foo;").unwrap().pop() {
+        Some(QueryResult::Rows(table)) => table.to_csv(&mut w, &CsvOptions::new()).unwrap(),
+        _ => panic!("expected rows"),
+    }
+    let csv = String::from_utf8(w.into_inner()).unwrap();
+    assert_eq!(csv.as_slice(), "Id,Name,Score\r\n1,\"Bar, \"\"the\"\" first\",1\r\n2,\"42\",\r\n3,\"\",3\r\n");
+
+    // It reads back the same.
+    let path = std::os::tmpdir().join("rusql_test_to_csv.csv");
+    std::io::File::create(&path).write_str(csv.as_slice()).unwrap();
+    db.execute("CREATE TABLE Copy(Id INTEGER PRIMARY KEY, Name TEXT, Score INTEGER);").unwrap();
+    assert_eq!(db.import_csv(&path, "Copy", &CsvOptions::new()), Ok(3));
+    std::io::fs::unlink(&path).unwrap();
+    let ids: Vec<isize> = db.query_map("SELECT Foo.Id FROM Foo JOIN Copy ON Foo.Id = Copy.Id
+                                        WHERE Foo.Name = Copy.Name AND Foo.Score IS Copy.Score;", &[],
+                                       |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_csv_delimiters() {
+    use rusql::{CsvOptions, Quoting};
+
+    let path = std::os::tmpdir().join("rusql_test_csv_delimiters.tsv");
+    std::io::File::create(&path).write_str("Id\tName\tNote\n1\t\"Bar\"\t\\N\n2\tBaz, Qux\t\n").unwrap();
+
+    let mut db = Rusql::new();
+    let options = CsvOptions { null: "\\N".to_string(), ..CsvOptions::tsv() };
+    assert_eq!(db.import_csv(&path, "Foo", &options), Ok(2));
+    std::io::fs::unlink(&path).unwrap();
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 1;", &[]), Ok("\"Bar\"".to_string()));
+    assert_eq!(db.query_scalar::<isize>("SELECT Id FROM Foo WHERE Note IS NULL;", &[]), Ok(1));
+    assert_eq!(db.query_scalar::<String>("SELECT Note FROM Foo WHERE Id = 2;", &[]), Ok("".to_string()));
+
+    let table = match db.execute("SELECT * FROM Foo;").unwrap().pop() {
+        Some(QueryResult::Rows(table)) => table,
+        _ => panic!("expected rows"),
+    };
+    let csv = |options: &CsvOptions| {
+        let mut w = std::io::MemWriter::new();
+        table.to_csv(&mut w, options).unwrap();
+        String::from_utf8(w.into_inner()).unwrap()
+    };
+
+    assert_eq!(csv(&options).as_slice(), "Id\tName\tNote\r\n1\t\"Bar\"\t\\N\r\n2\tBaz, Qux\t\r\n");
+    let pipes = CsvOptions { delimiter: '|', quoting: Quoting::Always, null: "NULL".to_string(), header: Some(false),
+                             ..CsvOptions::new() };
+    assert_eq!(csv(&pipes).as_slice(), "\"1\"|\"\"\"Bar\"\"\"|NULL\r\n\"2\"|\"Baz, Qux\"|\"\"\r\n");
+}
+
+#[test]
+fn test_import_json() {
+    let json = "[{\"Id\": 1, \"Name\": \"Bar \\\"1\\\" \\u00e9\\ud83d\\ude00\", \"Active\": true, \"Score\": 1.5},
+                 {\"Id\": 2, \"Name\": null, \"Tags\": [\"a\", {\"b\": 2}]},
+                 {\"Name\": \"Baz\", \"Id\": 3}]";
+
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Tagged(Id INTEGER PRIMARY KEY, Tags TEXT);").unwrap();
+    assert!(db.import_json(&mut std::io::MemReader::new(json.as_bytes().to_vec()), "Tagged").is_err());
+    assert_eq!(db.row_count("Tagged"), Ok(0));
+
+    // The table is made from the first object, which has no Tags.
+    assert!(db.import_json(&mut std::io::MemReader::new(json.as_bytes().to_vec()), "Foo").is_err());
+    assert!(!db.table_names().contains(&"Foo".to_string()));
+
+    let json = json.replace(", \"Tags\": [\"a\", {\"b\": 2}]", "");
+    assert_eq!(db.import_json(&mut std::io::MemReader::new(json.into_bytes()), "Foo"), Ok(3));
+    assert_eq!(db.column_names("Foo").unwrap(),
+               vec!["Id".to_string(), "Name".to_string(), "Active".to_string(), "Score".to_string()]);
+    assert_eq!(db.query_scalar::<String>("SELECT Name FROM Foo WHERE Id = 1;", &[]),
+               Ok("Bar \"1\" \u{e9}\u{1f600}".to_string()));
+    assert_eq!(db.query_scalar::<isize>("SELECT Id FROM Foo WHERE Name IS NULL AND Score IS NULL;", &[]), Ok(2));
+
+    let nested = "[{\"Id\": 1, \"Tags\": [\"a\", {\"b\": 2}]}]";
+    assert_eq!(db.import_json(&mut std::io::MemReader::new(nested.as_bytes().to_vec()), "Tagged"), Ok(1));
+    assert_eq!(db.query_scalar::<String>("SELECT Tags FROM Tagged;", &[]), Ok("[\"a\",{\"b\":2}]".to_string()));
+
+    let bad = "[{\"Id\": 4,}]";
+    assert!(db.import_json(&mut std::io::MemReader::new(bad.as_bytes().to_vec()), "Foo").is_err());
+}
+
+#[test]
+fn test_to_json() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Score, Data BLOB);
+                INSERT INTO Foo(Name, Score, Data) VALUES('Bar \"1\"\n', 2.0, x'0AFF'), (NULL, 0.5, NULL);").unwrap();
+
+    let json = |db: &mut Rusql, sql: &str| {
+        let mut w = std::io::MemWriter::new();
+        match db.execute(sql).unwrap().pop() {
+            Some(QueryResult::Rows(table)) => table.to_json(&mut w).unwrap(),
+            _ => panic!("expected rows"),
+        }
+        String::from_utf8(w.into_inner()).unwrap()
+    };
+
+    let text = json(&mut db, "SELECT * FROM Foo;");
+    assert_eq!(text.as_slice(), "[\n{\"Id\":1,\"Name\":\"Bar \\\"1\\\"\\n\",\"Score\":2.0,\"Data\":\"0AFF\"},\n\
+                                 {\"Id\":2,\"Name\":null,\"Score\":0.5,\"Data\":null}\n]\n");
+    assert_eq!(json(&mut db, "SELECT * FROM Foo WHERE Id > 2;").as_slice(), "[]\n");
+
+    // It reads back the same, but for the blob.
+    db.execute("CREATE TABLE Copy(Id INTEGER PRIMARY KEY, Name TEXT, Score, Data TEXT);").unwrap();
+    assert_eq!(db.import_json(&mut std::io::MemReader::new(text.into_bytes()), "Copy"), Ok(2));
+    let ids: Vec<isize> = db.query_map("SELECT Foo.Id FROM Foo JOIN Copy ON Foo.Id = Copy.Id
+                                        WHERE Foo.Name IS Copy.Name AND Foo.Score = Copy.Score;", &[],
+                                       |row| row.get(0)).unwrap();
+    assert_eq!(ids, vec![1, 2]);
+}
+
+#[test]
+fn test_export_json_lines() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT);
+                INSERT INTO Foo(Name) VALUES('Bar'), (NULL), ('Baz');").unwrap();
+
+    let mut w = std::io::MemWriter::new();
+    assert_eq!(db.export_json_lines("SELECT Id, Name FROM Foo WHERE Id < 3;", &mut w), Ok(2));
+    assert_eq!(String::from_utf8(w.into_inner()).unwrap().as_slice(),
+               "{\"Id\":1,\"Name\":\"Bar\"}\n{\"Id\":2,\"Name\":null}\n");
+
+    let mut w = std::io::MemWriter::new();
+    assert_eq!(db.export_json_lines("SELECT * FROM Foo ORDER BY Id DESC LIMIT 1;", &mut w), Ok(1));
+    assert_eq!(String::from_utf8(w.into_inner()).unwrap().as_slice(), "{\"Id\":3,\"Name\":\"Baz\"}\n");
+
+    assert!(db.export_json_lines("DELETE FROM Foo;", &mut std::io::MemWriter::new()).is_err());
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_to_parquet() {
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Score, Active BOOLEAN, Price NUMERIC(5,2));
+                INSERT INTO Foo(Name, Score, Active, Price) VALUES('Bar', 1, TRUE, 1.25), (NULL, 2.5, FALSE, NULL),
+                                                                 ('Baz', NULL, NULL, NULL);").unwrap();
+
+    let mut w = std::io::MemWriter::new();
+    match db.execute("SELECT * FROM Foo;").unwrap().pop() {
+        Some(QueryResult::Rows(table)) => table.to_parquet(&mut w).unwrap(),
+        _ => panic!("expected rows"),
+    }
+    let bytes = w.into_inner();
+    let len = bytes.len();
+
+    assert_eq!(&bytes[..4], b"PAR1");
+    assert_eq!(&bytes[len - 4..], b"PAR1");
+
+    // The metadata ends just before its length, with the column names in it.
+    let metadata_len = bytes[len - 8] as usize | (bytes[len - 7] as usize) << 8 | (bytes[len - 6] as usize) << 16
+                       | (bytes[len - 5] as usize) << 24;
+    assert!(metadata_len < len - 12);
+    let metadata = String::from_utf8_lossy(&bytes[len - 8 - metadata_len..len - 8]).into_owned();
+    for name in ["Id", "Name", "Score", "Active", "rusql"].iter() {
+        assert!(metadata.as_slice().contains(*name));
+    }
+
+    // Scores are doubles, with the NULL left out.
+    let score = bytes.windows(8).position(|window| window == [0, 0, 0, 0, 0, 0, 4, 64].as_slice());
+    assert!(score.is_some());
+
+    // So are decimals.
+    let price = bytes.windows(8).position(|window| window == [0, 0, 0, 0, 0, 0, 244, 63].as_slice());
+    assert!(price.is_some());
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn test_record_batch() {
+    use rusql::record_batch::{Array, DataType};
+    use rusql::table::Table;
+
+    let mut db = Rusql::new();
+    db.execute("CREATE TABLE Foo(Id INTEGER PRIMARY KEY, Name TEXT, Score, Data BLOB, Active BOOLEAN);
+                INSERT INTO Foo(Name, Score, Data, Active) VALUES('Bar', 1.5, x'00FF', TRUE),
+                                                                 (NULL, 2.5, NULL, NULL);").unwrap();
+
+    let table = match db.execute("SELECT * FROM Foo;").unwrap().pop() {
+        Some(QueryResult::Rows(table)) => table,
+        _ => panic!("expected rows"),
+    };
+    let batch = table.to_record_batch().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(batch.num_columns(), 5);
+    assert_eq!(batch.fields()[1].name, "Name".to_string());
+    assert_eq!(batch.column(2).data_type(), DataType::Float64);
+    assert_eq!(batch.column(2).null_count(), 0);
+    assert_eq!(batch.column(1).null_count(), 1);
+
+    // The buffers are laid out as Arrow has them.
+    let name = batch.column(1);
+    assert_eq!(name.validity(), [1u8].as_slice());
+    assert_eq!(name.offsets(), [0u8, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0].as_slice());
+    assert_eq!(name.values(), "Bar".as_bytes());
+    assert_eq!(batch.column(0).values(), [1u8, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0].as_slice());
+    assert_eq!(batch.column(4).value(0), LiteralValue::Boolean(true));
+
+    // It converts back to the same rows.
+    let copy = Table::from_record_batch(&batch).unwrap();
+    assert_eq!(copy.header.iter().map(|column| column.name.clone()).collect::<Vec<String>>(),
+               table.header.iter().map(|column| column.name.clone()).collect::<Vec<String>>());
+    assert_eq!(copy.data.values().cloned().collect::<Vec<TableRow>>(),
+               table.data.values().cloned().collect::<Vec<TableRow>>());
+
+    // Decimals are doubles, like the other numbers.
+    db.execute("CREATE TABLE Item(Price NUMERIC(5,2)); INSERT INTO Item VALUES(1.25), (2);").unwrap();
+    let batch = match db.execute("SELECT Price FROM Item;").unwrap().pop() {
+        Some(QueryResult::Rows(table)) => table.to_record_batch().unwrap(),
+        _ => panic!("expected rows"),
+    };
+    assert_eq!(batch.column(0).data_type(), DataType::Float64);
+    assert_eq!(batch.column(0).value(0), LiteralValue::Real(1.25));
+    assert_eq!(batch.column(0).value(1), LiteralValue::Real(2.0));
+
+    // Buffers that don't hold the values are turned down.
+    assert!(Array::new(DataType::Int64, 2, Vec::new(), Vec::new(), vec![0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    assert!(Array::new(DataType::Utf8, 1, Vec::new(), vec![0, 0, 0, 0, 9, 0, 0, 0], vec![97]).is_err());
+    assert!(Array::new(DataType::Utf8, 1, Vec::new(), vec![0, 0, 0, 0, 1, 0, 0, 0], vec![97]).is_ok());
+}
@@ -1,4 +1,4 @@
-#![allow(unstable)]
+#![allow(unstable, deprecated)]
 
 extern crate rusql;
 
@@ -6,7 +6,7 @@ use rusql::{rusql_exec, Rusql, LiteralValue};
 
 fn test(sql_str: &str, expected: Vec<LiteralValue>) {
     let mut db = Rusql::new();
-    let result_table = rusql_exec(&mut db, sql_str, |_,_| {}).unwrap();
+    let result_table = rusql_exec(&mut db, sql_str, |_,_| {}).remove(0);
 
     let results = result_table.data.get(&1).unwrap();
 
@@ -16,7 +16,7 @@ fn test(sql_str: &str, expected: Vec<LiteralValue>) {
 fn test_expect_ints(sql_str: &str, expected: Vec<isize>) {
     let mut db = Rusql::new();
     let mut results: Vec<isize> = Vec::new();
-    let result_table = rusql_exec(&mut db, sql_str, |_,_| {}).unwrap();
+    let result_table = rusql_exec(&mut db, sql_str, |_,_| {}).remove(0);
     let result_row = result_table.data.get(&1).unwrap();
 
     for column in result_row.iter() {
@@ -29,7 +29,7 @@ fn test_expect_ints(sql_str: &str, expected: Vec<isize>) {
 #[test]
 fn test_literal_values() {
     test("SELECT 26, \"Foo\";",
-         vec![LiteralValue::Integer(26), LiteralValue::Text("Foo".to_string())]);
+         vec![LiteralValue::Integer(26), LiteralValue::text("Foo")]);
 }
 
 #[test]
@@ -108,6 +108,15 @@ fn test_modulo() {
     test_expect_ints("SELECT 15%6;", vec![3]);
 }
 
+#[test]
+fn test_division_by_zero() {
+    test("SELECT 1/0, 1%0, 0/0;", vec![LiteralValue::Null, LiteralValue::Null, LiteralValue::Null]);
+
+    // The smallest integer divided by -1 doesn't fit in an integer.
+    test("SELECT (0 - 9223372036854775807 - 1) / -1, (0 - 9223372036854775807 - 1) % -1;",
+         vec![LiteralValue::Real(9223372036854775808.0), LiteralValue::Integer(0)]);
+}
+
 #[test]
 fn test_not_equals() {
     test_expect_ints("SELECT 5!=4, 5<>4, 5!=5;", vec![1, 1, 0]);
@@ -182,3 +191,34 @@ fn test_bit_neg() {
 fn test_mult_div_associativity() {
     test_expect_ints("SELECT 9/3*3;", vec![9]);
 }
+
+#[test]
+fn test_is_null() {
+    test("SELECT NULL IS NULL, 1 IS NULL, 1 IS NOT NULL;",
+         vec![LiteralValue::Boolean(true), LiteralValue::Boolean(false), LiteralValue::Boolean(true)]);
+}
+
+#[test]
+fn test_functions() {
+    test("SELECT lower(\"FoO\"), upper(\"FoO\"), length(\"FoO\"), abs(0 - 3);",
+         vec![LiteralValue::text("foo"), LiteralValue::text("FOO"),
+              LiteralValue::Integer(3), LiteralValue::Integer(3)]);
+}
+
+#[test]
+fn test_blobs() {
+    test("SELECT x'00fF10', X'', length(x'0102'), x'01' < x'0102', x'01' > \"text\";",
+         vec![LiteralValue::Blob(vec![0, 255, 16]), LiteralValue::Blob(Vec::new()),
+              LiteralValue::Integer(2), LiteralValue::Boolean(true), LiteralValue::Boolean(true)]);
+}
+
+#[test]
+fn test_reals() {
+    test("SELECT 0.5 + 0.25, 1.5 * 2, 1.50 = 1.5, 2 - 0.25, 1.0 / 4, 1.5 / 0;",
+         vec![LiteralValue::Real(0.75), LiteralValue::Real(3.0), LiteralValue::Boolean(true),
+              LiteralValue::Real(1.75), LiteralValue::Real(0.25), LiteralValue::Null]);
+
+    // Too many digits for an integer.
+    test("SELECT 99999999999999999999.5, 99999999999999999999;",
+         vec![LiteralValue::Real(1e20), LiteralValue::Real(1e20)]);
+}